@@ -1,8 +1,18 @@
 use math::Float;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct HdrColor<F: Float> {
     pub r: F,
     pub g: F,
     pub b: F,
 }
+
+impl<F: Float> HdrColor<F> {
+    pub fn new(r: F, g: F, b: F) -> Self {
+        HdrColor { r, g, b }
+    }
+
+    pub fn black() -> Self {
+        Self::new(F::zero(), F::zero(), F::zero())
+    }
+}
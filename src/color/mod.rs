@@ -1,5 +1,7 @@
+mod hdr_color;
 mod screen_space_color;
 mod xyz_color;
 
+pub use self::hdr_color::*;
 pub use self::screen_space_color::*;
 pub use self::xyz_color::*;
@@ -24,6 +24,30 @@ impl ScreenSpaceColor {
         Self { r, g, b, a: 255 }
     }
 
+    /// False color ramp from black over blue, cyan, green and yellow to red
+    /// for `value` in `0..=1`, clamped outside of it.
+    pub fn heat<F: Float>(value: F) -> Self {
+        const STOPS: [(u8, u8, u8); 6] = [
+            (0, 0, 0),
+            (0, 0, 255),
+            (0, 255, 255),
+            (0, 255, 0),
+            (255, 255, 0),
+            (255, 0, 0),
+        ];
+        let segments = F::from(STOPS.len() - 1).unwrap();
+        let position = value.max(F::zero()).min(F::one()) * segments;
+        let index = position.to_usize().unwrap_or(0).min(STOPS.len() - 2);
+        let t = position - F::from(index).unwrap();
+
+        let (from, to) = (STOPS[index], STOPS[index + 1]);
+        let mix = |a: u8, b: u8| {
+            let (a, b) = (F::from(a).unwrap(), F::from(b).unwrap());
+            (a + (b - a) * t).round().to_u8().unwrap_or(0)
+        };
+        Self::rgb(mix(from.0, to.0), mix(from.1, to.1), mix(from.2, to.2))
+    }
+
     pub fn as_rgb_u32(&self) -> u32 {
         ((self.r as u32) << 24) | ((self.g as u32) << 16) | ((self.b as u32) << 8)
     }
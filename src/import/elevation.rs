@@ -0,0 +1,283 @@
+use import::{ImportError, ParseError};
+use math::{Float, Point, Vector};
+use scenegraph::Heightfield;
+use shading::{Material, SampledTexture};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+/// Grid of elevation samples, row after row, ready for a `Heightfield`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Elevation<F: Float> {
+    pub columns: usize,
+    pub rows: usize,
+    pub samples: Vec<F>,
+}
+
+impl<F: Float> Elevation<F> {
+    /// Terrain spanning `size` from `origin`, see `Heightfield::new`. None
+    /// if the grid is smaller than two by two samples.
+    pub fn into_heightfield<M: Material<F>>(
+        self,
+        origin: Point<F>,
+        size: Vector<F>,
+        material: M,
+    ) -> Option<Heightfield<F, M>> {
+        Heightfield::new(
+            origin,
+            size,
+            self.columns,
+            self.rows,
+            self.samples,
+            material,
+        )
+    }
+
+    /// The samples as a texture, for example as a displacement map. None
+    /// without samples.
+    pub fn into_texture(self) -> Option<SampledTexture<F>> {
+        SampledTexture::new(self.columns, self.rows, self.samples)
+    }
+}
+
+/// Layout of an elevation file. Raw files carry no header, so their grid
+/// size has to be given.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElevationFormat {
+    /// Binary PGM (`P5`), 8 or 16 bit grayscale, scaled to `0..=1`.
+    Pgm,
+    /// Little endian unsigned 16 bit samples, as exported by most terrain
+    /// tools, scaled to `0..=1`.
+    RawU16 { columns: usize, rows: usize },
+    /// Little endian 32 bit floats, kept as they are.
+    RawF32 { columns: usize, rows: usize },
+}
+
+pub fn load_elevation<F: Float, P: AsRef<Path>>(
+    path: P,
+    format: ElevationFormat,
+) -> Result<Elevation<F>, ImportError> {
+    let path = path.as_ref();
+    let io_error = |e| ImportError::Io(path.to_path_buf(), e);
+    let mut reader = BufReader::new(File::open(path).map_err(io_error)?);
+    let byte_len = |columns: usize, rows: usize, width: usize| {
+        columns
+            .checked_mul(rows)
+            .and_then(|count| count.checked_mul(width))
+            .ok_or_else(|| {
+                let message = format!("{} by {} samples are too many", columns, rows);
+                io_error(io::Error::new(io::ErrorKind::InvalidInput, message))
+            })
+    };
+    match format {
+        ElevationFormat::Pgm => parse_pgm(reader).map_err(|e| e.in_file(path).into()),
+        ElevationFormat::RawU16 { columns, rows } => {
+            let len = byte_len(columns, rows, 2)?;
+            let samples = read_samples(&mut reader, len).map_err(io_error)?;
+            let max = F::from(u16::MAX).unwrap();
+            let samples = samples
+                .chunks(2)
+                .map(|b| F::from(u16::from_le_bytes([b[0], b[1]])).unwrap() / max)
+                .collect();
+            Ok(Elevation {
+                columns,
+                rows,
+                samples,
+            })
+        }
+        ElevationFormat::RawF32 { columns, rows } => {
+            let len = byte_len(columns, rows, 4)?;
+            let samples = read_samples(&mut reader, len).map_err(io_error)?;
+            let samples = samples
+                .chunks(4)
+                .map(|b| F::from(f32::from_le_bytes([b[0], b[1], b[2], b[3]])).unwrap())
+                .collect();
+            Ok(Elevation {
+                columns,
+                rows,
+                samples,
+            })
+        }
+    }
+}
+
+/// Reads exactly `len` bytes. The buffer grows with the data actually read,
+/// so a bogus size in a short file fails instead of allocating it up front.
+fn read_samples<R: Read>(reader: &mut R, len: usize) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    reader.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() < len {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "file ends before the last sample",
+        ));
+    }
+    Ok(bytes)
+}
+
+/// Reads a binary PGM image. Samples wider than 8 bit are big endian, as
+/// the format demands.
+pub fn parse_pgm<F: Float, R: BufRead>(mut reader: R) -> Result<Elevation<F>, ParseError> {
+    let mut line = 1;
+    let mut header = Vec::new();
+    while header.len() < 4 {
+        header.push(header_token(&mut reader, &mut line)?);
+    }
+    if header[0] != "P5" {
+        return Err(ParseError::new(line, "not a binary PGM file"));
+    }
+    let number = |token: &str, what: &str| {
+        token
+            .parse::<usize>()
+            .map_err(|_| ParseError::new(line, format!("invalid {} '{}'", what, token)))
+    };
+    let columns = number(&header[1], "width")?;
+    let rows = number(&header[2], "height")?;
+    let max = number(&header[3], "maximum value")?;
+    if max == 0 || max > usize::from(u16::MAX) {
+        return Err(ParseError::new(
+            line,
+            format!("unsupported maximum value {}", max),
+        ));
+    }
+
+    let width = if max > 255 { 2 } else { 1 };
+    let len = columns
+        .checked_mul(rows)
+        .and_then(|count| count.checked_mul(width))
+        .ok_or_else(|| {
+            let message = format!("image of {} by {} pixels is too large", columns, rows);
+            ParseError::new(line, message)
+        })?;
+    let bytes = read_samples(&mut reader, len)
+        .map_err(|_| ParseError::new(line, "pixel data ends early"))?;
+    let scale = F::from(max).unwrap().recip();
+    let samples = bytes
+        .chunks(width)
+        .map(|b| {
+            let value = b
+                .iter()
+                .fold(0u32, |value, &byte| value << 8 | u32::from(byte));
+            F::from(value).unwrap() * scale
+        })
+        .collect();
+
+    Ok(Elevation {
+        columns,
+        rows,
+        samples,
+    })
+}
+
+/// Next whitespace separated header field, skipping comments. Consumes the
+/// single whitespace byte ending the field, after the last one of the
+/// header the pixel data starts.
+fn header_token<R: BufRead>(reader: &mut R, line: &mut usize) -> Result<String, ParseError> {
+    let mut token = String::new();
+    let mut comment = false;
+    let mut byte = [0];
+    loop {
+        if reader.read(&mut byte).unwrap_or(0) == 0 {
+            return Err(ParseError::new(*line, "header ends early"));
+        }
+        let c = byte[0] as char;
+        if c == '\n' {
+            *line += 1;
+            comment = false;
+        }
+        if comment {
+            continue;
+        }
+        if c == '#' {
+            comment = true;
+        } else if c.is_ascii_whitespace() {
+            if !token.is_empty() {
+                return Ok(token);
+            }
+        } else {
+            token.push(c);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shading::DebugNormalMaterial;
+    use std::io::Cursor;
+    use std::{env, fs};
+
+    #[test]
+    fn test_parse_pgm() {
+        let mut file = b"P5\n# terrain\n3 2\n65535\n".to_vec();
+        for sample in &[0u16, 65535, 32768, 1, 256, 65535] {
+            file.extend_from_slice(&sample.to_be_bytes());
+        }
+        let elevation: Elevation<f64> = parse_pgm(Cursor::new(&file)).unwrap();
+        assert_eq!((elevation.columns, elevation.rows), (3, 2));
+        assert_eq!(elevation.samples[1], 1.0);
+        assert_eq!(elevation.samples[4], 256.0 / 65535.0);
+
+        let eight_bit = parse_pgm::<f64, _>(Cursor::new(b"P5 2 1 255 \x00\xff".to_vec())).unwrap();
+        assert_eq!(eight_bit.samples, vec![0.0, 1.0]);
+
+        let truncated = parse_pgm::<f64, _>(Cursor::new(&file[..file.len() - 1])).unwrap_err();
+        assert_eq!(truncated.line, 5);
+        assert!(parse_pgm::<f64, _>(Cursor::new(b"P2 2 1 255 0 255".to_vec())).is_err());
+
+        // sizes from the header are not trusted with memory
+        let huge = b"P5 400000 400000 65535 \x00\x00".to_vec();
+        let err = parse_pgm::<f64, _>(Cursor::new(huge)).unwrap_err();
+        assert_eq!(err.message, "pixel data ends early");
+        let overflowing = format!("P5 {} 3 255 \x00", usize::MAX / 2);
+        assert!(parse_pgm::<f64, _>(Cursor::new(overflowing.into_bytes())).is_err());
+    }
+
+    #[test]
+    fn test_load_raw() {
+        let path = env::temp_dir().join(format!("norays-elevation-{}.raw", ::std::process::id()));
+        let mut bytes = Vec::new();
+        for sample in &[-1.5f32, 0.0, 2.25, 1000.0] {
+            bytes.extend_from_slice(&sample.to_le_bytes());
+        }
+        fs::write(&path, &bytes).unwrap();
+
+        let floats = ElevationFormat::RawF32 {
+            columns: 2,
+            rows: 2,
+        };
+        let elevation: Elevation<f64> = load_elevation(&path, floats).unwrap();
+        assert_eq!(elevation.samples, vec![-1.5, 0.0, 2.25, 1000.0]);
+        let terrain = elevation.into_heightfield(
+            Point::origin(),
+            Vector::new(1.0, 1.0, 1.0),
+            DebugNormalMaterial {},
+        );
+        assert!(terrain.is_some());
+
+        // the same bytes hold eight 16 bit samples
+        let shorts = ElevationFormat::RawU16 {
+            columns: 4,
+            rows: 2,
+        };
+        let elevation: Elevation<f64> = load_elevation(&path, shorts).unwrap();
+        assert_eq!(elevation.samples[2], 0.0);
+        let too_many = ElevationFormat::RawU16 {
+            columns: 4,
+            rows: 3,
+        };
+        match load_elevation::<f64, _>(&path, too_many) {
+            Err(ImportError::Io(_, ref e)) => assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof),
+            _ => panic!("missing samples not detected"),
+        }
+        let overflowing = ElevationFormat::RawF32 {
+            columns: usize::MAX / 2,
+            rows: 3,
+        };
+        match load_elevation::<f64, _>(&path, overflowing) {
+            Err(ImportError::Io(_, ref e)) => assert_eq!(e.kind(), io::ErrorKind::InvalidInput),
+            _ => panic!("overflowing size not detected"),
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,72 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub file: Option<PathBuf>,
+    pub line: usize,
+    pub message: String,
+}
+
+impl ParseError {
+    pub fn new<S: Into<String>>(line: usize, message: S) -> Self {
+        ParseError {
+            file: None,
+            line,
+            message: message.into(),
+        }
+    }
+
+    pub fn in_file<P: Into<PathBuf>>(self, file: P) -> Self {
+        ParseError {
+            file: Some(file.into()),
+            ..self
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.file {
+            Some(ref file) => write!(f, "{}:{}: {}", file.display(), self.line, self.message),
+            None => write!(f, "line {}: {}", self.line, self.message),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+#[derive(Debug)]
+pub enum ImportError {
+    Io(PathBuf, io::Error),
+    Parse(ParseError),
+    NoGeometry,
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImportError::Io(path, err) => write!(f, "cannot read {}: {}", path.display(), err),
+            ImportError::Parse(err) => write!(f, "parse error at {}", err),
+            ImportError::NoGeometry => write!(f, "file contains no faces"),
+        }
+    }
+}
+
+impl Error for ImportError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ImportError::Io(_, err) => Some(err),
+            ImportError::Parse(err) => Some(err),
+            ImportError::NoGeometry => None,
+        }
+    }
+}
+
+impl From<ParseError> for ImportError {
+    fn from(err: ParseError) -> Self {
+        ImportError::Parse(err)
+    }
+}
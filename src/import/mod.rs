@@ -0,0 +1,11 @@
+mod elevation;
+mod error;
+mod mtl;
+mod obj;
+mod tokens;
+
+pub use self::elevation::*;
+pub use self::error::*;
+pub use self::mtl::*;
+pub use self::obj::*;
+pub use self::tokens::*;
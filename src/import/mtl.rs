@@ -0,0 +1,145 @@
+use color::HdrColor;
+use import::{ParseError, Statement};
+use math::Float;
+use std::collections::HashMap;
+use std::io::BufRead;
+
+/// Material description from a Wavefront `.mtl` library. Colors and texture
+/// paths are kept as written, mapping to a renderer `Material` is up to the
+/// caller.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjMaterial<F: Float> {
+    pub name: String,
+    pub ambient: HdrColor<F>,
+    pub diffuse: HdrColor<F>,
+    pub specular: HdrColor<F>,
+    pub emission: HdrColor<F>,
+    pub shininess: F,
+    pub ior: F,
+    pub dissolve: F,
+    pub illumination: u32,
+    pub diffuse_map: Option<String>,
+    pub specular_map: Option<String>,
+    pub bump_map: Option<String>,
+}
+
+impl<F: Float> ObjMaterial<F> {
+    pub fn new(name: String) -> Self {
+        ObjMaterial {
+            name,
+            ambient: HdrColor::black(),
+            diffuse: HdrColor::new(F::one(), F::one(), F::one()),
+            specular: HdrColor::black(),
+            emission: HdrColor::black(),
+            shininess: F::zero(),
+            ior: F::one(),
+            dissolve: F::one(),
+            illumination: 0,
+            diffuse_map: None,
+            specular_map: None,
+            bump_map: None,
+        }
+    }
+}
+
+fn color<F: Float>(stmt: &mut Statement) -> Result<HdrColor<F>, ParseError> {
+    let r = stmt.float()?;
+    // a single value means grey
+    let g = stmt.optional_float()?.unwrap_or(r);
+    let b = stmt.optional_float()?.unwrap_or(g);
+    Ok(HdrColor::new(r, g, b))
+}
+
+fn map_path(stmt: &mut Statement) -> Result<Option<String>, ParseError> {
+    // texture options come first, the file name is always the last token
+    stmt.remaining()
+        .last()
+        .map(|path| Some(path.to_string()))
+        .ok_or_else(|| stmt.error(format!("`{}` is missing a file name", stmt.keyword)))
+}
+
+pub fn parse_mtl<F: Float, R: BufRead>(
+    reader: R,
+) -> Result<HashMap<String, ObjMaterial<F>>, ParseError> {
+    let mut materials = HashMap::new();
+    let mut current: Option<ObjMaterial<F>> = None;
+
+    for (idx, line) in reader.lines().enumerate() {
+        let text = line.map_err(|e| ParseError::new(idx + 1, e.to_string()))?;
+        let mut stmt = match Statement::parse(idx + 1, &text) {
+            Some(stmt) => stmt,
+            None => continue,
+        };
+
+        if stmt.keyword == "newmtl" {
+            let name = stmt.rest();
+            if name.is_empty() {
+                return Err(stmt.error("`newmtl` is missing a name"));
+            }
+            if let Some(done) = current.take() {
+                materials.insert(done.name.clone(), done);
+            }
+            current = Some(ObjMaterial::new(name));
+            continue;
+        }
+
+        let material = match current.as_mut() {
+            Some(material) => material,
+            None => return Err(stmt.error(format!("`{}` before `newmtl`", stmt.keyword))),
+        };
+
+        match stmt.keyword {
+            "Ka" => material.ambient = color(&mut stmt)?,
+            "Kd" => material.diffuse = color(&mut stmt)?,
+            "Ks" => material.specular = color(&mut stmt)?,
+            "Ke" => material.emission = color(&mut stmt)?,
+            "Ns" => material.shininess = stmt.float()?,
+            "Ni" => material.ior = stmt.float()?,
+            "d" => material.dissolve = stmt.float()?,
+            "Tr" => material.dissolve = F::one() - stmt.float()?,
+            "illum" => {
+                let token = stmt.expect_token("an illumination model")?;
+                material.illumination = token
+                    .parse()
+                    .map_err(|_| stmt.error(format!("invalid illumination model `{}`", token)))?;
+            }
+            "map_Kd" => material.diffuse_map = map_path(&mut stmt)?,
+            "map_Ks" => material.specular_map = map_path(&mut stmt)?,
+            "map_bump" | "bump" => material.bump_map = map_path(&mut stmt)?,
+            // everything else (Tf, sharpness, other maps) is not used by the renderer
+            _ => {}
+        }
+    }
+
+    if let Some(done) = current {
+        materials.insert(done.name.clone(), done);
+    }
+
+    Ok(materials)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_parse_mtl() {
+        let materials = parse_mtl::<f64, _>(Cursor::new(
+            "newmtl red
+            Kd 1 0 0
+            Ns 10
+            map_Kd -s 1 1 1 red.png
+            newmtl grey
+            Kd 0.5
+            Tr 0.25
+            ",
+        ))
+        .unwrap();
+
+        assert_eq!(materials["red"].diffuse.r, 1.0);
+        assert_eq!(materials["red"].diffuse_map, Some("red.png".to_string()));
+        assert_eq!(materials["grey"].diffuse.b, 0.5);
+        assert_eq!(materials["grey"].dissolve, 0.75);
+    }
+}
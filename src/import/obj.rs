@@ -0,0 +1,545 @@
+use import::{parse_mtl, ImportError, ObjMaterial, ParseError, Statement};
+use math::{Float, Point, Vector, UV};
+use scenegraph::{
+    smooth_normals, MeshBvh, SahBuilder, ScreenEdgeLength, SubdivisionCage, SubdivisionScheme,
+    TriangleMesh,
+};
+use shading::Material;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use tracing::Projection;
+
+/// Triangulated mesh for a single group and material combination. All
+/// attributes share one index buffer, vertices are split where an OBJ file
+/// uses different normal or UV indices for the same position. `polygons`
+/// keeps the faces as they were before triangulation, into the same
+/// vertices.
+#[derive(Debug, Clone)]
+pub struct ObjMesh<F: Float> {
+    pub name: String,
+    pub material: Option<String>,
+    pub positions: Vec<Point<F>>,
+    pub normals: Option<Vec<Vector<F>>>,
+    pub uvs: Option<Vec<UV<F>>>,
+    pub indices: Vec<[usize; 3]>,
+    pub polygons: Vec<Vec<usize>>,
+}
+
+impl<F: Float> ObjMesh<F> {
+    /// The polygons as the control cage of a subdivision surface, with the
+    /// vertices split at normal and UV seams welded back together.
+    pub fn cage(&self) -> Option<SubdivisionCage<F>> {
+        SubdivisionCage::new(self.positions.clone(), self.polygons.clone()).map(|c| c.welded())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ObjScene<F: Float> {
+    pub meshes: Vec<ObjMesh<F>>,
+    pub materials: HashMap<String, ObjMaterial<F>>,
+    pub material_libs: Vec<String>,
+}
+
+impl<F: Float> ObjScene<F> {
+    /// Converts every mesh into `TriangleMesh`es and builds a SAH BVH with
+    /// each triangle in its own leaf. `material_for` receives the `usemtl`
+    /// material of a mesh, or `None` when it has none or it is not found in
+    /// any library.
+    pub fn into_bvh<M, Func>(self, mut material_for: Func) -> Result<MeshBvh<F, M>, ImportError>
+    where
+        M: Material<F>,
+        Func: FnMut(Option<&ObjMaterial<F>>) -> M,
+    {
+        let materials = self.materials;
+        let mut leaves = Vec::new();
+
+        for mesh in self.meshes {
+            let material = material_for(mesh.material.as_ref().and_then(|m| materials.get(m)));
+            let triangles = TriangleMesh::new(
+                mesh.positions,
+                mesh.normals,
+                mesh.uvs,
+                mesh.indices,
+                material,
+            )
+            .ok_or(ImportError::NoGeometry)?;
+            leaves.extend(triangles.into_triangles());
+        }
+
+        SahBuilder::new()
+            .build(leaves)
+            .ok_or(ImportError::NoGeometry)
+    }
+
+    /// Like `into_bvh`, but renders every mesh as a subdivision surface of
+    /// its polygons, tessellated by `SubdivisionCage::tessellate` before the
+    /// BVH is built. Normals come from the tessellated surface, UVs are not
+    /// carried over.
+    pub fn into_subdivided_bvh<M, C, Func>(
+        self,
+        scheme: SubdivisionScheme,
+        max_level: usize,
+        screen: Option<&ScreenEdgeLength<F, C>>,
+        mut material_for: Func,
+    ) -> Result<MeshBvh<F, M>, ImportError>
+    where
+        M: Material<F>,
+        C: Projection<F>,
+        Func: FnMut(Option<&ObjMaterial<F>>) -> M,
+    {
+        let materials = self.materials;
+        let mut leaves = Vec::new();
+
+        for mesh in self.meshes {
+            let material = material_for(mesh.material.as_ref().and_then(|m| materials.get(m)));
+            let triangles = mesh
+                .cage()
+                .and_then(|cage| {
+                    cage.tessellate(scheme, max_level, screen)
+                        .into_mesh(material)
+                })
+                .ok_or(ImportError::NoGeometry)?;
+            leaves.extend(triangles.into_triangles());
+        }
+
+        SahBuilder::new()
+            .build(leaves)
+            .ok_or(ImportError::NoGeometry)
+    }
+}
+
+/// Loads an `.obj` file together with all `.mtl` libraries it references,
+/// resolved relative to the directory of the `.obj` file.
+pub fn load_obj<F: Float, P: AsRef<Path>>(path: P) -> Result<ObjScene<F>, ImportError> {
+    let path = path.as_ref();
+    let file = File::open(path).map_err(|e| ImportError::Io(path.to_path_buf(), e))?;
+    let mut scene = parse_obj(BufReader::new(file)).map_err(|e| e.in_file(path))?;
+
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    for lib in &scene.material_libs {
+        let lib_path = dir.join(lib);
+        let file = File::open(&lib_path).map_err(|e| ImportError::Io(lib_path.clone(), e))?;
+        let materials = parse_mtl(BufReader::new(file)).map_err(|e| e.in_file(&lib_path))?;
+        scene.materials.extend(materials);
+    }
+
+    Ok(scene)
+}
+
+/// Decides which faces may share a vertex when the file does not provide
+/// normals, so generated normals are only averaged inside a smoothing group.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Smoothing {
+    Explicit,
+    Group(u32),
+    Face(usize),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct VertexKey {
+    position: usize,
+    uv: Option<usize>,
+    normal: Option<usize>,
+    smoothing: Smoothing,
+}
+
+struct MeshBuilder<F: Float> {
+    name: String,
+    material: Option<String>,
+    vertices: HashMap<VertexKey, usize>,
+    positions: Vec<Point<F>>,
+    normals: Vec<Option<Vector<F>>>,
+    uvs: Vec<Option<UV<F>>>,
+    indices: Vec<[usize; 3]>,
+    polygons: Vec<Vec<usize>>,
+}
+
+impl<F: Float> MeshBuilder<F> {
+    fn new(name: String, material: Option<String>) -> Self {
+        MeshBuilder {
+            name,
+            material,
+            vertices: HashMap::new(),
+            positions: Vec::new(),
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            indices: Vec::new(),
+            polygons: Vec::new(),
+        }
+    }
+
+    fn vertex(&mut self, key: VertexKey, data: &ObjData<F>) -> usize {
+        let positions = &mut self.positions;
+        let normals = &mut self.normals;
+        let uvs = &mut self.uvs;
+        *self.vertices.entry(key).or_insert_with(|| {
+            positions.push(data.positions[key.position]);
+            normals.push(key.normal.and_then(|n| data.normals[n]));
+            uvs.push(key.uv.map(|uv| data.uvs[uv]));
+            positions.len() - 1
+        })
+    }
+
+    fn build(self) -> ObjMesh<F> {
+        let normals = if self.normals.iter().all(Option::is_some) {
+            self.normals.into_iter().map(Option::unwrap).collect()
+        } else {
+            generate_normals(&self.positions, &self.normals, &self.indices)
+        };
+
+        let uvs = if self.uvs.iter().any(Option::is_some) {
+            Some(
+                self.uvs
+                    .into_iter()
+                    .map(Option::unwrap_or_default)
+                    .collect(),
+            )
+        } else {
+            None
+        };
+
+        ObjMesh {
+            name: self.name,
+            material: self.material,
+            positions: self.positions,
+            normals: Some(normals),
+            uvs,
+            indices: self.indices,
+            polygons: self.polygons,
+        }
+    }
+}
+
+/// Explicit normals where there are, `smooth_normals` for the other
+/// vertices. Vertices of flat shaded faces are never shared, so they end up
+/// with the plain face normal.
+fn generate_normals<F: Float>(
+    positions: &[Point<F>],
+    normals: &[Option<Vector<F>>],
+    indices: &[[usize; 3]],
+) -> Vec<Vector<F>> {
+    normals
+        .iter()
+        .zip(smooth_normals(positions, indices))
+        .map(|(normal, smooth)| normal.unwrap_or(smooth))
+        .collect()
+}
+
+/// Raw attribute streams, indexed the way OBJ faces refer to them. Zero
+/// length normals are kept as None, to be generated like missing ones.
+struct ObjData<F: Float> {
+    positions: Vec<Point<F>>,
+    normals: Vec<Option<Vector<F>>>,
+    uvs: Vec<UV<F>>,
+}
+
+/// Resolves a 1-based or negative (relative) OBJ index into a 0-based one.
+fn resolve_index(stmt: &Statement, token: &str, count: usize) -> Result<usize, ParseError> {
+    let index: isize = token
+        .parse()
+        .map_err(|_| stmt.error(format!("invalid index `{}`", token)))?;
+
+    let resolved = if index > 0 {
+        index - 1
+    } else if index < 0 {
+        count as isize + index
+    } else {
+        return Err(stmt.error("index 0 is not valid, OBJ indices start at 1"));
+    };
+
+    if resolved < 0 || resolved as usize >= count {
+        Err(stmt.error(format!(
+            "index {} is out of range, only {} defined so far",
+            index, count
+        )))
+    } else {
+        Ok(resolved as usize)
+    }
+}
+
+fn parse_face_vertex<F: Float>(
+    stmt: &Statement,
+    token: &str,
+    data: &ObjData<F>,
+    smoothing: Smoothing,
+) -> Result<VertexKey, ParseError> {
+    let mut parts = token.split('/');
+    let position = match parts.next() {
+        Some(p) if !p.is_empty() => resolve_index(stmt, p, data.positions.len())?,
+        _ => return Err(stmt.error(format!("face vertex `{}` has no position", token))),
+    };
+    let uv = match parts.next() {
+        Some(uv) if !uv.is_empty() => Some(resolve_index(stmt, uv, data.uvs.len())?),
+        _ => None,
+    };
+    let normal = match parts.next() {
+        Some(n) if !n.is_empty() => Some(resolve_index(stmt, n, data.normals.len())?),
+        _ => None,
+    };
+    if parts.next().is_some() {
+        return Err(stmt.error(format!("malformed face vertex `{}`", token)));
+    }
+
+    Ok(VertexKey {
+        position,
+        uv,
+        normal,
+        smoothing: if normal.is_some() {
+            Smoothing::Explicit
+        } else {
+            smoothing
+        },
+    })
+}
+
+/// Parses OBJ geometry. Polygons are fan triangulated, and faces are split
+/// into one mesh per group and material. Referenced material libraries are
+/// only listed in `material_libs`, use `load_obj` to read them as well.
+pub fn parse_obj<F: Float, R: BufRead>(reader: R) -> Result<ObjScene<F>, ParseError> {
+    let mut data = ObjData {
+        positions: Vec::new(),
+        normals: Vec::new(),
+        uvs: Vec::new(),
+    };
+    let mut material_libs = Vec::new();
+    let mut builders: Vec<MeshBuilder<F>> = Vec::new();
+    let mut builder_lookup: HashMap<(String, Option<String>), usize> = HashMap::new();
+
+    let mut group = String::from("default");
+    let mut material: Option<String> = None;
+    let mut smoothing_group = 0;
+    let mut face_count = 0;
+
+    for (idx, line) in reader.lines().enumerate() {
+        let text = line.map_err(|e| ParseError::new(idx + 1, e.to_string()))?;
+        let mut stmt = match Statement::parse(idx + 1, &text) {
+            Some(stmt) => stmt,
+            None => continue,
+        };
+
+        match stmt.keyword {
+            "v" => {
+                let (x, y, z) = (stmt.float()?, stmt.float()?, stmt.float()?);
+                data.positions.push(Point::new(x, y, z));
+            }
+            "vn" => {
+                let (x, y, z) = (stmt.float()?, stmt.float()?, stmt.float()?);
+                let normal = Vector::new(x, y, z);
+                // some exporters write zeros where they have no normal
+                data.normals.push(if normal.magnitude_sq() > F::zero() {
+                    Some(normal.normalized())
+                } else {
+                    None
+                });
+            }
+            "vt" => {
+                let u = stmt.float()?;
+                let v = stmt.optional_float()?.unwrap_or_else(F::zero);
+                data.uvs.push(UV::new(u, v));
+            }
+            "g" | "o" => {
+                let name = stmt.rest();
+                group = if name.is_empty() {
+                    String::from("default")
+                } else {
+                    name
+                };
+            }
+            "usemtl" => {
+                let name = stmt.rest();
+                material = if name.is_empty() { None } else { Some(name) };
+            }
+            "mtllib" => material_libs.extend(stmt.remaining().into_iter().map(String::from)),
+            "s" => {
+                let token = stmt.expect_token("a smoothing group")?;
+                smoothing_group = match token {
+                    "off" => 0,
+                    _ => token
+                        .parse()
+                        .map_err(|_| stmt.error(format!("invalid smoothing group `{}`", token)))?,
+                };
+            }
+            "f" => {
+                let smoothing = if smoothing_group == 0 {
+                    Smoothing::Face(face_count)
+                } else {
+                    Smoothing::Group(smoothing_group)
+                };
+                face_count += 1;
+
+                let keys = stmt
+                    .remaining()
+                    .into_iter()
+                    .map(|token| parse_face_vertex(&stmt, token, &data, smoothing))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if keys.len() < 3 {
+                    return Err(stmt.error("face needs at least 3 vertices"));
+                }
+
+                let lookup_key = (group.clone(), material.clone());
+                let builder_idx = *builder_lookup.entry(lookup_key).or_insert_with(|| {
+                    builders.push(MeshBuilder::new(group.clone(), material.clone()));
+                    builders.len() - 1
+                });
+                let builder = &mut builders[builder_idx];
+
+                let indices: Vec<_> = keys.into_iter().map(|k| builder.vertex(k, &data)).collect();
+                for i in 1..indices.len() - 1 {
+                    builder
+                        .indices
+                        .push([indices[0], indices[i], indices[i + 1]]);
+                }
+                builder.polygons.push(indices);
+            }
+            // lines, points, curves and unknown statements are ignored
+            _ => {}
+        }
+    }
+
+    Ok(ObjScene {
+        meshes: builders.into_iter().map(MeshBuilder::build).collect(),
+        materials: HashMap::new(),
+        material_libs,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    fn parse(text: &str) -> Result<ObjScene<f64>, ParseError> {
+        parse_obj(Cursor::new(text))
+    }
+
+    #[test]
+    fn test_parse_quad_with_negative_indices() {
+        let scene = parse(
+            "# quad
+            v 0 0 0
+            v 1 0 0
+            v 1 1 0
+            v 0 1 0
+            vt 0 0
+            vt 1 0
+            vt 1 1
+            vt 0 1
+            f -4/-4 -3/-3 -2/-2 -1/-1
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(scene.meshes.len(), 1);
+        let mesh = &scene.meshes[0];
+        assert_eq!(mesh.indices, vec![[0, 1, 2], [0, 2, 3]]);
+        assert_eq!(mesh.polygons, vec![vec![0, 1, 2, 3]]);
+        assert_eq!(mesh.uvs.as_ref().unwrap()[2], UV::new(1.0, 1.0));
+        // flat faces get the face normal
+        for n in mesh.normals.as_ref().unwrap() {
+            assert_eq!(*n, Vector::plus_z());
+        }
+    }
+
+    #[test]
+    fn test_zero_normals_are_generated() {
+        let scene = parse(
+            "v 0 0 0
+            v 1 0 0
+            v 0 1 0
+            vn 0 0 0
+            vn 0 1 0
+            f 1//1 2//1 3//2
+            ",
+        )
+        .unwrap();
+
+        let normals = scene.meshes[0].normals.as_ref().unwrap();
+        assert_eq!(normals[0], Vector::plus_z());
+        assert_eq!(normals[1], Vector::plus_z());
+        assert_eq!(normals[2], Vector::plus_y());
+    }
+
+    #[test]
+    fn test_groups_materials_and_smoothing() {
+        let scene = parse(
+            "mtllib scene.mtl
+            v 0 0 0
+            v 1 0 0
+            v 0 1 0
+            v 0 0 1
+            g first
+            usemtl red
+            s 1
+            f 1 2 3
+            f 1 4 2
+            g second
+            s off
+            f 1 2 3
+            f 1 4 2
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(scene.material_libs, vec!["scene.mtl".to_string()]);
+        assert_eq!(scene.meshes.len(), 2);
+        assert_eq!(scene.meshes[0].name, "first");
+        assert_eq!(scene.meshes[0].material, Some("red".to_string()));
+
+        // smoothed faces share vertices, flat ones do not
+        assert_eq!(scene.meshes[0].positions.len(), 4);
+        assert_eq!(scene.meshes[1].positions.len(), 6);
+        let shared = scene.meshes[0].normals.as_ref().unwrap()[0];
+        assert!((shared - Vector::new(0.0, 1.0, 1.0).normalized()).magnitude() < 1e-9);
+    }
+
+    #[test]
+    fn test_subdivided_cube() {
+        // flat shaded, so every face has vertices of its own
+        let scene = parse(
+            "v -1 -1 -1
+            v 1 -1 -1
+            v -1 1 -1
+            v 1 1 -1
+            v -1 -1 1
+            v 1 -1 1
+            v -1 1 1
+            v 1 1 1
+            f 1 3 4 2
+            f 5 6 8 7
+            f 1 2 6 5
+            f 3 7 8 4
+            f 1 5 7 3
+            f 2 4 8 6
+            ",
+        )
+        .unwrap();
+        let mesh = &scene.meshes[0];
+        assert_eq!(mesh.positions.len(), 24);
+        assert_eq!(mesh.cage().unwrap().positions().len(), 8);
+
+        let none: Option<&ScreenEdgeLength<f64, ::tracing::PlaneCamera<f64>>> = None;
+        let bvh = scene
+            .into_subdivided_bvh(SubdivisionScheme::CatmullClark, 2, none, |_| {
+                ::shading::DebugNormalMaterial {}
+            })
+            .unwrap();
+        // a closed surface, well inside the cage
+        let ray = ::math::Ray::new(Point::new(0.1, 0.2, -5.0), Vector::plus_z());
+        let (t, _) = ::tracing::Traceable::trace(&bvh, &ray).unwrap();
+        assert!(t > 4.05 && t < 4.4);
+    }
+
+    #[test]
+    fn test_errors_report_line_numbers() {
+        let err = parse("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 4\n").unwrap_err();
+        assert_eq!(err.line, 4);
+
+        let err = parse("v 0 0 0\nv 1 x 0\n").unwrap_err();
+        assert_eq!(err.line, 2);
+
+        let err = parse("v 0 0 0\nv 1 0 0\nf 1 2\n").unwrap_err();
+        assert_eq!(err.line, 3);
+    }
+}
@@ -0,0 +1,71 @@
+use import::ParseError;
+use math::Float;
+use std::str::{FromStr, SplitWhitespace};
+
+/// Whitespace separated statement of a line based text format, with the
+/// line number kept around for error reporting.
+pub struct Statement<'a> {
+    pub line: usize,
+    pub keyword: &'a str,
+    tokens: SplitWhitespace<'a>,
+}
+
+impl<'a> Statement<'a> {
+    /// Returns `None` for empty and comment-only lines.
+    pub fn parse(line: usize, text: &'a str) -> Option<Self> {
+        let text = match text.find('#') {
+            Some(idx) => &text[..idx],
+            None => text,
+        };
+        let mut tokens = text.split_whitespace();
+        tokens.next().map(|keyword| Statement {
+            line,
+            keyword,
+            tokens,
+        })
+    }
+
+    pub fn error<S: Into<String>>(&self, message: S) -> ParseError {
+        ParseError::new(self.line, message)
+    }
+
+    pub fn next_token(&mut self) -> Option<&'a str> {
+        self.tokens.next()
+    }
+
+    pub fn expect_token(&mut self, what: &str) -> Result<&'a str, ParseError> {
+        let line = self.line;
+        let keyword = self.keyword;
+        self.tokens
+            .next()
+            .ok_or_else(|| ParseError::new(line, format!("`{}` is missing {}", keyword, what)))
+    }
+
+    /// Remaining tokens joined with single spaces.
+    pub fn rest(&mut self) -> String {
+        self.tokens.by_ref().collect::<Vec<_>>().join(" ")
+    }
+
+    pub fn remaining(&mut self) -> Vec<&'a str> {
+        self.tokens.by_ref().collect()
+    }
+
+    pub fn float<F: Float>(&mut self) -> Result<F, ParseError> {
+        let token = self.expect_token("a number")?;
+        self.parse_float(token)
+    }
+
+    pub fn optional_float<F: Float>(&mut self) -> Result<Option<F>, ParseError> {
+        match self.tokens.next() {
+            Some(token) => self.parse_float(token).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn parse_float<F: Float>(&self, token: &str) -> Result<F, ParseError> {
+        f64::from_str(token)
+            .ok()
+            .and_then(F::from)
+            .ok_or_else(|| self.error(format!("invalid number `{}`", token)))
+    }
+}
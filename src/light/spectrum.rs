@@ -2,7 +2,7 @@ use math::Float;
 
 const WAVELENGTH_SAMPLES: usize = 4;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Spectrum<F: Float> {
     pub v: [F; WAVELENGTH_SAMPLES],
 }
@@ -8,6 +8,7 @@ extern crate num_traits;
 
 pub mod color;
 pub mod drawing;
+pub mod import;
 pub mod light;
 pub mod math;
 pub mod scenegraph;
@@ -15,26 +16,75 @@ pub mod scheduling;
 pub mod shading;
 pub mod tracing;
 
+#[cfg(test)]
+mod testing;
+
 use color::ScreenSpaceColor;
 use drawing::Framebuffer;
 use fibers::{Executor, Spawn, ThreadPoolExecutor};
+use futures::future::join_all;
 use futures::Future;
 use light::BounceQuota;
-use math::{Aabb, Point, Vector};
+use math::{Aabb, AnimatedTransform, Point, Point2D, Transform, Vector};
 use minifb::{Key, Window, WindowOptions};
 use nbchan::mpsc as nb_mpsc;
-use scenegraph::{Bvh, BvhNode, Scene, ShadedSphere};
+use scenegraph::{Bvh, HeatmapMode, SahBuilder, Scene, ShadedSphere};
 use scheduling::Job;
 use shading::DebugNormalMaterial;
+use std::env;
+use std::process;
 use std::time::{Duration, Instant};
-use tracing::PlaneCamera;
+use tracing::{AnimatedCamera, PlaneCamera};
 
 const WIDTH: usize = 640;
 const HEIGHT: usize = 360;
 
+/// Rays averaged per pixel, on a square grid of lens samples.
+const SAMPLES_PER_SIDE: usize = 2;
+/// Seconds the shutter stays open.
+const SHUTTER: f32 = 1.0 / 30.0;
+/// Radians per second the camera orbits the scene by.
+const ORBIT_SPEED: f32 = 0.7;
+
+const USAGE: &str = "usage: norays [--stats] [--heatmap nodes|primitives]";
+
+struct Options {
+    /// Show traversal work instead of shading.
+    heatmap: Option<HeatmapMode>,
+    /// Print statistics of the scene BVH.
+    stats: bool,
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+    let mut options = Options {
+        heatmap: None,
+        stats: false,
+    };
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--stats" => options.stats = true,
+            "--heatmap" => {
+                options.heatmap = match args.next().as_deref() {
+                    Some("nodes") => Some(HeatmapMode::NodeVisits),
+                    Some("primitives") => Some(HeatmapMode::PrimitiveTests),
+                    Some(mode) => return Err(format!("unknown heatmap mode '{}'", mode)),
+                    None => return Err("--heatmap needs a mode".to_string()),
+                }
+            }
+            _ => return Err(format!("unknown argument '{}'", arg)),
+        }
+    }
+    Ok(options)
+}
+
 fn main() {
     let start_time = Instant::now();
 
+    let options = parse_args(env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{}\n{}", e, USAGE);
+        process::exit(2);
+    });
+
     let mut framebuffer = Framebuffer::new(WIDTH, HEIGHT);
     let aspect_ratio = WIDTH as f32 / HEIGHT as f32;
 
@@ -45,31 +95,25 @@ fn main() {
 
     let mat = DebugNormalMaterial {};
 
-    let graph: Bvh<f32, Aabb<f32>, _, _, _> = Bvh::from_nodes(vec![
-        BvhNode::Leaf(ShadedSphere::new(
-            Point::new(-2.5, 0.0, 0.0),
-            1.0,
-            mat.clone(),
-        )),
-        BvhNode::Leaf(ShadedSphere::new(
-            Point::new(0.0, 0.0, 0.0),
-            1.0,
-            mat.clone(),
-        )),
-        BvhNode::Leaf(ShadedSphere::new(
-            Point::new(2.5, 0.0, 0.0),
-            1.0,
-            mat.clone(),
-        )),
-    ]).unwrap();
+    let graph: Bvh<f32, Aabb<f32>, _, _, _> = SahBuilder::new()
+        .build(vec![
+            ShadedSphere::new(Point::new(-2.5, 0.0, 0.0), 1.0, mat.clone()),
+            ShadedSphere::new(Point::new(0.0, 0.0, 0.0), 1.0, mat.clone()),
+            ShadedSphere::new(Point::new(2.5, 0.0, 0.0), 1.0, mat.clone()),
+        ]).unwrap();
+
+    if options.stats {
+        println!("{}", graph.stats());
+    }
 
     let quota = BounceQuota::new(30, 5, 5, 5);
 
     let scene = Scene::new(
-        camera_for_time(aspect_ratio, start_time.elapsed()),
+        orbiting_camera(aspect_ratio, start_time.elapsed()),
         graph,
         quota,
-    );
+    )
+    .with_shutter(0.0, SHUTTER);
 
     let mut executor = ThreadPoolExecutor::new().expect("Cannot create Executor");
     // let mut sync_executor = InPlaceExecutor::new().expect("Cannot create Sync Executor");
@@ -79,25 +123,42 @@ fn main() {
 
     let (pixel_tx, pixel_rx) = nb_mpsc::channel();
 
-    framebuffer.points().for_each(|point| {
-        let job = scene.job_for_fragment(&point);
+    if let Some(mode) = options.heatmap {
+        let max = scene.render_heatmap(&mut framebuffer, mode);
+        window.set_title(&format!("NoRays - heatmap, red at {} per pixel", max));
+    } else {
+        framebuffer.points().enumerate().for_each(|(n, point)| {
+            let samples = pixel_samples(n as u32)
+                .into_iter()
+                .map(|(lens, shutter)| {
+                    let job = scene.job_for_sample(&point, &lens, shutter);
+                    job.schedule(handle.clone())
+                })
+                .collect::<Vec<_>>();
 
-        let tx = pixel_tx.clone();
-        let pixel_future = job.schedule(handle.clone())
-            .and_then(|maybe_light| {
-                if let Some(spectrum) = maybe_light {
-                    let vec = Vector::new(spectrum.v[0], spectrum.v[1], spectrum.v[2]);
-                    let color: ScreenSpaceColor = vec.into();
+            let tx = pixel_tx.clone();
+            let pixel_future = join_all(samples)
+                .and_then(|lights| {
+                    let count = lights.len() as f32;
+                    let hits = lights
+                        .into_iter()
+                        .flatten()
+                        .map(|spectrum| Vector::new(spectrum.v[0], spectrum.v[1], spectrum.v[2]));
+                    // misses count as black, pixels missing everything stay empty
+                    let sum = hits.fold(None, |sum, vec| Some(sum.map_or(vec, |s| s + vec)));
+                    if let Some(sum) = sum {
+                        let color: ScreenSpaceColor = (sum * count.recip()).into();
 
-                    Ok((point, color.as_rgb_u32()))
-                } else {
-                    Err(())
-                }
-            })
-            .and_then(move |tuple| tx.send(tuple).map_err(|_| ()));
+                        Ok((point, color.as_rgb_u32()))
+                    } else {
+                        Err(())
+                    }
+                })
+                .and_then(move |tuple| tx.send(tuple).map_err(|_| ()));
 
-        handle.spawn_monitor(pixel_future);
-    });
+            handle.spawn_monitor(pixel_future);
+        });
+    }
 
     // let mut render_promise = scene.prepare_render_into(&mut framebuffer, executor.handle());
 
@@ -125,7 +186,7 @@ fn camera_for_time(aspect_ratio: f32, time: Duration) -> PlaneCamera<f32> {
     let ms = (time.as_secs() * 1_000_000_000 + time.subsec_nanos() as u64) / 1000;
 
     let wobble = (ms as f32 * 3e-7).sin() * 2.5;
-    let angle = ms as f32 * 7e-7;
+    let angle = ms as f32 * 1e-6 * ORBIT_SPEED;
 
     let r = 10.0;
 
@@ -137,3 +198,75 @@ fn camera_for_time(aspect_ratio: f32, time: Duration) -> PlaneCamera<f32> {
 
     PlaneCamera::new(eye, dir, Vector::plus_y(), aspect_ratio)
 }
+
+/// Camera of `camera_for_time`, orbiting on while the shutter is open.
+fn orbiting_camera(aspect_ratio: f32, time: Duration) -> AnimatedCamera<f32, PlaneCamera<f32>> {
+    let orbit = Transform::rotation(Vector::plus_y(), -ORBIT_SPEED * SHUTTER);
+    let motion = AnimatedTransform::new(vec![(0.0, Transform::identity()), (SHUTTER, orbit)])
+        .expect("rotations interpolate");
+    AnimatedCamera::new(camera_for_time(aspect_ratio, time), motion)
+}
+
+/// Lens and shutter samples of a pixel, one in every cell of a grid on the
+/// lens and one in every stretch of the shutter interval, with the cells and
+/// stretches paired up transposed so the two do not correlate. Jittered from
+/// `seed`.
+fn pixel_samples(seed: u32) -> Vec<(Point2D<f32>, f32)> {
+    let mut state = seed.wrapping_mul(2_654_435_761);
+    let mut jitter = || {
+        state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+        (state >> 8) as f32 / (1 << 24) as f32
+    };
+    let n = SAMPLES_PER_SIDE;
+    let (side, count) = (n as f32, (n * n) as f32);
+    let mut samples = Vec::with_capacity(n * n);
+    for i in 0..n {
+        for j in 0..n {
+            let lens = Point2D::new((i as f32 + jitter()) / side, (j as f32 + jitter()) / side);
+            let shutter = ((j * n + i) as f32 + jitter()) / count;
+            samples.push((lens, shutter));
+        }
+    }
+    samples
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let options = parse(&["--heatmap", "primitives", "--stats"]).unwrap();
+        assert_eq!(options.heatmap, Some(HeatmapMode::PrimitiveTests));
+        assert!(options.stats);
+        let quiet = parse(&[]).unwrap();
+        assert!(quiet.heatmap.is_none() && !quiet.stats);
+
+        assert!(parse(&["--heatmap"]).is_err());
+        assert!(parse(&["--heatmap", "leaves"]).is_err());
+        assert!(parse(&["--stat"]).is_err());
+    }
+
+    #[test]
+    fn test_pixel_samples_are_stratified() {
+        let samples = pixel_samples(17);
+        let n = SAMPLES_PER_SIDE;
+        let mut stretches: Vec<usize> = samples
+            .iter()
+            .map(|&(_, shutter)| (shutter * (n * n) as f32) as usize)
+            .collect();
+        stretches.sort();
+        assert_eq!(stretches, (0..n * n).collect::<Vec<_>>());
+        let mut cells: Vec<(usize, usize)> = samples
+            .iter()
+            .map(|(lens, _)| ((lens.x * n as f32) as usize, (lens.y * n as f32) as usize))
+            .collect();
+        cells.sort();
+        cells.dedup();
+        assert_eq!(cells.len(), n * n);
+    }
+}
@@ -1,4 +1,4 @@
-use math::{BoundingVolume, Float, Point, Ray};
+use math::{BoundingVolume, Float, Point, Ray, Vector};
 
 #[derive(Clone, Copy)]
 pub struct Aabb<F: Float> {
@@ -22,8 +22,19 @@ impl<F: Float> Aabb<F> {
         }
     }
 
-    pub fn from_points(points: &Vec<Point<F>>) -> Self {
-        let mut aabb = Aabb::unbound();
+    pub fn empty() -> Self {
+        Self {
+            x_min: F::max_value(),
+            x_max: F::min_value(),
+            y_min: F::max_value(),
+            y_max: F::min_value(),
+            z_min: F::max_value(),
+            z_max: F::min_value(),
+        }
+    }
+
+    pub fn from_points(points: &[Point<F>]) -> Self {
+        let mut aabb = Aabb::empty();
         for p in points {
             aabb.x_max = F::max(p.x, aabb.x_max);
             aabb.x_min = F::min(p.x, aabb.x_min);
@@ -34,6 +45,75 @@ impl<F: Float> Aabb<F> {
         }
         aabb
     }
+
+    /// False for `Aabb::unbound` and other boxes too large to measure, whose
+    /// surface area would throw off every SAH cost above them.
+    pub fn is_bounded(&self) -> bool {
+        self.surface_area().is_finite()
+    }
+
+    pub fn min_point(&self) -> Point<F> {
+        Point::new(self.x_min, self.y_min, self.z_min)
+    }
+
+    pub fn max_point(&self) -> Point<F> {
+        Point::new(self.x_max, self.y_max, self.z_max)
+    }
+
+    pub fn centroid(&self) -> Point<F> {
+        let half = (F::one() + F::one()).recip();
+        Point::new(
+            (self.x_min + self.x_max) * half,
+            (self.y_min + self.y_max) * half,
+            (self.z_min + self.z_max) * half,
+        )
+    }
+
+    pub fn largest_axis(&self) -> usize {
+        let dx = self.x_max - self.x_min;
+        let dy = self.y_max - self.y_min;
+        let dz = self.z_max - self.z_min;
+        if dx >= dy && dx >= dz {
+            0
+        } else if dy >= dz {
+            1
+        } else {
+            2
+        }
+    }
+
+    /// Tight box around a disk with the unit `normal`.
+    pub fn from_disk(center: Point<F>, normal: Vector<F>, radius: F) -> Self {
+        let extent = |n: F| radius * F::max(F::one() - n * n, F::zero()).sqrt();
+        let (ex, ey, ez) = (extent(normal.x), extent(normal.y), extent(normal.z));
+        Aabb {
+            x_min: center.x - ex,
+            x_max: center.x + ex,
+            y_min: center.y - ey,
+            y_max: center.y + ey,
+            z_min: center.z - ez,
+            z_max: center.z + ez,
+        }
+    }
+
+    /// Distances at which the ray line enters and leaves the box, ignoring
+    /// the ray interval.
+    pub fn intersect(&self, ray: &Ray<F>) -> Option<(F, F)> {
+        let (min, max) = (self.min_point(), self.max_point());
+        let mut near = F::neg_infinity();
+        let mut far = F::infinity();
+        for axis in 0..3 {
+            let t1 = (min[axis] - ray.origin[axis]) * ray.inv_direction[axis];
+            let t2 = (max[axis] - ray.origin[axis]) * ray.inv_direction[axis];
+            near = F::max(near, F::min(t1, t2));
+            far = F::min(far, F::max(t1, t2));
+        }
+        if near <= far {
+            Some((near, far))
+        } else {
+            None
+        }
+    }
 }
 
 impl<F: Float> BoundingVolume<F> for Aabb<F> {
@@ -55,7 +135,18 @@ impl<F: Float> BoundingVolume<F> for Aabb<F> {
         dx * dy * dz
     }
 
-    fn test(&self, ray: &Ray<F>) -> bool {
+    fn surface_area(&self) -> F {
+        let dx = self.x_max - self.x_min;
+        let dy = self.y_max - self.y_min;
+        let dz = self.z_max - self.z_min;
+        if dx < F::zero() || dy < F::zero() || dz < F::zero() {
+            // empty box
+            return F::zero();
+        }
+        (dx * dy + dy * dz + dz * dx) * (F::one() + F::one())
+    }
+
+    fn test(&self, ray: &Ray<F>) -> Option<F> {
         // TODO: branchless guards for NaN, Infinity and subnormals
         let tx1: F = (self.x_min - ray.origin.x) * ray.inv_direction.x;
         let tx2: F = (self.x_max - ray.origin.x) * ray.inv_direction.x;
@@ -69,6 +160,11 @@ impl<F: Float> BoundingVolume<F> for Aabb<F> {
         let tmin = F::max(F::max(F::min(tx1, tx2), F::min(ty1, ty2)), F::min(tz1, tz2));
         let tmax = F::min(F::min(F::max(tx1, tx2), F::max(ty1, ty2)), F::max(tz1, tz2));
 
-        return tmax >= tmin;
+        let entry = F::max(tmin, ray.t_min);
+        if F::min(tmax, ray.t_max) >= entry {
+            Some(entry)
+        } else {
+            None
+        }
     }
 }
@@ -3,7 +3,13 @@ use math::{Float, Ray};
 pub trait BoundingVolume<F: Float>: Clone + Copy {
     fn combine(&self, rhs: &Self) -> Self;
     fn estimated_volume(&self) -> F;
-    fn test(&self, against: &Ray<F>) -> bool;
+    /// Proportional to the chance of a random ray hitting the volume, which
+    /// is what surface area heuristic costs are weighted by.
+    fn surface_area(&self) -> F;
+    /// Distance along the ray at which it enters the volume, clamped to the
+    /// start of the ray interval. `None` when the volume is missed or lies
+    /// outside of the interval.
+    fn test(&self, against: &Ray<F>) -> Option<F>;
 }
 
 pub trait Bounded<F: Float, T: BoundingVolume<F>> {
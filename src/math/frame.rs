@@ -0,0 +1,54 @@
+use math::{Float, Point, Ray, Vector};
+
+/// Orthonormal coordinate frame, for primitives defined along an axis.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frame<F: Float> {
+    pub origin: Point<F>,
+    pub x: Vector<F>,
+    pub y: Vector<F>,
+    pub z: Vector<F>,
+}
+
+impl<F: Float> Frame<F> {
+    /// Frame with its z axis along `axis`, which does not need to be
+    /// normalized. The other two axes follow Duff et al., "Building an
+    /// Orthonormal Basis, Revisited".
+    pub fn from_axis(origin: Point<F>, axis: Vector<F>) -> Self {
+        let z = axis.normalized();
+        let sign = if z.z < F::zero() { -F::one() } else { F::one() };
+        let a = -(sign + z.z).recip();
+        let b = z.x * z.y * a;
+        Frame {
+            origin,
+            x: Vector::new(F::one() + sign * z.x * z.x * a, sign * b, -sign * z.x),
+            y: Vector::new(b, sign + z.y * z.y * a, -z.y),
+            z,
+        }
+    }
+
+    pub fn to_local_vector(&self, v: Vector<F>) -> Vector<F> {
+        Vector::new(v.dot(self.x), v.dot(self.y), v.dot(self.z))
+    }
+
+    pub fn to_local_point(&self, p: Point<F>) -> Point<F> {
+        self.to_local_vector(p - self.origin).into_point()
+    }
+
+    pub fn to_world_vector(&self, v: Vector<F>) -> Vector<F> {
+        self.x * v.x + self.y * v.y + self.z * v.z
+    }
+
+    pub fn to_world_point(&self, p: Point<F>) -> Point<F> {
+        self.origin + self.to_world_vector(p - Point::origin())
+    }
+
+    /// Same ray in local coordinates. Distances along it are unchanged.
+    pub fn to_local_ray(&self, ray: &Ray<F>) -> Ray<F> {
+        Ray::new(
+            self.to_local_point(ray.origin),
+            self.to_local_vector(ray.direction),
+        )
+        .with_interval(ray.t_min, ray.t_max)
+        .with_time(ray.time)
+    }
+}
@@ -0,0 +1,89 @@
+use math::Float;
+use std::ops::Mul;
+
+/// Row-major 4x4 matrix, acting on column vectors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Matrix4<F: Float> {
+    pub m: [[F; 4]; 4],
+}
+
+impl<F: Float> Matrix4<F> {
+    pub fn new(m: [[F; 4]; 4]) -> Self {
+        Matrix4 { m }
+    }
+
+    pub fn identity() -> Self {
+        let (o, z) = (F::one(), F::zero());
+        Matrix4 {
+            m: [[o, z, z, z], [z, o, z, z], [z, z, o, z], [z, z, z, o]],
+        }
+    }
+
+    pub fn transpose(&self) -> Self {
+        let mut out = *self;
+        for (r, row) in out.m.iter_mut().enumerate() {
+            for (c, value) in row.iter_mut().enumerate() {
+                *value = self.m[c][r];
+            }
+        }
+        out
+    }
+
+    /// Gauss-Jordan elimination with partial pivoting, `None` for singular
+    /// matrices.
+    pub fn inverse(&self) -> Option<Self> {
+        let mut a = self.m;
+        let mut inv = Self::identity().m;
+
+        for col in 0..4 {
+            let pivot = (col..4)
+                .max_by(|&x, &y| {
+                    a[x][col]
+                        .abs()
+                        .partial_cmp(&a[y][col].abs())
+                        .unwrap_or(::std::cmp::Ordering::Equal)
+                })
+                .unwrap();
+            if a[pivot][col] == F::zero() {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+
+            let scale = a[col][col].recip();
+            for c in 0..4 {
+                a[col][c] = a[col][c] * scale;
+                inv[col][c] = inv[col][c] * scale;
+            }
+
+            for row in 0..4 {
+                if row == col {
+                    continue;
+                }
+                let factor = a[row][col];
+                if factor == F::zero() {
+                    continue;
+                }
+                for c in 0..4 {
+                    a[row][c] = a[row][c] - factor * a[col][c];
+                    inv[row][c] = inv[row][c] - factor * inv[col][c];
+                }
+            }
+        }
+
+        Some(Matrix4 { m: inv })
+    }
+}
+
+impl<F: Float> Mul for Matrix4<F> {
+    type Output = Matrix4<F>;
+    fn mul(self, rhs: Matrix4<F>) -> Matrix4<F> {
+        let mut out = [[F::zero(); 4]; 4];
+        for (r, row) in out.iter_mut().enumerate() {
+            for (c, value) in row.iter_mut().enumerate() {
+                *value = (0..4).fold(F::zero(), |acc, k| acc + self.m[r][k] * rhs.m[k][c]);
+            }
+        }
+        Matrix4 { m: out }
+    }
+}
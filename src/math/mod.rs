@@ -1,17 +1,29 @@
 mod aabb;
 mod bounding_volume;
+mod dop;
 mod float;
+mod frame;
+mod matrix;
+mod motion;
+mod obb;
 mod point;
 mod point2d;
 mod ray;
+mod transform;
 mod uv;
 mod vector;
 
 pub use self::aabb::*;
 pub use self::bounding_volume::*;
+pub use self::dop::*;
 pub use self::float::*;
+pub use self::frame::*;
+pub use self::matrix::*;
+pub use self::motion::*;
+pub use self::obb::*;
 pub use self::point::*;
 pub use self::point2d::*;
 pub use self::ray::*;
+pub use self::transform::*;
 pub use self::uv::*;
 pub use self::vector::*;
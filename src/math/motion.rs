@@ -0,0 +1,401 @@
+use math::{Aabb, BoundingVolume, Float, Matrix4, Point, Transform, Vector};
+
+/// Keyframes to blend for `time` and the weight of the second, clamped to
+/// the first and last keyframe. `times` has to be sorted and not empty.
+pub fn keyframe_blend<F: Float>(times: &[F], time: F) -> (usize, usize, F) {
+    let last = times.len() - 1;
+    match times.iter().position(|&t| t > time) {
+        Some(0) => (0, 0, F::zero()),
+        Some(next) => {
+            let (start, end) = (times[next - 1], times[next]);
+            (next - 1, next, (time - start) / (end - start))
+        }
+        None => (last, last, F::zero()),
+    }
+}
+
+/// Unit quaternion, only used to interpolate rotations.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Quaternion<F: Float> {
+    w: F,
+    x: F,
+    y: F,
+    z: F,
+}
+
+impl<F: Float> Quaternion<F> {
+    /// From the upper 3x3 block of an orthonormal matrix without reflection.
+    fn from_rotation(m: &[[F; 4]; 4]) -> Self {
+        let (one, quarter) = (F::one(), F::from(0.25).unwrap());
+        let trace = m[0][0] + m[1][1] + m[2][2];
+        let q = if trace > F::zero() {
+            let s = (trace + one).sqrt() * (one + one);
+            Quaternion {
+                w: quarter * s,
+                x: (m[2][1] - m[1][2]) / s,
+                y: (m[0][2] - m[2][0]) / s,
+                z: (m[1][0] - m[0][1]) / s,
+            }
+        } else if m[0][0] > m[1][1] && m[0][0] > m[2][2] {
+            let s = (one + m[0][0] - m[1][1] - m[2][2]).sqrt() * (one + one);
+            Quaternion {
+                w: (m[2][1] - m[1][2]) / s,
+                x: quarter * s,
+                y: (m[0][1] + m[1][0]) / s,
+                z: (m[0][2] + m[2][0]) / s,
+            }
+        } else if m[1][1] > m[2][2] {
+            let s = (one + m[1][1] - m[0][0] - m[2][2]).sqrt() * (one + one);
+            Quaternion {
+                w: (m[0][2] - m[2][0]) / s,
+                x: (m[0][1] + m[1][0]) / s,
+                y: quarter * s,
+                z: (m[1][2] + m[2][1]) / s,
+            }
+        } else {
+            let s = (one + m[2][2] - m[0][0] - m[1][1]).sqrt() * (one + one);
+            Quaternion {
+                w: (m[1][0] - m[0][1]) / s,
+                x: (m[0][2] + m[2][0]) / s,
+                y: (m[1][2] + m[2][1]) / s,
+                z: quarter * s,
+            }
+        };
+        q.normalized()
+    }
+
+    fn dot(self, other: Self) -> F {
+        self.w * other.w + self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    fn scaled(self, factor: F) -> Self {
+        Quaternion {
+            w: self.w * factor,
+            x: self.x * factor,
+            y: self.y * factor,
+            z: self.z * factor,
+        }
+    }
+
+    fn plus(self, other: Self) -> Self {
+        Quaternion {
+            w: self.w + other.w,
+            x: self.x + other.x,
+            y: self.y + other.y,
+            z: self.z + other.z,
+        }
+    }
+
+    fn normalized(self) -> Self {
+        self.scaled(self.dot(self).sqrt().recip())
+    }
+
+    /// Angle of the rotation taking `self` to `other`.
+    fn angle_to(self, other: Self) -> F {
+        let cos = self.dot(other).abs().min(F::one());
+        cos.acos() * (F::one() + F::one())
+    }
+
+    /// Rotation at a constant angular velocity along the shorter way.
+    fn slerp(self, other: Self, f: F) -> Self {
+        let other = if self.dot(other) < F::zero() {
+            other.scaled(-F::one())
+        } else {
+            other
+        };
+        let cos = self.dot(other).min(F::one());
+        if cos > F::one() - F::epsilon().sqrt() {
+            // nearly the same rotation, where the sine below vanishes
+            return self.scaled(F::one() - f).plus(other.scaled(f)).normalized();
+        }
+        let angle = cos.acos();
+        let sin = angle.sin();
+        self.scaled(((F::one() - f) * angle).sin() / sin)
+            .plus(other.scaled((f * angle).sin() / sin))
+    }
+
+    fn matrix(self) -> Matrix4<F> {
+        let Quaternion { w, x, y, z } = self;
+        let (o, t, zero) = (F::one(), F::one() + F::one(), F::zero());
+        Matrix4::new([
+            [
+                o - t * (y * y + z * z),
+                t * (x * y - w * z),
+                t * (x * z + w * y),
+                zero,
+            ],
+            [
+                t * (x * y + w * z),
+                o - t * (x * x + z * z),
+                t * (y * z - w * x),
+                zero,
+            ],
+            [
+                t * (x * z - w * y),
+                t * (y * z + w * x),
+                o - t * (x * x + y * y),
+                zero,
+            ],
+            [zero, zero, zero, o],
+        ])
+    }
+}
+
+/// Affine transform split into translation, rotation and the remaining
+/// scale and shear, which interpolate without distorting the object.
+#[derive(Debug, Clone, Copy)]
+struct Keyframe<F: Float> {
+    translation: Vector<F>,
+    rotation: Quaternion<F>,
+    scale: Matrix4<F>,
+}
+
+impl<F: Float> Keyframe<F> {
+    /// Polar decomposition of the linear part. None for projective
+    /// transforms and for mirroring ones, which no rotation can reach.
+    fn decompose(transform: &Transform<F>) -> Option<Self> {
+        let m = transform.matrix().m;
+        let (zero, one) = (F::zero(), F::one());
+        if m[3] != [zero, zero, zero, one] {
+            return None;
+        }
+        let determinant = m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0]);
+        if determinant <= zero {
+            return None;
+        }
+
+        let mut linear = Matrix4::identity();
+        for (row, values) in linear.m.iter_mut().zip(&m).take(3) {
+            row[..3].copy_from_slice(&values[..3]);
+        }
+        // averaging with the inverse transpose converges to the rotation
+        let half = (one + one).recip();
+        let mut rotation = linear;
+        for _ in 0..100 {
+            let inverse_transpose = rotation.inverse()?.transpose();
+            let mut change = zero;
+            for (row, other) in rotation.m.iter_mut().zip(&inverse_transpose.m) {
+                for (value, &o) in row.iter_mut().zip(other) {
+                    let next = (*value + o) * half;
+                    change = change.max((next - *value).abs());
+                    *value = next;
+                }
+            }
+            if change < F::epsilon() * F::from(16).unwrap() {
+                break;
+            }
+        }
+
+        Some(Keyframe {
+            translation: Vector::new(m[0][3], m[1][3], m[2][3]),
+            rotation: Quaternion::from_rotation(&rotation.m),
+            scale: rotation.transpose() * linear,
+        })
+    }
+
+    fn blend(&self, other: &Self, f: F) -> Matrix4<F> {
+        let mut scale = self.scale;
+        for (row, other) in scale.m.iter_mut().zip(&other.scale.m) {
+            for (value, &o) in row.iter_mut().zip(other) {
+                *value = f.lerp(*value, o);
+            }
+        }
+        let mut matrix = self.rotation.slerp(other.rotation, f).matrix() * scale;
+        for axis in 0..3 {
+            matrix.m[axis][3] = f.lerp(self.translation[axis], other.translation[axis]);
+        }
+        matrix
+    }
+
+    /// Largest distance from the origin any point of the box reaches under
+    /// the scale of the keyframe.
+    fn scaled_radius(&self, aabb: &Aabb<F>) -> F {
+        let s = &self.scale.m;
+        corners(aabb)
+            .iter()
+            .map(|c| {
+                Vector::new(
+                    s[0][0] * c.x + s[0][1] * c.y + s[0][2] * c.z,
+                    s[1][0] * c.x + s[1][1] * c.y + s[1][2] * c.z,
+                    s[2][0] * c.x + s[2][1] * c.y + s[2][2] * c.z,
+                )
+                .magnitude()
+            })
+            .fold(F::zero(), F::max)
+    }
+}
+
+fn corners<F: Float>(aabb: &Aabb<F>) -> Vec<Point<F>> {
+    let (min, max) = (aabb.min_point(), aabb.max_point());
+    (0..8)
+        .map(|i| {
+            Point::new(
+                if i & 1 == 0 { min.x } else { max.x },
+                if i & 2 == 0 { min.y } else { max.y },
+                if i & 4 == 0 { min.z } else { max.z },
+            )
+        })
+        .collect()
+}
+
+/// Transform moving between keyframes over time. In between two keyframes
+/// the translation and scale are interpolated linearly and the rotation at a
+/// constant angular velocity, before the first and after the last keyframe
+/// the transform stands still.
+#[derive(Debug, Clone)]
+pub struct AnimatedTransform<F: Float> {
+    times: Vec<F>,
+    transforms: Vec<Transform<F>>,
+    keyframes: Vec<Keyframe<F>>,
+}
+
+impl<F: Float> AnimatedTransform<F> {
+    /// Keyframes as pairs of time and transform. None without keyframes,
+    /// unless the times strictly increase, and for projective or mirroring
+    /// transforms.
+    pub fn new(keyframes: Vec<(F, Transform<F>)>) -> Option<Self> {
+        let increasing = keyframes.windows(2).all(|pair| pair[0].0 < pair[1].0);
+        if keyframes.is_empty() || !increasing {
+            return None;
+        }
+        let decomposed = keyframes
+            .iter()
+            .map(|(_, transform)| Keyframe::decompose(transform))
+            .collect::<Option<Vec<_>>>()?;
+        Some(AnimatedTransform {
+            times: keyframes.iter().map(|&(time, _)| time).collect(),
+            transforms: keyframes
+                .into_iter()
+                .map(|(_, transform)| transform)
+                .collect(),
+            keyframes: decomposed,
+        })
+    }
+
+    pub fn times(&self) -> &[F] {
+        &self.times
+    }
+
+    pub fn at(&self, time: F) -> Transform<F> {
+        let (first, second, f) = keyframe_blend(&self.times, time);
+        if first == second || f == F::zero() {
+            return self.transforms[first];
+        }
+        let matrix = self.keyframes[first].blend(&self.keyframes[second], f);
+        // only a scale passing through zero is singular
+        Transform::from_matrix(matrix).unwrap_or(self.transforms[first])
+    }
+
+    /// Box around everything `aabb` sweeps over while moving. Boxes at a
+    /// few times between two keyframes are padded by how far a rotation
+    /// can carry a point away from the straight line in between.
+    pub fn motion_bounds(&self, aabb: &Aabb<F>) -> Aabb<F> {
+        let mut bounds = self.transforms[0].apply_aabb(aabb);
+        for (i, pair) in self.keyframes.windows(2).enumerate() {
+            let angle = pair[0].rotation.angle_to(pair[1].rotation);
+            // without rotation every point moves along a straight line
+            let steps = if angle > F::zero() { 16 } else { 1 };
+            let radius = F::max(pair[0].scaled_radius(aabb), pair[1].scaled_radius(aabb));
+            let pad = radius * angle / F::from(2 * steps).unwrap();
+
+            let (start, end) = (self.times[i], self.times[i + 1]);
+            let swept = (0..=steps)
+                .map(|step| {
+                    let f = F::from(step).unwrap() / F::from(steps).unwrap();
+                    self.at(f.lerp(start, end)).apply_aabb(aabb)
+                })
+                .fold(Aabb::empty(), |a, b| a.combine(&b));
+            let pad = Vector::new(pad, pad, pad);
+            let padded = Aabb::from_points(&[swept.min_point() - pad, swept.max_point() + pad]);
+            bounds = bounds.combine(&padded);
+        }
+        bounds
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use testing::close;
+
+    #[test]
+    fn test_interpolates_between_keyframes() {
+        let turned = Transform::rotation(Vector::plus_z(), ::std::f64::consts::FRAC_PI_2)
+            .then(&Transform::translation(Vector::new(2.0, 0.0, 0.0)));
+        let motion =
+            AnimatedTransform::new(vec![(0.0, Transform::identity()), (1.0, turned)]).unwrap();
+
+        let p = Point::new(1.0, 0.0, 0.0);
+        let half = ::std::f64::consts::FRAC_1_SQRT_2;
+        assert!(close(
+            motion.at(0.5).apply_point(p),
+            Point::new(1.0 + half, half, 0.0)
+        ));
+        assert!(close(
+            motion.at(1.0).apply_point(p),
+            Point::new(2.0, 1.0, 0.0)
+        ));
+        assert!(close(
+            motion.at(7.0).apply_point(p),
+            Point::new(2.0, 1.0, 0.0)
+        ));
+        assert_eq!(motion.at(-1.0), Transform::identity());
+
+        // scale and shear survive the decomposition
+        let general = Transform::rotation(Vector::new(1.0, 2.0, 3.0), 0.7)
+            .then(&Transform::scaling(2.0, 3.0, 0.5).unwrap())
+            .then(&Transform::rotation(Vector::plus_y(), -1.2))
+            .then(&Transform::translation(Vector::new(1.0, -2.0, 5.0)));
+        let keyframe = Keyframe::decompose(&general).unwrap();
+        let both = AnimatedTransform::new(vec![(0.0, general), (1.0, general)]).unwrap();
+        let q = Point::new(0.3, -1.2, 4.0);
+        assert!(close(both.at(0.5).apply_point(q), general.apply_point(q)));
+        assert!((keyframe.rotation.dot(keyframe.rotation) - 1.0).abs() < 1e-12);
+
+        let mirror = Transform::scaling(-1.0, 1.0, 1.0).unwrap();
+        assert!(AnimatedTransform::new(vec![(0.0, mirror)]).is_none());
+        assert!(AnimatedTransform::new(vec![(1.0, turned), (1.0, turned)]).is_none());
+    }
+
+    #[test]
+    fn test_motion_bounds_cover_sweep() {
+        let aabb = Aabb::from_points(&[Point::new(-1.0, -0.5, -0.2), Point::new(1.5, 0.5, 0.3)]);
+        let motion = AnimatedTransform::new(vec![
+            (0.0, Transform::translation(Vector::new(0.0, 0.0, -3.0))),
+            (
+                0.5,
+                Transform::rotation(Vector::new(0.3, 1.0, 0.2), 2.5)
+                    .then(&Transform::scaling(1.5, 1.0, 2.0).unwrap()),
+            ),
+            (1.0, Transform::translation(Vector::new(4.0, 1.0, 0.0))),
+        ])
+        .unwrap();
+        let bounds = motion.motion_bounds(&aabb);
+
+        let inside = |p: Point<f64>| {
+            let (min, max) = (bounds.min_point(), bounds.max_point());
+            (0..3).all(|axis| p[axis] >= min[axis] - 1e-9 && p[axis] <= max[axis] + 1e-9)
+        };
+        for step in 0..=1000 {
+            let transform = motion.at(step as f64 / 1000.0);
+            assert!(corners(&aabb)
+                .into_iter()
+                .all(|c| inside(transform.apply_point(c))));
+        }
+
+        // a translation only sweeps exactly between its ends
+        let sliding = AnimatedTransform::new(vec![
+            (0.0, Transform::identity()),
+            (1.0, Transform::translation(Vector::new(2.0, 0.0, 0.0))),
+        ])
+        .unwrap()
+        .motion_bounds(&aabb);
+        assert_eq!(sliding.min_point(), aabb.min_point());
+        assert_eq!(
+            sliding.max_point(),
+            aabb.max_point() + Vector::new(2.0, 0.0, 0.0)
+        );
+    }
+}
@@ -0,0 +1,280 @@
+use math::{Aabb, Bounded, BoundingVolume, Float, Point, Ray, Vector};
+
+/// Oriented bounding box: a box along three orthonormal axes, placed
+/// anywhere. Encloses thin diagonal geometry far tighter than an `Aabb`, at
+/// the price of a more expensive ray test.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Obb<F: Float> {
+    center: Point<F>,
+    axes: [Vector<F>; 3],
+    half_extents: [F; 3],
+}
+
+impl<F: Float> Obb<F> {
+    /// `axes` have to be orthonormal.
+    pub fn new(center: Point<F>, axes: [Vector<F>; 3], half_extents: [F; 3]) -> Self {
+        Obb {
+            center,
+            axes,
+            half_extents,
+        }
+    }
+
+    pub fn from_aabb(aabb: &Aabb<F>) -> Self {
+        let half = (F::one() + F::one()).recip();
+        Obb {
+            center: aabb.centroid(),
+            axes: [Vector::plus_x(), Vector::plus_y(), Vector::plus_z()],
+            half_extents: [
+                (aabb.x_max - aabb.x_min) * half,
+                (aabb.y_max - aabb.y_min) * half,
+                (aabb.z_max - aabb.z_min) * half,
+            ],
+        }
+    }
+
+    /// Smallest box along the given orthonormal `axes` holding all `points`.
+    pub fn from_points_along(points: &[Point<F>], axes: [Vector<F>; 3]) -> Self {
+        let mut min = [F::max_value(); 3];
+        let mut max = [F::min_value(); 3];
+        for point in points {
+            let position = *point - Point::origin();
+            for i in 0..3 {
+                let projected = position.dot(axes[i]);
+                min[i] = F::min(min[i], projected);
+                max[i] = F::max(max[i], projected);
+            }
+        }
+
+        let half = (F::one() + F::one()).recip();
+        let mid = |i: usize| (min[i] + max[i]) * half;
+        let center = axes[0] * mid(0) + axes[1] * mid(1) + axes[2] * mid(2);
+        Obb {
+            center: center.into_point(),
+            axes,
+            half_extents: [
+                (max[0] - min[0]) * half,
+                (max[1] - min[1]) * half,
+                (max[2] - min[2]) * half,
+            ],
+        }
+    }
+
+    /// Fits the box to the principal axes of `points`, falling back to the
+    /// world axes when that gives the larger box.
+    pub fn from_points(points: &[Point<F>]) -> Self {
+        let aligned = Self::from_aabb(&Aabb::from_points(points));
+        if points.len() < 3 {
+            return aligned;
+        }
+
+        let count = F::from(points.len()).unwrap();
+        let mean = points
+            .iter()
+            .fold(Vector::new(F::zero(), F::zero(), F::zero()), |sum, p| {
+                sum + (*p - Point::origin())
+            })
+            * count.recip();
+        let mut covariance = [[F::zero(); 3]; 3];
+        for point in points {
+            let d = (*point - Point::origin()) - mean;
+            for (i, row) in covariance.iter_mut().enumerate() {
+                for (j, entry) in row.iter_mut().enumerate() {
+                    *entry = *entry + d[i] * d[j];
+                }
+            }
+        }
+
+        let fitted = Self::from_points_along(points, principal_axes(covariance));
+        if fitted.surface_area() < aligned.surface_area() {
+            fitted
+        } else {
+            aligned
+        }
+    }
+
+    pub fn center(&self) -> Point<F> {
+        self.center
+    }
+
+    pub fn axes(&self) -> &[Vector<F>; 3] {
+        &self.axes
+    }
+
+    pub fn half_extents(&self) -> &[F; 3] {
+        &self.half_extents
+    }
+
+    pub fn corners(&self) -> [Point<F>; 8] {
+        let mut corners = [self.center; 8];
+        for (index, corner) in corners.iter_mut().enumerate() {
+            for axis in 0..3 {
+                let extent = self.axes[axis] * self.half_extents[axis];
+                if index & (1 << axis) == 0 {
+                    *corner += -extent;
+                } else {
+                    *corner += extent;
+                }
+            }
+        }
+        corners
+    }
+}
+
+/// Orthonormal eigenvectors of a symmetric matrix, by cyclic Jacobi
+/// rotations.
+fn principal_axes<F: Float>(matrix: [[F; 3]; 3]) -> [Vector<F>; 3] {
+    let two = F::one() + F::one();
+    let mut a = matrix;
+    let mut v = [[F::zero(); 3]; 3];
+    for (i, row) in v.iter_mut().enumerate() {
+        row[i] = F::one();
+    }
+
+    for _ in 0..16 {
+        let off_diagonal = a[0][1].abs() + a[0][2].abs() + a[1][2].abs();
+        let diagonal = a[0][0].abs() + a[1][1].abs() + a[2][2].abs();
+        if off_diagonal <= diagonal * F::epsilon() {
+            break;
+        }
+
+        for &(p, q) in &[(0, 1), (0, 2), (1, 2)] {
+            if a[p][q] == F::zero() {
+                continue;
+            }
+            let theta = (a[q][q] - a[p][p]) / (two * a[p][q]);
+            let t = theta.signum() / (theta.abs() + (theta * theta + F::one()).sqrt());
+            let c = (t * t + F::one()).sqrt().recip();
+            let s = t * c;
+
+            for row in a.iter_mut().chain(v.iter_mut()) {
+                let (kp, kq) = (row[p], row[q]);
+                row[p] = c * kp - s * kq;
+                row[q] = s * kp + c * kq;
+            }
+            let (low, high) = a.split_at_mut(q);
+            for (pk, qk) in low[p].iter_mut().zip(high[0].iter_mut()) {
+                let (x, y) = (*pk, *qk);
+                *pk = c * x - s * y;
+                *qk = s * x + c * y;
+            }
+        }
+    }
+
+    // eigenvectors are the columns, the third is rebuilt to stay orthonormal
+    let x = Vector::new(v[0][0], v[1][0], v[2][0]).normalized();
+    let y = Vector::new(v[0][1], v[1][1], v[2][1]).normalized();
+    let z = x.cross(y).normalized();
+    [x, z.cross(x), z]
+}
+
+impl<F: Float> BoundingVolume<F> for Obb<F> {
+    /// Encloses both boxes along the axes of one of them, whichever is
+    /// smaller.
+    fn combine(&self, rhs: &Self) -> Self {
+        let mut corners = [self.center; 16];
+        corners[..8].copy_from_slice(&self.corners());
+        corners[8..].copy_from_slice(&rhs.corners());
+
+        let along_self = Self::from_points_along(&corners, self.axes);
+        let along_rhs = Self::from_points_along(&corners, rhs.axes);
+        if along_rhs.surface_area() < along_self.surface_area() {
+            along_rhs
+        } else {
+            along_self
+        }
+    }
+
+    fn estimated_volume(&self) -> F {
+        let [x, y, z] = self.half_extents;
+        let eight = F::from(8).unwrap();
+        eight * x * y * z
+    }
+
+    fn surface_area(&self) -> F {
+        let [x, y, z] = self.half_extents;
+        let eight = F::from(8).unwrap();
+        eight * (x * y + y * z + z * x)
+    }
+
+    fn test(&self, ray: &Ray<F>) -> Option<F> {
+        let offset = ray.origin - self.center;
+        let mut tmin = ray.t_min;
+        let mut tmax = ray.t_max;
+
+        for axis in 0..3 {
+            let extent = self.half_extents[axis];
+            let position = offset.dot(self.axes[axis]);
+            let direction = ray.direction.dot(self.axes[axis]);
+            if direction == F::zero() {
+                // parallel to the slab
+                if position.abs() > extent {
+                    return None;
+                }
+                continue;
+            }
+
+            let inv_direction = direction.recip();
+            let t1 = (-extent - position) * inv_direction;
+            let t2 = (extent - position) * inv_direction;
+            tmin = F::max(tmin, F::min(t1, t2));
+            tmax = F::min(tmax, F::max(t1, t2));
+        }
+
+        if tmax >= tmin {
+            Some(tmin)
+        } else {
+            None
+        }
+    }
+}
+
+impl<F: Float> Bounded<F, Aabb<F>> for Obb<F> {
+    fn bounding_volume(&self) -> Aabb<F> {
+        Aabb::from_points(&self.corners())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_obb_fits_diagonal_points() {
+        // a thin rod along (1, 1, 0)
+        let points: Vec<Point<f64>> = (0..=10)
+            .flat_map(|i| {
+                let t = i as f64;
+                vec![Point::new(t, t, 0.0), Point::new(t, t, 0.1)]
+            })
+            .collect();
+        let obb = Obb::from_points(&points);
+        let aabb = Aabb::from_points(&points);
+        assert!(obb.estimated_volume() < 1e-6);
+        assert!(obb.surface_area() < aabb.surface_area() / 4.0);
+
+        let hit = |x: f64, y: f64| {
+            let ray = Ray::new(Point::new(x, y, 5.0), -Vector::plus_z());
+            obb.test(&ray)
+        };
+        assert!((hit(5.0, 5.0).unwrap() - 4.9).abs() < 1e-9);
+        // inside the box but far away from the rod
+        assert_eq!(hit(8.0, 2.0), None);
+        assert!(aabb
+            .test(&Ray::new(Point::new(8.0, 2.0, 5.0), -Vector::plus_z()))
+            .is_some());
+
+        let other = Obb::from_aabb(&Aabb::from_points(&[
+            Point::new(20.0, 0.0, 0.0),
+            Point::new(21.0, 1.0, 1.0),
+        ]));
+        let both = obb.combine(&other);
+        for corner in obb.corners().iter().chain(other.corners().iter()) {
+            let offset = *corner - both.center();
+            for axis in 0..3 {
+                let projected = offset.dot(both.axes()[axis]).abs();
+                assert!(projected <= both.half_extents()[axis] + 1e-9);
+            }
+        }
+    }
+}
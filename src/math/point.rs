@@ -1,4 +1,4 @@
-use std::ops::{Add, AddAssign, Sub};
+use std::ops::{Add, AddAssign, Index, Sub};
 
 use math::{Float, Vector};
 
@@ -64,3 +64,15 @@ impl<F: Float> Sub<Point<F>> for Point<F> {
         }
     }
 }
+
+impl<F: Float> Index<usize> for Point<F> {
+    type Output = F;
+    fn index(&self, axis: usize) -> &F {
+        match axis {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("Point axis out of range: {}", axis),
+        }
+    }
+}
@@ -1,10 +1,17 @@
 use math::{Float, Point, Vector};
 
+/// Half-open ray segment: only hits with `t_min < t < t_max` count, which
+/// allows offsetting secondary rays from surfaces and bounding shadow rays.
+/// `time` is the moment within the shutter interval the ray is sent at, which
+/// moving objects are placed at.
 #[derive(Debug, Clone)]
 pub struct Ray<F: Float> {
     pub origin: Point<F>,
     pub direction: Vector<F>,
     pub inv_direction: Vector<F>,
+    pub t_min: F,
+    pub t_max: F,
+    pub time: F,
 }
 
 impl<F: Float> Ray<F> {
@@ -13,9 +20,36 @@ impl<F: Float> Ray<F> {
             origin,
             inv_direction: dir.recip(),
             direction: dir,
+            t_min: F::zero(),
+            t_max: F::infinity(),
+            time: F::zero(),
         }
     }
 
+    pub fn with_interval(self, t_min: F, t_max: F) -> Self {
+        Self {
+            t_min,
+            t_max,
+            ..self
+        }
+    }
+
+    pub fn with_t_min(self, t_min: F) -> Self {
+        Self { t_min, ..self }
+    }
+
+    pub fn with_t_max(self, t_max: F) -> Self {
+        Self { t_max, ..self }
+    }
+
+    pub fn with_time(self, time: F) -> Self {
+        Self { time, ..self }
+    }
+
+    pub fn contains(&self, t: F) -> bool {
+        t > self.t_min && t < self.t_max
+    }
+
     pub fn point_at_distance(&self, t: F) -> Point<F> {
         self.origin + self.direction * t
     }
@@ -0,0 +1,216 @@
+use math::{Aabb, Float, Matrix4, Point, Ray, Vector};
+use std::ops::Mul;
+
+/// Invertible 4x4 transform with its inverse computed once up front, so
+/// rays can be moved into object space and normals back out without
+/// inverting per hit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform<F: Float> {
+    matrix: Matrix4<F>,
+    inverse: Matrix4<F>,
+}
+
+impl<F: Float> Transform<F> {
+    /// `None` when the matrix cannot be inverted.
+    pub fn from_matrix(matrix: Matrix4<F>) -> Option<Self> {
+        matrix
+            .inverse()
+            .map(|inverse| Transform { matrix, inverse })
+    }
+
+    pub fn identity() -> Self {
+        Transform {
+            matrix: Matrix4::identity(),
+            inverse: Matrix4::identity(),
+        }
+    }
+
+    pub fn translation(offset: Vector<F>) -> Self {
+        let mut matrix = Matrix4::identity();
+        let mut inverse = Matrix4::identity();
+        for axis in 0..3 {
+            matrix.m[axis][3] = offset[axis];
+            inverse.m[axis][3] = -offset[axis];
+        }
+        Transform { matrix, inverse }
+    }
+
+    /// Per-axis scale, `None` if any factor is zero.
+    pub fn scaling(x: F, y: F, z: F) -> Option<Self> {
+        if x == F::zero() || y == F::zero() || z == F::zero() {
+            return None;
+        }
+        let mut matrix = Matrix4::identity();
+        let mut inverse = Matrix4::identity();
+        for (axis, &factor) in [x, y, z].iter().enumerate() {
+            matrix.m[axis][axis] = factor;
+            inverse.m[axis][axis] = factor.recip();
+        }
+        Some(Transform { matrix, inverse })
+    }
+
+    /// Counter-clockwise rotation by `angle` radians around `axis`.
+    pub fn rotation(axis: Vector<F>, angle: F) -> Self {
+        let a = axis.normalized();
+        let (sin, cos) = angle.sin_cos();
+        let t = F::one() - cos;
+        let (o, z) = (F::one(), F::zero());
+
+        let matrix = Matrix4::new([
+            [
+                t * a.x * a.x + cos,
+                t * a.x * a.y - sin * a.z,
+                t * a.x * a.z + sin * a.y,
+                z,
+            ],
+            [
+                t * a.x * a.y + sin * a.z,
+                t * a.y * a.y + cos,
+                t * a.y * a.z - sin * a.x,
+                z,
+            ],
+            [
+                t * a.x * a.z - sin * a.y,
+                t * a.y * a.z + sin * a.x,
+                t * a.z * a.z + cos,
+                z,
+            ],
+            [z, z, z, o],
+        ]);
+
+        // rotations are orthonormal
+        Transform {
+            matrix,
+            inverse: matrix.transpose(),
+        }
+    }
+
+    pub fn matrix(&self) -> &Matrix4<F> {
+        &self.matrix
+    }
+
+    pub fn inverse_matrix(&self) -> &Matrix4<F> {
+        &self.inverse
+    }
+
+    pub fn inverse(&self) -> Self {
+        Transform {
+            matrix: self.inverse,
+            inverse: self.matrix,
+        }
+    }
+
+    /// Transform applying `self` first and `next` afterwards.
+    pub fn then(&self, next: &Self) -> Self {
+        *next * *self
+    }
+
+    pub fn apply_point(&self, p: Point<F>) -> Point<F> {
+        let m = &self.matrix.m;
+        let x = m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3];
+        let y = m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3];
+        let z = m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3];
+        let w = m[3][0] * p.x + m[3][1] * p.y + m[3][2] * p.z + m[3][3];
+        if w == F::one() {
+            Point::new(x, y, z)
+        } else {
+            let inv_w = w.recip();
+            Point::new(x * inv_w, y * inv_w, z * inv_w)
+        }
+    }
+
+    pub fn apply_vector(&self, v: Vector<F>) -> Vector<F> {
+        let m = &self.matrix.m;
+        Vector::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        )
+    }
+
+    /// Normals transform by the inverse transpose to stay perpendicular to
+    /// surfaces under non-uniform scale. The result is not normalized.
+    pub fn apply_normal(&self, n: Vector<F>) -> Vector<F> {
+        let inv = &self.inverse.m;
+        Vector::new(
+            inv[0][0] * n.x + inv[1][0] * n.y + inv[2][0] * n.z,
+            inv[0][1] * n.x + inv[1][1] * n.y + inv[2][1] * n.z,
+            inv[0][2] * n.x + inv[1][2] * n.y + inv[2][2] * n.z,
+        )
+    }
+
+    /// The direction is not renormalized, so distances along the transformed
+    /// ray match distances along the original one. The interval and time are
+    /// kept.
+    pub fn apply_ray(&self, ray: &Ray<F>) -> Ray<F> {
+        Ray::new(
+            self.apply_point(ray.origin),
+            self.apply_vector(ray.direction),
+        )
+        .with_interval(ray.t_min, ray.t_max)
+        .with_time(ray.time)
+    }
+
+    /// Box enclosing all eight transformed corners.
+    pub fn apply_aabb(&self, aabb: &Aabb<F>) -> Aabb<F> {
+        let corners: Vec<_> = (0..8)
+            .map(|i| {
+                Point::new(
+                    if i & 1 == 0 { aabb.x_min } else { aabb.x_max },
+                    if i & 2 == 0 { aabb.y_min } else { aabb.y_max },
+                    if i & 4 == 0 { aabb.z_min } else { aabb.z_max },
+                )
+            })
+            .map(|corner| self.apply_point(corner))
+            .collect();
+        Aabb::from_points(&corners)
+    }
+}
+
+impl<F: Float> Mul for Transform<F> {
+    type Output = Transform<F>;
+    /// Like matrices, `a * b` applies `b` first.
+    fn mul(self, rhs: Transform<F>) -> Transform<F> {
+        Transform {
+            matrix: self.matrix * rhs.matrix,
+            inverse: rhs.inverse * self.inverse,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use testing::close;
+
+    #[test]
+    fn test_compose_and_invert() {
+        let t = Transform::rotation(Vector::new(1.0, 2.0, 3.0), 0.7)
+            .then(&Transform::scaling(2.0, 3.0, 0.5).unwrap())
+            .then(&Transform::translation(Vector::new(1.0, -2.0, 5.0)));
+
+        let p = Point::new(0.3, -1.2, 4.0);
+        let back = t.inverse().apply_point(t.apply_point(p));
+        assert!(close(back - Point::origin(), p - Point::origin()));
+
+        let general = Transform::from_matrix(*t.matrix()).unwrap();
+        for (row, expected) in general.inverse_matrix().m.iter().zip(&t.inverse_matrix().m) {
+            for (a, b) in row.iter().zip(expected) {
+                assert!((a - b).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn test_normals_stay_perpendicular() {
+        let t: Transform<f64> = Transform::scaling(4.0, 1.0, 1.0).unwrap();
+        // plane x = y, tangent (1, 1, 0) and normal (1, -1, 0)
+        let tangent = t.apply_vector(Vector::new(1.0, 1.0, 0.0));
+        let normal = t.apply_normal(Vector::new(1.0, -1.0, 0.0));
+        assert!(tangent.dot(normal).abs() < 1e-9);
+        assert!(close(
+            Transform::translation(Vector::plus_x()).apply_vector(Vector::plus_y()),
+            Vector::plus_y()
+        ));
+    }
+}
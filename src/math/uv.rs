@@ -1,11 +1,17 @@
 use math::Float;
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct UV<F> {
     pub u: F,
     pub v: F,
 }
 
+impl<F: Float> UV<F> {
+    pub fn new(u: F, v: F) -> Self {
+        UV { u, v }
+    }
+}
+
 impl<F: Float> Default for UV<F> {
     fn default() -> Self {
         Self {
@@ -14,3 +20,14 @@ impl<F: Float> Default for UV<F> {
         }
     }
 }
+
+/// Angle of the direction `(x, y)` as a fraction of a full turn, in `0..1`,
+/// for the `u` coordinate of rotationally symmetric shapes.
+pub fn turns<F: Float>(y: F, x: F) -> F {
+    let turns = y.atan2(x) / (F::PI() + F::PI());
+    if turns < F::zero() {
+        turns + F::one()
+    } else {
+        turns
+    }
+}
@@ -1,6 +1,6 @@
 use math::{Float, Point};
 use num_traits::Zero;
-use std::ops::{Add, Mul, Sub};
+use std::ops::{Add, Index, Mul, Neg, Sub};
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Vector<F: Float> {
@@ -100,6 +100,29 @@ impl<F: Float> Mul<F> for Vector<F> {
     }
 }
 
+impl<F: Float> Neg for Vector<F> {
+    type Output = Vector<F>;
+    fn neg(self) -> Vector<F> {
+        Vector {
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+}
+
+impl<F: Float> Index<usize> for Vector<F> {
+    type Output = F;
+    fn index(&self, axis: usize) -> &F {
+        match axis {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("Vector axis out of range: {}", axis),
+        }
+    }
+}
+
 impl<F: Float> Zero for Vector<F> {
     fn zero() -> Self {
         Vector {
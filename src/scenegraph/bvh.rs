@@ -1,5 +1,5 @@
 use math::{Bounded, BoundingVolume, BoundingVolumeSum, Float, Ray};
-use scenegraph::{BvhLeaf, BvhNode};
+use scenegraph::{BvhLeaf, BvhNode, CountTraversal, CountedTraceable, TraversalCounters};
 use shading::Material;
 use std::cmp::Ordering::Equal;
 use std::marker::PhantomData;
@@ -41,6 +41,124 @@ where
                 _m: PhantomData,
             })
     }
+
+    pub fn children(&self) -> &[BvhNode<F, B, H, M, L>] {
+        &self.children
+    }
+
+    pub fn into_children(self) -> Vec<BvhNode<F, B, H, M, L>> {
+        self.children
+    }
+
+    /// Children can be changed in place, for example to move leaves. The
+    /// bounds go stale until `refit` is called.
+    pub fn children_mut(&mut self) -> &mut [BvhNode<F, B, H, M, L>] {
+        &mut self.children
+    }
+
+    /// Calls `update` for every leaf below this node. The bounds go stale
+    /// until `refit` is called.
+    pub fn for_each_leaf_mut<U: FnMut(&mut L)>(&mut self, update: &mut U) {
+        for child in &mut self.children {
+            match child {
+                BvhNode::Node(node) => node.for_each_leaf_mut(update),
+                BvhNode::Leaf(leaf) => update(leaf),
+            }
+        }
+    }
+
+    /// Recomputes the bounds of this node and every node below it from the
+    /// current leaf bounds, bottom-up, and returns the new bound. The
+    /// topology stays the same, so the tree gets worse the further leaves
+    /// move from where they were at build time. `SahBuilder::refit` also
+    /// reports by how much.
+    pub fn refit(&mut self) -> B {
+        let bound = self
+            .children
+            .iter_mut()
+            .map(|child| match child {
+                BvhNode::Node(node) => node.refit(),
+                BvhNode::Leaf(leaf) => leaf.bounding_volume(),
+            })
+            .bounding_sum()
+            .expect("BVH node without children");
+        self.bound = bound;
+        bound
+    }
+
+    /// Closest hit nearer than `t_max`, for a ray already known to hit this
+    /// node. Leaves are traced right away, child nodes are then visited front
+    /// to back and skipped once they start past the closest hit found so far.
+    pub fn trace_within(&self, ray: &Ray<F>, t_max: F) -> Option<(F, &H)> {
+        self.traverse(ray, t_max, &mut ())
+    }
+
+    fn traverse<C: CountTraversal>(
+        &self,
+        ray: &Ray<F>,
+        t_max: F,
+        counter: &mut C,
+    ) -> Option<(F, &H)> {
+        counter.visit_node();
+        let mut closest = None;
+        let mut limit = t_max;
+
+        // trees from the SAH builder are binary, so only wider nodes allocate
+        let mut near = [(F::zero(), self); 2];
+        let mut near_count = 0;
+        let mut overflow = Vec::new();
+
+        for child in &self.children {
+            match child {
+                BvhNode::Leaf(leaf) => {
+                    counter.test_primitive();
+                    if let Some(hit) = leaf.trace(ray) {
+                        if hit.0 < limit {
+                            limit = hit.0;
+                            closest = Some(hit);
+                        }
+                    }
+                }
+                BvhNode::Node(node) => match node.bound.test(ray) {
+                    Some(entry) if entry <= limit && near_count < near.len() => {
+                        near[near_count] = (entry, node);
+                        near_count += 1;
+                    }
+                    Some(entry) if entry <= limit => overflow.push((entry, node)),
+                    _ => {}
+                },
+            }
+        }
+
+        let order = if overflow.is_empty() {
+            &mut near[..near_count]
+        } else {
+            overflow.extend_from_slice(&near);
+            &mut overflow[..]
+        };
+        order.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Equal));
+
+        for &(entry, node) in order.iter() {
+            if entry > limit {
+                break;
+            }
+            if let Some(hit) = node.traverse(ray, limit, counter) {
+                limit = hit.0;
+                closest = Some(hit);
+            }
+        }
+
+        closest
+    }
+
+    /// Whether any shadow casting leaf is hit inside the ray interval, for a
+    /// ray already known to hit this node. Returns on the first hit found.
+    pub fn any_hit(&self, ray: &Ray<F>) -> bool {
+        self.children.iter().any(|child| match child {
+            BvhNode::Leaf(leaf) => leaf.occluded(ray, ray.t_max),
+            BvhNode::Node(node) => node.bound.test(ray).is_some() && node.any_hit(ray),
+        })
+    }
 }
 
 impl<F, B, H, M, L> Bounded<F, B> for Bvh<F, B, H, M, L>
@@ -65,13 +183,68 @@ where
     L: BvhLeaf<F, B, H, M>,
 {
     fn trace(&self, ray: &Ray<F>) -> Option<(F, &H)> {
-        if !&self.bounding_volume().test(ray) {
-            None
-        } else {
-            self.children
-                .iter()
-                .filter_map(|node| node.trace(&ray))
-                .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Equal))
-        }
+        self.bound
+            .test(ray)
+            .and_then(|_| self.trace_within(ray, ray.t_max))
+    }
+
+    fn occluded(&self, ray: &Ray<F>, t_max: F) -> bool {
+        let ray = ray.clone().with_t_max(F::min(ray.t_max, t_max));
+        self.bound.test(&ray).is_some() && self.any_hit(&ray)
+    }
+}
+
+impl<F, B, H, M, L> CountedTraceable<F, H, M> for Bvh<F, B, H, M, L>
+where
+    F: Float,
+    B: BoundingVolume<F>,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+    L: BvhLeaf<F, B, H, M>,
+{
+    fn trace_counted(&self, ray: &Ray<F>, counters: &mut TraversalCounters) -> Option<(F, &H)> {
+        self.bound
+            .test(ray)
+            .and_then(|_| self.traverse(ray, ray.t_max, counters))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use math::{Aabb, Point, Vector};
+    use scenegraph::{LinearBvh, SahBuilder, ShadedSphere};
+    use shading::DebugNormalMaterial;
+
+    #[test]
+    fn test_occlusion_queries() {
+        let spheres: Vec<_> = (0..8)
+            .map(|i| {
+                let sphere = ShadedSphere::new(
+                    Point::new(i as f64 * 3.0, 0.0, 0.0),
+                    1.0,
+                    DebugNormalMaterial {},
+                );
+                // the sphere at x = 6 is visible but lets light through
+                sphere.with_casts_shadows(i != 2)
+            })
+            .collect();
+
+        let bvh: Bvh<f64, Aabb<f64>, _, _, _> = SahBuilder::new()
+            .with_max_leaf_size(1)
+            .build(spheres)
+            .unwrap();
+
+        let towards = |x: f64| Ray::new(Point::new(x, 5.0, 0.0), -Vector::plus_y());
+        assert!(bvh.occluded(&towards(3.0), 10.0));
+        assert!(!bvh.occluded(&towards(3.0), 3.5));
+        assert!(!bvh.occluded(&towards(1.5), 10.0));
+        assert!(!bvh.occluded(&towards(6.0), 10.0));
+        assert!(bvh.trace(&towards(6.0)).is_some());
+
+        let linear = LinearBvh::from_bvh(bvh);
+        assert!(linear.occluded(&towards(3.0), 10.0));
+        assert!(!linear.occluded(&towards(3.0), 3.5));
+        assert!(!linear.occluded(&towards(6.0), 10.0));
     }
 }
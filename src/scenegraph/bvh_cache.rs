@@ -0,0 +1,645 @@
+use math::{Aabb, Bounded, BoundingVolume, Float, Ray};
+use scenegraph::{check_parts, Bvh, BvhLeaf, LinearBvh, LinearNode};
+use shading::Material;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use tracing::{Hitable, Traceable};
+
+const MAGIC: [u8; 8] = *b"NORAYBVH";
+
+/// Bumped whenever the file layout changes, older files are then rebuilt.
+pub const BVH_CACHE_VERSION: u32 = 1;
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// FNV-1a, stable across platforms and releases unlike `DefaultHasher`.
+#[derive(Debug, Clone, Copy)]
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Fnv(FNV_OFFSET)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ u64::from(byte)).wrapping_mul(FNV_PRIME);
+        }
+    }
+}
+
+/// Cache key for the BVH over `leaves`. Builders only look at leaf bounds,
+/// so hashing the bounds in order covers every change that can affect the
+/// tree, while for example material changes keep the cached tree valid.
+pub fn geometry_key<F, L>(leaves: &[L]) -> u64
+where
+    F: Float,
+    L: Bounded<F, Aabb<F>>,
+{
+    let mut hash = Fnv::new();
+    hash.write(&(leaves.len() as u64).to_le_bytes());
+    for leaf in leaves {
+        let bound: Aabb<F> = leaf.bounding_volume();
+        for value in &aabb_values(&bound) {
+            hash.write(&value.to_bits().to_le_bytes());
+        }
+    }
+    hash.0
+}
+
+fn aabb_values<F: Float>(aabb: &Aabb<F>) -> [f64; 6] {
+    let value = |v: F| v.to_f64().unwrap_or(f64::NAN);
+    [
+        value(aabb.x_min),
+        value(aabb.x_max),
+        value(aabb.y_min),
+        value(aabb.y_max),
+        value(aabb.z_min),
+        value(aabb.z_max),
+    ]
+}
+
+#[derive(Debug)]
+pub enum CacheError {
+    Io(io::Error),
+    NotACacheFile,
+    Version(u32),
+    /// The file was written for different geometry.
+    KeyMismatch,
+    Corrupt(String),
+}
+
+impl fmt::Display for CacheError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CacheError::Io(err) => write!(f, "{}", err),
+            CacheError::NotACacheFile => write!(f, "not a BVH cache file"),
+            CacheError::Version(version) => write!(
+                f,
+                "cache version {} instead of {}",
+                version, BVH_CACHE_VERSION
+            ),
+            CacheError::KeyMismatch => write!(f, "cache was written for other geometry"),
+            CacheError::Corrupt(message) => write!(f, "corrupt cache: {}", message),
+        }
+    }
+}
+
+impl Error for CacheError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CacheError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CacheError {
+    fn from(err: io::Error) -> Self {
+        CacheError::Io(err)
+    }
+}
+
+/// Leaf tagged with its index in the builder input, so the primitive order
+/// of the finished tree can be written to the cache.
+#[derive(Debug, Clone)]
+pub struct CacheLeaf<L> {
+    index: usize,
+    leaf: L,
+}
+
+impl<L> CacheLeaf<L> {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn into_leaf(self) -> L {
+        self.leaf
+    }
+}
+
+impl<F, H, M, L> Traceable<F, H, M> for CacheLeaf<L>
+where
+    F: Float,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+    L: Traceable<F, H, M>,
+{
+    fn trace(&self, ray: &Ray<F>) -> Option<(F, &H)> {
+        self.leaf.trace(ray)
+    }
+
+    fn casts_shadows(&self) -> bool {
+        self.leaf.casts_shadows()
+    }
+
+    fn occluded(&self, ray: &Ray<F>, t_max: F) -> bool {
+        self.leaf.occluded(ray, t_max)
+    }
+}
+
+impl<F, B, L> Bounded<F, B> for CacheLeaf<L>
+where
+    F: Float,
+    B: BoundingVolume<F>,
+    L: Bounded<F, B>,
+{
+    fn bounding_volume(&self) -> B {
+        self.leaf.bounding_volume()
+    }
+}
+
+/// Writes the nodes and primitive order of `bvh`, whose primitives must
+/// still carry their input indices, and returns the tree without them.
+pub fn write_bvh<W, F, H, M, L>(
+    writer: &mut W,
+    key: u64,
+    bvh: LinearBvh<F, Aabb<F>, H, M, CacheLeaf<L>>,
+) -> Result<LinearBvh<F, Aabb<F>, H, M, L>, CacheError>
+where
+    W: Write,
+    F: Float,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+    L: BvhLeaf<F, Aabb<F>, H, M>,
+{
+    let (nodes, primitives) = bvh.into_parts();
+
+    let mut bytes = Vec::with_capacity(40 + nodes.len() * 57 + primitives.len() * 8);
+    bytes.extend_from_slice(&MAGIC);
+    bytes.extend_from_slice(&BVH_CACHE_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&key.to_le_bytes());
+    bytes.extend_from_slice(&(nodes.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(&(primitives.len() as u64).to_le_bytes());
+
+    for node in &nodes {
+        for value in &aabb_values(node.bound()) {
+            bytes.extend_from_slice(&value.to_bits().to_le_bytes());
+        }
+        let range = if node.is_leaf() {
+            node.primitive_range()
+        } else {
+            node.child_range()
+        };
+        bytes.extend_from_slice(&(range.start as u32).to_le_bytes());
+        bytes.extend_from_slice(&(range.len() as u32).to_le_bytes());
+        bytes.push(node.is_leaf() as u8);
+    }
+    for primitive in &primitives {
+        bytes.extend_from_slice(&(primitive.index as u64).to_le_bytes());
+    }
+
+    let mut checksum = Fnv::new();
+    checksum.write(&bytes);
+    bytes.extend_from_slice(&checksum.0.to_le_bytes());
+    writer.write_all(&bytes)?;
+
+    let leaves = primitives.into_iter().map(CacheLeaf::into_leaf).collect();
+    LinearBvh::from_parts(nodes, leaves).map_err(CacheError::Corrupt)
+}
+
+/// Reads a tree written by `write_bvh` for the same `leaves`, given in the
+/// order they were passed to the builder. The leaves are handed back when
+/// the file cannot be used.
+#[allow(clippy::type_complexity)]
+pub fn read_bvh<R, F, H, M, L>(
+    reader: &mut R,
+    key: u64,
+    leaves: Vec<L>,
+) -> Result<LinearBvh<F, Aabb<F>, H, M, L>, (CacheError, Vec<L>)>
+where
+    R: Read,
+    F: Float,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+    L: BvhLeaf<F, Aabb<F>, H, M> + Bounded<F, Aabb<F>>,
+{
+    let mut bytes = Vec::new();
+    if let Err(err) = reader.read_to_end(&mut bytes) {
+        return Err((err.into(), leaves));
+    }
+    let (nodes, order) = match parse(&bytes, key, leaves.len()) {
+        Ok(parsed) => parsed,
+        Err(err) => return Err((err, leaves)),
+    };
+
+    // every leaf must still fit into the node the cache puts it in
+    let fits = nodes.iter().filter(|node| node.is_leaf()).all(|node| {
+        node.primitive_range().all(|slot| {
+            let bound: Aabb<F> = leaves[order[slot]].bounding_volume();
+            contains(node.bound(), &bound)
+        })
+    });
+    if !fits {
+        let err = CacheError::Corrupt("leaf outside of its node".to_string());
+        return Err((err, leaves));
+    }
+
+    let mut slots: Vec<Option<L>> = leaves.into_iter().map(Some).collect();
+    let primitives = order
+        .iter()
+        .map(|&index| slots[index].take().expect("validated permutation"))
+        .collect();
+
+    // `parse` checked the tree, so this cannot fail
+    Ok(LinearBvh::from_parts(nodes, primitives).expect("validated tree"))
+}
+
+fn contains<F: Float>(outer: &Aabb<F>, inner: &Aabb<F>) -> bool {
+    outer.x_min <= inner.x_min
+        && inner.x_max <= outer.x_max
+        && outer.y_min <= inner.y_min
+        && inner.y_max <= outer.y_max
+        && outer.z_min <= inner.z_min
+        && inner.z_max <= outer.z_max
+}
+
+struct Cursor<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], CacheError> {
+        if self.bytes.len() < count {
+            return Err(CacheError::Corrupt("file is truncated".to_string()));
+        }
+        let (head, tail) = self.bytes.split_at(count);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, CacheError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, CacheError> {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn u64(&mut self) -> Result<u64, CacheError> {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    fn float<F: Float>(&mut self) -> Result<F, CacheError> {
+        let value = f64::from_bits(self.u64()?);
+        F::from(value).ok_or_else(|| CacheError::Corrupt("invalid bound".to_string()))
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn parse<F: Float>(
+    bytes: &[u8],
+    key: u64,
+    leaf_count: usize,
+) -> Result<(Vec<LinearNode<Aabb<F>>>, Vec<usize>), CacheError> {
+    if bytes.len() < MAGIC.len() || bytes[..MAGIC.len()] != MAGIC {
+        return Err(CacheError::NotACacheFile);
+    }
+    let mut cursor = Cursor {
+        bytes: &bytes[MAGIC.len()..],
+    };
+    let version = cursor.u32()?;
+    if version != BVH_CACHE_VERSION {
+        return Err(CacheError::Version(version));
+    }
+    if cursor.u64()? != key {
+        return Err(CacheError::KeyMismatch);
+    }
+
+    if bytes.len() < MAGIC.len() + 8 {
+        return Err(CacheError::Corrupt("file is truncated".to_string()));
+    }
+    let (content, stored) = bytes.split_at(bytes.len() - 8);
+    let mut checksum = Fnv::new();
+    checksum.write(content);
+    if (Cursor { bytes: stored }).u64()? != checksum.0 {
+        return Err(CacheError::Corrupt("checksum mismatch".to_string()));
+    }
+
+    let node_count = cursor.u64()?;
+    let primitive_count = cursor.u64()?;
+    if primitive_count != leaf_count as u64 {
+        return Err(CacheError::Corrupt("primitive count differs".to_string()));
+    }
+    // checked before allocating, so a bad count cannot exhaust memory
+    let size = node_count
+        .checked_mul(57)
+        .and_then(|nodes| nodes.checked_add(primitive_count.checked_mul(8)?))
+        .and_then(|size| size.checked_add(8));
+    if size != Some(cursor.bytes.len() as u64) {
+        return Err(CacheError::Corrupt("unexpected file size".to_string()));
+    }
+    let (node_count, primitive_count) = (node_count as usize, leaf_count);
+
+    let mut nodes = Vec::with_capacity(node_count);
+    for _ in 0..node_count {
+        let bound = Aabb {
+            x_min: cursor.float()?,
+            x_max: cursor.float()?,
+            y_min: cursor.float()?,
+            y_max: cursor.float()?,
+            z_min: cursor.float()?,
+            z_max: cursor.float()?,
+        };
+        let first = cursor.u32()?;
+        let count = cursor.u32()?;
+        nodes.push(match cursor.u8()? {
+            0 => LinearNode::interior(bound, first, count),
+            1 => LinearNode::leaf(bound, first, count),
+            _ => return Err(CacheError::Corrupt("invalid node kind".to_string())),
+        });
+    }
+
+    let mut seen = vec![false; primitive_count];
+    let mut order = Vec::with_capacity(primitive_count);
+    for _ in 0..primitive_count {
+        let index = cursor.u64()? as usize;
+        if index >= primitive_count || seen[index] {
+            return Err(CacheError::Corrupt("invalid primitive order".to_string()));
+        }
+        seen[index] = true;
+        order.push(index);
+    }
+
+    check_parts(&nodes, primitive_count).map_err(CacheError::Corrupt)?;
+
+    // traversal skips whatever lies outside a node, so a shrunken bound
+    // would silently lose hits
+    let nested = nodes.iter().all(|node| {
+        node.child_range()
+            .all(|child| contains(node.bound(), nodes[child].bound()))
+    });
+    if !nested {
+        return Err(CacheError::Corrupt(
+            "node outside of its parent".to_string(),
+        ));
+    }
+
+    Ok((nodes, order))
+}
+
+/// How `BvhCache::load_or_build` got its tree.
+#[derive(Debug)]
+pub enum CacheOutcome {
+    Loaded,
+    /// Built from scratch. Cache files that were there but unusable, and
+    /// failures to write the new one, are passed on since neither is fatal.
+    Built {
+        load_error: Option<CacheError>,
+        save_error: Option<CacheError>,
+    },
+}
+
+/// Directory of flattened BVHs, one file per geometry key.
+#[derive(Debug, Clone)]
+pub struct BvhCache {
+    dir: PathBuf,
+    salt: u64,
+}
+
+impl BvhCache {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        BvhCache {
+            dir: dir.into(),
+            salt: 0,
+        }
+    }
+
+    /// Mixed into every key. Trees depend on builder settings too, so pick a
+    /// different salt per builder configuration.
+    pub fn with_salt(mut self, salt: u64) -> Self {
+        self.salt = salt;
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn key_for<F, L>(&self, leaves: &[L]) -> u64
+    where
+        F: Float,
+        L: Bounded<F, Aabb<F>>,
+    {
+        let mut hash = Fnv(geometry_key(leaves));
+        hash.write(&self.salt.to_le_bytes());
+        hash.0
+    }
+
+    pub fn path_for(&self, key: u64) -> PathBuf {
+        self.dir.join(format!("{:016x}.bvh", key))
+    }
+
+    /// Loads the tree for `leaves` from the cache, or builds it with
+    /// `build` and stores it for the next time. `None` if `build` does.
+    #[allow(clippy::type_complexity)]
+    pub fn load_or_build<F, H, M, L, Build>(
+        &self,
+        leaves: Vec<L>,
+        build: Build,
+    ) -> Option<(LinearBvh<F, Aabb<F>, H, M, L>, CacheOutcome)>
+    where
+        F: Float,
+        H: Hitable<F, Material = M>,
+        M: Material<F>,
+        L: BvhLeaf<F, Aabb<F>, H, M> + Bounded<F, Aabb<F>>,
+        Build: FnOnce(Vec<CacheLeaf<L>>) -> Option<Bvh<F, Aabb<F>, H, M, CacheLeaf<L>>>,
+    {
+        let key = self.key_for(&leaves);
+        let path = self.path_for(key);
+
+        let (leaves, load_error) = match File::open(&path) {
+            Ok(file) => match read_bvh(&mut BufReader::new(file), key, leaves) {
+                Ok(bvh) => return Some((bvh, CacheOutcome::Loaded)),
+                Err((err, leaves)) => (leaves, Some(err)),
+            },
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => (leaves, None),
+            Err(err) => (leaves, Some(err.into())),
+        };
+
+        let tagged = leaves
+            .into_iter()
+            .enumerate()
+            .map(|(index, leaf)| CacheLeaf { index, leaf })
+            .collect();
+        let tagged = LinearBvh::from_bvh(build(tagged)?);
+
+        let (bvh, save_error) = match self.save(&path, key, tagged) {
+            Ok(bvh) => (bvh, None),
+            Err((err, bvh)) => (bvh, Some(err)),
+        };
+        Some((
+            bvh,
+            CacheOutcome::Built {
+                load_error,
+                save_error,
+            },
+        ))
+    }
+
+    /// Writes to a temporary file first, so concurrent readers never see a
+    /// partially written cache.
+    #[allow(clippy::type_complexity)]
+    fn save<F, H, M, L>(
+        &self,
+        path: &Path,
+        key: u64,
+        bvh: LinearBvh<F, Aabb<F>, H, M, CacheLeaf<L>>,
+    ) -> Result<LinearBvh<F, Aabb<F>, H, M, L>, (CacheError, LinearBvh<F, Aabb<F>, H, M, L>)>
+    where
+        F: Float,
+        H: Hitable<F, Material = M>,
+        M: Material<F>,
+        L: BvhLeaf<F, Aabb<F>, H, M>,
+    {
+        let mut bytes = Vec::new();
+        let bvh = write_bvh(&mut bytes, key, bvh).expect("freshly built tree is valid");
+
+        let temporary = path.with_extension("bvh.tmp");
+        let written = fs::create_dir_all(&self.dir)
+            .and_then(|_| File::create(&temporary))
+            .and_then(|file| {
+                let mut writer = BufWriter::new(file);
+                writer.write_all(&bytes)?;
+                writer.flush()
+            })
+            .and_then(|_| fs::rename(&temporary, path));
+
+        match written {
+            Ok(()) => Ok(bvh),
+            Err(err) => Err((err.into(), bvh)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use math::{Point, Vector};
+    use scenegraph::{SahBuilder, ShadedSphere};
+    use shading::DebugNormalMaterial;
+    use std::env;
+
+    /// Replaces the checksum to match the edited bytes.
+    fn resealed(mut bytes: Vec<u8>) -> Vec<u8> {
+        let content = bytes.len() - 8;
+        let mut checksum = Fnv::new();
+        checksum.write(&bytes[..content]);
+        bytes[content..].copy_from_slice(&checksum.0.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn test_cache_round_trip_and_invalidation() {
+        let spheres = |offset: f64| -> Vec<ShadedSphere<f64, DebugNormalMaterial>> {
+            (0..20)
+                .map(|i| {
+                    let position = Point::new(i as f64 * 3.0, (i % 3) as f64 + offset, 0.0);
+                    ShadedSphere::new(position, 1.0, DebugNormalMaterial {})
+                })
+                .collect()
+        };
+        let dir = env::temp_dir().join(format!("norays-bvh-cache-{}", ::std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let cache = BvhCache::new(&dir);
+        let build = |leaves| SahBuilder::new().with_max_leaf_size(2).build(leaves);
+
+        let (built, outcome) = cache.load_or_build(spheres(0.0), build).unwrap();
+        assert!(matches!(
+            outcome,
+            CacheOutcome::Built {
+                load_error: None,
+                save_error: None
+            }
+        ));
+        let (loaded, outcome) = cache.load_or_build(spheres(0.0), build).unwrap();
+        assert!(matches!(outcome, CacheOutcome::Loaded));
+        assert_eq!(loaded.nodes().len(), built.nodes().len());
+
+        for i in 0..60 {
+            let ray = Ray::new(Point::new(i as f64, 1.0, 5.0), -Vector::plus_z());
+            assert_eq!(
+                loaded.trace(&ray).map(|h| h.0),
+                built.trace(&ray).map(|h| h.0)
+            );
+        }
+
+        // moved geometry gets another key and its own file
+        let (_, outcome) = cache.load_or_build(spheres(0.5), build).unwrap();
+        assert!(matches!(
+            outcome,
+            CacheOutcome::Built {
+                load_error: None,
+                ..
+            }
+        ));
+
+        // damaged files are detected and replaced
+        let path = cache.path_for(cache.key_for(&spheres(0.0)));
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[40] ^= 1;
+        fs::write(&path, &bytes).unwrap();
+        let (_, outcome) = cache.load_or_build(spheres(0.0), build).unwrap();
+        assert!(matches!(
+            outcome,
+            CacheOutcome::Built {
+                load_error: Some(CacheError::Corrupt(_)),
+                save_error: None
+            }
+        ));
+        let (_, outcome) = cache.load_or_build(spheres(0.0), build).unwrap();
+        assert!(matches!(outcome, CacheOutcome::Loaded));
+
+        // even with a valid checksum, a root that no longer encloses its
+        // children is rejected
+        let mut bytes = fs::read(&path).unwrap();
+        let mut x_min = [0; 8];
+        x_min.copy_from_slice(&bytes[36..44]);
+        let x_max = f64::from_bits(u64::from_le_bytes(x_min)) + 1.0;
+        bytes[44..52].copy_from_slice(&x_max.to_bits().to_le_bytes());
+        fs::write(&path, resealed(bytes)).unwrap();
+        let (_, outcome) = cache.load_or_build(spheres(0.0), build).unwrap();
+        assert!(matches!(
+            outcome,
+            CacheOutcome::Built {
+                load_error: Some(CacheError::Corrupt(_)),
+                save_error: None
+            }
+        ));
+
+        // and a node count too large to compute the file size of
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[20..28].copy_from_slice(&u64::MAX.to_le_bytes());
+        fs::write(&path, resealed(bytes)).unwrap();
+        let (_, outcome) = cache.load_or_build(spheres(0.0), build).unwrap();
+        assert!(matches!(
+            outcome,
+            CacheOutcome::Built {
+                load_error: Some(CacheError::Corrupt(_)),
+                save_error: None
+            }
+        ));
+
+        // so are files written for other geometry
+        let other = cache.path_for(cache.key_for(&spheres(0.5)));
+        fs::copy(&other, &path).unwrap();
+        let (_, outcome) = cache.load_or_build(spheres(0.0), build).unwrap();
+        assert!(matches!(
+            outcome,
+            CacheOutcome::Built {
+                load_error: Some(CacheError::KeyMismatch),
+                ..
+            }
+        ));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -64,4 +64,18 @@ where
             BvhNode::Leaf(n) => n.trace(ray),
         }
     }
+
+    fn casts_shadows(&self) -> bool {
+        match self {
+            BvhNode::Node(_) => true,
+            BvhNode::Leaf(n) => n.casts_shadows(),
+        }
+    }
+
+    fn occluded(&self, ray: &Ray<F>, t_max: F) -> bool {
+        match self {
+            BvhNode::Node(n) => n.occluded(ray, t_max),
+            BvhNode::Leaf(n) => n.occluded(ray, t_max),
+        }
+    }
 }
@@ -0,0 +1,176 @@
+use math::{BoundingVolume, Float, Ray};
+use scenegraph::{Bvh, BvhLeaf, BvhNode, SahBuilder};
+use shading::Material;
+use std::fmt;
+use tracing::{Hitable, Traceable};
+
+/// Receives the work done while traversing an acceleration structure. The
+/// implementation for `()` ignores everything and compiles away, so regular
+/// traces share their traversal code with counted ones.
+pub trait CountTraversal {
+    fn visit_node(&mut self);
+    fn test_primitive(&mut self);
+}
+
+impl CountTraversal for () {
+    fn visit_node(&mut self) {}
+    fn test_primitive(&mut self) {}
+}
+
+/// Work done by traces, summed over all traces it was passed to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TraversalCounters {
+    pub node_visits: u32,
+    pub primitive_tests: u32,
+}
+
+impl CountTraversal for TraversalCounters {
+    fn visit_node(&mut self) {
+        self.node_visits += 1;
+    }
+
+    fn test_primitive(&mut self) {
+        self.primitive_tests += 1;
+    }
+}
+
+/// Acceleration structures that can report how much work a trace took.
+/// Only the structure itself is counted, not work inside its primitives.
+pub trait CountedTraceable<F, H, M>: Traceable<F, H, M>
+where
+    F: Float,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+{
+    fn trace_counted(&self, ray: &Ray<F>, counters: &mut TraversalCounters) -> Option<(F, &H)>;
+}
+
+/// Shape and expected cost of a `Bvh`, see `Bvh::stats`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BvhStats<F: Float> {
+    /// Nodes on the longest path from the root, leaves not included.
+    pub depth: usize,
+    pub node_count: usize,
+    pub leaf_count: usize,
+    /// `SahBuilder::sah_cost` with the default costs.
+    pub sah_cost: F,
+    /// Number of nodes by how many leaves they hold directly.
+    pub leaf_histogram: Vec<usize>,
+}
+
+impl<F: Float> fmt::Display for BvhStats<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "depth {}, {} nodes, {} leaves, SAH cost {:.3}",
+            self.depth,
+            self.node_count,
+            self.leaf_count,
+            self.sah_cost.to_f64().unwrap_or(0.0)
+        )?;
+        write!(f, "leaves per node:")?;
+        for (size, count) in self.leaf_histogram.iter().enumerate() {
+            if *count > 0 {
+                write!(f, " {}: {}", size, count)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<F, B, H, M, L> Bvh<F, B, H, M, L>
+where
+    F: Float,
+    B: BoundingVolume<F>,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+    L: BvhLeaf<F, B, H, M>,
+{
+    pub fn stats(&self) -> BvhStats<F> {
+        let mut stats = BvhStats {
+            depth: 0,
+            node_count: 0,
+            leaf_count: 0,
+            sah_cost: SahBuilder::default().sah_cost(self),
+            leaf_histogram: Vec::new(),
+        };
+        self.collect_stats(1, &mut stats);
+        stats
+    }
+
+    fn collect_stats(&self, depth: usize, stats: &mut BvhStats<F>) {
+        stats.depth = stats.depth.max(depth);
+        stats.node_count += 1;
+
+        let mut leaves = 0;
+        for child in self.children() {
+            match child {
+                BvhNode::Node(node) => node.collect_stats(depth + 1, stats),
+                BvhNode::Leaf(_) => leaves += 1,
+            }
+        }
+
+        stats.leaf_count += leaves;
+        if stats.leaf_histogram.len() <= leaves {
+            stats.leaf_histogram.resize(leaves + 1, 0);
+        }
+        stats.leaf_histogram[leaves] += 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use math::{Aabb, Point, Vector};
+    use scenegraph::{LinearBvh, ShadedSphere};
+    use shading::DebugNormalMaterial;
+
+    #[test]
+    fn test_stats_and_counters() {
+        let spheres: Vec<_> = (0..16)
+            .map(|i| {
+                ShadedSphere::new(
+                    Point::new(i as f64 * 3.0, 0.0, 0.0),
+                    1.0,
+                    DebugNormalMaterial {},
+                )
+            })
+            .collect();
+        let bvh: Bvh<f64, Aabb<f64>, _, _, _> = SahBuilder::new()
+            .with_max_leaf_size(2)
+            .build(spheres)
+            .unwrap();
+
+        let stats = bvh.stats();
+        assert_eq!(stats.leaf_count, 16);
+        assert_eq!(stats.node_count, 15);
+        assert_eq!(stats.depth, 4);
+        assert_eq!(stats.leaf_histogram, vec![7, 0, 8]);
+        assert!(stats.sah_cost > 0.0);
+
+        // straight down onto one sphere: one path from the root to its leaf
+        let ray = Ray::new(Point::new(9.0, 5.0, 0.0), -Vector::plus_y());
+        let mut counters = TraversalCounters::default();
+        assert_eq!(
+            bvh.trace_counted(&ray, &mut counters).map(|h| h.0),
+            Some(4.0)
+        );
+        assert_eq!(
+            counters,
+            TraversalCounters {
+                node_visits: 4,
+                primitive_tests: 2
+            }
+        );
+
+        let linear = LinearBvh::from_bvh(bvh);
+        let mut linear_counters = TraversalCounters::default();
+        assert!(linear.trace_counted(&ray, &mut linear_counters).is_some());
+        assert_eq!(linear_counters, counters);
+
+        let miss = Ray::new(Point::new(0.0, 5.0, 0.0), Vector::plus_y());
+        let mut counters = TraversalCounters::default();
+        assert!(linear.trace_counted(&miss, &mut counters).is_none());
+        assert_eq!(counters, TraversalCounters::default());
+    }
+}
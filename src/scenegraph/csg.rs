@@ -0,0 +1,508 @@
+use math::{Aabb, Bounded, BoundingVolume, Float, Ray};
+use scenegraph::{Cuboid, ShadedSphere, Shape};
+use shading::Material;
+use std::cmp::Ordering::Equal;
+use std::marker::PhantomData;
+use tracing::{HitPoint, Hitable, Traceable};
+
+/// Where a ray line crosses the surface of a solid.
+#[derive(Debug)]
+pub struct Boundary<'a, F: Float, H: 'a> {
+    pub t: F,
+    pub hitable: &'a H,
+    /// The surface normal of `hitable` points into the solid here, as on
+    /// the surfaces a `Csg` difference cuts with.
+    pub flipped: bool,
+}
+
+impl<'a, F: Float, H: 'a> Clone for Boundary<'a, F, H> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, F: Float, H: 'a> Copy for Boundary<'a, F, H> {}
+
+/// Stretch of a ray line inside a solid.
+#[derive(Debug)]
+pub struct Span<'a, F: Float, H: 'a> {
+    pub enter: Boundary<'a, F, H>,
+    pub exit: Boundary<'a, F, H>,
+}
+
+impl<'a, F: Float, H: 'a> Clone for Span<'a, F, H> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, F: Float, H: 'a> Copy for Span<'a, F, H> {}
+
+/// Closed shape that knows every stretch of a ray inside of it, which is
+/// what `Csg` combines. `H` is the hitable of the surfaces, so solids can
+/// only be combined if their surfaces share the hitable type, like `Shape`.
+pub trait Solid<F, H, M>
+where
+    F: Float,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+{
+    /// Sorted, disjoint spans along the whole line of the ray, ignoring its
+    /// interval, so rays starting inside see the span they start in.
+    fn spans<'a>(&'a self, ray: &Ray<F>) -> Vec<Span<'a, F, H>>;
+}
+
+/// Spans of any closed `Traceable` found by tracing the line repeatedly,
+/// taking every other surface as entry. Each trace starts a small relative
+/// distance behind the previous hit so the same surface is not found twice.
+pub fn traced_spans<'a, F, H, M, T>(object: &'a T, ray: &Ray<F>) -> Vec<Span<'a, F, H>>
+where
+    F: Float,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+    T: Traceable<F, H, M>,
+{
+    let offset = F::epsilon() * F::from(64).unwrap();
+    let mut line = ray.clone().with_interval(F::neg_infinity(), F::infinity());
+    let mut spans = Vec::new();
+    let mut enter = None;
+    while let Some((t, hitable)) = object.trace(&line) {
+        let boundary = Boundary {
+            t,
+            hitable,
+            flipped: false,
+        };
+        match enter.take() {
+            None => enter = Some(boundary),
+            Some(enter) => spans.push(Span {
+                enter,
+                exit: boundary,
+            }),
+        }
+        line.t_min = t + offset * F::max(F::one(), t.abs());
+    }
+    // an entry without exit means the surface was not closed, drop it
+    spans
+}
+
+/// Adapts any closed `Traceable`, such as a watertight `TriangleMesh` or an
+/// `Instance`, into a `Solid` through `traced_spans`.
+pub struct Closed<F, H, M, T>
+where
+    F: Float,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+    T: Traceable<F, H, M>,
+{
+    object: T,
+    _f: PhantomData<F>,
+    _h: PhantomData<H>,
+    _m: PhantomData<M>,
+}
+
+impl<F, H, M, T> Closed<F, H, M, T>
+where
+    F: Float,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+    T: Traceable<F, H, M>,
+{
+    pub fn new(object: T) -> Self {
+        Closed {
+            object,
+            _f: PhantomData,
+            _h: PhantomData,
+            _m: PhantomData,
+        }
+    }
+
+    pub fn object(&self) -> &T {
+        &self.object
+    }
+}
+
+impl<F, H, M, T> Solid<F, H, M> for Closed<F, H, M, T>
+where
+    F: Float,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+    T: Traceable<F, H, M>,
+{
+    fn spans<'a>(&'a self, ray: &Ray<F>) -> Vec<Span<'a, F, H>> {
+        traced_spans(&self.object, ray)
+    }
+}
+
+impl<F, B, H, M, T> Bounded<F, B> for Closed<F, H, M, T>
+where
+    F: Float,
+    B: BoundingVolume<F>,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+    T: Traceable<F, H, M> + Bounded<F, B>,
+{
+    fn bounding_volume(&self) -> B {
+        self.object.bounding_volume()
+    }
+}
+
+fn span_between<F: Float, H>(hitable: &H, enter: F, exit: F) -> Span<'_, F, H> {
+    let boundary = |t| Boundary {
+        t,
+        hitable,
+        flipped: false,
+    };
+    Span {
+        enter: boundary(enter),
+        exit: boundary(exit),
+    }
+}
+
+impl<F: Float, M: Material<F>> Solid<F, Self, M> for ShadedSphere<F, M> {
+    fn spans<'a>(&'a self, ray: &Ray<F>) -> Vec<Span<'a, F, Self>> {
+        self.sphere()
+            .intersect(ray)
+            .map(|(near, far)| span_between(self, near, far))
+            .into_iter()
+            .collect()
+    }
+}
+
+impl<F: Float, M: Material<F>> Solid<F, Self, M> for Cuboid<F, M> {
+    fn spans<'a>(&'a self, ray: &Ray<F>) -> Vec<Span<'a, F, Self>> {
+        self.intersect(ray)
+            .map(|(near, far)| span_between(self, near, far))
+            .into_iter()
+            .collect()
+    }
+}
+
+/// Spheres and cuboids are intersected directly, other closed shapes are
+/// traced repeatedly. Open surfaces like `Triangle` or `Plane` give no spans.
+impl<F: Float, M: Material<F>> Solid<F, Self, M> for Shape<F, M> {
+    fn spans<'a>(&'a self, ray: &Ray<F>) -> Vec<Span<'a, F, Self>> {
+        let interval = match self {
+            Shape::Sphere(s) => s.sphere().intersect(ray),
+            Shape::Cuboid(c) => c.intersect(ray),
+            _ => None,
+        };
+        match self {
+            Shape::Sphere(_) | Shape::Cuboid(_) => interval
+                .map(|(near, far)| span_between(self, near, far))
+                .into_iter()
+                .collect(),
+            Shape::Triangle(_) | Shape::MeshTriangle(_) => Vec::new(),
+            Shape::Plane(_) | Shape::Rectangle(_) | Shape::Disk(_) => Vec::new(),
+            _ => traced_spans(self, ray),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CsgOperation {
+    Union,
+    Intersection,
+    /// The first solid with the second one cut out of it.
+    Difference,
+}
+
+impl CsgOperation {
+    fn inside(self, a: bool, b: bool) -> bool {
+        match self {
+            CsgOperation::Union => a || b,
+            CsgOperation::Intersection => a && b,
+            CsgOperation::Difference => a && !b,
+        }
+    }
+}
+
+/// Boolean combination of two solids, which can be `Csg` nodes again.
+///
+/// Every surface of the result keeps its own material, so the walls of a
+/// hole cut by a difference show the material of the cutting solid, with
+/// the normal flipped to point out of the result. Like `Instance`, the node
+/// is its own hitable and finds the surface again in `get_hit`.
+pub struct Csg<F, H, M, A, B>
+where
+    F: Float,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+    A: Solid<F, H, M>,
+    B: Solid<F, H, M>,
+{
+    operation: CsgOperation,
+    a: A,
+    b: B,
+    _f: PhantomData<F>,
+    _h: PhantomData<H>,
+    _m: PhantomData<M>,
+}
+
+impl<F, H, M, A, B> Csg<F, H, M, A, B>
+where
+    F: Float,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+    A: Solid<F, H, M>,
+    B: Solid<F, H, M>,
+{
+    pub fn new(operation: CsgOperation, a: A, b: B) -> Self {
+        Csg {
+            operation,
+            a,
+            b,
+            _f: PhantomData,
+            _h: PhantomData,
+            _m: PhantomData,
+        }
+    }
+
+    pub fn union(a: A, b: B) -> Self {
+        Self::new(CsgOperation::Union, a, b)
+    }
+
+    pub fn intersection(a: A, b: B) -> Self {
+        Self::new(CsgOperation::Intersection, a, b)
+    }
+
+    pub fn difference(a: A, b: B) -> Self {
+        Self::new(CsgOperation::Difference, a, b)
+    }
+
+    pub fn operation(&self) -> CsgOperation {
+        self.operation
+    }
+
+    /// Closest boundary of the result inside the ray interval.
+    fn first_boundary(&self, ray: &Ray<F>) -> Option<Boundary<'_, F, H>> {
+        self.spans(ray)
+            .into_iter()
+            .flat_map(|span| vec![span.enter, span.exit])
+            .find(|boundary| ray.contains(boundary.t))
+    }
+}
+
+impl<F, H, M, A, B> Solid<F, H, M> for Csg<F, H, M, A, B>
+where
+    F: Float,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+    A: Solid<F, H, M>,
+    B: Solid<F, H, M>,
+{
+    fn spans<'a>(&'a self, ray: &Ray<F>) -> Vec<Span<'a, F, H>> {
+        let cuts = self.operation == CsgOperation::Difference;
+        // degenerate rays can give NaN distances, which have no place in
+        // the order, so their spans are dropped whole to keep the parity
+        let ordered = |span: &Span<'a, F, H>| !span.enter.t.is_nan() && !span.exit.t.is_nan();
+        let mut events: Vec<_> = self
+            .a
+            .spans(ray)
+            .into_iter()
+            .filter(&ordered)
+            .flat_map(|span| vec![(span.enter, true), (span.exit, true)])
+            .collect();
+        let b_spans = self.b.spans(ray).into_iter().filter(&ordered);
+        events.extend(b_spans.flat_map(|span| {
+            let flip = |boundary: Boundary<'a, F, H>| Boundary {
+                flipped: boundary.flipped != cuts,
+                ..boundary
+            };
+            vec![(flip(span.enter), false), (flip(span.exit), false)]
+        }));
+        events.sort_by(|(x, _), (y, _)| x.t.partial_cmp(&y.t).unwrap_or(Equal));
+
+        let (mut in_a, mut in_b) = (false, false);
+        let mut enter = None;
+        let mut spans = Vec::new();
+        for (boundary, from_a) in events {
+            let was_inside = self.operation.inside(in_a, in_b);
+            if from_a {
+                in_a = !in_a;
+            } else {
+                in_b = !in_b;
+            }
+            match (was_inside, self.operation.inside(in_a, in_b)) {
+                (false, true) => enter = Some(boundary),
+                (true, false) => spans.push(Span {
+                    enter: enter.take().expect("span closed before it was opened"),
+                    exit: boundary,
+                }),
+                _ => {}
+            }
+        }
+        spans
+    }
+}
+
+impl<F, H, M, A, B> Hitable<F> for Csg<F, H, M, A, B>
+where
+    F: Float,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+    A: Solid<F, H, M>,
+    B: Solid<F, H, M>,
+{
+    type Material = M;
+    fn get_hit(&self, ray: &Ray<F>, distance: F) -> HitPoint<F, M> {
+        let boundary = self
+            .spans(ray)
+            .into_iter()
+            .flat_map(|span| vec![span.enter, span.exit])
+            .fold(
+                None,
+                |closest: Option<Boundary<F, H>>, boundary| match closest {
+                    Some(closest)
+                        if (closest.t - distance).abs() <= (boundary.t - distance).abs() =>
+                    {
+                        Some(closest)
+                    }
+                    _ => Some(boundary),
+                },
+            )
+            .expect("hit vanished when tracing the same ray again");
+
+        let mut hit = boundary.hitable.get_hit(ray, distance);
+        if boundary.flipped {
+            hit.data.normal = -hit.data.normal;
+        }
+        hit
+    }
+}
+
+impl<F, H, M, A, B> Traceable<F, Self, M> for Csg<F, H, M, A, B>
+where
+    F: Float,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+    A: Solid<F, H, M>,
+    B: Solid<F, H, M>,
+{
+    fn trace(&self, ray: &Ray<F>) -> Option<(F, &Self)> {
+        self.first_boundary(ray).map(|boundary| (boundary.t, self))
+    }
+}
+
+impl<F, H, M, A, B> Bounded<F, Aabb<F>> for Csg<F, H, M, A, B>
+where
+    F: Float,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+    A: Solid<F, H, M> + Bounded<F, Aabb<F>>,
+    B: Solid<F, H, M> + Bounded<F, Aabb<F>>,
+{
+    fn bounding_volume(&self) -> Aabb<F> {
+        let a: Aabb<F> = self.a.bounding_volume();
+        let b: Aabb<F> = self.b.bounding_volume();
+        match self.operation {
+            CsgOperation::Union => a.combine(&b),
+            CsgOperation::Intersection => Aabb {
+                x_min: F::max(a.x_min, b.x_min),
+                x_max: F::min(a.x_max, b.x_max),
+                y_min: F::max(a.y_min, b.y_min),
+                y_max: F::min(a.y_max, b.y_max),
+                z_min: F::max(a.z_min, b.z_min),
+                z_max: F::min(a.z_max, b.z_max),
+            },
+            CsgOperation::Difference => a,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use math::{Point, Vector};
+    use scenegraph::Torus;
+    use shading::DebugNormalMaterial;
+    use testing::close;
+
+    #[test]
+    fn test_difference_normals_and_materials() {
+        let sphere: Shape<f64, _> =
+            ShadedSphere::new(Point::origin(), 1.0, DebugNormalMaterial {}).into();
+        let slab: Shape<f64, _> = Cuboid::new(
+            Point::new(0.5, -2.0, -2.0),
+            Point::new(2.0, 2.0, 2.0),
+            DebugNormalMaterial {},
+        )
+        .into();
+        let cut = Csg::difference(sphere, slab);
+        let ray = Ray::new(Point::new(5.0, 0.0, 0.0), -Vector::plus_x());
+
+        // the first surface is the face of the slab, facing out of the hole
+        let (t, hitable) = cut.trace(&ray).unwrap();
+        assert!((t - 4.5).abs() < 1e-9);
+        assert!(close(
+            hitable.get_hit(&ray, t).data.normal,
+            Vector::plus_x()
+        ));
+
+        let spans = cut.spans(&ray);
+        assert_eq!(spans.len(), 1);
+        assert!(spans[0].enter.flipped && !spans[0].exit.flipped);
+        assert!((spans[0].exit.t - 6.0).abs() < 1e-9);
+        match spans[0].enter.hitable {
+            Shape::Cuboid(_) => {}
+            _ => panic!("cut face should belong to the cuboid"),
+        }
+
+        // from inside the hole the sphere is never entered along +x
+        let inside = Ray::new(Point::new(0.75, 0.0, 0.0), Vector::plus_x());
+        assert!(cut.trace(&inside).is_none());
+        let bounds: Aabb<f64> = cut.bounding_volume();
+        assert_eq!((bounds.x_min, bounds.x_max), (-1.0, 1.0));
+    }
+
+    #[test]
+    fn test_union_and_intersection() {
+        let sphere = |x: f64| -> Shape<f64, _> {
+            ShadedSphere::new(Point::new(x, 0.0, 0.0), 1.0, DebugNormalMaterial {}).into()
+        };
+        let ray = Ray::new(Point::new(-5.0, 0.0, 0.0), Vector::plus_x());
+
+        // the surfaces inside the other sphere are skipped
+        let union = Csg::union(sphere(-0.5), sphere(0.5));
+        let spans = union.spans(&ray);
+        assert_eq!(spans.len(), 1);
+        assert!((spans[0].enter.t - 3.5).abs() < 1e-9);
+        assert!((spans[0].exit.t - 6.5).abs() < 1e-9);
+
+        let lens = Csg::intersection(sphere(-0.5), sphere(0.5));
+        let (t, hitable) = lens.trace(&ray).unwrap();
+        assert!((t - 4.5).abs() < 1e-9);
+        assert!(close(
+            hitable.get_hit(&ray, t).data.normal,
+            -Vector::plus_x()
+        ));
+        let bounds: Aabb<f64> = lens.bounding_volume();
+        assert_eq!((bounds.x_min, bounds.x_max), (-0.5, 0.5));
+
+        // nested nodes and traced solids combine the same way
+        let torus: Shape<f64, _> = Torus::new(
+            Point::origin(),
+            Vector::plus_z(),
+            2.0,
+            0.5,
+            DebugNormalMaterial {},
+        )
+        .into();
+        let ring = Csg::difference(Closed::new(torus), Csg::union(sphere(-0.5), sphere(0.5)));
+        let spans = ring.spans(&ray);
+        assert_eq!(spans.len(), 2);
+        assert!((spans[0].enter.t - 2.5).abs() < 1e-6);
+        assert!((spans[1].exit.t - 7.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_degenerate_ray() {
+        let sphere = |x: f64| -> Shape<f64, _> {
+            ShadedSphere::new(Point::new(x, 0.0, 0.0), 1.0, DebugNormalMaterial {}).into()
+        };
+        // no direction at all, from inside both spheres, gives NaN distances
+        let ray = Ray::new(Point::origin(), Vector::new(0.0, 0.0, 0.0));
+        let union = Csg::union(sphere(-0.5), sphere(0.5));
+        assert!(union.spans(&ray).is_empty());
+        assert!(union.trace(&ray).is_none());
+    }
+}
@@ -0,0 +1,132 @@
+use math::{Aabb, Bounded, Float, Point, Ray, Vector, UV};
+use shading::Material;
+use tracing::{HitPoint, Hitable, Traceable};
+
+/// Solid axis aligned box, use an `Instance` to rotate it. The UV of each
+/// face run from 0 to 1 along the two following axes, so y and z for the
+/// faces facing along x, then z and x, then x and y.
+#[derive(Clone, Copy)]
+pub struct Cuboid<F: Float, M: Material<F>> {
+    bounds: Aabb<F>,
+    material: M,
+    casts_shadows: bool,
+}
+
+impl<F: Float, M: Material<F>> Cuboid<F, M> {
+    pub fn new(min: Point<F>, max: Point<F>, material: M) -> Self {
+        Cuboid {
+            bounds: Aabb::from_points(&[min, max]),
+            material,
+            casts_shadows: true,
+        }
+    }
+
+    pub fn with_casts_shadows(mut self, casts_shadows: bool) -> Self {
+        self.casts_shadows = casts_shadows;
+        self
+    }
+
+    /// Distances at which the ray line enters and leaves the box.
+    pub(crate) fn intersect(&self, ray: &Ray<F>) -> Option<(F, F)> {
+        self.bounds.intersect(ray)
+    }
+}
+
+impl<F: Float, M: Material<F>> Hitable<F> for Cuboid<F, M> {
+    type Material = M;
+    fn get_hit(&self, ray: &Ray<F>, distance: F) -> HitPoint<F, M> {
+        let point = ray.point_at_distance(distance);
+        let (min, max) = (self.bounds.min_point(), self.bounds.max_point());
+        let center = self.bounds.centroid();
+
+        // the face is along the axis where the point is relatively farthest
+        // out from the center
+        let relative = |axis: usize| {
+            let half = (max[axis] - min[axis]) * (F::one() + F::one()).recip();
+            (point[axis] - center[axis]) / half
+        };
+        let axis = (1..3).fold(0, |best, axis| {
+            if relative(axis).abs() > relative(best).abs() {
+                axis
+            } else {
+                best
+            }
+        });
+
+        let mut normal = [F::zero(); 3];
+        normal[axis] = relative(axis).signum();
+        let along = |axis: usize| (point[axis] - min[axis]) / (max[axis] - min[axis]);
+        let uv = UV::new(along((axis + 1) % 3), along((axis + 2) % 3));
+
+        HitPoint::new(
+            point,
+            Vector::new(normal[0], normal[1], normal[2]),
+            ray.direction,
+            uv,
+            self.material.clone(),
+        )
+    }
+}
+
+impl<F: Float, M: Material<F>> Traceable<F, Self, M> for Cuboid<F, M> {
+    fn trace(&self, ray: &Ray<F>) -> Option<(F, &Self)> {
+        let (near, far) = self.intersect(ray)?;
+
+        // from inside the box the near face is behind the ray origin
+        if ray.contains(near) {
+            Some((near, self))
+        } else if ray.contains(far) {
+            Some((far, self))
+        } else {
+            None
+        }
+    }
+
+    fn casts_shadows(&self) -> bool {
+        self.casts_shadows
+    }
+}
+
+impl<F: Float, M: Material<F>> Bounded<F, Aabb<F>> for Cuboid<F, M> {
+    fn bounding_volume(&self) -> Aabb<F> {
+        self.bounds
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shading::DebugNormalMaterial;
+
+    #[test]
+    fn test_cuboid_faces() {
+        let cuboid = Cuboid::new(
+            Point::new(2.0, 1.0, 1.0),
+            Point::new(-2.0, -1.0, -1.0),
+            DebugNormalMaterial {},
+        );
+        let hit = |origin: Point<f64>, direction: Vector<f64>| {
+            let ray = Ray::new(origin, direction);
+            cuboid
+                .trace(&ray)
+                .map(|(t, h)| (t, h.get_hit(&ray, t).data))
+        };
+
+        let (t, data) = hit(Point::new(-5.0, 0.5, 0.0), Vector::plus_x()).unwrap();
+        assert_eq!(t, 3.0);
+        assert_eq!(data.normal, -Vector::plus_x());
+        assert_eq!(data.uv, UV::new(0.75, 0.5));
+
+        let (t, data) = hit(Point::new(1.0, 0.0, 5.0), -Vector::plus_z()).unwrap();
+        assert_eq!(t, 4.0);
+        assert_eq!(data.normal, Vector::plus_z());
+        assert_eq!(data.uv, UV::new(0.75, 0.5));
+
+        // from inside the far face is hit
+        let (t, data) = hit(Point::origin(), Vector::plus_y()).unwrap();
+        assert_eq!(t, 1.0);
+        assert_eq!(data.normal, Vector::plus_y());
+
+        assert!(hit(Point::new(0.0, 3.0, 0.0), Vector::plus_y()).is_none());
+    }
+}
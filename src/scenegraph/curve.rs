@@ -0,0 +1,467 @@
+use math::{Aabb, Bounded, BoundingVolume, Float, Frame, Point, Ray, Vector, UV};
+use scenegraph::Bvh;
+use shading::Material;
+use std::cmp::Ordering::Equal;
+use std::sync::Arc;
+use tracing::{HitPoint, Hitable, Traceable};
+
+/// How the flat strip a curve is intersected as gets shaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CurveKind {
+    /// Strip turned towards every ray, with the normal facing the ray. Fine
+    /// for hair and fur seen from a distance.
+    Ribbon,
+    /// Strip shaded with the normals of a round tube of the same width, for
+    /// thicker fibers and cables.
+    Cylinder,
+}
+
+/// Control points and widths shared by all segments of a curve.
+#[derive(Debug, Clone)]
+pub struct CurveData<F: Float, M: Material<F>> {
+    points: Vec<Point<F>>,
+    widths: Vec<F>,
+    kind: CurveKind,
+    material: M,
+    casts_shadows: bool,
+}
+
+impl<F: Float, M: Material<F>> CurveData<F, M> {
+    pub fn points(&self) -> &[Point<F>] {
+        &self.points
+    }
+
+    pub fn widths(&self) -> &[F] {
+        &self.widths
+    }
+
+    pub fn kind(&self) -> CurveKind {
+        self.kind
+    }
+
+    pub fn segment_count(&self) -> usize {
+        self.widths.len() - 1
+    }
+}
+
+/// Position and derivative of a cubic Bézier curve at `u`.
+fn evaluate<F: Float>(p: &[Point<F>; 4], u: F) -> (Point<F>, Vector<F>) {
+    let lerp = |a: Point<F>, b: Point<F>| a + (b - a) * u;
+    let (a, b, c) = (lerp(p[0], p[1]), lerp(p[1], p[2]), lerp(p[2], p[3]));
+    let (d, e) = (lerp(a, b), lerp(b, c));
+    let three = F::from(3).unwrap();
+    (lerp(d, e), (e - d) * three)
+}
+
+/// Both halves of a cubic Bézier curve.
+fn split<F: Float>(p: &[Point<F>; 4]) -> ([Point<F>; 4], [Point<F>; 4]) {
+    let half = (F::one() + F::one()).recip();
+    let mid = |a: Point<F>, b: Point<F>| a + (b - a) * half;
+    let (a, b, c) = (mid(p[0], p[1]), mid(p[1], p[2]), mid(p[2], p[3]));
+    let (d, e) = (mid(a, b), mid(b, c));
+    let center = mid(d, e);
+    ([p[0], a, d, center], [center, e, c, p[3]])
+}
+
+/// Single cubic Bézier segment of a `Curve`, the leaf to put into a `Bvh`.
+#[derive(Debug)]
+pub struct CurveSegment<F: Float, M: Material<F>> {
+    curve: Arc<CurveData<F, M>>,
+    index: usize,
+}
+
+impl<F: Float, M: Material<F>> Clone for CurveSegment<F, M> {
+    fn clone(&self) -> Self {
+        CurveSegment {
+            curve: self.curve.clone(),
+            index: self.index,
+        }
+    }
+}
+
+impl<F: Float, M: Material<F>> CurveSegment<F, M> {
+    pub fn control_points(&self) -> [Point<F>; 4] {
+        let p = &self.curve.points[3 * self.index..];
+        [p[0], p[1], p[2], p[3]]
+    }
+
+    /// Widths at the start and end of the segment.
+    pub fn widths(&self) -> (F, F) {
+        (
+            self.curve.widths[self.index],
+            self.curve.widths[self.index + 1],
+        )
+    }
+
+    pub fn curve(&self) -> &Arc<CurveData<F, M>> {
+        &self.curve
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Distance of the closest hit and where along the segment it is.
+    fn intersect(&self, ray: &Ray<F>) -> Option<(F, F)> {
+        // in a frame looking down the ray, the ray hits wherever the curve
+        // passes within half its width of the z axis
+        let frame = Frame::from_axis(ray.origin, ray.direction);
+        let mut local = self.control_points();
+        for p in local.iter_mut() {
+            *p = frame.to_local_point(*p);
+        }
+
+        // subdivide until the pieces are about straight, see Pharr, Jakob
+        // and Humphreys, "Physically Based Rendering", 3rd edition, 9.3
+        let (w0, w1) = self.widths();
+        let flatness = (0..2).fold(F::zero(), |flatness, i| {
+            let bend = (local[i] - Point::origin()) + (local[i + 2] - Point::origin())
+                - (local[i + 1] - Point::origin()) * (F::one() + F::one());
+            F::max(
+                flatness,
+                F::max(bend.x.abs(), F::max(bend.y.abs(), bend.z.abs())),
+            )
+        });
+        let tolerance = F::max(w0, w1) * F::from(0.05).unwrap();
+        let depth = if flatness > tolerance {
+            let levels = (F::from(6.0 / 8.0 * 2f64.sqrt()).unwrap() * flatness / tolerance)
+                .log(F::from(4).unwrap());
+            levels.round().to_usize().unwrap_or(0).min(10)
+        } else {
+            0
+        };
+
+        let speed = ray.direction.magnitude();
+        let search = Search {
+            ray,
+            speed,
+            widths: (w0, w1),
+        };
+        search
+            .recurse(&local, F::zero(), F::one(), depth, ray.t_max * speed)
+            .map(|(z, u)| (z / speed, u))
+    }
+}
+
+struct Search<'a, F: Float + 'a> {
+    ray: &'a Ray<F>,
+    speed: F,
+    widths: (F, F),
+}
+
+impl<'a, F: Float> Search<'a, F> {
+    fn width(&self, u: F) -> F {
+        self.widths.0 + (self.widths.1 - self.widths.0) * u
+    }
+
+    /// Closest hit nearer than `z_max` on the piece from `u0` to `u1`, as
+    /// distance along the normalized ray and position on the segment.
+    fn recurse(&self, p: &[Point<F>; 4], u0: F, u1: F, depth: usize, z_max: F) -> Option<(F, F)> {
+        let half_width = F::max(self.width(u0), self.width(u1)) * (F::one() + F::one()).recip();
+        let bounds = Aabb::from_points(p);
+        if bounds.x_min > half_width
+            || bounds.x_max < -half_width
+            || bounds.y_min > half_width
+            || bounds.y_max < -half_width
+            || bounds.z_max < self.ray.t_min * self.speed - half_width
+            || bounds.z_min > z_max + half_width
+        {
+            return None;
+        }
+
+        if depth > 0 {
+            let (first, second) = split(p);
+            let mid = (u0 + u1) * (F::one() + F::one()).recip();
+            let near = self.recurse(&first, u0, mid, depth - 1, z_max);
+            let z_max = near.map_or(z_max, |(z, _)| z);
+            return self.recurse(&second, mid, u1, depth - 1, z_max).or(near);
+        }
+
+        // the piece is taken as straight, only ray positions beside it that
+        // are not past either end count
+        let start = (p[1].y - p[0].y) * -p[0].y + p[0].x * (p[0].x - p[1].x);
+        let end = (p[2].y - p[3].y) * -p[3].y + p[3].x * (p[3].x - p[2].x);
+        if start < F::zero() || end < F::zero() {
+            return None;
+        }
+        let (dx, dy) = (p[3].x - p[0].x, p[3].y - p[0].y);
+        let length_sq = dx * dx + dy * dy;
+        if length_sq == F::zero() {
+            return None;
+        }
+        let w = F::min(
+            F::max(-(p[0].x * dx + p[0].y * dy) / length_sq, F::zero()),
+            F::one(),
+        );
+        let u = u0 + (u1 - u0) * w;
+
+        let (point, _) = evaluate(p, w);
+        let width = self.width(u);
+        if (point.x * point.x + point.y * point.y) * F::from(4).unwrap() > width * width {
+            return None;
+        }
+        let t = point.z / self.speed;
+        if self.ray.contains(t) && point.z < z_max {
+            Some((point.z, u))
+        } else {
+            None
+        }
+    }
+}
+
+impl<F: Float, M: Material<F>> Hitable<F> for CurveSegment<F, M> {
+    type Material = M;
+    fn get_hit(&self, ray: &Ray<F>, distance: F) -> HitPoint<F, M> {
+        let u = self
+            .intersect(ray)
+            .map(|(_, u)| u)
+            .expect("hit vanished when tracing the same ray again");
+        let point = ray.point_at_distance(distance);
+        let (center, derivative) = evaluate(&self.control_points(), u);
+        let tangent = derivative.normalized();
+
+        // facing the ray, and tilted around the tangent for a tube
+        let towards = -ray.direction;
+        let facing = (towards - tangent * towards.dot(tangent)).normalized();
+        let side = tangent.cross(facing);
+        let half_width = self.widths().0 + (self.widths().1 - self.widths().0) * u;
+        let half_width = half_width * (F::one() + F::one()).recip();
+        let across = F::min(
+            F::max((point - center).dot(side) / half_width, -F::one()),
+            F::one(),
+        );
+        let normal = match self.curve.kind {
+            CurveKind::Ribbon => facing,
+            CurveKind::Cylinder => {
+                facing * F::max(F::one() - across * across, F::zero()).sqrt() + side * across
+            }
+        };
+
+        let half = (F::one() + F::one()).recip();
+        let along =
+            (F::from(self.index).unwrap() + u) / F::from(self.curve.segment_count()).unwrap();
+        HitPoint::new(
+            point,
+            normal,
+            ray.direction,
+            UV::new((across + F::one()) * half, along),
+            self.curve.material.clone(),
+        )
+        .with_tangent(tangent)
+    }
+}
+
+impl<F: Float, M: Material<F>> Traceable<F, Self, M> for CurveSegment<F, M> {
+    fn trace(&self, ray: &Ray<F>) -> Option<(F, &Self)> {
+        self.intersect(ray).map(|(distance, _)| (distance, self))
+    }
+
+    fn casts_shadows(&self) -> bool {
+        self.curve.casts_shadows
+    }
+}
+
+impl<F: Float, M: Material<F>> Bounded<F, Aabb<F>> for CurveSegment<F, M> {
+    /// The convex hull of the control points grown by half the width.
+    fn bounding_volume(&self) -> Aabb<F> {
+        let (w0, w1) = self.widths();
+        let r = F::max(w0, w1) * (F::one() + F::one()).recip();
+        let hull = Aabb::from_points(&self.control_points());
+        Aabb {
+            x_min: hull.x_min - r,
+            x_max: hull.x_max + r,
+            y_min: hull.y_min - r,
+            y_max: hull.y_max + r,
+            z_min: hull.z_min - r,
+            z_max: hull.z_max + r,
+        }
+    }
+}
+
+/// BVH with every curve segment in its own leaf.
+pub type CurveBvh<F, M> = Bvh<F, Aabb<F>, CurveSegment<F, M>, M, CurveSegment<F, M>>;
+
+/// Chain of cubic Bézier segments with a width varying linearly along each
+/// of them. Can be traced as a single object, or split with `into_segments`
+/// to put every segment into its own BVH leaf.
+///
+/// Hits report the tangent of the curve, `u` across its width from 0 to 1
+/// and `v` along the whole curve from 0 to 1.
+pub struct Curve<F: Float, M: Material<F>> {
+    segments: Vec<CurveSegment<F, M>>,
+    bound: Aabb<F>,
+}
+
+impl<F: Float, M: Material<F>> Curve<F, M> {
+    /// Takes `3 n + 1` control points for `n` segments, neighbours sharing
+    /// their end points, and `n + 1` widths at the ends of the segments.
+    /// Returns `None` for other counts.
+    pub fn new(
+        points: Vec<Point<F>>,
+        widths: Vec<F>,
+        kind: CurveKind,
+        material: M,
+    ) -> Option<Self> {
+        if widths.len() < 2 || points.len() != 3 * (widths.len() - 1) + 1 {
+            return None;
+        }
+        Some(Self::from_data(CurveData {
+            points,
+            widths,
+            kind,
+            material,
+            casts_shadows: true,
+        }))
+    }
+
+    fn from_data(data: CurveData<F, M>) -> Self {
+        let curve = Arc::new(data);
+        let segments: Vec<_> = (0..curve.segment_count())
+            .map(|index| CurveSegment {
+                curve: curve.clone(),
+                index,
+            })
+            .collect();
+
+        let bound = segments
+            .iter()
+            .map(|segment| segment.bounding_volume())
+            .fold(Aabb::empty(), |a, b| a.combine(&b));
+
+        Curve { segments, bound }
+    }
+
+    pub fn with_casts_shadows(mut self, casts_shadows: bool) -> Self {
+        let curve = self.segments[0].curve.clone();
+        self.segments.clear();
+        let mut data = Arc::try_unwrap(curve).unwrap_or_else(|shared| (*shared).clone());
+        data.casts_shadows = casts_shadows;
+        Self::from_data(data)
+    }
+
+    pub fn segments(&self) -> &[CurveSegment<F, M>] {
+        &self.segments
+    }
+
+    pub fn into_segments(self) -> Vec<CurveSegment<F, M>> {
+        self.segments
+    }
+}
+
+impl<F, M> Traceable<F, CurveSegment<F, M>, M> for Curve<F, M>
+where
+    F: Float,
+    M: Material<F>,
+{
+    fn trace(&self, ray: &Ray<F>) -> Option<(F, &CurveSegment<F, M>)> {
+        self.bound.test(ray)?;
+        self.segments
+            .iter()
+            .filter_map(|segment| segment.trace(ray))
+            .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Equal))
+    }
+
+    fn casts_shadows(&self) -> bool {
+        self.segments[0].curve.casts_shadows
+    }
+}
+
+impl<F: Float, M: Material<F>> Bounded<F, Aabb<F>> for Curve<F, M> {
+    fn bounding_volume(&self) -> Aabb<F> {
+        self.bound
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use scenegraph::SahBuilder;
+    use shading::DebugNormalMaterial;
+    use testing::{close_within, lcg};
+
+    #[test]
+    fn test_straight_fiber() {
+        // along x from 0 to 3, narrowing from 0.4 to nothing
+        let points = (0..4).map(|i| Point::new(i as f64, 0.0, 0.0)).collect();
+        let fiber = Curve::new(
+            points,
+            vec![0.4, 0.0],
+            CurveKind::Cylinder,
+            DebugNormalMaterial {},
+        )
+        .unwrap();
+        let down = |x: f64, z: f64| Ray::new(Point::new(x, 5.0, z), -Vector::plus_y() * 2.0);
+        let hit = |x: f64, z: f64| {
+            let ray = down(x, z);
+            fiber.trace(&ray).map(|(t, h)| (t, h.get_hit(&ray, t).data))
+        };
+
+        let (t, data) = hit(0.3, 0.0).unwrap();
+        assert!((t - 2.5).abs() < 1e-9);
+        assert!(close_within(data.normal, Vector::plus_y(), 1e-6));
+        assert!(close_within(data.tangent.unwrap(), Vector::plus_x(), 1e-6));
+        assert!((data.uv.v - 0.1).abs() < 1e-6);
+        assert!((data.uv.u - 0.5).abs() < 1e-6);
+
+        // half way out to the edge the tube normal leans 30 degrees
+        let (_, data) = hit(0.3, 0.09).unwrap();
+        assert!(close_within(
+            data.normal,
+            Vector::new(0.0, 0.75f64.sqrt(), 0.5),
+            1e-6
+        ));
+        assert!((data.uv.u - 0.75).abs() < 1e-6);
+
+        assert!(hit(0.3, 0.2).is_none());
+        assert!(hit(2.7, 0.05).is_none());
+        assert!(hit(-0.1, 0.0).is_none());
+    }
+
+    #[test]
+    fn test_segments_in_bvh() {
+        // a wave of two segments in the xz plane
+        let points = vec![
+            Point::new(0.0, 0.0, 0.0),
+            Point::new(1.0, 0.0, 2.0),
+            Point::new(2.0, 0.0, 2.0),
+            Point::new(3.0, 0.0, 0.0),
+            Point::new(4.0, 0.0, -2.0),
+            Point::new(5.0, 0.0, -2.0),
+            Point::new(6.0, 0.0, 0.0),
+        ];
+        let wave = Curve::new(
+            points,
+            vec![0.1, 0.3, 0.1],
+            CurveKind::Ribbon,
+            DebugNormalMaterial {},
+        )
+        .unwrap();
+        let bvh: CurveBvh<f64, _> = SahBuilder::new().build(wave.segments().to_vec()).unwrap();
+
+        let mut next = lcg(12345);
+        let mut hits = 0;
+        for _ in 0..400 {
+            let ray = Ray::new(
+                Point::new(next() * 6.0, 3.0, next() * 4.0 - 2.0),
+                Vector::new(next() - 0.5, -1.0, next() - 0.5),
+            );
+            let expected = wave.trace(&ray).map(|(t, s)| (t, s.index()));
+            assert_eq!(bvh.trace(&ray).map(|(t, s)| (t, s.index())), expected);
+
+            if let Some((t, segment)) = wave.trace(&ray) {
+                hits += 1;
+                let data = segment.get_hit(&ray, t).data;
+                assert!(data.normal.dot(ray.direction) < 0.0);
+                let u = data.uv.v * 2.0 - segment.index() as f64;
+                let (center, _) = evaluate(&segment.control_points(), u);
+                assert!((data.point - center).magnitude() < 0.3);
+            }
+        }
+        assert!(hits > 10);
+
+        // the middle of the wave is where the segments meet
+        let ray = Ray::new(Point::new(3.02, 1.0, -0.04), -Vector::plus_y());
+        let (t, segment) = wave.trace(&ray).unwrap();
+        assert!((segment.get_hit(&ray, t).data.uv.v - 0.5).abs() < 0.01);
+    }
+}
@@ -0,0 +1,326 @@
+use math::{turns, Aabb, Bounded, BoundingVolume, Float, Frame, Point, Ray, Vector, UV};
+use shading::Material;
+use tracing::{HitPoint, Hitable, Traceable};
+
+/// Both roots of `a t² + 2 half_b t + c`, ascending.
+fn solve_quadratic<F: Float>(a: F, half_b: F, c: F) -> Option<(F, F)> {
+    if a == F::zero() {
+        if half_b == F::zero() {
+            return None;
+        }
+        let t = -c / (half_b + half_b);
+        return Some((t, t));
+    }
+    let discriminant = half_b * half_b - a * c;
+    if discriminant < F::zero() {
+        return None;
+    }
+    let root = discriminant.sqrt();
+    let (t1, t2) = ((-half_b - root) / a, (-half_b + root) / a);
+    Some((F::min(t1, t2), F::max(t1, t2)))
+}
+
+/// Closest of the candidate distances inside the ray interval.
+fn closest<F: Float, I: IntoIterator<Item = F>>(ray: &Ray<F>, candidates: I) -> Option<F> {
+    candidates
+        .into_iter()
+        .filter(|&t| ray.contains(t))
+        .fold(None, |best, t| match best {
+            Some(best) if best <= t => Some(best),
+            _ => Some(t),
+        })
+}
+
+/// Distance to the plane z = `z` of the local ray, if within `radius` of
+/// the axis there.
+fn cap<F: Float>(ray: &Ray<F>, z: F, radius: F) -> Option<F> {
+    if ray.direction.z == F::zero() {
+        return None;
+    }
+    let t = (z - ray.origin.z) / ray.direction.z;
+    let point = ray.point_at_distance(t);
+    if point.x * point.x + point.y * point.y <= radius * radius {
+        Some(t)
+    } else {
+        None
+    }
+}
+
+/// UV on the caps, like on a `Disk`.
+fn cap_uv<F: Float>(local: Point<F>, radius: F) -> UV<F> {
+    UV::new(turns(local.y, local.x), local.x.hypot(local.y) / radius)
+}
+
+/// Cylinder closed by a disk at either end. On the side `u` is the angle
+/// around the axis as a fraction of a turn and `v` runs from 0 at the base
+/// to 1 at the top, the caps are mapped like a `Disk`.
+#[derive(Clone, Copy, Debug)]
+pub struct Cylinder<F: Float, M: Material<F>> {
+    frame: Frame<F>,
+    radius: F,
+    height: F,
+    material: M,
+    casts_shadows: bool,
+}
+
+impl<F: Float, M: Material<F>> Cylinder<F, M> {
+    pub fn new(base: Point<F>, top: Point<F>, radius: F, material: M) -> Self {
+        Cylinder {
+            frame: Frame::from_axis(base, top - base),
+            radius,
+            height: (top - base).magnitude(),
+            material,
+            casts_shadows: true,
+        }
+    }
+
+    pub fn with_casts_shadows(mut self, casts_shadows: bool) -> Self {
+        self.casts_shadows = casts_shadows;
+        self
+    }
+}
+
+impl<F: Float, M: Material<F>> Hitable<F> for Cylinder<F, M> {
+    type Material = M;
+    fn get_hit(&self, ray: &Ray<F>, distance: F) -> HitPoint<F, M> {
+        let point = ray.point_at_distance(distance);
+        let local = self.frame.to_local_point(point);
+        let radial = local.x.hypot(local.y);
+
+        // the part whose surface is closest to the hit
+        let to_side = (radial - self.radius).abs();
+        let to_top = (local.z - self.height).abs();
+        let (normal, uv) = if to_side <= local.z.abs() && to_side <= to_top {
+            let normal = Vector::new(local.x, local.y, F::zero()) * radial.recip();
+            let uv = UV::new(turns(local.y, local.x), local.z / self.height);
+            (normal, uv)
+        } else if local.z.abs() < to_top {
+            (-Vector::plus_z(), cap_uv(local, self.radius))
+        } else {
+            (Vector::plus_z(), cap_uv(local, self.radius))
+        };
+
+        HitPoint::new(
+            point,
+            self.frame.to_world_vector(normal),
+            ray.direction,
+            uv,
+            self.material.clone(),
+        )
+    }
+}
+
+impl<F: Float, M: Material<F>> Traceable<F, Self, M> for Cylinder<F, M> {
+    fn trace(&self, ray: &Ray<F>) -> Option<(F, &Self)> {
+        let local = self.frame.to_local_ray(ray);
+        let (o, d) = (local.origin, local.direction);
+
+        let within = |t: F| {
+            let z = o.z + d.z * t;
+            z >= F::zero() && z <= self.height
+        };
+        let side = solve_quadratic(
+            d.x * d.x + d.y * d.y,
+            o.x * d.x + o.y * d.y,
+            o.x * o.x + o.y * o.y - self.radius * self.radius,
+        );
+        let side = side
+            .map(|(t1, t2)| vec![t1, t2])
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|&t| within(t));
+        let caps = cap(&local, F::zero(), self.radius).into_iter().chain(cap(
+            &local,
+            self.height,
+            self.radius,
+        ));
+
+        closest(&local, side.chain(caps)).map(|t| (t, self))
+    }
+
+    fn casts_shadows(&self) -> bool {
+        self.casts_shadows
+    }
+}
+
+impl<F: Float, M: Material<F>> Bounded<F, Aabb<F>> for Cylinder<F, M> {
+    fn bounding_volume(&self) -> Aabb<F> {
+        let top = self.frame.origin + self.frame.z * self.height;
+        let base = Aabb::from_disk(self.frame.origin, self.frame.z, self.radius);
+        base.combine(&Aabb::from_disk(top, self.frame.z, self.radius))
+    }
+}
+
+/// Cone closed by a disk at its base. Mapped like a `Cylinder`, with `v`
+/// reaching 1 at the apex.
+#[derive(Clone, Copy, Debug)]
+pub struct Cone<F: Float, M: Material<F>> {
+    frame: Frame<F>,
+    radius: F,
+    height: F,
+    material: M,
+    casts_shadows: bool,
+}
+
+impl<F: Float, M: Material<F>> Cone<F, M> {
+    pub fn new(base: Point<F>, apex: Point<F>, radius: F, material: M) -> Self {
+        Cone {
+            frame: Frame::from_axis(base, apex - base),
+            radius,
+            height: (apex - base).magnitude(),
+            material,
+            casts_shadows: true,
+        }
+    }
+
+    pub fn with_casts_shadows(mut self, casts_shadows: bool) -> Self {
+        self.casts_shadows = casts_shadows;
+        self
+    }
+
+    /// Radius change per unit of height.
+    fn slope(&self) -> F {
+        self.radius / self.height
+    }
+}
+
+impl<F: Float, M: Material<F>> Hitable<F> for Cone<F, M> {
+    type Material = M;
+    fn get_hit(&self, ray: &Ray<F>, distance: F) -> HitPoint<F, M> {
+        let point = ray.point_at_distance(distance);
+        let local = self.frame.to_local_point(point);
+        let radial = local.x.hypot(local.y);
+        let k = self.slope();
+
+        let to_side = (radial - k * (self.height - local.z)).abs();
+        let (normal, uv) = if to_side <= local.z.abs() {
+            // gradient of x² + y² - k²(h - z)², straight up at the apex
+            let gradient = Vector::new(local.x, local.y, k * k * (self.height - local.z));
+            let normal = if gradient.magnitude_sq() > F::zero() {
+                gradient.normalized()
+            } else {
+                Vector::plus_z()
+            };
+            let uv = UV::new(turns(local.y, local.x), local.z / self.height);
+            (normal, uv)
+        } else {
+            (-Vector::plus_z(), cap_uv(local, self.radius))
+        };
+
+        HitPoint::new(
+            point,
+            self.frame.to_world_vector(normal),
+            ray.direction,
+            uv,
+            self.material.clone(),
+        )
+    }
+}
+
+impl<F: Float, M: Material<F>> Traceable<F, Self, M> for Cone<F, M> {
+    fn trace(&self, ray: &Ray<F>) -> Option<(F, &Self)> {
+        let local = self.frame.to_local_ray(ray);
+        let (o, d) = (local.origin, local.direction);
+        let k2 = self.slope() * self.slope();
+        let below_apex = self.height - o.z;
+
+        let within = |t: F| {
+            let z = o.z + d.z * t;
+            z >= F::zero() && z <= self.height
+        };
+        let side = solve_quadratic(
+            d.x * d.x + d.y * d.y - k2 * d.z * d.z,
+            o.x * d.x + o.y * d.y + k2 * below_apex * d.z,
+            o.x * o.x + o.y * o.y - k2 * below_apex * below_apex,
+        );
+        let side = side
+            .map(|(t1, t2)| vec![t1, t2])
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|&t| within(t));
+        let base = cap(&local, F::zero(), self.radius);
+
+        closest(&local, side.chain(base)).map(|t| (t, self))
+    }
+
+    fn casts_shadows(&self) -> bool {
+        self.casts_shadows
+    }
+}
+
+impl<F: Float, M: Material<F>> Bounded<F, Aabb<F>> for Cone<F, M> {
+    fn bounding_volume(&self) -> Aabb<F> {
+        let apex = self.frame.origin + self.frame.z * self.height;
+        Aabb::from_disk(self.frame.origin, self.frame.z, self.radius)
+            .combine(&Aabb::from_points(&[apex]))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shading::DebugNormalMaterial;
+    use testing::close;
+
+    #[test]
+    fn test_capped_cylinder() {
+        // lying along x from 0 to 4
+        let cylinder = Cylinder::new(
+            Point::origin(),
+            Point::new(4.0, 0.0, 0.0),
+            1.0,
+            DebugNormalMaterial {},
+        );
+        let hit = |origin: Point<f64>, direction: Vector<f64>| {
+            let ray = Ray::new(origin, direction);
+            cylinder
+                .trace(&ray)
+                .map(|(t, h)| (t, h.get_hit(&ray, t).data))
+        };
+
+        let (t, data) = hit(Point::new(1.0, 5.0, 0.0), -Vector::plus_y()).unwrap();
+        assert!((t - 4.0).abs() < 1e-9);
+        assert!(close(data.normal, Vector::plus_y()));
+        assert!((data.uv.v - 0.25).abs() < 1e-9);
+
+        let (t, data) = hit(Point::new(6.0, 0.5, 0.0), -Vector::plus_x()).unwrap();
+        assert!((t - 2.0).abs() < 1e-9);
+        assert!(close(data.normal, Vector::plus_x()));
+        assert!((data.uv.v - 0.5).abs() < 1e-9);
+
+        assert!(hit(Point::new(4.5, 5.0, 0.0), -Vector::plus_y()).is_none());
+
+        let bounds: Aabb<f64> = cylinder.bounding_volume();
+        assert_eq!(
+            (bounds.x_min, bounds.x_max, bounds.y_min, bounds.z_max),
+            (0.0, 4.0, -1.0, 1.0)
+        );
+    }
+
+    #[test]
+    fn test_cone() {
+        let cone = Cone::new(
+            Point::origin(),
+            Point::new(0.0, 2.0, 0.0),
+            1.0,
+            DebugNormalMaterial {},
+        );
+        let hit = |origin: Point<f64>, direction: Vector<f64>| {
+            let ray = Ray::new(origin, direction);
+            cone.trace(&ray).map(|(t, h)| (t, h.get_hit(&ray, t).data))
+        };
+
+        // halfway up the radius is 0.5
+        let (t, data) = hit(Point::new(-3.0, 1.0, 0.0), Vector::plus_x()).unwrap();
+        assert!((t - 2.5).abs() < 1e-9);
+        assert!(close(data.normal, Vector::new(-2.0, 1.0, 0.0).normalized()));
+        assert!((data.uv.v - 0.5).abs() < 1e-9);
+
+        let (t, data) = hit(Point::new(0.5, -1.0, 0.0), Vector::plus_y()).unwrap();
+        assert!((t - 1.0).abs() < 1e-9);
+        assert!(close(data.normal, -Vector::plus_y()));
+
+        assert!(hit(Point::new(-3.0, 1.0, 0.6), Vector::plus_x()).is_none());
+        assert!(hit(Point::new(-3.0, 2.5, 0.0), Vector::plus_x()).is_none());
+    }
+}
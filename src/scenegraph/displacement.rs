@@ -0,0 +1,423 @@
+use math::{Float, Point, Vector, UV};
+use scenegraph::{smooth_normals, MeshData, MeshTriangle};
+use shading::{Material, ScalarTexture};
+use std::cmp::Ordering::Equal;
+use std::collections::HashMap;
+use std::mem::size_of;
+
+/// Vertex attributes and triangles of a tessellated mesh.
+pub(crate) type Tessellation<F> = (
+    Vec<Point<F>>,
+    Vec<Vector<F>>,
+    Option<Vec<UV<F>>>,
+    Vec<[usize; 3]>,
+);
+
+const DEFAULT_MEMORY_BUDGET: usize = 256 << 20;
+
+/// Displacement map for `TriangleMesh::with_displacement`. Every triangle is
+/// split into a grid of micro-triangles, and every vertex moved along its
+/// normal by the texture value at its UV times `scale`, which changes the
+/// silhouette as well as the shading. Meshes without UVs sample the texture
+/// at the origin everywhere.
+///
+/// The normal is averaged over all vertices at the same position, so
+/// vertices split at hard edges move together and the surface stays closed,
+/// as long as the texture agrees across UV seams.
+///
+/// The whole mesh is tessellated up front, when it is displaced. Every edge
+/// is split as often as it needs to be for no piece to be longer than
+/// `edge_length`, and both triangles along an edge use its vertices, so
+/// small triangles next to large ones stay coarse without cracks opening
+/// between them.
+///
+/// The memory budget limits the number of micro-triangles of the whole
+/// mesh, estimated with their vertex data, indices and BVH leaves. Meshes
+/// over it have all their edges split less by the same factor.
+#[derive(Clone)]
+pub struct Displacement<F: Float, T: ScalarTexture<F>> {
+    texture: T,
+    scale: F,
+    edge_length: F,
+    memory_budget: usize,
+}
+
+impl<F: Float, T: ScalarTexture<F>> Displacement<F, T> {
+    /// None unless `edge_length` is positive and finite.
+    pub fn new(texture: T, scale: F, edge_length: F) -> Option<Self> {
+        if !(edge_length > F::zero() && edge_length.is_finite()) {
+            return None;
+        }
+        Some(Displacement {
+            texture,
+            scale,
+            edge_length,
+            memory_budget: DEFAULT_MEMORY_BUDGET,
+        })
+    }
+
+    /// Largest number of bytes the tessellated mesh may take, 256 MiB by
+    /// default. The mesh is still displaced without being split if even that
+    /// does not fit.
+    pub fn with_memory_budget(mut self, bytes: usize) -> Self {
+        self.memory_budget = bytes;
+        self
+    }
+
+    /// Number of micro-triangles along every edge, keyed by the position
+    /// groups of its ends, so triangles split apart at the edge still agree.
+    fn edge_rates<M: Material<F>>(
+        &self,
+        mesh: &MeshData<F, M>,
+        groups: &[usize],
+    ) -> HashMap<(usize, usize), usize> {
+        let p = mesh.positions();
+        let mut wanted = HashMap::new();
+        for &[a, b, c] in mesh.indices() {
+            for &(u, v) in &[(a, b), (b, c), (c, a)] {
+                let key = (groups[u].min(groups[v]), groups[u].max(groups[v]));
+                wanted.insert(key, ((p[v] - p[u]).magnitude() / self.edge_length).ceil());
+            }
+        }
+
+        // a triangle gets a grid as fine as its finest edge, about the square
+        // of its rate in micro-triangles, with half a vertex for each
+        let per_triangle = size_of::<[usize; 3]>()
+            + size_of::<MeshTriangle<F, M>>()
+            + (size_of::<Point<F>>() + size_of::<Vector<F>>() + size_of::<UV<F>>()) / 2;
+        let affordable = F::from(self.memory_budget / per_triangle).unwrap();
+        let total = mesh
+            .indices()
+            .iter()
+            .map(|&[a, b, c]| {
+                let finest = [(a, b), (b, c), (c, a)]
+                    .iter()
+                    .map(|&(u, v)| wanted[&(groups[u].min(groups[v]), groups[u].max(groups[v]))])
+                    .fold(F::one(), F::max);
+                finest * finest
+            })
+            .fold(F::zero(), |sum, count| sum + count);
+        // over budget, every edge gets coarser by the same factor
+        let (scale, round): (F, fn(F) -> F) = if total > affordable {
+            ((affordable / total).sqrt(), F::floor)
+        } else {
+            (F::one(), F::ceil)
+        };
+
+        wanted
+            .into_iter()
+            .map(|(edge, rate)| {
+                let rate = round(rate * scale).to_usize().unwrap_or(usize::MAX);
+                (edge, rate.max(1))
+            })
+            .collect()
+    }
+
+    pub(crate) fn tessellate<M: Material<F>>(&self, mesh: &MeshData<F, M>) -> Tessellation<F> {
+        let base_normals = match mesh.normals() {
+            Some(normals) => normals.to_vec(),
+            None => smooth_normals(mesh.positions(), mesh.indices()),
+        };
+        let (groups, normals) = shared_normals(mesh.positions(), &base_normals);
+        let rates = self.edge_rates(mesh, &groups);
+        let rate =
+            |u: usize, v: usize| rates[&(groups[u].min(groups[v]), groups[u].max(groups[v]))];
+
+        let mut lookup = HashMap::new();
+        let mut positions = Vec::new();
+        let mut uvs = Vec::new();
+        let mut indices = Vec::new();
+        for &[t0, t1, t2] in mesh.indices() {
+            let sides = [rate(t0, t1), rate(t1, t2), rate(t2, t0)];
+            let n = sides.iter().cloned().fold(1, usize::max);
+            // a grid point `t` steps along a side of `n` snaps to the nearest
+            // point of the side's own rate, cells collapsing on the way are
+            // left out
+            let snap = |t: usize, side: usize| (2 * t * sides[side] + n) / (2 * n);
+
+            // vertex of the grid point `i` steps towards the second and `j`
+            // towards the third corner, as weights on the corners out of a
+            // total, shared by the same weights on the same corners. They are
+            // summed in the order of their positions, so split corners give
+            // the same result.
+            let mut vertex = |i: usize, j: usize| {
+                let corner = |v: usize, weight: usize| (groups[v], v, weight);
+                let (mut key, total) = if j == 0 {
+                    let k = snap(i, 0);
+                    (vec![corner(t0, sides[0] - k), corner(t1, k)], sides[0])
+                } else if i + j == n {
+                    let k = snap(j, 1);
+                    (vec![corner(t1, sides[1] - k), corner(t2, k)], sides[1])
+                } else if i == 0 {
+                    let k = snap(n - j, 2);
+                    (vec![corner(t2, sides[2] - k), corner(t0, k)], sides[2])
+                } else {
+                    let inside = vec![corner(t0, n - i - j), corner(t1, i), corner(t2, j)];
+                    (inside, n)
+                };
+                key.sort();
+                key.dedup_by(|later, first| {
+                    let same = later.1 == first.1;
+                    if same {
+                        first.2 += later.2;
+                    }
+                    same
+                });
+                key.retain(|&(_, _, weight)| weight > 0);
+                // corners are reached from sides of different rates
+                let key = if key.len() == 1 {
+                    (vec![(key[0].0, key[0].1, 1)], 1)
+                } else {
+                    (key, total)
+                };
+                *lookup.entry(key).or_insert_with_key(|(key, total)| {
+                    let total = F::from(*total).unwrap();
+                    let zero = Vector::new(F::zero(), F::zero(), F::zero());
+                    let (offset, normal, uv) = key.iter().fold(
+                        (zero, zero, UV::default()),
+                        |(offset, normal, uv), &(_, v, weight)| {
+                            let w = F::from(weight).unwrap() / total;
+                            let vertex_uv = mesh.uvs().map_or(UV::default(), |uvs| uvs[v]);
+                            (
+                                offset + (mesh.positions()[v] - Point::origin()) * w,
+                                normal + normals[v] * w,
+                                UV::new(uv.u + vertex_uv.u * w, uv.v + vertex_uv.v * w),
+                            )
+                        },
+                    );
+                    let height = self.texture.value(uv) * self.scale;
+                    let direction = if normal.magnitude_sq() > F::zero() {
+                        normal.normalized()
+                    } else {
+                        zero
+                    };
+                    positions.push(Point::origin() + offset + direction * height);
+                    uvs.push(uv);
+                    positions.len() - 1
+                })
+            };
+
+            let mut push = |[a, b, c]: [usize; 3]| {
+                if a != b && b != c && c != a {
+                    indices.push([a, b, c]);
+                }
+            };
+            for j in 0..n {
+                for i in 0..n - j {
+                    let (a, b, c) = (vertex(i, j), vertex(i + 1, j), vertex(i, j + 1));
+                    push([a, b, c]);
+                    if i + j + 1 < n {
+                        push([b, vertex(i + 1, j + 1), c]);
+                    }
+                }
+            }
+        }
+
+        let normals = smooth_normals(&positions, &indices);
+        let uvs = mesh.uvs().map(|_| uvs);
+        (positions, normals, uvs, indices)
+    }
+}
+
+/// Position group of every vertex, numbered by first appearance in sorted
+/// order, and the normal of every vertex summed over its group.
+fn shared_normals<F: Float>(
+    positions: &[Point<F>],
+    normals: &[Vector<F>],
+) -> (Vec<usize>, Vec<Vector<F>>) {
+    let mut order: Vec<usize> = (0..positions.len()).collect();
+    let p = positions;
+    order.sort_by(|&a, &b| {
+        (p[a].x, p[a].y, p[a].z)
+            .partial_cmp(&(p[b].x, p[b].y, p[b].z))
+            .unwrap_or(Equal)
+    });
+    let mut groups = vec![0; p.len()];
+    let mut sums = Vec::new();
+    for (i, &v) in order.iter().enumerate() {
+        if i == 0 || p[v] != p[order[i - 1]] {
+            sums.push(Vector::new(F::zero(), F::zero(), F::zero()));
+        }
+        groups[v] = sums.len() - 1;
+        sums[groups[v]] = sums[groups[v]] + normals[v];
+    }
+    let shared = groups.iter().map(|&g| sums[g]).collect();
+    (groups, shared)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use math::{Aabb, Bounded, Ray};
+    use scenegraph::{SahBuilder, TriangleMesh};
+    use shading::DebugNormalMaterial;
+    use tracing::Traceable;
+
+    /// Unit square in the xz plane facing up, with UVs along x and z.
+    fn square() -> TriangleMesh<f64, DebugNormalMaterial> {
+        let corners = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+        TriangleMesh::new(
+            corners
+                .iter()
+                .map(|&(x, z)| Point::new(x, 0.0, z))
+                .collect(),
+            Some(vec![Vector::plus_y(); 4]),
+            Some(corners.iter().map(|&(u, v)| UV::new(u, v)).collect()),
+            vec![[0, 3, 2], [0, 2, 1]],
+            DebugNormalMaterial {},
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_displaced_bounds_in_bvh() {
+        let bump = |uv: UV<f64>| {
+            (uv.u * ::std::f64::consts::PI).sin() * (uv.v * ::std::f64::consts::PI).sin()
+        };
+        let displacement = Displacement::new(bump, 0.5, 0.25).unwrap();
+        let mesh = square().with_displacement(&displacement);
+        // the diagonal is split six times and the sides four, which leaves
+        // out some of the six by six cells along the sides
+        assert_eq!(mesh.triangles().len(), 64);
+
+        let highest = mesh.triangles()[0]
+            .mesh()
+            .positions()
+            .iter()
+            .fold(0.0f64, |high, p| high.max(p.y));
+        assert!(highest > 0.45);
+        let bvh = SahBuilder::new().build(mesh.into_triangles()).unwrap();
+        let bound: Aabb<f64> = bvh.bounding_volume();
+        assert_eq!(bound.max_point().y, highest);
+        assert_eq!(bound.min_point().y, 0.0);
+
+        // the bump rises above the flat square in the middle
+        let ray = Ray::new(Point::new(0.45, 2.0, 0.55), -Vector::plus_y());
+        let (t, _) = bvh.trace(&ray).unwrap();
+        let height = 2.0 - t;
+        assert!(height > 0.44 && height < 0.49);
+    }
+
+    #[test]
+    fn test_hard_edges_stay_closed() {
+        // two faces at an angle, split along their shared edge
+        let up = Vector::plus_y();
+        let side = Vector::new(-1.0, -1.0, 1.0).normalized();
+        let mesh = TriangleMesh::new(
+            vec![
+                Point::new(0.0, 0.0, 0.0),
+                Point::new(1.0, 0.0, 1.0),
+                Point::new(1.0, 0.0, 0.0),
+                Point::new(0.0, 0.0, 0.0),
+                Point::new(1.0, 0.0, 1.0),
+                Point::new(0.0, 1.0, 1.0),
+            ],
+            Some(vec![up, up, up, side, side, side]),
+            None,
+            vec![[0, 1, 2], [3, 4, 5]],
+            DebugNormalMaterial {},
+        )
+        .unwrap();
+        let flat = |_: UV<f64>| 1.0;
+        let displaced = mesh.with_displacement(&Displacement::new(flat, 0.1, 0.25).unwrap());
+
+        // the points of the shared edge once, six steps along it
+        let positions = displaced.triangles()[0].mesh().positions();
+        let mut distinct: Vec<_> = positions
+            .iter()
+            .map(|p| (p.x.to_bits(), p.y.to_bits(), p.z.to_bits()))
+            .collect();
+        distinct.sort();
+        distinct.dedup();
+        assert_eq!(distinct.len(), positions.len() - 7);
+    }
+
+    #[test]
+    fn test_edge_rates_follow_triangle_sizes() {
+        // a long sliver next to a small triangle, sharing its short edge
+        let mesh = TriangleMesh::new(
+            vec![
+                Point::new(0.0, 0.0, 0.0),
+                Point::new(4.0, 0.0, 0.0),
+                Point::new(4.0, 0.0, 0.25),
+                Point::new(4.25, 0.0, 0.0),
+            ],
+            None,
+            None,
+            vec![[0, 1, 2], [1, 3, 2]],
+            DebugNormalMaterial {},
+        )
+        .unwrap();
+        let flat = |_: UV<f64>| 1.0;
+        let displaced = mesh.with_displacement(&Displacement::new(flat, 0.0, 0.25).unwrap());
+        let triangles: Vec<[Point<f64>; 3]> = displaced
+            .triangles()
+            .iter()
+            .map(|triangle| {
+                let v = triangle.vertices();
+                [v[0], v[1], v[2]]
+            })
+            .collect();
+
+        // the small triangle is not split as finely as the sliver
+        let small = triangles.iter().filter(|v| v[0].x + v[1].x + v[2].x > 12.0);
+        assert!(small.count() <= 4);
+
+        // the micro-triangles cover both without overlapping
+        let area = triangles.iter().fold(0.0, |sum, v| {
+            let normal = (v[1] - v[0]).cross(v[2] - v[0]);
+            assert!(normal.y < 0.0);
+            sum + normal.magnitude() / 2.0
+        });
+        assert!((area - (0.5 + 0.03125)).abs() < 1e-9);
+
+        // and only the outline has edges without a twin
+        let edges: Vec<(Point<f64>, Point<f64>)> = triangles
+            .iter()
+            .flat_map(|v| vec![(v[0], v[1]), (v[1], v[2]), (v[2], v[0])])
+            .collect();
+        let outline = edges
+            .iter()
+            .filter(|&&(a, b)| !edges.contains(&(b, a)))
+            .fold(0.0, |sum, &(a, b)| sum + (b - a).magnitude());
+        let expected = 4.0 + 0.25 + 0.125f64.sqrt() + 4.0f64.hypot(0.25);
+        assert!((outline - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_edge_length_must_be_positive() {
+        let flat = |_: UV<f64>| 1.0;
+        assert!(Displacement::new(flat, 0.1, 0.0).is_none());
+        assert!(Displacement::new(flat, 0.1, -1.0).is_none());
+        assert!(Displacement::new(flat, 0.1, f64::NAN).is_none());
+    }
+
+    #[test]
+    fn test_memory_budget_limits_rate() {
+        let flat = |_: UV<f64>| 1.0;
+        let displacement = Displacement::new(flat, 0.25, 0.01).unwrap();
+        let full = square().with_displacement(&displacement);
+        let full_count = full.triangles().len();
+        assert!(full_count > 2 * 100 * 100 && full_count <= 2 * 142 * 142);
+
+        let budget = 100_000;
+        let limited = square().with_displacement(&displacement.clone().with_memory_budget(budget));
+        let count = limited.triangles().len();
+        assert!(count > 100 && count < full_count);
+        let mesh = limited.triangles()[0].mesh();
+        assert!(mesh.positions().iter().all(|p| p.y == 0.25));
+        assert!(mesh
+            .normals()
+            .unwrap()
+            .iter()
+            .all(|&n| (n - Vector::plus_y()).magnitude() < 1e-12));
+
+        // without room to split, the triangles are only moved
+        let moved = square().with_displacement(&displacement.with_memory_budget(0));
+        assert_eq!(moved.triangles().len(), 2);
+        assert_eq!(
+            moved.triangles()[1].vertices()[2],
+            Point::new(1.0, 0.25, 0.0)
+        );
+    }
+}
@@ -0,0 +1,356 @@
+use math::{Aabb, Bounded, Float, Point, Ray, Vector, UV};
+use scenegraph::intersect_triangle;
+use shading::Material;
+use tracing::{HitPoint, Hitable, Traceable};
+
+/// Terrain over a regular grid of elevation samples, without building any
+/// triangles. The grid spans `size.x` along x and `size.z` along z from
+/// `origin`, and a sample `s` lies at the height `origin.y + s * size.y`.
+///
+/// Each cell is split into two triangles, which the ray is only tested
+/// against in the cells it crosses in a 2D DDA, and only if it passes within
+/// the height range of the cell. Normals are interpolated from central
+/// differences of the samples, `u` runs from 0 to 1 along x and `v` along z.
+#[derive(Clone)]
+pub struct Heightfield<F: Float, M: Material<F>> {
+    origin: Point<F>,
+    size: Vector<F>,
+    columns: usize,
+    rows: usize,
+    samples: Vec<F>,
+    bounds: Aabb<F>,
+    material: M,
+    casts_shadows: bool,
+}
+
+impl<F: Float, M: Material<F>> Heightfield<F, M> {
+    /// Grid of `columns` samples along x by `rows` along z, stored row after
+    /// row. None if there are not exactly that many samples, or fewer than
+    /// two in either direction.
+    pub fn new(
+        origin: Point<F>,
+        size: Vector<F>,
+        columns: usize,
+        rows: usize,
+        samples: Vec<F>,
+        material: M,
+    ) -> Option<Self> {
+        if columns < 2 || rows < 2 || samples.len() != columns * rows {
+            return None;
+        }
+        let (low, high) = samples
+            .iter()
+            .fold((F::infinity(), F::neg_infinity()), |(low, high), &s| {
+                (F::min(low, s), F::max(high, s))
+            });
+        let corner = |s: F| origin + Vector::new(F::zero(), s * size.y, F::zero());
+        let bounds = Aabb::from_points(&[
+            corner(low),
+            corner(high) + Vector::new(size.x, F::zero(), size.z),
+        ]);
+        Some(Heightfield {
+            origin,
+            size,
+            columns,
+            rows,
+            samples,
+            bounds,
+            material,
+            casts_shadows: true,
+        })
+    }
+
+    pub fn with_casts_shadows(mut self, casts_shadows: bool) -> Self {
+        self.casts_shadows = casts_shadows;
+        self
+    }
+
+    pub fn columns(&self) -> usize {
+        self.columns
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    fn cell_size(&self) -> (F, F) {
+        (
+            self.size.x / F::from(self.columns - 1).unwrap(),
+            self.size.z / F::from(self.rows - 1).unwrap(),
+        )
+    }
+
+    fn height(&self, column: usize, row: usize) -> F {
+        self.origin.y + self.samples[row * self.columns + column] * self.size.y
+    }
+
+    fn vertex(&self, column: usize, row: usize) -> Point<F> {
+        let (dx, dz) = self.cell_size();
+        Point::new(
+            self.origin.x + dx * F::from(column).unwrap(),
+            self.height(column, row),
+            self.origin.z + dz * F::from(row).unwrap(),
+        )
+    }
+
+    /// Normal at a sample from the slopes towards its neighbours, one sided
+    /// at the edges of the grid.
+    fn vertex_normal(&self, column: usize, row: usize) -> Vector<F> {
+        let (dx, dz) = self.cell_size();
+        let (left, right) = (column.saturating_sub(1), (column + 1).min(self.columns - 1));
+        let (back, front) = (row.saturating_sub(1), (row + 1).min(self.rows - 1));
+        let slope_x = (self.height(right, row) - self.height(left, row))
+            / (dx * F::from(right - left).unwrap());
+        let slope_z = (self.height(column, front) - self.height(column, back))
+            / (dz * F::from(front - back).unwrap());
+        Vector::new(-slope_x, F::one(), -slope_z).normalized()
+    }
+
+    /// Cell containing the coordinate, clamped to the grid, and the position
+    /// within it from 0 to 1.
+    fn locate(&self, x: F, z: F) -> (usize, usize, F, F) {
+        let (dx, dz) = self.cell_size();
+        let cell = |offset: F, size: F, cells: usize| {
+            let position = offset / size;
+            let index = position
+                .floor()
+                .max(F::zero())
+                .to_usize()
+                .unwrap_or(0)
+                .min(cells - 1);
+            (index, position - F::from(index).unwrap())
+        };
+        let (column, fx) = cell(x - self.origin.x, dx, self.columns - 1);
+        let (row, fz) = cell(z - self.origin.z, dz, self.rows - 1);
+        (column, row, fx, fz)
+    }
+
+    /// Closest hit on the two triangles of a cell.
+    fn intersect_cell(&self, ray: &Ray<F>, column: usize, row: usize) -> Option<F> {
+        let p00 = self.vertex(column, row);
+        let p10 = self.vertex(column + 1, row);
+        let p01 = self.vertex(column, row + 1);
+        let p11 = self.vertex(column + 1, row + 1);
+        let first = intersect_triangle(ray, p00, p10, p11).map(|(t, _)| t);
+        let second = intersect_triangle(ray, p00, p11, p01).map(|(t, _)| t);
+        match (first, second) {
+            (Some(a), Some(b)) => Some(F::min(a, b)),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+impl<F: Float, M: Material<F>> Hitable<F> for Heightfield<F, M> {
+    type Material = M;
+    fn get_hit(&self, ray: &Ray<F>, distance: F) -> HitPoint<F, M> {
+        let point = ray.point_at_distance(distance);
+        let (column, row, fx, fz) = self.locate(point.x, point.z);
+        let blend = |a: Vector<F>, b: Vector<F>, f: F| a * (F::one() - f) + b * f;
+        let back = blend(
+            self.vertex_normal(column, row),
+            self.vertex_normal(column + 1, row),
+            fx,
+        );
+        let front = blend(
+            self.vertex_normal(column, row + 1),
+            self.vertex_normal(column + 1, row + 1),
+            fx,
+        );
+        let uv = UV::new(
+            (point.x - self.origin.x) / self.size.x,
+            (point.z - self.origin.z) / self.size.z,
+        );
+
+        HitPoint::new(
+            point,
+            blend(back, front, fz).normalized(),
+            ray.direction,
+            uv,
+            self.material.clone(),
+        )
+    }
+}
+
+impl<F: Float, M: Material<F>> Traceable<F, Self, M> for Heightfield<F, M> {
+    fn trace(&self, ray: &Ray<F>) -> Option<(F, &Self)> {
+        let (near, far) = self.bounds.intersect(ray)?;
+        let mut t = F::max(near, ray.t_min);
+        let end = F::min(far, ray.t_max);
+        if t > end {
+            return None;
+        }
+
+        let entry = ray.point_at_distance(t);
+        let (mut column, mut row, _, _) = self.locate(entry.x, entry.z);
+        let (dx, dz) = self.cell_size();
+
+        // distance along the ray to the next cell border on either axis and
+        // between two borders
+        let setup = |origin: F, direction: F, index: usize, size: F, start: F| {
+            if direction == F::zero() {
+                return (F::infinity(), F::infinity());
+            }
+            let next = if direction > F::zero() {
+                index + 1
+            } else {
+                index
+            };
+            let border = start + size * F::from(next).unwrap();
+            ((border - origin) / direction, size / direction.abs())
+        };
+        let (mut next_x, delta_x) = setup(ray.origin.x, ray.direction.x, column, dx, self.origin.x);
+        let (mut next_z, delta_z) = setup(ray.origin.z, ray.direction.z, row, dz, self.origin.z);
+
+        loop {
+            let exit = F::min(F::min(next_x, next_z), end);
+
+            // skip cells the ray passes entirely above or below
+            let (y0, y1) = (ray.point_at_distance(t).y, ray.point_at_distance(exit).y);
+            let corners = [
+                self.height(column, row),
+                self.height(column + 1, row),
+                self.height(column, row + 1),
+                self.height(column + 1, row + 1),
+            ];
+            let low = corners.iter().fold(F::infinity(), |m, &h| F::min(m, h));
+            let high = corners.iter().fold(F::neg_infinity(), |m, &h| F::max(m, h));
+            let slack = (high - low + F::one()) * F::epsilon() * F::from(64).unwrap();
+            if F::max(y0, y1) >= low - slack && F::min(y0, y1) <= high + slack {
+                if let Some(hit) = self.intersect_cell(ray, column, row) {
+                    return Some((hit, self));
+                }
+            }
+
+            if exit >= end {
+                return None;
+            }
+            t = exit;
+            if next_x < next_z {
+                next_x = next_x + delta_x;
+                if ray.direction.x > F::zero() {
+                    column += 1;
+                    if column == self.columns - 1 {
+                        return None;
+                    }
+                } else if column == 0 {
+                    return None;
+                } else {
+                    column -= 1;
+                }
+            } else {
+                next_z = next_z + delta_z;
+                if ray.direction.z > F::zero() {
+                    row += 1;
+                    if row == self.rows - 1 {
+                        return None;
+                    }
+                } else if row == 0 {
+                    return None;
+                } else {
+                    row -= 1;
+                }
+            }
+        }
+    }
+
+    fn casts_shadows(&self) -> bool {
+        self.casts_shadows
+    }
+}
+
+impl<F: Float, M: Material<F>> Bounded<F, Aabb<F>> for Heightfield<F, M> {
+    fn bounding_volume(&self) -> Aabb<F> {
+        self.bounds
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shading::DebugNormalMaterial;
+    use testing::lcg;
+
+    /// Rolling hills over a 9 by 7 grid covering 0..8 by 0..6.
+    fn hills() -> Heightfield<f64, DebugNormalMaterial> {
+        let (columns, rows) = (9, 7);
+        let samples = (0..columns * rows)
+            .map(|i| {
+                let (x, z) = ((i % columns) as f64, (i / columns) as f64);
+                (x * 0.8).sin() * (z * 0.6).cos()
+            })
+            .collect();
+        Heightfield::new(
+            Point::origin(),
+            Vector::new(8.0, 0.5, 6.0),
+            columns,
+            rows,
+            samples,
+            DebugNormalMaterial {},
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_flat_terrain() {
+        let flat = Heightfield::new(
+            Point::new(-2.0, 1.0, -2.0),
+            Vector::new(4.0, 3.0, 4.0),
+            3,
+            3,
+            vec![0.0; 9],
+            DebugNormalMaterial {},
+        )
+        .unwrap();
+        let ray = Ray::new(Point::new(1.0, 5.0, 0.0), -Vector::plus_y());
+        let (t, hitable) = flat.trace(&ray).unwrap();
+        assert_eq!(t, 4.0);
+        let data = hitable.get_hit(&ray, t).data;
+        assert_eq!(data.normal, Vector::plus_y());
+        assert_eq!(data.uv, UV::new(0.75, 0.5));
+
+        // grazing along the ground and missing the grid
+        let along = Ray::new(Point::new(-5.0, 1.5, 0.0), Vector::new(1.0, -0.1, 0.3));
+        assert!(flat.trace(&along).is_some());
+        let beside = Ray::new(Point::new(-5.0, 2.0, 3.0), Vector::new(1.0, -0.1, 0.0));
+        assert!(flat.trace(&beside).is_none());
+
+        assert!(Heightfield::new(
+            Point::origin(),
+            Vector::plus_x(),
+            3,
+            3,
+            vec![0.0; 8],
+            DebugNormalMaterial {}
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn test_traversal_matches_all_cells() {
+        let terrain = hills();
+        let mut next = lcg(12345);
+
+        for _ in 0..500 {
+            let origin = Point::new(next() * 12.0 - 2.0, next() * 2.0 + 0.2, next() * 10.0 - 2.0);
+            let target = Point::new(next() * 8.0, next() - 0.5, next() * 6.0);
+            let ray = Ray::new(origin, target - origin);
+
+            let mut expected = None;
+            for row in 0..terrain.rows() - 1 {
+                for column in 0..terrain.columns() - 1 {
+                    if let Some(t) = terrain.intersect_cell(&ray, column, row) {
+                        expected = Some(expected.map_or(t, |e: f64| e.min(t)));
+                    }
+                }
+            }
+            let actual = terrain.trace(&ray).map(|(t, _)| t);
+            assert_eq!(actual, expected);
+
+            if let Some(t) = actual {
+                let data = terrain.get_hit(&ray, t).data;
+                assert!((data.normal.magnitude() - 1.0).abs() < 1e-9);
+                assert!(data.normal.y > 0.0);
+            }
+        }
+    }
+}
@@ -0,0 +1,235 @@
+use math::{Aabb, AnimatedTransform, Bounded, Float, Ray, Transform};
+use shading::Material;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use tracing::{HitPoint, Hitable, Traceable};
+
+/// Shared object placed in the world by a transform. Rays are moved into
+/// object space instead of moving the geometry, so one mesh or `Bvh` can be
+/// referenced by any number of instances. A moving instance follows an
+/// `AnimatedTransform` to the time of each ray.
+///
+/// Since `trace` can only hand out references into the instance, the
+/// instance is its own `Hitable` and traces the object once more in
+/// `get_hit` to shade the final hit.
+#[derive(Debug)]
+pub struct Instance<F, T, H, M>
+where
+    F: Float,
+    T: Traceable<F, H, M>,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+{
+    object: Arc<T>,
+    transform: Transform<F>,
+    motion: Option<AnimatedTransform<F>>,
+    phantom_h: PhantomData<H>,
+    phantom_m: PhantomData<M>,
+}
+
+impl<F, T, H, M> Clone for Instance<F, T, H, M>
+where
+    F: Float,
+    T: Traceable<F, H, M>,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+{
+    fn clone(&self) -> Self {
+        Instance {
+            object: self.object.clone(),
+            transform: self.transform,
+            motion: self.motion.clone(),
+            phantom_h: PhantomData,
+            phantom_m: PhantomData,
+        }
+    }
+}
+
+impl<F, T, H, M> Instance<F, T, H, M>
+where
+    F: Float,
+    T: Traceable<F, H, M>,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+{
+    /// `transform` maps object space to world space.
+    pub fn new(object: Arc<T>, transform: Transform<F>) -> Self {
+        Instance {
+            object,
+            transform,
+            motion: None,
+            phantom_h: PhantomData,
+            phantom_m: PhantomData,
+        }
+    }
+
+    /// Instance moving along `motion`, from object space to world space.
+    pub fn moving(object: Arc<T>, motion: AnimatedTransform<F>) -> Self {
+        Instance {
+            object,
+            transform: motion.at(motion.times()[0]),
+            motion: Some(motion),
+            phantom_h: PhantomData,
+            phantom_m: PhantomData,
+        }
+    }
+
+    pub fn object(&self) -> &Arc<T> {
+        &self.object
+    }
+
+    /// The transform at the first keyframe for moving instances.
+    pub fn transform(&self) -> &Transform<F> {
+        &self.transform
+    }
+
+    pub fn motion(&self) -> Option<&AnimatedTransform<F>> {
+        self.motion.as_ref()
+    }
+
+    /// Places the instance, a moving one stops moving.
+    pub fn set_transform(&mut self, transform: Transform<F>) {
+        self.transform = transform;
+        self.motion = None;
+    }
+
+    pub fn transform_at(&self, time: F) -> Transform<F> {
+        match self.motion {
+            Some(ref motion) => motion.at(time),
+            None => self.transform,
+        }
+    }
+
+    fn object_ray(&self, ray: &Ray<F>) -> Ray<F> {
+        self.transform_at(ray.time).inverse().apply_ray(ray)
+    }
+}
+
+impl<F, T, H, M> Hitable<F> for Instance<F, T, H, M>
+where
+    F: Float,
+    T: Traceable<F, H, M>,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+{
+    type Material = M;
+    fn get_hit(&self, ray: &Ray<F>, distance: F) -> HitPoint<F, M> {
+        let object_ray = self.object_ray(ray);
+        let (_, hitable) = self
+            .object
+            .trace(&object_ray)
+            .expect("instance hit vanished when tracing in object space");
+        let mut hit = hitable.get_hit(&object_ray, distance);
+
+        hit.data.point = ray.point_at_distance(distance);
+        hit.data.normal = self
+            .transform_at(ray.time)
+            .apply_normal(hit.data.normal)
+            .normalized();
+        hit.data.incoming_dir = ray.direction;
+        hit
+    }
+}
+
+impl<F, T, H, M> Traceable<F, Self, M> for Instance<F, T, H, M>
+where
+    F: Float,
+    T: Traceable<F, H, M>,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+{
+    fn trace(&self, ray: &Ray<F>) -> Option<(F, &Self)> {
+        // the object space direction is not renormalized, so distances agree
+        self.object
+            .trace(&self.object_ray(ray))
+            .map(|(distance, _)| (distance, self))
+    }
+
+    fn casts_shadows(&self) -> bool {
+        self.object.casts_shadows()
+    }
+
+    fn occluded(&self, ray: &Ray<F>, t_max: F) -> bool {
+        self.object.occluded(&self.object_ray(ray), t_max)
+    }
+}
+
+impl<F, T, H, M> Bounded<F, Aabb<F>> for Instance<F, T, H, M>
+where
+    F: Float,
+    T: Traceable<F, H, M> + Bounded<F, Aabb<F>>,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+{
+    fn bounding_volume(&self) -> Aabb<F> {
+        let object = self.object.bounding_volume();
+        match self.motion {
+            Some(ref motion) => motion.motion_bounds(&object),
+            None => self.transform.apply_aabb(&object),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use math::{Point, Vector};
+    use scenegraph::ShadedSphere;
+    use shading::DebugNormalMaterial;
+
+    #[test]
+    fn test_scaled_sphere_instance() {
+        let sphere: Arc<ShadedSphere<f64, _>> = Arc::new(ShadedSphere::new(
+            Point::origin(),
+            1.0,
+            DebugNormalMaterial {},
+        ));
+        let transform = Transform::scaling(2.0, 1.0, 1.0)
+            .unwrap()
+            .then(&Transform::translation(Vector::new(0.0, 0.0, 5.0)));
+        let instance = Instance::new(sphere, transform);
+
+        let ray = Ray::new(Point::origin(), Vector::plus_z());
+        let (distance, hit) = instance.trace(&ray).unwrap();
+        assert!((distance - 4.0).abs() < 1e-9);
+
+        // x axis hit of the stretched sphere, the normal must stay along x
+        let ray = Ray::new(Point::new(-10.0, 0.0, 5.0), Vector::plus_x());
+        let (distance, hit_x) = instance.trace(&ray).unwrap();
+        assert!((distance - 8.0).abs() < 1e-9);
+        let normal = hit_x.get_hit(&ray, distance).data.normal;
+        assert!((normal - -Vector::plus_x()).magnitude() < 1e-9);
+
+        let bound = hit.bounding_volume();
+        assert_eq!((bound.x_min, bound.x_max), (-2.0, 2.0));
+        assert_eq!((bound.z_min, bound.z_max), (4.0, 6.0));
+        assert!(instance.occluded(&ray, 9.0));
+        assert!(!instance.occluded(&ray, 7.0));
+    }
+
+    #[test]
+    fn test_moving_instance() {
+        let sphere: Arc<ShadedSphere<f64, _>> = Arc::new(ShadedSphere::new(
+            Point::origin(),
+            1.0,
+            DebugNormalMaterial {},
+        ));
+        let motion = AnimatedTransform::new(vec![
+            (0.0, Transform::identity()),
+            (1.0, Transform::translation(Vector::new(4.0, 0.0, 0.0))),
+        ])
+        .unwrap();
+        let instance = Instance::moving(sphere, motion);
+
+        // the sphere passes in front of the ray half way through the shutter
+        let ray = Ray::new(Point::new(2.0, 0.0, -5.0), Vector::plus_z());
+        assert!(instance.trace(&ray).is_none());
+        let (distance, hit) = instance.trace(&ray.clone().with_time(0.5)).unwrap();
+        assert!((distance - 4.0).abs() < 1e-9);
+        let normal = hit.get_hit(&ray.with_time(0.5), distance).data.normal;
+        assert!((normal - -Vector::plus_z()).magnitude() < 1e-9);
+
+        let bound = instance.bounding_volume();
+        assert_eq!((bound.x_min, bound.x_max), (-1.0, 5.0));
+    }
+}
@@ -0,0 +1,494 @@
+use fibers::sync::oneshot::MonitorError;
+use fibers::Spawn;
+use futures::{finished, lazy, Future};
+use math::{Aabb, Bounded, BoundingVolume, Float, Point};
+use scenegraph::{Bvh, BvhLeaf, BvhNode, TreeletOptimizer};
+use shading::Material;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use tracing::Hitable;
+
+/// Bits per Morton code, trading memory and precision for sort speed. 30 bit
+/// codes quantize each axis to 1024 cells, which is too coarse for very
+/// large or very unevenly spread scenes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MortonBits {
+    Bits30,
+    Bits63,
+}
+
+impl MortonBits {
+    fn bits_per_axis(self) -> u32 {
+        match self {
+            MortonBits::Bits30 => 10,
+            MortonBits::Bits63 => 21,
+        }
+    }
+}
+
+/// Spreads the lowest 21 bits of `v` out to every third bit.
+fn spread_bits(v: u64) -> u64 {
+    let mut x = v & 0x1f_ffff;
+    x = (x | x << 32) & 0x001f_0000_0000_ffff;
+    x = (x | x << 16) & 0x001f_0000_ff00_00ff;
+    x = (x | x << 8) & 0x100f_00f0_0f00_f00f;
+    x = (x | x << 4) & 0x10c3_0c30_c30c_30c3;
+    x = (x | x << 2) & 0x1249_2492_4924_9249;
+    x
+}
+
+/// Interleaves the bits of three cell coordinates, x being the lowest.
+pub fn morton_code(x: u32, y: u32, z: u32) -> u64 {
+    spread_bits(u64::from(x)) | spread_bits(u64::from(y)) << 1 | spread_bits(u64::from(z)) << 2
+}
+
+struct MortonItem<F: Float, L> {
+    leaf: L,
+    bound: Aabb<F>,
+    code: u64,
+}
+
+/// Sorted run of items for the k-way merge, ordered by its smallest code.
+struct Run<F: Float, L> {
+    head: MortonItem<F, L>,
+    rest: ::std::vec::IntoIter<MortonItem<F, L>>,
+}
+
+impl<F: Float, L> PartialEq for Run<F, L> {
+    fn eq(&self, other: &Self) -> bool {
+        self.head.code == other.head.code
+    }
+}
+
+impl<F: Float, L> Eq for Run<F, L> {}
+
+impl<F: Float, L> PartialOrd for Run<F, L> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<F: Float, L> Ord for Run<F, L> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // reversed, `BinaryHeap` is a max-heap
+        other.head.code.cmp(&self.head.code)
+    }
+}
+
+type BuildFuture<T> = Box<dyn Future<Item = T, Error = ()> + Send>;
+
+/// Parallel linear BVH builder after Lauterbach et al. and Karras.
+///
+/// Primitives are sorted along a Morton curve through their centroids and
+/// the tree is formed by splitting at the highest differing bit of the
+/// sorted codes. Bounds, codes and sorting of chunks, as well as all large
+/// subtrees, run as fibers on the given executor. The result is a regular
+/// `Bvh`, much faster to build than with `SahBuilder` but slower to trace,
+/// which `TreeletOptimizer` can largely make up for.
+#[derive(Debug, Clone, Copy)]
+pub struct LbvhBuilder<F: Float> {
+    bits: MortonBits,
+    max_leaf_size: usize,
+    grain_size: usize,
+    treelets: Option<TreeletOptimizer<F>>,
+}
+
+impl<F: Float> Default for LbvhBuilder<F> {
+    fn default() -> Self {
+        LbvhBuilder {
+            bits: MortonBits::Bits63,
+            max_leaf_size: 4,
+            grain_size: 16 * 1024,
+            treelets: None,
+        }
+    }
+}
+
+impl<F: Float + 'static> LbvhBuilder<F> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_morton_bits(mut self, bits: MortonBits) -> Self {
+        self.bits = bits;
+        self
+    }
+
+    /// Ranges of at most this many primitives become one node.
+    pub fn with_max_leaf_size(mut self, max_leaf_size: usize) -> Self {
+        self.max_leaf_size = max_leaf_size.max(1);
+        self
+    }
+
+    /// Number of primitives below which work is not split into more fibers.
+    pub fn with_grain_size(mut self, grain_size: usize) -> Self {
+        self.grain_size = grain_size.max(1);
+        self
+    }
+
+    /// Restructures every node with `optimizer` as soon as its subtrees
+    /// are built, so the optimization runs in parallel as well.
+    pub fn with_treelet_optimizer(mut self, optimizer: TreeletOptimizer<F>) -> Self {
+        self.treelets = Some(optimizer);
+        self
+    }
+
+    /// Resolves to `None` if there are no leaves, or leaves that are not
+    /// bounded, such as `Shape::Plane`.
+    #[allow(clippy::type_complexity)]
+    pub fn build<B, H, M, L, S>(
+        &self,
+        leaves: Vec<L>,
+        handle: S,
+    ) -> BuildFuture<Option<Bvh<F, B, H, M, L>>>
+    where
+        B: BoundingVolume<F> + Send + 'static,
+        H: Hitable<F, Material = M> + Send + 'static,
+        M: Material<F> + Send + 'static,
+        L: BvhLeaf<F, B, H, M> + Bounded<F, Aabb<F>> + Send + 'static,
+        S: Spawn + Clone + Send + 'static,
+    {
+        if leaves.is_empty() {
+            return Box::new(finished(None));
+        }
+
+        let builder = *self;
+        let chunks = self.chunks(leaves);
+
+        // bounds per chunk, then the centroid bound of the whole scene
+        let bound_handle = handle.clone();
+        let bounded = ::futures::future::join_all(chunks.into_iter().map(move |chunk| {
+            spawn(&bound_handle, move || {
+                let items: Vec<_> = chunk
+                    .into_iter()
+                    .map(|leaf| {
+                        let bound: Aabb<F> = leaf.bounding_volume();
+                        MortonItem {
+                            leaf,
+                            bound,
+                            code: 0,
+                        }
+                    })
+                    .collect();
+                let centroids = items.iter().fold(Aabb::empty(), |acc, item| {
+                    acc.combine(&Aabb::from_points(&[item.bound.centroid()]))
+                });
+                let bounded = items.iter().all(|item| item.bound.is_bounded());
+                (items, centroids, bounded)
+            })
+        }));
+
+        let sort_handle = handle.clone();
+        let sorted = bounded.and_then(move |chunks| {
+            let centroids = chunks
+                .iter()
+                .fold(Aabb::empty(), |acc, chunk| acc.combine(&chunk.1));
+            let bounded = chunks.iter().all(|chunk| chunk.2);
+            let runs = chunks.into_iter().map(move |(mut items, _, _)| {
+                spawn(&sort_handle, move || {
+                    for item in &mut items {
+                        item.code = builder.code(&centroids, item.bound.centroid());
+                    }
+                    items.sort_unstable_by_key(|item| item.code);
+                    items
+                })
+            });
+            ::futures::future::join_all(runs).map(move |runs| (runs, bounded))
+        });
+
+        let tree_handle = handle.clone();
+        let tree = sorted.and_then(move |(runs, bounded)| -> BuildFuture<_> {
+            if !bounded {
+                return Box::new(finished(None));
+            }
+            let (codes, leaves) = merge_runs(runs)
+                .into_iter()
+                .map(|item| (item.code, item.leaf))
+                .unzip();
+            Box::new(builder.build_range(codes, leaves, tree_handle).map(Some))
+        });
+
+        Box::new(tree)
+    }
+
+    fn chunks<L>(&self, mut leaves: Vec<L>) -> Vec<Vec<L>> {
+        // split from the back, so every leaf is copied only once
+        let mut chunks = Vec::new();
+        while leaves.len() > self.grain_size {
+            let at = (leaves.len() - 1) / self.grain_size * self.grain_size;
+            chunks.push(leaves.split_off(at));
+        }
+        chunks.push(leaves);
+        chunks.reverse();
+        chunks
+    }
+
+    fn code(&self, centroids: &Aabb<F>, centroid: Point<F>) -> u64 {
+        let cells = (1u32 << self.bits.bits_per_axis()) - 1;
+        let cells_f = F::from(cells).unwrap();
+        let min = centroids.min_point();
+        let max = centroids.max_point();
+
+        let mut cell = [0u32; 3];
+        for (axis, c) in cell.iter_mut().enumerate() {
+            let extent = max[axis] - min[axis];
+            if extent > F::zero() {
+                let t = (centroid[axis] - min[axis]) / extent;
+                *c = (t * cells_f).to_u32().unwrap_or(0).min(cells);
+            }
+        }
+        morton_code(cell[0], cell[1], cell[2])
+    }
+
+    /// Builds the subtree for `items`, sorted by code, spawning fibers for
+    /// both halves while they are larger than the grain size.
+    fn build_range<B, H, M, L, S>(
+        self,
+        mut codes: Vec<u64>,
+        mut leaves: Vec<L>,
+        handle: S,
+    ) -> BuildFuture<Bvh<F, B, H, M, L>>
+    where
+        B: BoundingVolume<F> + Send + 'static,
+        H: Hitable<F, Material = M> + Send + 'static,
+        M: Material<F> + Send + 'static,
+        L: BvhLeaf<F, B, H, M> + Send + 'static,
+        S: Spawn + Clone + Send + 'static,
+    {
+        if codes.len() <= self.grain_size {
+            return spawn(&handle, move || {
+                self.build_node(&codes, &mut leaves.into_iter())
+            });
+        }
+
+        let split = split_index(&codes);
+        let right_codes = codes.split_off(split);
+        let right_leaves = leaves.split_off(split);
+        let left_future = self.build_range(codes, leaves, handle.clone());
+        let right_future = self.build_range(right_codes, right_leaves, handle);
+
+        Box::new(left_future.join(right_future).map(move |(left, right)| {
+            self.finish_node(vec![BvhNode::Node(left), BvhNode::Node(right)])
+        }))
+    }
+
+    /// Builds the node for `codes`, taking their leaves from `leaves` in
+    /// order, so the leaves are moved only once.
+    fn build_node<B, H, M, L, I>(&self, codes: &[u64], leaves: &mut I) -> Bvh<F, B, H, M, L>
+    where
+        B: BoundingVolume<F>,
+        H: Hitable<F, Material = M>,
+        M: Material<F>,
+        L: BvhLeaf<F, B, H, M>,
+        I: Iterator<Item = L>,
+    {
+        let children = if codes.len() <= self.max_leaf_size {
+            leaves.take(codes.len()).map(BvhNode::Leaf).collect()
+        } else {
+            let (left, right) = codes.split_at(split_index(codes));
+            vec![
+                self.build_child(left, leaves),
+                self.build_child(right, leaves),
+            ]
+        };
+        self.finish_node(children)
+    }
+
+    fn build_child<B, H, M, L, I>(&self, codes: &[u64], leaves: &mut I) -> BvhNode<F, B, H, M, L>
+    where
+        B: BoundingVolume<F>,
+        H: Hitable<F, Material = M>,
+        M: Material<F>,
+        L: BvhLeaf<F, B, H, M>,
+        I: Iterator<Item = L>,
+    {
+        if codes.len() == 1 {
+            BvhNode::Leaf(leaves.next().expect("fewer leaves than codes"))
+        } else {
+            BvhNode::Node(self.build_node(codes, leaves))
+        }
+    }
+
+    fn finish_node<B, H, M, L>(&self, children: Vec<BvhNode<F, B, H, M, L>>) -> Bvh<F, B, H, M, L>
+    where
+        B: BoundingVolume<F>,
+        H: Hitable<F, Material = M>,
+        M: Material<F>,
+        L: BvhLeaf<F, B, H, M>,
+    {
+        let bvh = Bvh::from_nodes(children).expect("BVH node without children");
+        match self.treelets {
+            Some(optimizer) => optimizer.optimize_node(bvh),
+            None => bvh,
+        }
+    }
+}
+
+/// Index splitting sorted `codes` at the highest bit in which the first and
+/// last code differ, or in the middle if all codes are equal.
+fn split_index(codes: &[u64]) -> usize {
+    let first = codes[0];
+    let last = codes[codes.len() - 1];
+    if first == last {
+        return codes.len() / 2;
+    }
+
+    let prefix = (first ^ last).leading_zeros();
+    // first index whose code no longer shares `prefix + 1` bits with `first`
+    let mut low = 0;
+    let mut high = codes.len() - 1;
+    while low + 1 < high {
+        let mid = (low + high) / 2;
+        if (first ^ codes[mid]).leading_zeros() > prefix {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    high
+}
+
+fn merge_runs<F: Float, L>(runs: Vec<Vec<MortonItem<F, L>>>) -> Vec<MortonItem<F, L>> {
+    let total = runs.iter().map(|run| run.len()).sum();
+    let mut heap: BinaryHeap<_> = runs
+        .into_iter()
+        .filter_map(|run| {
+            let mut rest = run.into_iter();
+            rest.next().map(|head| Run { head, rest })
+        })
+        .collect();
+
+    let mut merged = Vec::with_capacity(total);
+    while let Some(Run { head, mut rest }) = heap.pop() {
+        merged.push(head);
+        if let Some(head) = rest.next() {
+            heap.push(Run { head, rest });
+        }
+    }
+    merged
+}
+
+fn spawn<S, T, W>(handle: &S, work: W) -> BuildFuture<T>
+where
+    S: Spawn,
+    T: Send + 'static,
+    W: FnOnce() -> T + Send + 'static,
+{
+    let monitor = handle.spawn_monitor(lazy(move || Ok::<T, ()>(work())));
+    Box::new(monitor.map_err(|_: MonitorError<()>| ()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fibers::{Executor, ThreadPoolExecutor};
+    use math::Ray;
+    use scenegraph::{SahBuilder, ShadedSphere};
+    use shading::DebugNormalMaterial;
+    use std::time::Instant;
+    use tracing::Traceable;
+
+    type Sphere = ShadedSphere<f64, DebugNormalMaterial>;
+
+    /// Deterministic pseudo random spheres in a 100 unit cube.
+    fn scattered_spheres(count: usize) -> Vec<Sphere> {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 11) as f64 / (1u64 << 53) as f64
+        };
+        (0..count)
+            .map(|_| {
+                let center = Point::new(next() * 100.0, next() * 100.0, next() * 100.0);
+                ShadedSphere::new(center, 0.2 + next(), DebugNormalMaterial {})
+            })
+            .collect()
+    }
+
+    fn build(
+        builder: LbvhBuilder<f64>,
+        spheres: Vec<Sphere>,
+    ) -> Bvh<f64, Aabb<f64>, Sphere, DebugNormalMaterial, Sphere> {
+        let mut executor = ThreadPoolExecutor::with_thread_count(4).unwrap();
+        let future = builder.build(spheres, executor.handle());
+        executor.run_future(future).unwrap().unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_morton_code_interleaves_bits() {
+        assert_eq!(morton_code(1, 0, 0), 0b001);
+        assert_eq!(morton_code(0, 1, 0), 0b010);
+        assert_eq!(morton_code(0, 0, 1), 0b100);
+        assert_eq!(morton_code(3, 0, 1), 0b001_101);
+        let max = (1 << 21) - 1;
+        assert_eq!(morton_code(max, max, max), (1 << 63) - 1);
+    }
+
+    #[test]
+    fn test_parallel_build_matches_brute_force() {
+        let spheres = scattered_spheres(2000);
+        let sah = SahBuilder::new();
+
+        for &bits in &[MortonBits::Bits30, MortonBits::Bits63] {
+            let builder = LbvhBuilder::new()
+                .with_morton_bits(bits)
+                .with_grain_size(100);
+            let plain = build(builder, spheres.clone());
+            let optimized = build(
+                builder.with_treelet_optimizer(TreeletOptimizer::new()),
+                spheres.clone(),
+            );
+            assert!(sah.sah_cost(&optimized) < sah.sah_cost(&plain));
+
+            for i in 0..200 {
+                let bound: Aabb<f64> = spheres[i * 7].bounding_volume();
+                let target = bound.centroid();
+                let origin = Point::new(-10.0, i as f64 * 0.5, 50.0);
+                let ray = Ray::new(origin, target - origin);
+
+                let expected = spheres
+                    .iter()
+                    .filter_map(|s| s.trace(&ray))
+                    .map(|h| h.0)
+                    .fold(f64::INFINITY, f64::min);
+                assert_eq!(plain.trace(&ray).map(|h| h.0), Some(expected));
+                assert_eq!(optimized.trace(&ray).map(|h| h.0), Some(expected));
+            }
+        }
+    }
+
+    /// Build times and SAH costs of the builders on one million spheres, run
+    /// with `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_lbvh_vs_sah() {
+        let spheres = scattered_spheres(1_000_000);
+        let sah = SahBuilder::new();
+
+        let start = Instant::now();
+        let bvh: Bvh<f64, Aabb<f64>, _, _, _> = sah.build(spheres.clone()).unwrap();
+        println!("sah: {:?}, cost {:.2}", start.elapsed(), sah.sah_cost(&bvh));
+
+        let builder = LbvhBuilder::new();
+        let start = Instant::now();
+        let bvh = build(builder, spheres.clone());
+        println!(
+            "lbvh: {:?}, cost {:.2}",
+            start.elapsed(),
+            sah.sah_cost(&bvh)
+        );
+
+        let start = Instant::now();
+        let bvh = build(
+            builder.with_treelet_optimizer(TreeletOptimizer::new()),
+            spheres,
+        );
+        println!(
+            "lbvh + treelets: {:?}, cost {:.2}",
+            start.elapsed(),
+            sah.sah_cost(&bvh)
+        );
+    }
+}
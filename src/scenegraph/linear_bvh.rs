@@ -0,0 +1,561 @@
+use math::{Bounded, BoundingVolume, BoundingVolumeSum, Float, Ray};
+use scenegraph::{Bvh, BvhLeaf, BvhNode, CountTraversal, CountedTraceable, TraversalCounters};
+use shading::Material;
+use std::cmp::Ordering::Equal;
+use std::marker::PhantomData;
+use std::ops::Range;
+use tracing::{Hitable, Traceable};
+
+/// Node of a `LinearBvh`. All children of an interior node sit next to each
+/// other, so a node expansion tests the bounds of its children from a single
+/// run of memory. Leaves reference a range of primitives instead.
+#[derive(Clone, Copy, Debug)]
+pub struct LinearNode<B> {
+    bound: B,
+    first: u32,
+    count: u32,
+    leaf: bool,
+}
+
+impl<B> LinearNode<B> {
+    /// Interior node whose `count` children start at node index `first`.
+    pub fn interior(bound: B, first: u32, count: u32) -> Self {
+        LinearNode {
+            bound,
+            first,
+            count,
+            leaf: false,
+        }
+    }
+
+    /// Leaf referencing `count` primitives starting at index `first`.
+    pub fn leaf(bound: B, first: u32, count: u32) -> Self {
+        LinearNode {
+            bound,
+            first,
+            count,
+            leaf: true,
+        }
+    }
+
+    pub fn bound(&self) -> &B {
+        &self.bound
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.leaf
+    }
+
+    /// Indices of child nodes, empty for leaves.
+    pub fn child_range(&self) -> Range<usize> {
+        if self.leaf {
+            0..0
+        } else {
+            self.range()
+        }
+    }
+
+    /// Range of `primitives` referenced by a leaf, empty for interior nodes.
+    pub fn primitive_range(&self) -> Range<usize> {
+        if self.leaf {
+            self.range()
+        } else {
+            0..0
+        }
+    }
+
+    fn range(&self) -> Range<usize> {
+        let first = self.first as usize;
+        first..first + self.count as usize
+    }
+}
+
+/// Fixed size traversal stack of node indices and their entry distances.
+/// It only allocates for unusually deep or wide trees, in which case all
+/// entries move to the heap so the top of the stack stays contiguous.
+pub struct NodeStack<F: Float> {
+    inline: [(u32, F); 64],
+    len: usize,
+    spill: Vec<(u32, F)>,
+}
+
+impl<F: Float> NodeStack<F> {
+    pub fn new() -> Self {
+        NodeStack {
+            inline: [(0, F::zero()); 64],
+            len: 0,
+            spill: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, node: usize, entry: F) {
+        if self.spill.is_empty() && self.len < self.inline.len() {
+            self.inline[self.len] = (node as u32, entry);
+            self.len += 1;
+        } else {
+            if self.spill.is_empty() {
+                self.spill.extend_from_slice(&self.inline[..self.len]);
+                self.len = 0;
+            }
+            self.spill.push((node as u32, entry));
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<(usize, F)> {
+        if let Some((node, entry)) = self.spill.pop() {
+            Some((node as usize, entry))
+        } else if self.len > 0 {
+            self.len -= 1;
+            let (node, entry) = self.inline[self.len];
+            Some((node as usize, entry))
+        } else {
+            None
+        }
+    }
+
+    /// The last `count` pushed entries, the last one being popped first.
+    pub fn top_mut(&mut self, count: usize) -> &mut [(u32, F)] {
+        if self.spill.is_empty() {
+            &mut self.inline[self.len - count..self.len]
+        } else {
+            let len = self.spill.len();
+            &mut self.spill[len - count..]
+        }
+    }
+}
+
+impl<F: Float> Default for NodeStack<F> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Depth-first linearized form of a `Bvh`, with all nodes in one contiguous
+/// array addressed by child offsets and all primitives in another. Traversal is an iterative loop
+/// instead of recursion through nested `Vec`s.
+pub struct LinearBvh<F, B, H, M, L>
+where
+    F: Float,
+    B: BoundingVolume<F>,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+    L: BvhLeaf<F, B, H, M>,
+{
+    nodes: Vec<LinearNode<B>>,
+    primitives: Vec<L>,
+    _f: PhantomData<F>,
+    _h: PhantomData<H>,
+    _m: PhantomData<M>,
+}
+
+impl<F, B, H, M, L> LinearBvh<F, B, H, M, L>
+where
+    F: Float,
+    B: BoundingVolume<F>,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+    L: BvhLeaf<F, B, H, M>,
+{
+    pub fn from_bvh(bvh: Bvh<F, B, H, M, L>) -> Self {
+        let mut nodes = vec![LinearNode {
+            bound: bvh.bounding_volume(),
+            first: 0,
+            count: 0,
+            leaf: false,
+        }];
+        let mut primitives = Vec::new();
+        Self::flatten(0, bvh, &mut nodes, &mut primitives);
+
+        LinearBvh {
+            nodes,
+            primitives,
+            _f: PhantomData,
+            _h: PhantomData,
+            _m: PhantomData,
+        }
+    }
+
+    /// Fills in the already allocated node at `index`, placing all of its
+    /// children in one contiguous block before descending into them.
+    fn flatten(
+        index: usize,
+        bvh: Bvh<F, B, H, M, L>,
+        nodes: &mut Vec<LinearNode<B>>,
+        primitives: &mut Vec<L>,
+    ) {
+        let children = bvh.into_children();
+
+        // a node with only leaf children becomes a single multi-primitive leaf
+        if children.iter().all(|child| match child {
+            BvhNode::Leaf(_) => true,
+            BvhNode::Node(_) => false,
+        }) {
+            let first = primitives.len();
+            primitives.extend(children.into_iter().filter_map(|child| match child {
+                BvhNode::Leaf(leaf) => Some(leaf),
+                BvhNode::Node(_) => None,
+            }));
+            nodes[index].first = first as u32;
+            nodes[index].count = (primitives.len() - first) as u32;
+            nodes[index].leaf = true;
+            return;
+        }
+
+        let first = nodes.len();
+        nodes[index].first = first as u32;
+        nodes[index].count = children.len() as u32;
+        nodes.extend(children.iter().map(|child| LinearNode {
+            bound: child.bounding_volume(),
+            first: 0,
+            count: 0,
+            leaf: false,
+        }));
+
+        for (offset, child) in children.into_iter().enumerate() {
+            match child {
+                BvhNode::Node(node) => Self::flatten(first + offset, node, nodes, primitives),
+                BvhNode::Leaf(leaf) => {
+                    let node = &mut nodes[first + offset];
+                    node.first = primitives.len() as u32;
+                    node.count = 1;
+                    node.leaf = true;
+                    primitives.push(leaf);
+                }
+            }
+        }
+    }
+
+    /// Reassembles a tree taken apart with `into_parts`. Fails unless every
+    /// node but the root is the child of exactly one node stored before it,
+    /// and every primitive belongs to exactly one leaf.
+    pub fn from_parts(nodes: Vec<LinearNode<B>>, primitives: Vec<L>) -> Result<Self, String> {
+        check_parts(&nodes, primitives.len())?;
+
+        Ok(LinearBvh {
+            nodes,
+            primitives,
+            _f: PhantomData,
+            _h: PhantomData,
+            _m: PhantomData,
+        })
+    }
+
+    pub fn into_parts(self) -> (Vec<LinearNode<B>>, Vec<L>) {
+        (self.nodes, self.primitives)
+    }
+
+    pub fn nodes(&self) -> &[LinearNode<B>] {
+        &self.nodes
+    }
+
+    pub fn primitives(&self) -> &[L] {
+        &self.primitives
+    }
+
+    /// Node bounds are not updated when primitives change, call `refit`
+    /// afterwards.
+    pub fn primitives_mut(&mut self) -> &mut [L] {
+        &mut self.primitives
+    }
+
+    /// Recomputes all node bounds from the current primitive bounds while
+    /// keeping the tree topology. Much cheaper than a rebuild, but the tree
+    /// degrades when primitives move far from where it was built.
+    pub fn refit(&mut self) {
+        // children are always stored after their parent
+        for index in (0..self.nodes.len()).rev() {
+            let node = self.nodes[index];
+            let bound = if node.leaf {
+                self.primitives[node.primitive_range()]
+                    .iter()
+                    .map(|primitive| primitive.bounding_volume())
+                    .bounding_sum()
+            } else {
+                self.nodes[node.child_range()]
+                    .iter()
+                    .map(|child| child.bound)
+                    .bounding_sum()
+            };
+            self.nodes[index].bound = bound.expect("BVH node without children");
+        }
+    }
+
+    fn traverse<C: CountTraversal>(&self, ray: &Ray<F>, counter: &mut C) -> Option<(F, &H)> {
+        let mut closest: Option<(F, &H)> = None;
+        let mut limit = ray.t_max;
+        let mut stack = NodeStack::new();
+
+        match self.nodes[0].bound.test(ray) {
+            Some(entry) => stack.push(0, entry),
+            None => return None,
+        }
+
+        while let Some((index, entry)) = stack.pop() {
+            // the closest hit may have moved in front of this node since it was pushed
+            if entry > limit {
+                continue;
+            }
+            let node = &self.nodes[index];
+            counter.visit_node();
+
+            for primitive in &self.primitives[node.primitive_range()] {
+                counter.test_primitive();
+                if let Some(hit) = primitive.trace(ray) {
+                    if hit.0 < limit {
+                        limit = hit.0;
+                        closest = Some(hit);
+                    }
+                }
+            }
+
+            let mut pushed = 0;
+            for child in node.child_range() {
+                match self.nodes[child].bound.test(ray) {
+                    Some(entry) if entry <= limit => {
+                        stack.push(child, entry);
+                        pushed += 1;
+                    }
+                    _ => {}
+                }
+            }
+
+            // farthest first, so the nearest child is popped next
+            if pushed > 1 {
+                stack
+                    .top_mut(pushed)
+                    .sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Equal));
+            }
+        }
+
+        closest
+    }
+}
+
+/// Checks that `nodes` form a tree over `primitive_count` primitives, as
+/// required by `LinearBvh::from_parts`.
+pub(crate) fn check_parts<B>(
+    nodes: &[LinearNode<B>],
+    primitive_count: usize,
+) -> Result<(), String> {
+    if nodes.is_empty() {
+        return Err("no nodes".to_string());
+    }
+
+    let mut node_refs = vec![0u32; nodes.len()];
+    let mut primitive_refs = vec![0u32; primitive_count];
+    for (index, node) in nodes.iter().enumerate() {
+        let range = node.range();
+        if node.count == 0 {
+            return Err(format!("node {} is empty", index));
+        }
+        if node.leaf {
+            if range.end > primitive_count {
+                return Err(format!("leaf {} references missing primitives", index));
+            }
+            for primitive in range {
+                primitive_refs[primitive] += 1;
+            }
+        } else {
+            if range.start <= index || range.end > nodes.len() {
+                return Err(format!("node {} has invalid children", index));
+            }
+            for child in range {
+                node_refs[child] += 1;
+            }
+        }
+    }
+
+    if node_refs[0] != 0 || node_refs[1..].iter().any(|&refs| refs != 1) {
+        return Err("nodes are not a tree".to_string());
+    }
+    if primitive_refs.iter().any(|&refs| refs != 1) {
+        return Err("primitives are not referenced exactly once".to_string());
+    }
+
+    Ok(())
+}
+
+impl<F, B, H, M, L> Bounded<F, B> for LinearBvh<F, B, H, M, L>
+where
+    F: Float,
+    B: BoundingVolume<F>,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+    L: BvhLeaf<F, B, H, M>,
+{
+    fn bounding_volume(&self) -> B {
+        self.nodes[0].bound
+    }
+}
+
+impl<F, B, H, M, L> Traceable<F, H, M> for LinearBvh<F, B, H, M, L>
+where
+    F: Float,
+    B: BoundingVolume<F>,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+    L: BvhLeaf<F, B, H, M>,
+{
+    fn trace(&self, ray: &Ray<F>) -> Option<(F, &H)> {
+        self.traverse(ray, &mut ())
+    }
+
+    fn occluded(&self, ray: &Ray<F>, t_max: F) -> bool {
+        let ray = ray.clone().with_t_max(F::min(ray.t_max, t_max));
+        let mut stack = NodeStack::new();
+
+        match self.nodes[0].bound.test(&ray) {
+            Some(entry) => stack.push(0, entry),
+            None => return false,
+        }
+
+        // no ordering needed, any hit ends the search
+        while let Some((index, _)) = stack.pop() {
+            let node = &self.nodes[index];
+
+            for primitive in &self.primitives[node.primitive_range()] {
+                if primitive.occluded(&ray, ray.t_max) {
+                    return true;
+                }
+            }
+
+            for child in node.child_range() {
+                if let Some(entry) = self.nodes[child].bound.test(&ray) {
+                    stack.push(child, entry);
+                }
+            }
+        }
+
+        false
+    }
+}
+
+impl<F, B, H, M, L> CountedTraceable<F, H, M> for LinearBvh<F, B, H, M, L>
+where
+    F: Float,
+    B: BoundingVolume<F>,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+    L: BvhLeaf<F, B, H, M>,
+{
+    fn trace_counted(&self, ray: &Ray<F>, counters: &mut TraversalCounters) -> Option<(F, &H)> {
+        self.traverse(ray, counters)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use math::{Aabb, Point};
+    use scenegraph::{MeshBvh, MeshTriangle, SahBuilder, ShadedSphere, TriangleMesh};
+    use shading::DebugNormalMaterial;
+    use std::time::Instant;
+    use testing::lcg;
+
+    type Mat = DebugNormalMaterial;
+
+    /// Wavy heightfield with `2 * size * size` triangles.
+    fn terrain(size: usize) -> Vec<MeshTriangle<f64, Mat>> {
+        let mut positions = Vec::new();
+        for z in 0..=size {
+            for x in 0..=size {
+                let (fx, fz) = (x as f64 / size as f64, z as f64 / size as f64);
+                let y = (fx * 37.0).sin() * (fz * 23.0).cos() * 0.05;
+                positions.push(Point::new(fx * 2.0 - 1.0, y, fz * 2.0 - 1.0));
+            }
+        }
+        let mut indices = Vec::new();
+        for z in 0..size {
+            for x in 0..size {
+                let i = z * (size + 1) + x;
+                indices.push([i, i + 1, i + size + 2]);
+                indices.push([i, i + size + 2, i + size + 1]);
+            }
+        }
+        TriangleMesh::new(positions, None, None, indices, DebugNormalMaterial {})
+            .unwrap()
+            .into_triangles()
+    }
+
+    fn rays(count: usize) -> Vec<Ray<f64>> {
+        let origin = Point::new(0.0, 2.0, -3.0);
+        (0..count * count)
+            .map(|i| {
+                let (x, y) = ((i % count) as f64, (i / count) as f64);
+                let target = Point::new(
+                    x / count as f64 * 2.4 - 1.2,
+                    0.0,
+                    y / count as f64 * 2.4 - 1.2,
+                );
+                Ray::new(origin, (target - origin).normalized())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_linear_bvh_matches_recursive() {
+        let bvh: MeshBvh<f64, Mat> = SahBuilder::new().build(terrain(16)).unwrap();
+        let reference: MeshBvh<f64, Mat> = SahBuilder::new().build(terrain(16)).unwrap();
+        let linear = LinearBvh::from_bvh(bvh);
+
+        let root: Aabb<f64> = linear.bounding_volume();
+        assert_eq!(root.centroid().x, reference.bounding_volume().centroid().x);
+        assert_eq!(linear.primitives().len(), 2 * 16 * 16);
+
+        for ray in rays(32) {
+            let expected = reference.trace(&ray).map(|(d, h)| (d, h.index()));
+            let actual = linear.trace(&ray).map(|(d, h)| (d, h.index()));
+            assert_eq!(actual, expected);
+        }
+    }
+
+    /// Small spheres scattered through a unit cube by a fixed LCG.
+    fn scattered_spheres(count: usize) -> Vec<ShadedSphere<f32, Mat>> {
+        let mut random = lcg(12345);
+        let mut next = || random() as f32 * 2.0 - 1.0;
+        (0..count)
+            .map(|_| {
+                let center = Point::new(next(), next(), next());
+                ShadedSphere::new(center, 0.004, DebugNormalMaterial {})
+            })
+            .collect()
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_linear_vs_recursive() {
+        let count = 250_000;
+        let build_start = Instant::now();
+        let bvh: Bvh<f32, Aabb<f32>, _, _, _> =
+            SahBuilder::new().build(scattered_spheres(count)).unwrap();
+        println!("built {} spheres in {:?}", count, build_start.elapsed());
+
+        let origin = Point::new(0.0, 0.0, -3.0);
+        let side = 512;
+        let rays: Vec<_> = (0..side * side)
+            .map(|i| {
+                let (x, y) = (
+                    (i % side) as f32 / side as f32,
+                    (i / side) as f32 / side as f32,
+                );
+                let target = Point::new(x * 2.0 - 1.0, y * 2.0 - 1.0, 0.0);
+                Ray::new(origin, (target - origin).normalized())
+            })
+            .collect();
+
+        let rays_per_sec = |trace: &dyn Fn(&Ray<f32>) -> Option<f32>| {
+            let start = Instant::now();
+            let hits = rays.iter().filter_map(trace).count();
+            (rays.len() as f64 / start.elapsed().as_secs_f64(), hits)
+        };
+
+        let (recursive, recursive_hits) = rays_per_sec(&|ray| bvh.trace(ray).map(|h| h.0));
+        let linear = LinearBvh::from_bvh(bvh);
+        let (flat, flat_hits) = rays_per_sec(&|ray| linear.trace(ray).map(|h| h.0));
+
+        println!("recursive: {:.0} rays/s", recursive);
+        println!("linear:    {:.0} rays/s ({:.2}x)", flat, flat / recursive);
+        assert_eq!(recursive_hits, flat_hits);
+    }
+}
@@ -1,9 +1,53 @@
 mod bvh;
+mod bvh_cache;
 mod bvh_node;
+mod bvh_stats;
+mod csg;
+mod cuboid;
+mod curve;
+mod cylinder;
+mod displacement;
+mod heightfield;
+mod instance;
+mod lbvh_builder;
+mod linear_bvh;
+mod plane;
+mod sah_builder;
 mod scene;
+mod sdf;
+mod shape;
 mod sphere;
+mod subdivision;
+mod tlas;
+mod torus;
+mod treelet;
+mod triangle;
+mod triangle_mesh;
+mod volume_benchmark;
 
 pub use self::bvh::*;
+pub use self::bvh_cache::*;
 pub use self::bvh_node::*;
+pub use self::bvh_stats::*;
+pub use self::csg::*;
+pub use self::cuboid::*;
+pub use self::curve::*;
+pub use self::cylinder::*;
+pub use self::displacement::*;
+pub use self::heightfield::*;
+pub use self::instance::*;
+pub use self::lbvh_builder::*;
+pub use self::linear_bvh::*;
+pub use self::plane::*;
+pub use self::sah_builder::*;
 pub use self::scene::*;
+pub use self::sdf::*;
+pub use self::shape::*;
 pub use self::sphere::*;
+pub use self::subdivision::*;
+pub use self::tlas::*;
+pub use self::torus::*;
+pub use self::treelet::*;
+pub use self::triangle::*;
+pub use self::triangle_mesh::*;
+pub use self::volume_benchmark::*;
@@ -0,0 +1,402 @@
+use math::{turns, Aabb, Bounded, Float, Frame, Point, Ray, Vector, UV};
+use scenegraph::{CountTraversal, CountedTraceable, TraversalCounters};
+use shading::Material;
+use std::marker::PhantomData;
+use tracing::{HitPoint, Hitable, Traceable};
+
+/// Distance at which the ray crosses the plane through `origin` with the
+/// given `normal`, if it is inside the ray interval.
+fn intersect_plane<F: Float>(ray: &Ray<F>, origin: Point<F>, normal: Vector<F>) -> Option<F> {
+    let denominator = ray.direction.dot(normal);
+    if denominator == F::zero() {
+        return None;
+    }
+    let t = (origin - ray.origin).dot(normal) / denominator;
+    if ray.contains(t) {
+        Some(t)
+    } else {
+        None
+    }
+}
+
+/// Infinite plane. It has no bounds, so it cannot be put into a `Bvh`: keep
+/// it next to the bounded part of the scene with `Unbounded` instead.
+///
+/// The UV are the plane coordinates of the hit along two axes perpendicular
+/// to the normal, without any wrapping.
+#[derive(Clone, Copy, Debug)]
+pub struct Plane<F: Float, M: Material<F>> {
+    frame: Frame<F>,
+    material: M,
+    casts_shadows: bool,
+}
+
+impl<F: Float, M: Material<F>> Plane<F, M> {
+    pub fn new(point: Point<F>, normal: Vector<F>, material: M) -> Self {
+        Plane {
+            frame: Frame::from_axis(point, normal),
+            material,
+            casts_shadows: true,
+        }
+    }
+
+    pub fn with_casts_shadows(mut self, casts_shadows: bool) -> Self {
+        self.casts_shadows = casts_shadows;
+        self
+    }
+
+    pub fn normal(&self) -> Vector<F> {
+        self.frame.z
+    }
+}
+
+impl<F: Float, M: Material<F>> Hitable<F> for Plane<F, M> {
+    type Material = M;
+    fn get_hit(&self, ray: &Ray<F>, distance: F) -> HitPoint<F, M> {
+        let point = ray.point_at_distance(distance);
+        let local = self.frame.to_local_point(point);
+        HitPoint::new(
+            point,
+            self.frame.z,
+            ray.direction,
+            UV::new(local.x, local.y),
+            self.material.clone(),
+        )
+    }
+}
+
+impl<F: Float, M: Material<F>> Traceable<F, Self, M> for Plane<F, M> {
+    fn trace(&self, ray: &Ray<F>) -> Option<(F, &Self)> {
+        intersect_plane(ray, self.frame.origin, self.frame.z).map(|t| (t, self))
+    }
+
+    fn casts_shadows(&self) -> bool {
+        self.casts_shadows
+    }
+}
+
+/// Parallelogram spanned by two edges from a corner, usually a rectangle.
+/// UV run from 0 to 1 along the edges.
+#[derive(Clone, Copy, Debug)]
+pub struct Rectangle<F: Float, M: Material<F>> {
+    corner: Point<F>,
+    edge_u: Vector<F>,
+    edge_v: Vector<F>,
+    normal: Vector<F>,
+    material: M,
+    casts_shadows: bool,
+}
+
+impl<F: Float, M: Material<F>> Rectangle<F, M> {
+    /// The normal points along `edge_u × edge_v`.
+    pub fn new(corner: Point<F>, edge_u: Vector<F>, edge_v: Vector<F>, material: M) -> Self {
+        Rectangle {
+            corner,
+            edge_u,
+            edge_v,
+            normal: edge_u.cross(edge_v).normalized(),
+            material,
+            casts_shadows: true,
+        }
+    }
+
+    pub fn with_casts_shadows(mut self, casts_shadows: bool) -> Self {
+        self.casts_shadows = casts_shadows;
+        self
+    }
+
+    fn uv_at(&self, point: Point<F>) -> UV<F> {
+        let offset = point - self.corner;
+        let area = self.edge_u.cross(self.edge_v).dot(self.normal);
+        UV::new(
+            offset.cross(self.edge_v).dot(self.normal) / area,
+            self.edge_u.cross(offset).dot(self.normal) / area,
+        )
+    }
+}
+
+impl<F: Float, M: Material<F>> Hitable<F> for Rectangle<F, M> {
+    type Material = M;
+    fn get_hit(&self, ray: &Ray<F>, distance: F) -> HitPoint<F, M> {
+        let point = ray.point_at_distance(distance);
+        HitPoint::new(
+            point,
+            self.normal,
+            ray.direction,
+            self.uv_at(point),
+            self.material.clone(),
+        )
+    }
+}
+
+impl<F: Float, M: Material<F>> Traceable<F, Self, M> for Rectangle<F, M> {
+    fn trace(&self, ray: &Ray<F>) -> Option<(F, &Self)> {
+        let t = intersect_plane(ray, self.corner, self.normal)?;
+        let uv = self.uv_at(ray.point_at_distance(t));
+        let inside = |x: F| x >= F::zero() && x <= F::one();
+        if inside(uv.u) && inside(uv.v) {
+            Some((t, self))
+        } else {
+            None
+        }
+    }
+
+    fn casts_shadows(&self) -> bool {
+        self.casts_shadows
+    }
+}
+
+impl<F: Float, M: Material<F>> Bounded<F, Aabb<F>> for Rectangle<F, M> {
+    fn bounding_volume(&self) -> Aabb<F> {
+        Aabb::from_points(&[
+            self.corner,
+            self.corner + self.edge_u,
+            self.corner + self.edge_v,
+            self.corner + self.edge_u + self.edge_v,
+        ])
+    }
+}
+
+/// Flat disk. `u` is the angle around the center as a fraction of a turn,
+/// `v` the distance from the center relative to the radius.
+#[derive(Clone, Copy, Debug)]
+pub struct Disk<F: Float, M: Material<F>> {
+    frame: Frame<F>,
+    radius: F,
+    material: M,
+    casts_shadows: bool,
+}
+
+impl<F: Float, M: Material<F>> Disk<F, M> {
+    pub fn new(center: Point<F>, normal: Vector<F>, radius: F, material: M) -> Self {
+        Disk {
+            frame: Frame::from_axis(center, normal),
+            radius,
+            material,
+            casts_shadows: true,
+        }
+    }
+
+    pub fn with_casts_shadows(mut self, casts_shadows: bool) -> Self {
+        self.casts_shadows = casts_shadows;
+        self
+    }
+}
+
+impl<F: Float, M: Material<F>> Hitable<F> for Disk<F, M> {
+    type Material = M;
+    fn get_hit(&self, ray: &Ray<F>, distance: F) -> HitPoint<F, M> {
+        let point = ray.point_at_distance(distance);
+        let local = self.frame.to_local_point(point);
+        let uv = UV::new(
+            turns(local.y, local.x),
+            local.x.hypot(local.y) / self.radius,
+        );
+        HitPoint::new(
+            point,
+            self.frame.z,
+            ray.direction,
+            uv,
+            self.material.clone(),
+        )
+    }
+}
+
+impl<F: Float, M: Material<F>> Traceable<F, Self, M> for Disk<F, M> {
+    fn trace(&self, ray: &Ray<F>) -> Option<(F, &Self)> {
+        let t = intersect_plane(ray, self.frame.origin, self.frame.z)?;
+        let offset = ray.point_at_distance(t) - self.frame.origin;
+        if offset.magnitude_sq() <= self.radius * self.radius {
+            Some((t, self))
+        } else {
+            None
+        }
+    }
+
+    fn casts_shadows(&self) -> bool {
+        self.casts_shadows
+    }
+}
+
+impl<F: Float, M: Material<F>> Bounded<F, Aabb<F>> for Disk<F, M> {
+    fn bounding_volume(&self) -> Aabb<F> {
+        Aabb::from_disk(self.frame.origin, self.frame.z, self.radius)
+    }
+}
+
+/// Bounded scene part, usually a `Bvh`, together with objects that cannot
+/// be bounded such as `Plane`. Every ray is tested against all of the
+/// unbounded objects, so keep them few. Both parts share their hitable type,
+/// to mix a `Plane` with other primitives use `Shape` for it.
+pub struct Unbounded<F, H, M, T, U>
+where
+    F: Float,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+    T: Traceable<F, H, M>,
+    U: Traceable<F, H, M>,
+{
+    bounded: T,
+    unbounded: Vec<U>,
+    _f: PhantomData<F>,
+    _h: PhantomData<H>,
+    _m: PhantomData<M>,
+}
+
+impl<F, H, M, T, U> Unbounded<F, H, M, T, U>
+where
+    F: Float,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+    T: Traceable<F, H, M>,
+    U: Traceable<F, H, M>,
+{
+    pub fn new(bounded: T, unbounded: Vec<U>) -> Self {
+        Unbounded {
+            bounded,
+            unbounded,
+            _f: PhantomData,
+            _h: PhantomData,
+            _m: PhantomData,
+        }
+    }
+
+    pub fn bounded(&self) -> &T {
+        &self.bounded
+    }
+
+    pub fn unbounded(&self) -> &[U] {
+        &self.unbounded
+    }
+
+    fn trace_with<'a, C: CountTraversal>(
+        &'a self,
+        ray: &Ray<F>,
+        closest: Option<(F, &'a H)>,
+        counters: &mut C,
+    ) -> Option<(F, &'a H)> {
+        self.unbounded.iter().fold(closest, |closest, object| {
+            counters.test_primitive();
+            let ray = match closest {
+                Some((t, _)) => ray.clone().with_t_max(t),
+                None => ray.clone(),
+            };
+            object.trace(&ray).or(closest)
+        })
+    }
+}
+
+impl<F, H, M, T, U> Traceable<F, H, M> for Unbounded<F, H, M, T, U>
+where
+    F: Float,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+    T: Traceable<F, H, M>,
+    U: Traceable<F, H, M>,
+{
+    fn trace(&self, ray: &Ray<F>) -> Option<(F, &H)> {
+        self.trace_with(ray, self.bounded.trace(ray), &mut ())
+    }
+
+    fn occluded(&self, ray: &Ray<F>, t_max: F) -> bool {
+        self.unbounded
+            .iter()
+            .any(|object| object.occluded(ray, t_max))
+            || self.bounded.occluded(ray, t_max)
+    }
+}
+
+impl<F, H, M, T, U> CountedTraceable<F, H, M> for Unbounded<F, H, M, T, U>
+where
+    F: Float,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+    T: CountedTraceable<F, H, M>,
+    U: Traceable<F, H, M>,
+{
+    fn trace_counted(&self, ray: &Ray<F>, counters: &mut TraversalCounters) -> Option<(F, &H)> {
+        let closest = self.bounded.trace_counted(ray, counters);
+        self.trace_with(ray, closest, counters)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fibers::{Executor, ThreadPoolExecutor};
+    use scenegraph::{Bvh, LbvhBuilder, SahBuilder, ShadedSphere, Shape};
+    use shading::DebugNormalMaterial;
+
+    fn down(x: f64, y: f64) -> Ray<f64> {
+        Ray::new(Point::new(x, y, 5.0), -Vector::plus_z())
+    }
+
+    #[test]
+    fn test_flat_primitives() {
+        let rectangle = Rectangle::new(
+            Point::new(1.0, 1.0, 0.0),
+            Vector::new(2.0, 0.0, 0.0),
+            Vector::new(0.0, 4.0, 0.0),
+            DebugNormalMaterial {},
+        );
+        let ray = down(2.5, 2.0);
+        let (t, hit) = rectangle.trace(&ray).unwrap();
+        let hit = hit.get_hit(&ray, t);
+        assert_eq!(t, 5.0);
+        assert_eq!(hit.data.normal, Vector::plus_z());
+        assert_eq!(hit.data.uv, UV::new(0.75, 0.25));
+        assert!(rectangle.trace(&down(0.5, 2.0)).is_none());
+
+        let disk = Disk::new(
+            Point::origin(),
+            Vector::plus_z(),
+            2.0,
+            DebugNormalMaterial {},
+        );
+        let ray = down(0.0, 1.0);
+        let (t, hit) = disk.trace(&ray).unwrap();
+        let uv = hit.get_hit(&ray, t).data.uv;
+        assert!((uv.u - 0.25).abs() < 1e-9 && (uv.v - 0.5).abs() < 1e-9);
+        assert!(disk.trace(&down(1.5, 1.5)).is_none());
+        let bounds: Aabb<f64> = disk.bounding_volume();
+        assert_eq!((bounds.x_max, bounds.z_max), (2.0, 0.0));
+    }
+
+    #[test]
+    fn test_ground_plane_next_to_bvh() {
+        let material = DebugNormalMaterial {};
+        let spheres = vec![Shape::from(ShadedSphere::new(
+            Point::new(0.0, 1.0, 0.0),
+            1.0,
+            material.clone(),
+        ))];
+        let ground = Plane::new(Point::origin(), Vector::plus_y(), material);
+        // builders refuse the plane as a leaf
+        let mut with_ground = spheres.clone();
+        with_ground.push(Shape::from(ground.clone()));
+        let refused: Option<Bvh<f64, Aabb<f64>, _, _, _>> = SahBuilder::new().build(with_ground);
+        assert!(refused.is_none());
+        let mut executor = ThreadPoolExecutor::new().unwrap();
+        let future = LbvhBuilder::new().build(vec![Shape::from(ground.clone())], executor.handle());
+        let refused: Option<Bvh<f64, Aabb<f64>, _, _, _>> =
+            executor.run_future(future).unwrap().unwrap();
+        assert!(refused.is_none());
+
+        let bvh: Bvh<f64, Aabb<f64>, _, _, _> = SahBuilder::new().build(spheres).unwrap();
+        let scene = Unbounded::new(bvh, vec![Shape::from(ground)]);
+
+        let down = |x: f64| Ray::new(Point::new(x, 5.0, 0.0), -Vector::plus_y());
+        let (t, hit) = scene.trace(&down(0.0)).unwrap();
+        assert_eq!(t, 3.0);
+        assert!(matches!(hit, Shape::Sphere(_)));
+        let (t, hit) = scene.trace(&down(3.0)).unwrap();
+        assert_eq!(t, 5.0);
+        assert!(matches!(hit, Shape::Plane(_)));
+
+        // the ground is below, shadows from above only hit the sphere
+        let up = Ray::new(Point::new(3.0, 0.5, 0.0), Vector::plus_y());
+        assert!(!scene.occluded(&up, 10.0));
+        assert!(scene.occluded(&down(3.0), 10.0));
+        assert!(!scene.occluded(&down(3.0), 4.0));
+    }
+}
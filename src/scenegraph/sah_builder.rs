@@ -0,0 +1,419 @@
+use math::{Aabb, Bounded, BoundingVolume, Float, Point};
+use scenegraph::{Bvh, BvhLeaf, BvhNode};
+use shading::Material;
+use tracing::Hitable;
+
+struct BuildItem<F: Float, L> {
+    leaf: L,
+    bound: Aabb<F>,
+    centroid: Point<F>,
+}
+
+/// Top-down BVH builder using the surface area heuristic, evaluated over a
+/// fixed number of bins along the widest axis of the primitive centroids.
+///
+/// Leaves are always split into `Aabb`s for the cost estimate, while the
+/// resulting tree stores bounds of whatever volume type `B` the `Bvh` uses.
+#[derive(Debug, Clone, Copy)]
+pub struct SahBuilder<F: Float> {
+    max_leaf_size: usize,
+    bin_count: usize,
+    traversal_cost: F,
+    intersection_cost: F,
+}
+
+impl<F: Float> Default for SahBuilder<F> {
+    fn default() -> Self {
+        SahBuilder {
+            max_leaf_size: 4,
+            bin_count: 16,
+            traversal_cost: F::from(0.125).unwrap(),
+            intersection_cost: F::one(),
+        }
+    }
+}
+
+impl<F: Float> SahBuilder<F> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Nodes with more primitives than this are always split.
+    pub fn with_max_leaf_size(mut self, max_leaf_size: usize) -> Self {
+        self.max_leaf_size = max_leaf_size.max(1);
+        self
+    }
+
+    pub fn with_bin_count(mut self, bin_count: usize) -> Self {
+        self.bin_count = bin_count.max(2);
+        self
+    }
+
+    /// Relative cost of visiting a node versus intersecting one primitive.
+    pub fn with_costs(mut self, traversal_cost: F, intersection_cost: F) -> Self {
+        self.traversal_cost = traversal_cost;
+        self.intersection_cost = intersection_cost;
+        self
+    }
+
+    pub fn max_leaf_size(&self) -> usize {
+        self.max_leaf_size
+    }
+
+    pub fn traversal_cost(&self) -> F {
+        self.traversal_cost
+    }
+
+    pub fn intersection_cost(&self) -> F {
+        self.intersection_cost
+    }
+
+    /// Expected cost of tracing a random ray through `bvh`, relative to the
+    /// cost of one primitive intersection when `intersection_cost` is one.
+    /// Every node costs one traversal step plus one intersection per leaf
+    /// child, weighted by the chance of hitting it given the root was hit.
+    pub fn sah_cost<B, H, M, L>(&self, bvh: &Bvh<F, B, H, M, L>) -> F
+    where
+        B: BoundingVolume<F>,
+        H: Hitable<F, Material = M>,
+        M: Material<F>,
+        L: BvhLeaf<F, B, H, M>,
+    {
+        let root_area = bvh.bounding_volume().surface_area();
+        let inv_root_area = if root_area > F::zero() {
+            root_area.recip()
+        } else {
+            F::one()
+        };
+        self.node_cost(bvh, inv_root_area)
+    }
+
+    fn node_cost<B, H, M, L>(&self, bvh: &Bvh<F, B, H, M, L>, inv_root_area: F) -> F
+    where
+        B: BoundingVolume<F>,
+        H: Hitable<F, Material = M>,
+        M: Material<F>,
+        L: BvhLeaf<F, B, H, M>,
+    {
+        let mut leaf_count = 0;
+        let mut children_cost = F::zero();
+        for child in bvh.children() {
+            match child {
+                BvhNode::Node(node) => {
+                    children_cost = children_cost + self.node_cost(node, inv_root_area)
+                }
+                BvhNode::Leaf(_) => leaf_count += 1,
+            }
+        }
+
+        let own_cost = self.traversal_cost + self.intersection_cost * F::from(leaf_count).unwrap();
+        bvh.bounding_volume().surface_area() * inv_root_area * own_cost + children_cost
+    }
+
+    /// Refits `bvh` to its moved leaves and returns the growth of its
+    /// `sah_cost`, the new cost divided by the cost before refitting.
+    ///
+    /// Growth compounds over repeated refits, so callers deciding when to
+    /// rebuild should multiply the results, or compare against `sah_cost`
+    /// taken right after the build. Rebuilding once that drifts past about
+    /// 1.5 is a reasonable default.
+    pub fn refit<B, H, M, L>(&self, bvh: &mut Bvh<F, B, H, M, L>) -> F
+    where
+        B: BoundingVolume<F>,
+        H: Hitable<F, Material = M>,
+        M: Material<F>,
+        L: BvhLeaf<F, B, H, M>,
+    {
+        let before = self.sah_cost(bvh);
+        bvh.refit();
+        let after = self.sah_cost(bvh);
+
+        if before > F::zero() {
+            after / before
+        } else {
+            F::one()
+        }
+    }
+
+    /// None without leaves, and with leaves that are not bounded, such as
+    /// `Shape::Plane`.
+    pub fn build<B, H, M, L>(&self, leaves: Vec<L>) -> Option<Bvh<F, B, H, M, L>>
+    where
+        B: BoundingVolume<F>,
+        H: Hitable<F, Material = M>,
+        M: Material<F>,
+        L: BvhLeaf<F, B, H, M> + Bounded<F, Aabb<F>>,
+    {
+        if leaves.is_empty() {
+            return None;
+        }
+
+        let items: Vec<_> = leaves
+            .into_iter()
+            .map(|leaf| {
+                let bound: Aabb<F> = leaf.bounding_volume();
+                BuildItem {
+                    leaf,
+                    bound,
+                    centroid: bound.centroid(),
+                }
+            })
+            .collect();
+        if !items.iter().all(|item| item.bound.is_bounded()) {
+            return None;
+        }
+
+        Some(self.build_node(items))
+    }
+
+    fn build_node<B, H, M, L>(&self, items: Vec<BuildItem<F, L>>) -> Bvh<F, B, H, M, L>
+    where
+        B: BoundingVolume<F>,
+        H: Hitable<F, Material = M>,
+        M: Material<F>,
+        L: BvhLeaf<F, B, H, M>,
+    {
+        let nodes = match self.split(items) {
+            Ok((left, right)) => vec![self.build_child(left), self.build_child(right)],
+            Err(items) => items
+                .into_iter()
+                .map(|item| BvhNode::Leaf(item.leaf))
+                .collect(),
+        };
+        Bvh::from_nodes(nodes).expect("BVH node without children")
+    }
+
+    fn build_child<B, H, M, L>(&self, mut items: Vec<BuildItem<F, L>>) -> BvhNode<F, B, H, M, L>
+    where
+        B: BoundingVolume<F>,
+        H: Hitable<F, Material = M>,
+        M: Material<F>,
+        L: BvhLeaf<F, B, H, M>,
+    {
+        if items.len() == 1 {
+            BvhNode::Leaf(items.pop().unwrap().leaf)
+        } else {
+            BvhNode::Node(self.build_node(items))
+        }
+    }
+
+    /// Partitions items into two non-empty halves, or gives them back when
+    /// keeping them together in one leaf is cheaper.
+    #[allow(clippy::type_complexity)]
+    fn split<L>(
+        &self,
+        mut items: Vec<BuildItem<F, L>>,
+    ) -> Result<(Vec<BuildItem<F, L>>, Vec<BuildItem<F, L>>), Vec<BuildItem<F, L>>> {
+        let count = items.len();
+        if count <= 1 {
+            return Err(items);
+        }
+
+        let (bound, centroid_bound) = items.iter().fold(
+            (Aabb::empty(), Aabb::empty()),
+            |(bound, centroids), item| {
+                (
+                    bound.combine(&item.bound),
+                    centroids.combine(&Aabb::from_points(&[item.centroid])),
+                )
+            },
+        );
+
+        let axis = centroid_bound.largest_axis();
+        let low = centroid_bound.min_point()[axis];
+        let high = centroid_bound.max_point()[axis];
+
+        if high <= low {
+            // all centroids coincide, no plane can separate them
+            return if count <= self.max_leaf_size {
+                Err(items)
+            } else {
+                let right = items.split_off(count / 2);
+                Ok((items, right))
+            };
+        }
+
+        let bins = self.bin_count;
+        let scale = F::from(bins).unwrap() / (high - low);
+        let bin_of = |centroid: &Point<F>| {
+            ((centroid[axis] - low) * scale)
+                .to_usize()
+                .unwrap_or(0)
+                .min(bins - 1)
+        };
+
+        let mut bin_counts = vec![0usize; bins];
+        let mut bin_bounds = vec![Aabb::empty(); bins];
+        for item in &items {
+            let bin = bin_of(&item.centroid);
+            bin_counts[bin] += 1;
+            bin_bounds[bin] = bin_bounds[bin].combine(&item.bound);
+        }
+
+        // sweep from the right to know bounds of everything past each plane
+        let mut right_areas = vec![F::zero(); bins];
+        let mut right_counts = vec![0usize; bins];
+        let mut acc_bound = Aabb::empty();
+        let mut acc_count = 0;
+        for bin in (1..bins).rev() {
+            acc_bound = acc_bound.combine(&bin_bounds[bin]);
+            acc_count += bin_counts[bin];
+            right_areas[bin] = acc_bound.surface_area();
+            right_counts[bin] = acc_count;
+        }
+
+        let parent_area = bound.surface_area();
+        let inv_parent_area = if parent_area > F::zero() {
+            parent_area.recip()
+        } else {
+            F::one()
+        };
+
+        let mut best: Option<(usize, F)> = None;
+        let mut acc_bound = Aabb::empty();
+        let mut acc_count = 0;
+        for plane in 1..bins {
+            acc_bound = acc_bound.combine(&bin_bounds[plane - 1]);
+            acc_count += bin_counts[plane - 1];
+            if acc_count == 0 || right_counts[plane] == 0 {
+                continue;
+            }
+
+            let weighted = acc_bound.surface_area() * F::from(acc_count).unwrap()
+                + right_areas[plane] * F::from(right_counts[plane]).unwrap();
+            let cost = self.traversal_cost + weighted * inv_parent_area * self.intersection_cost;
+
+            if best.is_none_or(|(_, best_cost)| cost < best_cost) {
+                best = Some((plane, cost));
+            }
+        }
+
+        let leaf_cost = self.intersection_cost * F::from(count).unwrap();
+        match best {
+            Some((_, cost)) if count <= self.max_leaf_size && cost >= leaf_cost => Err(items),
+            Some((plane, _)) => Ok(items
+                .into_iter()
+                .partition(|item| bin_of(&item.centroid) < plane)),
+            None if count <= self.max_leaf_size => Err(items),
+            None => {
+                let right = items.split_off(count / 2);
+                Ok((items, right))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use math::{Ray, Transform, Vector};
+    use scenegraph::{Instance, ShadedSphere};
+    use shading::DebugNormalMaterial;
+    use std::sync::Arc;
+    use tracing::Traceable;
+
+    fn depth<B, H, M, L>(bvh: &Bvh<f64, B, H, M, L>) -> usize
+    where
+        B: BoundingVolume<f64>,
+        H: Hitable<f64, Material = M>,
+        M: Material<f64>,
+        L: BvhLeaf<f64, B, H, M>,
+    {
+        1 + bvh
+            .children()
+            .iter()
+            .map(|child| match child {
+                BvhNode::Node(node) => depth(node),
+                BvhNode::Leaf(_) => 0,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    #[test]
+    fn test_sah_build_is_balanced_and_traces() {
+        let spheres: Vec<_> = (0..64)
+            .map(|i| {
+                let (x, y) = ((i % 8) as f64 * 3.0, (i / 8) as f64 * 3.0);
+                ShadedSphere::new(Point::new(x, y, 0.0), 1.0, DebugNormalMaterial {})
+            })
+            .collect();
+
+        let bvh: Bvh<f64, Aabb<f64>, _, _, _> = SahBuilder::new()
+            .with_max_leaf_size(2)
+            .build(spheres.clone())
+            .unwrap();
+
+        assert_eq!(bvh.children().len(), 2);
+        assert!(depth(&bvh) <= 8);
+
+        for sphere in &spheres {
+            for dx in &[-0.5, 0.0, 0.5] {
+                let target = ray_target(sphere, *dx);
+                let ray = Ray::new(target + Vector::new(0.0, 0.0, 10.0), -Vector::plus_z());
+                let expected = spheres.iter().filter_map(|s| s.trace(&ray)).map(|h| h.0);
+                let expected = expected.fold(f64::INFINITY, f64::min);
+                assert_eq!(bvh.trace(&ray).map(|h| h.0), Some(expected));
+            }
+        }
+    }
+
+    #[test]
+    fn test_refit_tracks_moved_leaves() {
+        let sphere = Arc::new(ShadedSphere::new(
+            Point::origin(),
+            1.0,
+            DebugNormalMaterial {},
+        ));
+        let place = |i: usize| {
+            let offset = Vector::new((i % 8) as f64 * 3.0, (i / 8) as f64 * 3.0, 0.0);
+            Transform::translation(offset)
+        };
+        let instances: Vec<_> = (0..64)
+            .map(|i| Instance::new(sphere.clone(), place(i)))
+            .collect();
+
+        let builder = SahBuilder::new().with_max_leaf_size(2);
+        let mut bvh: Bvh<f64, Aabb<f64>, _, _, _> = builder.build(instances).unwrap();
+        let built_cost = builder.sah_cost(&bvh);
+
+        // moving everything together keeps the tree as good as it was
+        let shift = Transform::translation(Vector::new(0.0, 0.0, -5.0));
+        bvh.for_each_leaf_mut(&mut |instance| {
+            let moved = instance.transform().then(&shift);
+            instance.set_transform(moved);
+        });
+        assert!((builder.refit(&mut bvh) - 1.0).abs() < 1e-9);
+        let down = |x: f64, y: f64| Ray::new(Point::new(x, y, 10.0), -Vector::plus_z());
+        assert_eq!(bvh.trace(&down(3.0, 6.0)).map(|h| h.0), Some(14.0));
+
+        // mirroring every other leaf across x interleaves subtrees that were disjoint
+        let mut index = 0;
+        bvh.for_each_leaf_mut(&mut |instance| {
+            if index % 2 == 0 {
+                let flip = Transform::scaling(-1.0, 1.0, 1.0).unwrap();
+                let moved = instance.transform().then(&flip);
+                instance.set_transform(moved);
+            }
+            index += 1;
+        });
+        assert!(builder.refit(&mut bvh) > 1.2);
+        assert!(builder.sah_cost(&bvh) > built_cost);
+
+        let mut centers = Vec::new();
+        bvh.for_each_leaf_mut(&mut |instance| {
+            centers.push(instance.transform().apply_point(Point::origin()))
+        });
+        for center in centers {
+            assert_eq!(
+                bvh.trace(&down(center.x, center.y)).map(|h| h.0),
+                Some(14.0)
+            );
+        }
+    }
+
+    fn ray_target(sphere: &ShadedSphere<f64, DebugNormalMaterial>, dx: f64) -> Point<f64> {
+        let bound: Aabb<f64> = sphere.bounding_volume();
+        bound.centroid() + Vector::new(dx, 0.0, 0.0)
+    }
+}
@@ -1,11 +1,21 @@
+use color::ScreenSpaceColor;
+use drawing::Framebuffer;
 use light::BounceQuota;
 use math::{Float, Point2D};
+use scenegraph::{CountedTraceable, TraversalCounters};
 use scheduling::TracingJob;
 use shading::Material;
 use std::marker::PhantomData;
 use std::sync::Arc;
 use tracing::{Camera, Hitable, Traceable};
 
+/// Which traversal counter `Scene::render_heatmap` shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeatmapMode {
+    NodeVisits,
+    PrimitiveTests,
+}
+
 pub struct Scene<F, H, M, T, C>
 where
     F: Float,
@@ -17,6 +27,7 @@ where
     camera: C,
     traceable: Arc<T>,
     quota: BounceQuota,
+    shutter: (F, F),
     _f: PhantomData<F>,
     _h: PhantomData<H>,
     _m: PhantomData<M>,
@@ -35,6 +46,7 @@ where
             camera,
             traceable: Arc::new(traceable),
             quota,
+            shutter: (F::zero(), F::zero()),
             _f: PhantomData,
             _h: PhantomData,
             _m: PhantomData,
@@ -45,6 +57,13 @@ where
         self.camera = camera
     }
 
+    /// Times the shutter opens and closes, which rays are spread over by
+    /// `job_for_sample`. Closed at time zero by default.
+    pub fn with_shutter(mut self, open: F, close: F) -> Self {
+        self.shutter = (open, close);
+        self
+    }
+
     // pub fn render_into(&self, framebuffer: &mut Framebuffer) {
     //     framebuffer.fill(|p| {
     //         let ray = self.camera.screen_ray(&p);
@@ -55,6 +74,42 @@ where
     // }
 }
 
+impl<F, H, M, T, C> Scene<F, H, M, T, C>
+where
+    F: Float,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+    T: CountedTraceable<F, H, M>,
+    C: Camera<F>,
+{
+    /// Debug view showing how much traversal work the primary ray of each
+    /// pixel takes, as a false color heatmap scaled to the busiest pixel.
+    /// Returns the count shown in full red.
+    pub fn render_heatmap(&self, framebuffer: &mut Framebuffer, mode: HeatmapMode) -> u32 {
+        let mut counts = Vec::with_capacity(framebuffer.width() * framebuffer.height());
+        framebuffer.fill(|point: Point2D<F>| {
+            let ray = self.camera.screen_ray(&point);
+            let ray = self.camera.at_time(ray, self.shutter.0);
+            let mut counters = TraversalCounters::default();
+            self.traceable.trace_counted(&ray, &mut counters);
+            counts.push(match mode {
+                HeatmapMode::NodeVisits => counters.node_visits,
+                HeatmapMode::PrimitiveTests => counters.primitive_tests,
+            });
+            ScreenSpaceColor::rgb(0, 0, 0)
+        });
+
+        let max = counts.iter().cloned().max().unwrap_or(0).max(1);
+        let scale = F::from(max).unwrap().recip();
+        let mut counts = counts.into_iter();
+        framebuffer.fill(|_: Point2D<F>| {
+            let count = counts.next().unwrap_or(0);
+            ScreenSpaceColor::heat(F::from(count).unwrap() * scale)
+        });
+        max
+    }
+}
+
 impl<F, H, M, T, C> Scene<F, H, M, T, C>
 where
     F: Float,
@@ -64,9 +119,29 @@ where
     C: Camera<F> + Sync,
 {
     pub fn job_for_fragment(&self, point: &Point2D<F>) -> TracingJob<F, H, M, T> {
-        let ray = self.camera.screen_ray(&point);
+        let (open, _) = self.shutter;
+        let ray = self.camera.at_time(self.camera.screen_ray(point), open);
+
+        TracingJob::new(ray, self.traceable.clone(), self.quota)
+    }
+
+    /// Job for a ray from the point of the lens picked by `lens_sample`,
+    /// sent `shutter_sample` of the way from the shutter opening to closing.
+    /// Averaging samples spread over `0..1` blurs whatever is out of focus
+    /// or moves in the meantime, the camera included.
+    pub fn job_for_sample(
+        &self,
+        point: &Point2D<F>,
+        lens_sample: &Point2D<F>,
+        shutter_sample: F,
+    ) -> TracingJob<F, H, M, T> {
+        let (open, close) = self.shutter;
+        let ray = self.camera.at_time(
+            self.camera.lens_ray(point, lens_sample),
+            shutter_sample.lerp(open, close),
+        );
 
-        TracingJob::new(ray, self.traceable.clone(), self.quota.clone())
+        TracingJob::new(ray, self.traceable.clone(), self.quota)
     }
 
     // pub fn prepare_render_into<'a, H: 'a + Spawn + Clone + Sync>(
@@ -0,0 +1,518 @@
+use math::{Aabb, Bounded, Float, Point, Ray, Vector, UV};
+use shading::Material;
+use tracing::{HitPoint, Hitable, Traceable};
+
+/// Signed distance field, negative inside the shape. Fields that are not
+/// exact distances, like a `Twist`, must never overestimate the distance by
+/// more than the step scale of the `SdfObject` tracing them allows.
+pub trait Sdf<F: Float> {
+    fn distance(&self, point: Point<F>) -> F;
+
+    /// Direction in which the distance grows fastest, by central differences
+    /// `h` apart unless the field knows it analytically. Only the direction
+    /// matters for the normal.
+    fn gradient(&self, point: Point<F>, h: F) -> Vector<F> {
+        let difference =
+            |offset: Vector<F>| self.distance(point + offset) - self.distance(point - offset);
+        Vector::new(
+            difference(Vector::plus_x() * h),
+            difference(Vector::plus_y() * h),
+            difference(Vector::plus_z() * h),
+        ) * (h + h).recip()
+    }
+
+    /// Blends into the union with `other` within a distance of about `k`,
+    /// a `k` of zero gives the plain union.
+    fn smooth_union<B: Sdf<F>>(self, other: B, k: F) -> SmoothUnion<F, Self, B>
+    where
+        Self: Sized,
+    {
+        SmoothUnion {
+            a: self,
+            b: other,
+            k,
+        }
+    }
+
+    /// This shape with `other` cut out of it.
+    fn subtract<B: Sdf<F>>(self, other: B) -> Subtraction<Self, B>
+    where
+        Self: Sized,
+    {
+        Subtraction { a: self, b: other }
+    }
+
+    /// Infinitely many copies `period` apart, centered on the origin, along
+    /// each axis with a nonzero period. The shape must fit in one period.
+    fn repeat(self, period: Vector<F>) -> Repetition<F, Self>
+    where
+        Self: Sized,
+    {
+        Repetition {
+            inner: self,
+            period,
+        }
+    }
+
+    /// Rotated around the y axis by `rate` radians per unit of height.
+    fn twist(self, rate: F) -> Twist<F, Self>
+    where
+        Self: Sized,
+    {
+        Twist { inner: self, rate }
+    }
+
+    /// Grown by `radius`, rounding off edges and corners.
+    fn rounded(self, radius: F) -> Rounded<F, Self>
+    where
+        Self: Sized,
+    {
+        Rounded {
+            inner: self,
+            radius,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SdfSphere<F: Float> {
+    center: Point<F>,
+    radius: F,
+}
+
+impl<F: Float> SdfSphere<F> {
+    pub fn new(center: Point<F>, radius: F) -> Self {
+        SdfSphere { center, radius }
+    }
+}
+
+impl<F: Float> Sdf<F> for SdfSphere<F> {
+    fn distance(&self, point: Point<F>) -> F {
+        (point - self.center).magnitude() - self.radius
+    }
+
+    fn gradient(&self, point: Point<F>, _h: F) -> Vector<F> {
+        point - self.center
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SdfBox<F: Float> {
+    center: Point<F>,
+    half_extents: Vector<F>,
+}
+
+impl<F: Float> SdfBox<F> {
+    pub fn new(center: Point<F>, half_extents: Vector<F>) -> Self {
+        SdfBox {
+            center,
+            half_extents,
+        }
+    }
+}
+
+impl<F: Float> Sdf<F> for SdfBox<F> {
+    fn distance(&self, point: Point<F>) -> F {
+        let p = point - self.center;
+        let q = Vector::new(
+            p.x.abs() - self.half_extents.x,
+            p.y.abs() - self.half_extents.y,
+            p.z.abs() - self.half_extents.z,
+        );
+        let outside = Vector::new(
+            F::max(q.x, F::zero()),
+            F::max(q.y, F::zero()),
+            F::max(q.z, F::zero()),
+        );
+        outside.magnitude() + F::min(F::max(q.x, F::max(q.y, q.z)), F::zero())
+    }
+}
+
+/// Torus around the y axis.
+#[derive(Clone, Copy, Debug)]
+pub struct SdfTorus<F: Float> {
+    center: Point<F>,
+    major_radius: F,
+    minor_radius: F,
+}
+
+impl<F: Float> SdfTorus<F> {
+    pub fn new(center: Point<F>, major_radius: F, minor_radius: F) -> Self {
+        SdfTorus {
+            center,
+            major_radius,
+            minor_radius,
+        }
+    }
+}
+
+impl<F: Float> Sdf<F> for SdfTorus<F> {
+    fn distance(&self, point: Point<F>) -> F {
+        let p = point - self.center;
+        (p.x.hypot(p.z) - self.major_radius).hypot(p.y) - self.minor_radius
+    }
+}
+
+/// Polynomial smooth minimum of two fields, see `Sdf::smooth_union`.
+#[derive(Clone, Copy, Debug)]
+pub struct SmoothUnion<F: Float, A: Sdf<F>, B: Sdf<F>> {
+    a: A,
+    b: B,
+    k: F,
+}
+
+impl<F: Float, A: Sdf<F>, B: Sdf<F>> SmoothUnion<F, A, B> {
+    /// Weight of `a` in the blend, and its derivative with respect to the
+    /// difference `b - a`.
+    fn blend(&self, a: F, b: F) -> (F, F) {
+        let half = (F::one() + F::one()).recip();
+        let h = half + half * (b - a) / self.k;
+        if h <= F::zero() {
+            (F::zero(), F::zero())
+        } else if h >= F::one() {
+            (F::one(), F::zero())
+        } else {
+            (h, half / self.k)
+        }
+    }
+}
+
+impl<F: Float, A: Sdf<F>, B: Sdf<F>> Sdf<F> for SmoothUnion<F, A, B> {
+    fn distance(&self, point: Point<F>) -> F {
+        let (a, b) = (self.a.distance(point), self.b.distance(point));
+        if self.k <= F::zero() {
+            return F::min(a, b);
+        }
+        let (h, _) = self.blend(a, b);
+        b + (a - b) * h - self.k * h * (F::one() - h)
+    }
+
+    fn gradient(&self, point: Point<F>, h: F) -> Vector<F> {
+        let (a, b) = (self.a.distance(point), self.b.distance(point));
+        let (ga, gb) = (self.a.gradient(point, h), self.b.gradient(point, h));
+        // only the directions of the gradients are known, scale them to
+        // unit length before blending
+        let (ga, gb) = (unit(ga), unit(gb));
+        if self.k <= F::zero() {
+            return if a < b { ga } else { gb };
+        }
+        let (w, dw) = self.blend(a, b);
+        let two = F::one() + F::one();
+        let dh = (gb - ga) * dw;
+        gb + (ga - gb) * w + dh * (a - b) - dh * (self.k * (F::one() - two * w))
+    }
+}
+
+fn unit<F: Float>(v: Vector<F>) -> Vector<F> {
+    if v.magnitude_sq() > F::zero() {
+        v.normalized()
+    } else {
+        v
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Subtraction<A, B> {
+    a: A,
+    b: B,
+}
+
+impl<F: Float, A: Sdf<F>, B: Sdf<F>> Sdf<F> for Subtraction<A, B> {
+    fn distance(&self, point: Point<F>) -> F {
+        F::max(self.a.distance(point), -self.b.distance(point))
+    }
+
+    fn gradient(&self, point: Point<F>, h: F) -> Vector<F> {
+        if self.a.distance(point) > -self.b.distance(point) {
+            self.a.gradient(point, h)
+        } else {
+            -self.b.gradient(point, h)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Repetition<F: Float, S: Sdf<F>> {
+    inner: S,
+    period: Vector<F>,
+}
+
+impl<F: Float, S: Sdf<F>> Repetition<F, S> {
+    /// The point moved into the copy around the origin.
+    fn wrap(&self, point: Point<F>) -> Point<F> {
+        let wrap = |x: F, period: F| {
+            if period == F::zero() {
+                x
+            } else {
+                x - period * (x / period).round()
+            }
+        };
+        Point::new(
+            wrap(point.x, self.period.x),
+            wrap(point.y, self.period.y),
+            wrap(point.z, self.period.z),
+        )
+    }
+}
+
+impl<F: Float, S: Sdf<F>> Sdf<F> for Repetition<F, S> {
+    fn distance(&self, point: Point<F>) -> F {
+        self.inner.distance(self.wrap(point))
+    }
+
+    fn gradient(&self, point: Point<F>, h: F) -> Vector<F> {
+        self.inner.gradient(self.wrap(point), h)
+    }
+}
+
+/// Twisted shapes are no exact distance fields anymore, trace them with a
+/// step scale below one, the stronger the twist the smaller.
+#[derive(Clone, Copy, Debug)]
+pub struct Twist<F: Float, S: Sdf<F>> {
+    inner: S,
+    rate: F,
+}
+
+impl<F: Float, S: Sdf<F>> Sdf<F> for Twist<F, S> {
+    fn distance(&self, point: Point<F>) -> F {
+        let (sin, cos) = (-self.rate * point.y).sin_cos();
+        let x = point.x * cos - point.z * sin;
+        let z = point.x * sin + point.z * cos;
+        self.inner.distance(Point::new(x, point.y, z))
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Rounded<F: Float, S: Sdf<F>> {
+    inner: S,
+    radius: F,
+}
+
+impl<F: Float, S: Sdf<F>> Sdf<F> for Rounded<F, S> {
+    fn distance(&self, point: Point<F>) -> F {
+        self.inner.distance(point) - self.radius
+    }
+
+    fn gradient(&self, point: Point<F>, h: F) -> Vector<F> {
+        self.inner.gradient(point, h)
+    }
+}
+
+/// Sphere traces a distance field inside `bounds`, which must contain the
+/// whole surface, as anything outside is cut off. Being `Bounded` it can go
+/// into a `Bvh`, next to other primitives through `Shape::erased`.
+///
+/// Hits are reported within `tolerance` of the surface, which also spaces
+/// the samples for finite difference normals. The UV of the surface are
+/// always zero.
+#[derive(Clone, Copy)]
+pub struct SdfObject<F: Float, S: Sdf<F>, M: Material<F>> {
+    sdf: S,
+    bounds: Aabb<F>,
+    material: M,
+    tolerance: F,
+    step_scale: F,
+    max_steps: usize,
+    casts_shadows: bool,
+}
+
+impl<F: Float, S: Sdf<F>, M: Material<F>> SdfObject<F, S, M> {
+    /// Starts with a tolerance of a millionth of the bounds diagonal and at
+    /// most 256 full steps.
+    pub fn new(sdf: S, bounds: Aabb<F>, material: M) -> Self {
+        let diagonal = (bounds.max_point() - bounds.min_point()).magnitude();
+        SdfObject {
+            sdf,
+            bounds,
+            material,
+            tolerance: diagonal * F::from(1e-6).unwrap(),
+            step_scale: F::one(),
+            max_steps: 256,
+            casts_shadows: true,
+        }
+    }
+
+    pub fn with_tolerance(mut self, tolerance: F) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Fraction of the distance to advance each step, below one for fields
+    /// that overestimate the distance.
+    pub fn with_step_scale(mut self, step_scale: F) -> Self {
+        self.step_scale = step_scale;
+        self
+    }
+
+    /// Rays taking more steps than this, mostly ones grazing the surface,
+    /// are counted as misses.
+    pub fn with_max_steps(mut self, max_steps: usize) -> Self {
+        self.max_steps = max_steps;
+        self
+    }
+
+    pub fn with_casts_shadows(mut self, casts_shadows: bool) -> Self {
+        self.casts_shadows = casts_shadows;
+        self
+    }
+
+    pub fn sdf(&self) -> &S {
+        &self.sdf
+    }
+}
+
+impl<F: Float, S: Sdf<F>, M: Material<F>> Hitable<F> for SdfObject<F, S, M> {
+    type Material = M;
+    fn get_hit(&self, ray: &Ray<F>, distance: F) -> HitPoint<F, M> {
+        let point = ray.point_at_distance(distance);
+        let gradient = self.sdf.gradient(point, self.tolerance);
+        let normal = if gradient.magnitude_sq() > F::zero() {
+            gradient.normalized()
+        } else {
+            -ray.direction.normalized()
+        };
+        HitPoint::new(
+            point,
+            normal,
+            ray.direction,
+            UV::default(),
+            self.material.clone(),
+        )
+    }
+}
+
+impl<F: Float, S: Sdf<F>, M: Material<F>> Traceable<F, Self, M> for SdfObject<F, S, M> {
+    fn trace(&self, ray: &Ray<F>) -> Option<(F, &Self)> {
+        let (near, far) = self.bounds.intersect(ray)?;
+        let end = F::min(far, ray.t_max);
+        let mut t = F::max(near, ray.t_min);
+
+        // from inside the shape march towards its surface from within
+        let distance = |t: F| self.sdf.distance(ray.point_at_distance(t));
+        let side = if distance(t) < F::zero() {
+            -F::one()
+        } else {
+            F::one()
+        };
+        // the speed of the ray turns distances into steps along it
+        let speed = ray.direction.magnitude();
+
+        for _ in 0..self.max_steps {
+            if t > end {
+                return None;
+            }
+            let d = side * distance(t);
+            if d < self.tolerance && ray.contains(t) {
+                return Some((t, self));
+            }
+            t = t + F::max(d * self.step_scale, self.tolerance) / speed;
+        }
+        None
+    }
+
+    fn casts_shadows(&self) -> bool {
+        self.casts_shadows
+    }
+}
+
+impl<F: Float, S: Sdf<F>, M: Material<F>> Bounded<F, Aabb<F>> for SdfObject<F, S, M> {
+    fn bounding_volume(&self) -> Aabb<F> {
+        self.bounds
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use scenegraph::{Bvh, SahBuilder, ShadedSphere, Shape};
+    use shading::DebugNormalMaterial;
+    use testing::close_within;
+
+    #[test]
+    fn test_combinators() {
+        let ball = SdfSphere::new(Point::origin(), 1.0);
+        let cube = SdfBox::new(Point::origin(), Vector::new(1.0, 1.0, 1.0));
+        let p = Point::new(2.0, 0.0, 0.0);
+        assert_eq!(ball.distance(p), 1.0);
+        assert_eq!(cube.distance(Point::new(2.0, 2.0, 1.0)), 2f64.sqrt());
+        assert_eq!(cube.rounded(0.5).distance(p), 0.5);
+
+        // the blend reaches further out than either shape
+        let pair = ball.smooth_union(SdfSphere::new(Point::new(0.0, 3.0, 0.0), 1.0), 1.0);
+        let middle = Point::new(0.0, 1.5, 0.0);
+        assert!(pair.distance(middle) < 0.5);
+        assert!(close_within(
+            pair.gradient(middle, 1e-3),
+            Vector::new(0.0, 0.0, 0.0),
+            1e-9
+        ));
+        let side = Point::new(1.2, 0.5, 0.3);
+        let numeric = Vector::new(1e-5, 0.0, 0.0);
+        let expected = (pair.distance(side + numeric) - pair.distance(side - numeric)) / 2e-5;
+        assert!((pair.gradient(side, 1e-3).x - expected).abs() < 1e-6);
+
+        let copies = ball.repeat(Vector::new(4.0, 0.0, 0.0));
+        assert_eq!(copies.distance(Point::new(8.5, 0.0, 0.0)), -0.5);
+        assert_eq!(copies.distance(Point::new(8.0, 3.0, 0.0)), 2.0);
+
+        let hollow = cube.subtract(ball.rounded(0.2));
+        assert!(hollow.distance(Point::origin()) > 0.0);
+        assert!(close_within(
+            hollow.gradient(Point::new(1.0, 0.0, 0.0), 1e-3),
+            -Vector::plus_x(),
+            1e-9
+        ));
+
+        // a quarter turn at height 1 maps x onto z
+        let bar = SdfBox::new(Point::origin(), Vector::new(2.0, 2.0, 0.1))
+            .twist(::std::f64::consts::FRAC_PI_2);
+        assert!(bar.distance(Point::new(0.0, 1.0, 1.5)) < 0.0);
+        assert!(bar.distance(Point::new(1.5, 1.0, 0.0)) > 0.0);
+    }
+
+    #[test]
+    fn test_sphere_tracing() {
+        let torus = SdfTorus::new(Point::origin(), 2.0, 0.5);
+        let bounds = Aabb::from_points(&[Point::new(-3.0, -1.0, -3.0), Point::new(3.0, 1.0, 3.0)]);
+        let object = SdfObject::new(torus, bounds, DebugNormalMaterial {});
+        let hit = |origin: Point<f64>, direction: Vector<f64>| {
+            let ray = Ray::new(origin, direction);
+            object
+                .trace(&ray)
+                .map(|(t, h)| (t, h.get_hit(&ray, t).data))
+        };
+
+        let (t, data) = hit(Point::new(-5.0, 0.0, 0.0), Vector::plus_x()).unwrap();
+        assert!((t - 2.5).abs() < 1e-5);
+        assert!(close_within(data.normal, -Vector::plus_x(), 1e-4));
+
+        // unnormalized directions and rays from inside the tube
+        let (t, data) = hit(Point::new(2.0, 5.0, 0.0), -Vector::plus_y() * 2.0).unwrap();
+        assert!((t - 2.25).abs() < 1e-5);
+        assert!(close_within(data.normal, Vector::plus_y(), 1e-4));
+        let (t, data) = hit(Point::new(2.0, 0.0, 0.0), Vector::plus_y()).unwrap();
+        assert!((t - 0.5).abs() < 1e-5);
+        assert!(close_within(data.normal, Vector::plus_y(), 1e-4));
+
+        // through the hole
+        assert!(hit(Point::new(0.0, 5.0, 0.0), -Vector::plus_y()).is_none());
+
+        // next to a sphere in one `Bvh`
+        let shapes = vec![
+            Shape::from(ShadedSphere::new(
+                Point::new(0.0, 0.0, 6.0),
+                1.0,
+                DebugNormalMaterial {},
+            )),
+            Shape::erased(object),
+        ];
+        let bvh: Bvh<f64, Aabb<f64>, _, _, _> = SahBuilder::new().build(shapes).unwrap();
+        let trace = |origin: Point<f64>, direction: Vector<f64>| {
+            bvh.trace(&Ray::new(origin, direction)).map(|(t, _)| t)
+        };
+        let t = trace(Point::new(0.0, 0.0, 10.0), -Vector::plus_z()).unwrap();
+        assert!((t - 3.0).abs() < 1e-9);
+        let t = trace(Point::new(-5.0, 0.0, 0.0), Vector::plus_x()).unwrap();
+        assert!((t - 2.5).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,286 @@
+use math::{Aabb, Bounded, Float, Ray};
+use scenegraph::{
+    Cone, Cuboid, Cylinder, Disk, MeshTriangle, Plane, Rectangle, ShadedSphere, Torus, Triangle,
+};
+use shading::Material;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use tracing::{HitPoint, Hitable, Traceable};
+
+/// Object safe view of a bounded `Traceable`, hiding its hitable type so
+/// arbitrary objects can share one `Bvh` through `Shape::Erased`.
+pub trait ErasedTraceable<F: Float, M: Material<F>>: Send + Sync {
+    fn trace_distance(&self, ray: &Ray<F>) -> Option<F>;
+
+    /// Hit point for a `distance` returned by `trace_distance` on the same
+    /// ray.
+    fn hit_at(&self, ray: &Ray<F>, distance: F) -> HitPoint<F, M>;
+
+    fn aabb(&self) -> Aabb<F>;
+
+    fn casts_shadows(&self) -> bool;
+
+    fn occluded(&self, ray: &Ray<F>, t_max: F) -> bool;
+}
+
+struct Erased<T, H> {
+    object: T,
+    _h: PhantomData<fn() -> H>,
+}
+
+impl<F, H, M, T> ErasedTraceable<F, M> for Erased<T, H>
+where
+    F: Float,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+    T: Traceable<F, H, M> + Bounded<F, Aabb<F>> + Send + Sync,
+{
+    fn trace_distance(&self, ray: &Ray<F>) -> Option<F> {
+        self.object.trace(ray).map(|(distance, _)| distance)
+    }
+
+    fn hit_at(&self, ray: &Ray<F>, distance: F) -> HitPoint<F, M> {
+        let (_, hitable) = self
+            .object
+            .trace(ray)
+            .expect("hit vanished when tracing the same ray again");
+        hitable.get_hit(ray, distance)
+    }
+
+    fn aabb(&self) -> Aabb<F> {
+        self.object.bounding_volume()
+    }
+
+    fn casts_shadows(&self) -> bool {
+        self.object.casts_shadows()
+    }
+
+    fn occluded(&self, ray: &Ray<F>, t_max: F) -> bool {
+        self.object.occluded(ray, t_max)
+    }
+}
+
+/// Leaf type for scenes mixing different kinds of geometry in one `Bvh`.
+///
+/// The primitives of this crate are dispatched through the enum without any
+/// indirection. Anything else, like a whole mesh `Bvh` or an `Instance`, can
+/// be wrapped with `Shape::erased` at the cost of a virtual call and tracing
+/// it once more for the final hit. Materials are mixed by choosing an enum
+/// material such as `AnyMaterial` for `M`.
+///
+/// `Shape::Plane` is unbounded and BVH builders refuse it, it only exists
+/// so planes can share the hitable type with the rest of the scene inside
+/// an `Unbounded`.
+///
+/// Homogeneous scenes should keep using their primitive type as leaf
+/// directly, which avoids the dispatch entirely.
+#[derive(Clone)]
+pub enum Shape<F: Float, M: Material<F>> {
+    Sphere(ShadedSphere<F, M>),
+    Triangle(Triangle<F, M>),
+    MeshTriangle(MeshTriangle<F, M>),
+    Plane(Plane<F, M>),
+    Rectangle(Rectangle<F, M>),
+    Disk(Disk<F, M>),
+    Cuboid(Cuboid<F, M>),
+    Cylinder(Cylinder<F, M>),
+    Cone(Cone<F, M>),
+    Torus(Torus<F, M>),
+    Erased(Arc<dyn ErasedTraceable<F, M>>),
+}
+
+/// Matches every primitive variant binding it to `$p`, and `Erased` to `$o`.
+macro_rules! dispatch {
+    ($shape:expr, $p:ident => $primitive:expr, $o:ident => $erased:expr) => {
+        match $shape {
+            Shape::Sphere($p) => $primitive,
+            Shape::Triangle($p) => $primitive,
+            Shape::MeshTriangle($p) => $primitive,
+            Shape::Plane($p) => $primitive,
+            Shape::Rectangle($p) => $primitive,
+            Shape::Disk($p) => $primitive,
+            Shape::Cuboid($p) => $primitive,
+            Shape::Cylinder($p) => $primitive,
+            Shape::Cone($p) => $primitive,
+            Shape::Torus($p) => $primitive,
+            Shape::Erased($o) => $erased,
+        }
+    };
+}
+
+impl<F: Float, M: Material<F>> Shape<F, M> {
+    pub fn erased<H, T>(object: T) -> Self
+    where
+        H: Hitable<F, Material = M> + 'static,
+        T: Traceable<F, H, M> + Bounded<F, Aabb<F>> + Send + Sync + 'static,
+    {
+        Shape::Erased(Arc::new(Erased {
+            object,
+            _h: PhantomData,
+        }))
+    }
+}
+
+impl<F: Float, M: Material<F>> Hitable<F> for Shape<F, M> {
+    type Material = M;
+    fn get_hit(&self, ray: &Ray<F>, distance: F) -> HitPoint<F, M> {
+        dispatch!(self, p => p.get_hit(ray, distance), o => o.hit_at(ray, distance))
+    }
+}
+
+impl<F: Float, M: Material<F>> Traceable<F, Self, M> for Shape<F, M> {
+    fn trace(&self, ray: &Ray<F>) -> Option<(F, &Self)> {
+        let distance = dispatch!(self, p => p.trace(ray).map(|h| h.0), o => o.trace_distance(ray));
+        distance.map(|distance| (distance, self))
+    }
+
+    fn casts_shadows(&self) -> bool {
+        dispatch!(self, p => p.casts_shadows(), o => o.casts_shadows())
+    }
+
+    fn occluded(&self, ray: &Ray<F>, t_max: F) -> bool {
+        dispatch!(self, p => p.occluded(ray, t_max), o => o.occluded(ray, t_max))
+    }
+}
+
+impl<F: Float, M: Material<F>> Bounded<F, Aabb<F>> for Shape<F, M> {
+    /// `Aabb::unbound` for `Shape::Plane`.
+    fn bounding_volume(&self) -> Aabb<F> {
+        match self {
+            Shape::Sphere(s) => s.bounding_volume(),
+            Shape::Triangle(t) => t.bounding_volume(),
+            Shape::MeshTriangle(t) => t.bounding_volume(),
+            Shape::Plane(_) => Aabb::unbound(),
+            Shape::Rectangle(r) => r.bounding_volume(),
+            Shape::Disk(d) => d.bounding_volume(),
+            Shape::Cuboid(c) => c.bounding_volume(),
+            Shape::Cylinder(c) => c.bounding_volume(),
+            Shape::Cone(c) => c.bounding_volume(),
+            Shape::Torus(t) => t.bounding_volume(),
+            Shape::Erased(o) => o.aabb(),
+        }
+    }
+}
+
+impl<F: Float, M: Material<F>> From<ShadedSphere<F, M>> for Shape<F, M> {
+    fn from(sphere: ShadedSphere<F, M>) -> Self {
+        Shape::Sphere(sphere)
+    }
+}
+
+impl<F: Float, M: Material<F>> From<Triangle<F, M>> for Shape<F, M> {
+    fn from(triangle: Triangle<F, M>) -> Self {
+        Shape::Triangle(triangle)
+    }
+}
+
+impl<F: Float, M: Material<F>> From<MeshTriangle<F, M>> for Shape<F, M> {
+    fn from(triangle: MeshTriangle<F, M>) -> Self {
+        Shape::MeshTriangle(triangle)
+    }
+}
+
+impl<F: Float, M: Material<F>> From<Plane<F, M>> for Shape<F, M> {
+    fn from(plane: Plane<F, M>) -> Self {
+        Shape::Plane(plane)
+    }
+}
+
+impl<F: Float, M: Material<F>> From<Rectangle<F, M>> for Shape<F, M> {
+    fn from(rectangle: Rectangle<F, M>) -> Self {
+        Shape::Rectangle(rectangle)
+    }
+}
+
+impl<F: Float, M: Material<F>> From<Disk<F, M>> for Shape<F, M> {
+    fn from(disk: Disk<F, M>) -> Self {
+        Shape::Disk(disk)
+    }
+}
+
+impl<F: Float, M: Material<F>> From<Cuboid<F, M>> for Shape<F, M> {
+    fn from(cuboid: Cuboid<F, M>) -> Self {
+        Shape::Cuboid(cuboid)
+    }
+}
+
+impl<F: Float, M: Material<F>> From<Cylinder<F, M>> for Shape<F, M> {
+    fn from(cylinder: Cylinder<F, M>) -> Self {
+        Shape::Cylinder(cylinder)
+    }
+}
+
+impl<F: Float, M: Material<F>> From<Cone<F, M>> for Shape<F, M> {
+    fn from(cone: Cone<F, M>) -> Self {
+        Shape::Cone(cone)
+    }
+}
+
+impl<F: Float, M: Material<F>> From<Torus<F, M>> for Shape<F, M> {
+    fn from(torus: Torus<F, M>) -> Self {
+        Shape::Torus(torus)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use light::Spectrum;
+    use math::{Point, Vector};
+    use scenegraph::{Bvh, SahBuilder, TriangleMesh};
+    use shading::{AnyMaterial, ConstantMaterial, DebugNormalMaterial};
+
+    #[test]
+    fn test_mixed_shapes_and_materials() {
+        let red = AnyMaterial::from(ConstantMaterial::new(Spectrum {
+            v: [1.0, 0.0, 0.0, 0.0],
+        }));
+        let normals = AnyMaterial::from(DebugNormalMaterial {});
+
+        let quad = TriangleMesh::new(
+            vec![
+                Point::new(4.0, -1.0, 0.0),
+                Point::new(6.0, -1.0, 0.0),
+                Point::new(6.0, 1.0, 0.0),
+                Point::new(4.0, 1.0, 0.0),
+            ],
+            None,
+            None,
+            vec![[0, 1, 2], [0, 2, 3]],
+            red.clone(),
+        )
+        .unwrap();
+
+        let shapes = vec![
+            Shape::from(ShadedSphere::new(Point::origin(), 1.0, normals.clone())),
+            Shape::from(Triangle::new(
+                [
+                    Point::new(-6.0, -1.0, 0.0),
+                    Point::new(-4.0, -1.0, 0.0),
+                    Point::new(-5.0, 1.0, 0.0),
+                ],
+                red.clone(),
+            )),
+            Shape::erased(quad),
+        ];
+        let bvh: Bvh<f64, Aabb<f64>, _, _, _> = SahBuilder::new().build(shapes).unwrap();
+
+        let down = |x: f64| Ray::new(Point::new(x, 0.0, 5.0), -Vector::plus_z());
+        let hit = |x: f64| {
+            let ray = down(x);
+            bvh.trace(&ray).map(|(t, h)| (t, h.get_hit(&ray, t)))
+        };
+
+        let (t, h) = hit(0.0).expect("sphere missed");
+        assert_eq!(t, 4.0);
+        assert!(matches!(h.material, AnyMaterial::DebugNormal(_)));
+
+        for &x in &[-5.0, 5.0] {
+            let (t, h) = hit(x).expect("triangle missed");
+            assert_eq!(t, 5.0);
+            assert!((h.data.point - Point::new(x, 0.0, 0.0)).magnitude() < 1e-9);
+            assert!(matches!(h.material, AnyMaterial::Constant(_)));
+        }
+        assert!(hit(2.5).is_none());
+    }
+}
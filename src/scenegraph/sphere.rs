@@ -1,4 +1,6 @@
-use math::{Aabb, Bounded, BoundingVolume, Float, Point, Ray, Vector};
+use math::{
+    keyframe_blend, turns, Aabb, Bounded, BoundingVolume, Dop14, Float, Obb, Point, Ray, Vector, UV,
+};
 use shading::Material;
 use tracing::{HitPoint, Hitable, Traceable};
 
@@ -18,15 +20,72 @@ impl<F: Float> Sphere<F> {
         }
     }
 
+    /// Enclosing sphere after Ritter, "An Efficient Bounding Sphere". Up to
+    /// about a fifth larger than the smallest one.
+    pub fn from_points(points: &[Point<F>]) -> Self {
+        let half = (F::one() + F::one()).recip();
+        let first = match points.first() {
+            Some(first) => *first,
+            None => return Sphere::new(Point::origin(), F::zero()),
+        };
+        let farthest_from = |from: Point<F>| {
+            points.iter().cloned().fold(from, |best, p| {
+                if (p - from).magnitude_sq() > (best - from).magnitude_sq() {
+                    p
+                } else {
+                    best
+                }
+            })
+        };
+        let a = farthest_from(first);
+        let b = farthest_from(a);
+
+        let mut sphere = Sphere::new(a + (b - a) * half, (b - a).magnitude() * half);
+        for p in points {
+            let distance = (*p - sphere.center).magnitude();
+            if distance > sphere.radius {
+                sphere = sphere.combine(&Sphere::new(*p, F::zero()));
+            }
+        }
+        sphere
+    }
+
+    pub fn center(&self) -> Point<F> {
+        self.center
+    }
+
+    pub fn radius(&self) -> F {
+        self.radius
+    }
+
     pub fn normal_at(&self, point: Point<F>) -> Vector<F> {
         (point - self.center).normalized()
     }
+
+    /// Both distances at which the ray line crosses the sphere, ignoring the
+    /// ray interval. The direction does not need to be normalized.
+    pub fn intersect(&self, ray: &Ray<F>) -> Option<(F, F)> {
+        let oc = self.center - ray.origin;
+        let a = ray.direction.magnitude_sq();
+        let half_b = oc.dot(ray.direction);
+        let c = oc.magnitude_sq() - self.radius_sq;
+
+        let discriminant = half_b * half_b - a * c;
+        if discriminant < F::zero() {
+            return None;
+        }
+
+        let root = discriminant.sqrt();
+        let inv_a = a.recip();
+        Some(((half_b - root) * inv_a, (half_b + root) * inv_a))
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
 pub struct ShadedSphere<F: Float, M: Material<F>> {
     inner: Sphere<F>,
     material: M,
+    casts_shadows: bool,
 }
 
 impl<F: Float, M: Material<F>> ShadedSphere<F, M> {
@@ -34,50 +93,164 @@ impl<F: Float, M: Material<F>> ShadedSphere<F, M> {
         ShadedSphere {
             inner: Sphere::new(center, radius),
             material,
+            casts_shadows: true,
         }
     }
+
+    pub fn with_casts_shadows(mut self, casts_shadows: bool) -> Self {
+        self.casts_shadows = casts_shadows;
+        self
+    }
+
+    pub fn sphere(&self) -> &Sphere<F> {
+        &self.inner
+    }
 }
 
 impl<F: Float, M: Material<F>> Hitable<F> for ShadedSphere<F, M> {
     type Material = M;
     fn get_hit(&self, ray: &Ray<F>, distance: F) -> HitPoint<F, Self::Material> {
         let point = ray.point_at_distance(distance);
-
+        let normal = self.inner.normal_at(point);
         HitPoint::new(
             point,
-            self.inner.normal_at(point),
+            normal,
             ray.direction,
-            Default::default(),
+            sphere_uv(normal),
             self.material.clone(),
         )
     }
 }
 
+/// Longitude around the y axis, latitude from the bottom.
+fn sphere_uv<F: Float>(normal: Vector<F>) -> UV<F> {
+    UV::new(
+        turns(normal.z, normal.x),
+        F::one() - normal.y.max(-F::one()).min(F::one()).acos() / F::PI(),
+    )
+}
+
+/// Closest root inside the ray interval. From inside the sphere the near
+/// root is behind the ray origin.
+fn nearest_root<F: Float>(sphere: &Sphere<F>, ray: &Ray<F>) -> Option<F> {
+    let (near, far) = sphere.intersect(ray)?;
+    if ray.contains(near) {
+        Some(near)
+    } else if ray.contains(far) {
+        Some(far)
+    } else {
+        None
+    }
+}
+
+fn occludes<F: Float>(sphere: &Sphere<F>, ray: &Ray<F>, t_max: F) -> bool {
+    let t_max = F::min(ray.t_max, t_max);
+    let inside = |t: F| t > ray.t_min && t < t_max;
+    sphere
+        .intersect(ray)
+        .is_some_and(|(near, far)| inside(near) || inside(far))
+}
+
 impl<F, M> Traceable<F, Self, M> for ShadedSphere<F, M>
 where
     F: Float,
     M: Material<F>,
 {
     fn trace(&self, ray: &Ray<F>) -> Option<(F, &Self)> {
-        let oc = self.inner.center - ray.origin;
-        let closest_tangent_dist = oc.dot(ray.direction);
+        nearest_root(&self.inner, ray).map(|t| (t, self))
+    }
 
-        if closest_tangent_dist < F::zero() {
-            // sphere behind ray
-            return None;
-        };
+    fn casts_shadows(&self) -> bool {
+        self.casts_shadows
+    }
+
+    fn occluded(&self, ray: &Ray<F>, t_max: F) -> bool {
+        self.casts_shadows && occludes(&self.inner, ray, t_max)
+    }
+}
 
-        let closest_on_tangent = ray.point_at_distance(closest_tangent_dist);
-        let sq_distance_to_tangent = (self.inner.center - closest_on_tangent).magnitude_sq();
+/// Sphere with a center moving between keyframes, which rays meet where it
+/// is at their `time`. The center moves in straight lines between the
+/// keyframes and rests before the first and after the last.
+#[derive(Clone, Debug)]
+pub struct MovingSphere<F: Float, M: Material<F>> {
+    times: Vec<F>,
+    centers: Vec<Point<F>>,
+    radius: F,
+    material: M,
+    casts_shadows: bool,
+}
 
-        if sq_distance_to_tangent > self.inner.radius_sq {
+impl<F: Float, M: Material<F>> MovingSphere<F, M> {
+    /// Keyframes as pairs of time and center. None without keyframes, or
+    /// unless the times strictly increase.
+    pub fn new(keyframes: Vec<(F, Point<F>)>, radius: F, material: M) -> Option<Self> {
+        let increasing = keyframes.windows(2).all(|pair| pair[0].0 < pair[1].0);
+        if keyframes.is_empty() || !increasing {
             return None;
-        };
+        }
+        Some(MovingSphere {
+            times: keyframes.iter().map(|&(time, _)| time).collect(),
+            centers: keyframes.iter().map(|&(_, center)| center).collect(),
+            radius,
+            material,
+            casts_shadows: true,
+        })
+    }
 
-        let dist_to_radius_diff = (self.inner.radius_sq - sq_distance_to_tangent).sqrt();
-        let distance = closest_tangent_dist - dist_to_radius_diff;
+    pub fn with_casts_shadows(mut self, casts_shadows: bool) -> Self {
+        self.casts_shadows = casts_shadows;
+        self
+    }
 
-        Some((distance, &self))
+    pub fn sphere_at(&self, time: F) -> Sphere<F> {
+        let (first, second, f) = keyframe_blend(&self.times, time);
+        let (a, b) = (self.centers[first], self.centers[second]);
+        Sphere::new(a + (b - a) * f, self.radius)
+    }
+}
+
+impl<F: Float, M: Material<F>> Hitable<F> for MovingSphere<F, M> {
+    type Material = M;
+    fn get_hit(&self, ray: &Ray<F>, distance: F) -> HitPoint<F, Self::Material> {
+        let point = ray.point_at_distance(distance);
+        let normal = self.sphere_at(ray.time).normal_at(point);
+        HitPoint::new(
+            point,
+            normal,
+            ray.direction,
+            sphere_uv(normal),
+            self.material.clone(),
+        )
+    }
+}
+
+impl<F, M> Traceable<F, Self, M> for MovingSphere<F, M>
+where
+    F: Float,
+    M: Material<F>,
+{
+    fn trace(&self, ray: &Ray<F>) -> Option<(F, &Self)> {
+        nearest_root(&self.sphere_at(ray.time), ray).map(|t| (t, self))
+    }
+
+    fn casts_shadows(&self) -> bool {
+        self.casts_shadows
+    }
+
+    fn occluded(&self, ray: &Ray<F>, t_max: F) -> bool {
+        self.casts_shadows && occludes(&self.sphere_at(ray.time), ray, t_max)
+    }
+}
+
+/// Covers the whole path, the spheres at the keyframes bound every straight
+/// stretch in between.
+impl<F: Float, M: Material<F>> Bounded<F, Aabb<F>> for MovingSphere<F, M> {
+    fn bounding_volume(&self) -> Aabb<F> {
+        self.centers
+            .iter()
+            .map(|&center| Sphere::new(center, self.radius).bounding_volume())
+            .fold(Aabb::empty(), |a: Aabb<F>, b| a.combine(&b))
     }
 }
 
@@ -88,6 +261,14 @@ impl<F: Float> BoundingVolume<F> for Sphere<F> {
         let disp = rhs.center - self.center;
         let distance = disp.magnitude();
 
+        // one inside the other, which also covers equal centers
+        if distance + rhs.radius <= self.radius {
+            return *self;
+        }
+        if distance + self.radius <= rhs.radius {
+            return *rhs;
+        }
+
         let new_diameter = distance + self.radius + rhs.radius;
         let new_radius = new_diameter * half;
 
@@ -102,17 +283,20 @@ impl<F: Float> BoundingVolume<F> for Sphere<F> {
         four_over_three * self.radius * self.radius * self.radius * F::PI()
     }
 
-    fn test(&self, ray: &Ray<F>) -> bool {
-        let oc = self.center - ray.origin;
-        let closest_tangent_dist = oc.dot(ray.direction);
+    fn surface_area(&self) -> F {
+        let four = F::one() + F::one() + F::one() + F::one();
+        four * self.radius_sq * F::PI()
+    }
 
-        if closest_tangent_dist < F::zero() {
-            return false;
-        };
+    fn test(&self, ray: &Ray<F>) -> Option<F> {
+        let (near, far) = self.intersect(ray)?;
+        let entry = F::max(near, ray.t_min);
 
-        let closest_on_tangent = ray.point_at_distance(closest_tangent_dist);
-        let sq_distance_to_tangent = (self.center - closest_on_tangent).magnitude_sq();
-        return sq_distance_to_tangent <= self.radius_sq;
+        if F::min(far, ray.t_max) >= entry {
+            Some(entry)
+        } else {
+            None
+        }
     }
 }
 
@@ -147,9 +331,23 @@ impl<F: Float> Bounded<F, Aabb<F>> for Sphere<F> {
     }
 }
 
+impl<F: Float> Bounded<F, Obb<F>> for Sphere<F> {
+    fn bounding_volume(&self) -> Obb<F> {
+        let aabb: Aabb<F> = self.bounding_volume();
+        Obb::from_aabb(&aabb)
+    }
+}
+
+impl<F: Float> Bounded<F, Dop14<F>> for Sphere<F> {
+    fn bounding_volume(&self) -> Dop14<F> {
+        Dop14::from_sphere(self.center, self.radius)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use shading::DebugNormalMaterial;
 
     #[test]
     fn test_sphere_combine() {
@@ -158,6 +356,55 @@ mod test {
 
         let expected = Sphere::new(Point::new(1.0, -3.0, 0.0), 5.0);
 
-        assert_eq!(s1.combine(&s2), expected)
+        assert_eq!(s1.combine(&s2), expected);
+
+        let inner = Sphere::new(Point::new(0.0, -3.0, 0.0), 1.0);
+        assert_eq!(expected.combine(&inner), expected);
+        assert_eq!(inner.combine(&expected), expected);
+    }
+
+    #[test]
+    fn test_shaded_sphere_respects_ray_interval() {
+        let sphere = ShadedSphere::new(Point::new(0.0, 0.0, 0.0), 1.0, DebugNormalMaterial {});
+        let ray = Ray::new(Point::new(0.0, 0.0, -3.0), Vector::plus_z());
+
+        assert_eq!(sphere.trace(&ray).map(|h| h.0), Some(2.0));
+        assert_eq!(
+            sphere.trace(&ray.clone().with_t_min(2.5)).map(|h| h.0),
+            Some(4.0)
+        );
+        assert!(sphere.trace(&ray.clone().with_t_max(1.5)).is_none());
+
+        // from inside, the far root is the hit
+        let inside = Ray::new(Point::new(0.0, 0.0, 0.5), Vector::plus_z());
+        assert_eq!(sphere.trace(&inside).map(|h| h.0), Some(0.5));
+        let behind = Ray::new(Point::new(0.0, 0.0, 2.0), Vector::plus_z());
+        assert!(sphere.trace(&behind).is_none());
+    }
+
+    #[test]
+    fn test_moving_sphere() {
+        let keyframes = vec![
+            (0.0, Point::new(0.0, 0.0, 0.0)),
+            (0.5, Point::new(2.0, 0.0, 0.0)),
+            (1.0, Point::new(2.0, 2.0, 0.0)),
+        ];
+        let sphere = MovingSphere::new(keyframes, 0.5, DebugNormalMaterial {}).unwrap();
+        let ray = Ray::new(Point::new(1.0, 0.0, -3.0), Vector::plus_z());
+
+        assert!(sphere.trace(&ray).is_none());
+        let quarter = ray.clone().with_time(0.25);
+        let (t, hit) = sphere.trace(&quarter).unwrap();
+        assert_eq!(t, 2.5);
+        assert_eq!(hit.get_hit(&quarter, t).data.normal, -Vector::plus_z());
+        assert!(sphere.occluded(&quarter, 3.0));
+        // resting at the last keyframe
+        let up = Ray::new(Point::new(2.0, 2.0, -3.0), Vector::plus_z()).with_time(5.0);
+        assert_eq!(sphere.trace(&up).map(|h| h.0), Some(2.5));
+
+        let bound: Aabb<f64> = sphere.bounding_volume();
+        assert_eq!(bound.min_point(), Point::new(-0.5, -0.5, -0.5));
+        assert_eq!(bound.max_point(), Point::new(2.5, 2.5, 0.5));
+        assert!(MovingSphere::new(vec![], 1.0, DebugNormalMaterial {}).is_none());
     }
 }
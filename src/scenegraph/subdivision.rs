@@ -0,0 +1,1019 @@
+use math::{Float, Point, Vector};
+use scenegraph::{smooth_normals, TriangleMesh};
+use shading::Material;
+use std::cmp::Ordering::Equal;
+use std::collections::HashMap;
+use tracing::Projection;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubdivisionScheme {
+    /// Quads from any polygons, for the quad dominant cages of modelling
+    /// tools.
+    CatmullClark,
+    /// Triangles, polygons are fan triangulated before the first level.
+    Loop,
+}
+
+/// Target for adaptive tessellation: every face is subdivided until its
+/// edges would be no longer than `max_pixels` on screen, taking each level
+/// to halve them. Only edges with an end on screen count, so geometry out
+/// of view does not drive the level up.
+#[derive(Debug, Clone)]
+pub struct ScreenEdgeLength<F: Float, C: Projection<F>> {
+    camera: C,
+    width: F,
+    height: F,
+    max_pixels: F,
+}
+
+impl<F: Float, C: Projection<F>> ScreenEdgeLength<F, C> {
+    pub fn new(camera: C, width: usize, height: usize, max_pixels: F) -> Self {
+        ScreenEdgeLength {
+            camera,
+            width: F::from(width).unwrap(),
+            height: F::from(height).unwrap(),
+            max_pixels,
+        }
+    }
+
+    fn pixels(&self, a: Point<F>, b: Point<F>) -> F {
+        let on_screen =
+            |x: F, y: F| x >= F::zero() && x <= F::one() && y >= F::zero() && y <= F::one();
+        match (self.camera.project(&a), self.camera.project(&b)) {
+            (Some(a), Some(b)) if on_screen(a.x, a.y) || on_screen(b.x, b.y) => {
+                ((a.x - b.x) * self.width).hypot((a.y - b.y) * self.height)
+            }
+            _ => F::zero(),
+        }
+    }
+}
+
+fn edge_key(a: usize, b: usize) -> (usize, usize) {
+    (a.min(b), a.max(b))
+}
+
+/// Edges of a polygon mesh with the faces along them.
+struct Topology<F: Float> {
+    edges: Vec<(usize, usize)>,
+    lookup: HashMap<(usize, usize), usize>,
+    edge_faces: Vec<Vec<usize>>,
+    sharpness: Vec<F>,
+    vertex_edges: Vec<Vec<usize>>,
+    vertex_faces: Vec<Vec<usize>>,
+}
+
+impl<F: Float> Topology<F> {
+    fn new(cage: &SubdivisionCage<F>) -> Self {
+        let mut topology = Topology {
+            edges: Vec::new(),
+            lookup: HashMap::new(),
+            edge_faces: Vec::new(),
+            sharpness: Vec::new(),
+            vertex_edges: vec![Vec::new(); cage.positions.len()],
+            vertex_faces: vec![Vec::new(); cage.positions.len()],
+        };
+        for (f, face) in cage.faces.iter().enumerate() {
+            for (i, &v) in face.iter().enumerate() {
+                topology.vertex_faces[v].push(f);
+                let key = edge_key(v, face[(i + 1) % face.len()]);
+                let edges = &mut topology.edges;
+                let edge_faces = &mut topology.edge_faces;
+                let vertex_edges = &mut topology.vertex_edges;
+                let e = *topology.lookup.entry(key).or_insert_with(|| {
+                    edges.push(key);
+                    edge_faces.push(Vec::new());
+                    vertex_edges[key.0].push(edges.len() - 1);
+                    vertex_edges[key.1].push(edges.len() - 1);
+                    edges.len() - 1
+                });
+                topology.edge_faces[e].push(f);
+            }
+        }
+        // boundary and non-manifold edges stay sharp at every level
+        topology.sharpness = (0..topology.edges.len())
+            .map(|e| {
+                if topology.edge_faces[e].len() != 2 {
+                    F::infinity()
+                } else {
+                    cage.creases
+                        .get(&topology.edges[e])
+                        .cloned()
+                        .unwrap_or_else(F::zero)
+                }
+            })
+            .collect();
+        topology
+    }
+
+    fn edge(&self, a: usize, b: usize) -> usize {
+        self.lookup[&edge_key(a, b)]
+    }
+
+    fn other_end(&self, e: usize, v: usize) -> usize {
+        let (a, b) = self.edges[e];
+        if a == v {
+            b
+        } else {
+            a
+        }
+    }
+
+    /// Edge point from its smooth position, blended towards the midpoint by
+    /// the sharpness of the edge.
+    fn edge_point(&self, e: usize, positions: &[Point<F>], smooth: Point<F>) -> Point<F> {
+        let (a, b) = self.edges[e];
+        let half = (F::one() + F::one()).recip();
+        let midpoint = positions[a] + (positions[b] - positions[a]) * half;
+        let weight = F::min(self.sharpness[e], F::one());
+        smooth + (midpoint - smooth) * weight
+    }
+
+    /// Vertex point from its smooth position, using the crease rule on two
+    /// sharp edges and keeping corners of more in place. Semi-sharp edges
+    /// blend between the rules. Boundary vertices of a single face are
+    /// corners too, so the corners of open cages stay put.
+    fn vertex_point(&self, v: usize, positions: &[Point<F>], smooth: Point<F>) -> Point<F> {
+        let sharp: Vec<usize> = self.vertex_edges[v]
+            .iter()
+            .cloned()
+            .filter(|&e| self.sharpness[e] > F::zero())
+            .collect();
+        if sharp.len() < 2 {
+            return smooth;
+        }
+        let p = positions[v];
+        let rule = if sharp.len() == 2 && self.vertex_faces[v].len() > 1 {
+            let (a, b) = (
+                positions[self.other_end(sharp[0], v)],
+                positions[self.other_end(sharp[1], v)],
+            );
+            let eighth = F::from(0.125).unwrap();
+            p + ((a - p) + (b - p)) * eighth
+        } else {
+            p
+        };
+        let total = sharp.iter().fold(F::zero(), |sum, &e| {
+            sum + F::min(self.sharpness[e], F::one())
+        });
+        let weight = total / F::from(sharp.len()).unwrap();
+        smooth + (rule - smooth) * weight
+    }
+
+    /// Creases of the next level, one sharpness step softer, on the two
+    /// halves of every creased edge.
+    fn child_creases(
+        &self,
+        edge_vertex: &dyn Fn(usize) -> usize,
+        vertex: &dyn Fn(usize) -> usize,
+    ) -> HashMap<(usize, usize), F> {
+        let mut creases = HashMap::new();
+        for (e, &(a, b)) in self.edges.iter().enumerate() {
+            let sharpness = self.sharpness[e] - F::one();
+            if self.edge_faces[e].len() == 2 && sharpness > F::zero() {
+                creases.insert(edge_key(vertex(a), edge_vertex(e)), sharpness);
+                creases.insert(edge_key(edge_vertex(e), vertex(b)), sharpness);
+            }
+        }
+        creases
+    }
+}
+
+/// Position of a vertex on the grid of a face being tessellated, counted in
+/// steps of the current level from its first corner.
+type Tag = (usize, usize);
+
+/// Layout of the grid over a single face, `n` steps along every side. The
+/// sides run from corner to corner in the order of the face.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Grid {
+    /// Corners at `(0, 0)`, `(n, 0)`, `(n, n)` and `(0, n)`.
+    Quad,
+    /// Corners at `(0, 0)`, `(n, 0)` and `(0, n)`.
+    Triangle,
+}
+
+impl Grid {
+    fn side_point(self, side: usize, n: usize, t: usize) -> Tag {
+        match (self, side) {
+            (_, 0) => (t, 0),
+            (Grid::Quad, 1) => (n, t),
+            (Grid::Quad, 2) => (n - t, n),
+            (Grid::Quad, _) => (0, n - t),
+            (Grid::Triangle, 1) => (n - t, t),
+            (Grid::Triangle, _) => (0, n - t),
+        }
+    }
+
+    /// Steps from the first corner of `side`, if the tag lies on it.
+    fn along(self, side: usize, n: usize, (i, j): Tag) -> Option<usize> {
+        match (self, side) {
+            (_, 0) if j == 0 => Some(i),
+            (Grid::Quad, 1) if i == n => Some(j),
+            (Grid::Quad, 2) if j == n => Some(n - i),
+            (Grid::Quad, 3) if i == 0 => Some(n - j),
+            (Grid::Triangle, 1) if i + j == n => Some(j),
+            (Grid::Triangle, 2) if i == 0 => Some(n - j),
+            _ => None,
+        }
+    }
+
+    fn sides(self) -> usize {
+        match self {
+            Grid::Quad => 4,
+            Grid::Triangle => 3,
+        }
+    }
+
+    /// Whether an edge between the tags is one step of the grid.
+    fn adjacent(self, a: Tag, b: Tag) -> bool {
+        let step = (b.0 as isize - a.0 as isize, b.1 as isize - a.1 as isize);
+        match step {
+            (0, 1) | (0, -1) | (1, 0) | (-1, 0) => true,
+            (1, -1) | (-1, 1) => self == Grid::Triangle,
+            _ => false,
+        }
+    }
+
+    /// Cells of the grid, wound like the face.
+    fn cells(self, n: usize) -> Vec<Vec<Tag>> {
+        let mut cells = Vec::new();
+        for j in 0..n {
+            for i in 0..n {
+                match self {
+                    Grid::Quad => cells.push(vec![(i, j), (i + 1, j), (i + 1, j + 1), (i, j + 1)]),
+                    Grid::Triangle if i + j < n => {
+                        cells.push(vec![(i, j), (i + 1, j), (i, j + 1)]);
+                        if i + j + 1 < n {
+                            cells.push(vec![(i + 1, j), (i + 1, j + 1), (i, j + 1)]);
+                        }
+                    }
+                    Grid::Triangle => {}
+                }
+            }
+        }
+        cells
+    }
+}
+
+/// A face together with the faces sharing a vertex with it, refined level
+/// by level. That is all the subdivision rules look at for the vertices of
+/// the face, so they come out as on the whole cage. After every level only
+/// the faces touching the face are kept, whose vertices are still exact.
+struct Patch<F: Float> {
+    cage: SubdivisionCage<F>,
+    /// Grid position of the vertices of the face, None for the others.
+    tags: Vec<Option<Tag>>,
+}
+
+impl<F: Float> Patch<F> {
+    /// The given faces of `cage`, with only the vertices they use.
+    fn new<T>(cage: &SubdivisionCage<F>, faces: &[usize], tag: T) -> Self
+    where
+        T: Fn(usize) -> Option<Tag>,
+    {
+        let mut remap = HashMap::new();
+        let mut positions = Vec::new();
+        let mut tags = Vec::new();
+        let mut creases = HashMap::new();
+        let faces = faces
+            .iter()
+            .map(|&f| {
+                let face = &cage.faces[f];
+                let new_face: Vec<usize> = face
+                    .iter()
+                    .map(|&v| {
+                        *remap.entry(v).or_insert_with(|| {
+                            positions.push(cage.positions[v]);
+                            tags.push(tag(v));
+                            positions.len() - 1
+                        })
+                    })
+                    .collect();
+                for i in 0..face.len() {
+                    let next = (i + 1) % face.len();
+                    if let Some(&sharpness) = cage.creases.get(&edge_key(face[i], face[next])) {
+                        creases.insert(edge_key(new_face[i], new_face[next]), sharpness);
+                    }
+                }
+                new_face
+            })
+            .collect();
+        Patch {
+            cage: SubdivisionCage {
+                positions,
+                faces,
+                creases,
+            },
+            tags,
+        }
+    }
+
+    fn refine(&self, scheme: SubdivisionScheme, grid: Grid) -> Self {
+        let topology = Topology::new(&self.cage);
+        let refined = match scheme {
+            SubdivisionScheme::CatmullClark => self.cage.catmull_clark(&topology),
+            SubdivisionScheme::Loop => self.cage.loop_subdivide(&topology),
+        };
+
+        // vertex points double their tag, points between two tagged
+        // vertices get the sum, which is the midpoint at the next level
+        let mut tags: Vec<Option<Tag>> = self
+            .tags
+            .iter()
+            .map(|tag| tag.map(|(i, j)| (2 * i, 2 * j)))
+            .collect();
+        tags.extend(
+            topology
+                .edges
+                .iter()
+                .map(|&(a, b)| match (self.tags[a], self.tags[b]) {
+                    (Some(s), Some(t)) if grid.adjacent(s, t) => Some((s.0 + t.0, s.1 + t.1)),
+                    _ => None,
+                }),
+        );
+        if scheme == SubdivisionScheme::CatmullClark {
+            tags.extend(self.cage.faces.iter().map(|face| {
+                let corners: Option<Vec<Tag>> = face.iter().map(|&v| self.tags[v]).collect();
+                let corners = corners?;
+                let low = corners
+                    .iter()
+                    .fold(corners[0], |l, t| (l.0.min(t.0), l.1.min(t.1)));
+                let mut offsets: Vec<Tag> =
+                    corners.iter().map(|t| (t.0 - low.0, t.1 - low.1)).collect();
+                offsets.sort();
+                if offsets != [(0, 0), (0, 1), (1, 0), (1, 1)] {
+                    return None;
+                }
+                Some((2 * low.0 + 1, 2 * low.1 + 1))
+            }));
+        }
+
+        let touching: Vec<usize> = (0..refined.faces.len())
+            .filter(|&f| refined.faces[f].iter().any(|&v| tags[v].is_some()))
+            .collect();
+        Patch::new(&refined, &touching, |v| tags[v])
+    }
+
+    fn grid_points(&self) -> HashMap<Tag, Point<F>> {
+        self.tags
+            .iter()
+            .zip(&self.cage.positions)
+            .filter_map(|(tag, &p)| tag.map(|tag| (tag, p)))
+            .collect()
+    }
+}
+
+/// Output of adaptive tessellation. Corners and sides of the cage faces
+/// are kept at the level of the first face that reaches them.
+struct Stitching<F: Float> {
+    positions: Vec<Point<F>>,
+    faces: Vec<Vec<usize>>,
+    corners: HashMap<usize, usize>,
+    /// Vertices inside each side, from its lower to its higher corner.
+    sides: HashMap<(usize, usize), Vec<usize>>,
+}
+
+impl<F: Float> Stitching<F> {
+    fn new() -> Self {
+        Stitching {
+            positions: Vec::new(),
+            faces: Vec::new(),
+            corners: HashMap::new(),
+            sides: HashMap::new(),
+        }
+    }
+
+    fn push(&mut self, p: Point<F>) -> usize {
+        self.positions.push(p);
+        self.positions.len() - 1
+    }
+
+    /// Adds the grid of `n` steps a side over the face with the cage
+    /// vertices `corners`. Cells along sides stored at a finer level are
+    /// fanned around their center to take in the extra vertices.
+    fn add_face(
+        &mut self,
+        corners: &[usize],
+        grid: Grid,
+        n: usize,
+        points: &HashMap<Tag, Point<F>>,
+    ) {
+        let sides: Vec<Vec<usize>> = (0..corners.len())
+            .map(|side| {
+                let (a, b) = (corners[side], corners[(side + 1) % corners.len()]);
+                let first = self.corner(a, points[&grid.side_point(side, n, 0)]);
+                let last = self.corner(b, points[&grid.side_point(side, n, n)]);
+                let key = edge_key(a, b);
+                if !self.sides.contains_key(&key) {
+                    let mut inner: Vec<usize> = (1..n)
+                        .map(|t| self.push(points[&grid.side_point(side, n, t)]))
+                        .collect();
+                    if a != key.0 {
+                        inner.reverse();
+                    }
+                    self.sides.insert(key, inner);
+                }
+                let inner = &self.sides[&key];
+                let mut vertices = vec![first];
+                if a == key.0 {
+                    vertices.extend(inner.iter().cloned());
+                } else {
+                    vertices.extend(inner.iter().rev().cloned());
+                }
+                vertices.push(last);
+                vertices
+            })
+            .collect();
+
+        let mut inside = HashMap::new();
+        for cell in grid.cells(n) {
+            let mut polygon = Vec::new();
+            let mut stitched = false;
+            for k in 0..cell.len() {
+                let (from, to) = (cell[k], cell[(k + 1) % cell.len()]);
+                let on_side = (0..grid.sides()).find_map(|side| {
+                    match (grid.along(side, n, from), grid.along(side, n, to)) {
+                        (Some(s), Some(t)) if t == s + 1 => Some((side, s)),
+                        _ => None,
+                    }
+                });
+                match on_side {
+                    Some((side, t)) => {
+                        let steps = (sides[side].len() - 1) / n;
+                        polygon.extend_from_slice(&sides[side][t * steps..(t + 1) * steps]);
+                        stitched |= steps > 1;
+                    }
+                    None => {
+                        let on_boundary = (0..grid.sides())
+                            .find_map(|side| grid.along(side, n, from).map(|t| (side, t)));
+                        polygon.push(match on_boundary {
+                            Some((side, t)) => sides[side][t * (sides[side].len() - 1) / n],
+                            None => *inside
+                                .entry(from)
+                                .or_insert_with(|| self.push(points[&from])),
+                        });
+                    }
+                }
+            }
+            if stitched {
+                let center = average(polygon.iter().map(|&v| self.positions[v]));
+                let center = self.push(center);
+                for k in 0..polygon.len() {
+                    let next = polygon[(k + 1) % polygon.len()];
+                    self.faces.push(vec![polygon[k], next, center]);
+                }
+            } else {
+                self.faces.push(polygon);
+            }
+        }
+    }
+
+    fn corner(&mut self, v: usize, p: Point<F>) -> usize {
+        if let Some(&index) = self.corners.get(&v) {
+            return index;
+        }
+        let index = self.push(p);
+        self.corners.insert(v, index);
+        index
+    }
+}
+
+fn average<F: Float, I: IntoIterator<Item = Point<F>>>(points: I) -> Point<F> {
+    let (sum, count) = points.into_iter().fold(
+        (Vector::new(F::zero(), F::zero(), F::zero()), 0),
+        |(sum, count), p| (sum + (p - Point::origin()), count + 1),
+    );
+    Point::origin() + sum * F::from(count).unwrap().recip()
+}
+
+/// Polygon control mesh of a subdivision surface, with optional creases.
+///
+/// Edges used by a single face are boundaries, which are kept sharp so the
+/// surface ends at the limit curve of its boundary. Creases have a
+/// sharpness, the number of levels they stay sharp for, after which they
+/// smooth out, fractions blend in between (DeRose, Kass and Truong,
+/// "Subdivision Surfaces in Character Animation").
+#[derive(Debug, Clone)]
+pub struct SubdivisionCage<F: Float> {
+    positions: Vec<Point<F>>,
+    faces: Vec<Vec<usize>>,
+    creases: HashMap<(usize, usize), F>,
+}
+
+impl<F: Float> SubdivisionCage<F> {
+    /// None without faces, for faces of fewer than three vertices, faces
+    /// using a vertex twice and for indices past the positions.
+    pub fn new(positions: Vec<Point<F>>, faces: Vec<Vec<usize>>) -> Option<Self> {
+        let valid = |face: &Vec<usize>| {
+            let distinct = (1..face.len()).all(|i| !face[..i].contains(&face[i]));
+            face.len() >= 3 && distinct && face.iter().all(|&v| v < positions.len())
+        };
+        if faces.is_empty() || !faces.iter().all(valid) {
+            return None;
+        }
+        Some(SubdivisionCage {
+            positions,
+            faces,
+            creases: HashMap::new(),
+        })
+    }
+
+    /// Sharpens the edge between two vertices. Infinity keeps it sharp at
+    /// every level.
+    pub fn with_crease(mut self, a: usize, b: usize, sharpness: F) -> Self {
+        self.creases.insert(edge_key(a, b), sharpness);
+        self
+    }
+
+    /// Merges vertices at the same position, which mesh files split where
+    /// normals or UVs change, so the surface does not tear open there.
+    /// Faces collapsing onto fewer than three vertices are dropped.
+    pub fn welded(self) -> Self {
+        let mut order: Vec<usize> = (0..self.positions.len()).collect();
+        let p = &self.positions;
+        order.sort_by(|&a, &b| {
+            (p[a].x, p[a].y, p[a].z)
+                .partial_cmp(&(p[b].x, p[b].y, p[b].z))
+                .unwrap_or(Equal)
+        });
+        let mut remap = vec![0; p.len()];
+        let mut positions = Vec::new();
+        for (i, &v) in order.iter().enumerate() {
+            let w = order[i.saturating_sub(1)];
+            if i == 0 || p[v] != p[w] {
+                positions.push(p[v]);
+            }
+            remap[v] = positions.len() - 1;
+        }
+        SubdivisionCage {
+            faces: self
+                .faces
+                .iter()
+                .filter_map(|face| {
+                    let mut welded: Vec<usize> = face.iter().map(|&v| remap[v]).collect();
+                    welded.dedup();
+                    if welded.len() > 1 && welded[0] == welded[welded.len() - 1] {
+                        welded.pop();
+                    }
+                    let distinct = (1..welded.len()).all(|i| !welded[..i].contains(&welded[i]));
+                    if welded.len() >= 3 && distinct {
+                        Some(welded)
+                    } else {
+                        None
+                    }
+                })
+                .collect(),
+            creases: self
+                .creases
+                .iter()
+                .map(|(&(a, b), &s)| (edge_key(remap[a], remap[b]), s))
+                .collect(),
+            positions,
+        }
+    }
+
+    pub fn positions(&self) -> &[Point<F>] {
+        &self.positions
+    }
+
+    pub fn faces(&self) -> &[Vec<usize>] {
+        &self.faces
+    }
+
+    /// The next level of the surface.
+    pub fn subdivide(&self, scheme: SubdivisionScheme) -> Self {
+        match scheme {
+            SubdivisionScheme::CatmullClark => self.catmull_clark(&Topology::new(self)),
+            SubdivisionScheme::Loop => {
+                let triangles = self.triangulated();
+                triangles.loop_subdivide(&Topology::new(&triangles))
+            }
+        }
+    }
+
+    /// Subdivides `max_level` times, or with a `screen` target only as far
+    /// as each face needs, see `ScreenEdgeLength`.
+    ///
+    /// Adaptive results are stitched: where faces of different levels meet,
+    /// the coarser side takes over the vertices of the finer one, so the
+    /// surface has no cracks. They are meant for `into_mesh` and carry no
+    /// creases. Catmull-Clark cages with faces other than quads get one
+    /// uniform level first.
+    pub fn tessellate<C: Projection<F>>(
+        self,
+        scheme: SubdivisionScheme,
+        max_level: usize,
+        screen: Option<&ScreenEdgeLength<F, C>>,
+    ) -> Self {
+        let screen = match screen {
+            Some(screen) => screen,
+            None => {
+                return (0..max_level).fold(self, |cage, _| cage.subdivide(scheme));
+            }
+        };
+        let (cage, max_level, grid) = match scheme {
+            SubdivisionScheme::Loop => (self.triangulated(), max_level, Grid::Triangle),
+            SubdivisionScheme::CatmullClark if self.faces.iter().all(|face| face.len() == 4) => {
+                (self, max_level, Grid::Quad)
+            }
+            SubdivisionScheme::CatmullClark if max_level > 0 => {
+                (self.subdivide(scheme), max_level - 1, Grid::Quad)
+            }
+            SubdivisionScheme::CatmullClark => return self,
+        };
+
+        let levels: Vec<usize> = cage
+            .faces
+            .iter()
+            .map(|face| cage.face_level(face, max_level, screen))
+            .collect();
+        let mut vertex_faces = vec![Vec::new(); cage.positions.len()];
+        for (f, face) in cage.faces.iter().enumerate() {
+            for &v in face {
+                vertex_faces[v].push(f);
+            }
+        }
+
+        // the finest faces go first, so every shared corner and side is
+        // stored at the finest level of the faces around it
+        let mut order: Vec<usize> = (0..cage.faces.len()).collect();
+        order.sort_by(|&a, &b| levels[b].cmp(&levels[a]));
+        let mut stitching = Stitching::new();
+        for f in order {
+            let mut around: Vec<usize> = cage.faces[f]
+                .iter()
+                .flat_map(|&v| vertex_faces[v].iter().cloned())
+                .collect();
+            around.sort();
+            around.dedup();
+            let corners = &cage.faces[f];
+            let mut patch = Patch::new(&cage, &around, |v| {
+                let corner = corners.iter().position(|&c| c == v)?;
+                Some(grid.side_point(corner, 1, 0))
+            });
+            for _ in 0..levels[f] {
+                patch = patch.refine(scheme, grid);
+            }
+            stitching.add_face(corners, grid, 1 << levels[f], &patch.grid_points());
+        }
+
+        SubdivisionCage {
+            positions: stitching.positions,
+            faces: stitching.faces,
+            creases: HashMap::new(),
+        }
+    }
+
+    /// Levels until the longest on screen edge of the face gets short
+    /// enough, taking each level to halve it.
+    fn face_level<C: Projection<F>>(
+        &self,
+        face: &[usize],
+        max_level: usize,
+        screen: &ScreenEdgeLength<F, C>,
+    ) -> usize {
+        let longest = (0..face.len())
+            .map(|i| {
+                let (a, b) = (face[i], face[(i + 1) % face.len()]);
+                screen.pixels(self.positions[a], self.positions[b])
+            })
+            .fold(F::zero(), F::max);
+        let mut level = 0;
+        let mut reach = screen.max_pixels;
+        while level < max_level && longest > reach {
+            level += 1;
+            reach = reach + reach;
+        }
+        level
+    }
+
+    fn triangulated(&self) -> Self {
+        let faces = self
+            .faces
+            .iter()
+            .flat_map(|face| (1..face.len() - 1).map(move |i| vec![face[0], face[i], face[i + 1]]))
+            .collect();
+        SubdivisionCage {
+            positions: self.positions.clone(),
+            faces,
+            creases: self.creases.clone(),
+        }
+    }
+
+    /// Vertex points first, then edge points, then face points.
+    fn catmull_clark(&self, topology: &Topology<F>) -> Self {
+        let (vertex_count, edge_count) = (self.positions.len(), topology.edges.len());
+        let p = &self.positions;
+
+        let face_points: Vec<_> = self
+            .faces
+            .iter()
+            .map(|face| average(face.iter().map(|&v| p[v])))
+            .collect();
+        let edge_points: Vec<_> = (0..edge_count)
+            .map(|e| {
+                let (a, b) = topology.edges[e];
+                let faces = &topology.edge_faces[e];
+                let smooth = if faces.len() == 2 {
+                    average(vec![
+                        p[a],
+                        p[b],
+                        face_points[faces[0]],
+                        face_points[faces[1]],
+                    ])
+                } else {
+                    average(vec![p[a], p[b]])
+                };
+                topology.edge_point(e, p, smooth)
+            })
+            .collect();
+        let vertex_points = (0..vertex_count).map(|v| {
+            let edges = &topology.vertex_edges[v];
+            if edges.is_empty() {
+                return p[v];
+            }
+            let n = F::from(edges.len()).unwrap();
+            let q = average(topology.vertex_faces[v].iter().map(|&f| face_points[f]));
+            let r = average(edges.iter().map(|&e| {
+                let (a, b) = topology.edges[e];
+                average(vec![p[a], p[b]])
+            }));
+            let two = F::one() + F::one();
+            let three = two + F::one();
+            let smooth = Point::origin()
+                + ((q - Point::origin())
+                    + (r - Point::origin()) * two
+                    + (p[v] - Point::origin()) * (n - three))
+                    * n.recip();
+            topology.vertex_point(v, p, smooth)
+        });
+
+        let mut positions: Vec<_> = vertex_points.collect();
+        positions.extend(edge_points);
+        positions.extend(face_points);
+
+        let edge_vertex = |e: usize| vertex_count + e;
+        let mut faces = Vec::new();
+        for (f, face) in self.faces.iter().enumerate() {
+            let n = face.len();
+            for i in 0..n {
+                let (previous, v, next) = (face[(i + n - 1) % n], face[i], face[(i + 1) % n]);
+                faces.push(vec![
+                    v,
+                    edge_vertex(topology.edge(v, next)),
+                    vertex_count + edge_count + f,
+                    edge_vertex(topology.edge(previous, v)),
+                ]);
+            }
+        }
+
+        SubdivisionCage {
+            positions,
+            faces,
+            creases: topology.child_creases(&edge_vertex, &|v| v),
+        }
+    }
+
+    /// Vertex points first, then edge points. Expects triangles only.
+    fn loop_subdivide(&self, topology: &Topology<F>) -> Self {
+        let vertex_count = self.positions.len();
+        let p = &self.positions;
+
+        let edge_points = (0..topology.edges.len()).map(|e| {
+            let (a, b) = topology.edges[e];
+            let faces = &topology.edge_faces[e];
+            let half = (F::one() + F::one()).recip();
+            let midpoint = p[a] + (p[b] - p[a]) * half;
+            let smooth = if faces.len() == 2 {
+                // 3/8 of either end and 1/8 of either opposite vertex
+                let opposite = |f: usize| {
+                    self.faces[f]
+                        .iter()
+                        .cloned()
+                        .find(|&v| v != a && v != b)
+                        .unwrap()
+                };
+                let wings = average(vec![p[opposite(faces[0])], p[opposite(faces[1])]]);
+                midpoint + (wings - midpoint) * F::from(0.25).unwrap()
+            } else {
+                midpoint
+            };
+            topology.edge_point(e, p, smooth)
+        });
+        let vertex_points = (0..vertex_count).map(|v| {
+            let edges = &topology.vertex_edges[v];
+            if edges.is_empty() {
+                return p[v];
+            }
+            let n = edges.len();
+            let beta = if n == 3 {
+                F::from(3.0 / 16.0).unwrap()
+            } else {
+                F::from(3.0 / (8.0 * n as f64)).unwrap()
+            };
+            let ring = average(edges.iter().map(|&e| p[topology.other_end(e, v)]));
+            let smooth = p[v] + (ring - p[v]) * (beta * F::from(n).unwrap());
+            topology.vertex_point(v, p, smooth)
+        });
+
+        let mut positions: Vec<_> = vertex_points.collect();
+        positions.extend(edge_points);
+
+        let edge_vertex = |e: usize| vertex_count + e;
+        let faces = self
+            .faces
+            .iter()
+            .flat_map(|face| {
+                let (a, b, c) = (face[0], face[1], face[2]);
+                let (ab, bc, ca) = (
+                    edge_vertex(topology.edge(a, b)),
+                    edge_vertex(topology.edge(b, c)),
+                    edge_vertex(topology.edge(c, a)),
+                );
+                vec![
+                    vec![a, ab, ca],
+                    vec![b, bc, ab],
+                    vec![c, ca, bc],
+                    vec![ab, bc, ca],
+                ]
+            })
+            .collect();
+
+        SubdivisionCage {
+            positions,
+            faces,
+            creases: topology.child_creases(&edge_vertex, &|v| v),
+        }
+    }
+
+    /// Triangle mesh of the current level, with normals averaged over the
+    /// faces around each vertex.
+    pub fn into_mesh<M: Material<F>>(self, material: M) -> Option<TriangleMesh<F, M>> {
+        let indices: Vec<[usize; 3]> = self
+            .faces
+            .iter()
+            .flat_map(|face| (1..face.len() - 1).map(move |i| [face[0], face[i], face[i + 1]]))
+            .collect();
+        let normals = smooth_normals(&self.positions, &indices);
+        TriangleMesh::new(self.positions, Some(normals), None, indices, material)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shading::DebugNormalMaterial;
+    use tracing::PlaneCamera;
+
+    /// Unit cube around the origin, faces wound outwards.
+    fn cube() -> SubdivisionCage<f64> {
+        let positions = (0..8)
+            .map(|i| {
+                let coordinate = |bit: usize| if i & bit == 0 { -1.0 } else { 1.0 };
+                Point::new(coordinate(1), coordinate(2), coordinate(4))
+            })
+            .collect();
+        let faces = vec![
+            vec![0, 2, 3, 1],
+            vec![4, 5, 7, 6],
+            vec![0, 1, 5, 4],
+            vec![2, 6, 7, 3],
+            vec![0, 4, 6, 2],
+            vec![1, 3, 7, 5],
+        ];
+        SubdivisionCage::new(positions, faces).unwrap()
+    }
+
+    fn radius_range(cage: &SubdivisionCage<f64>) -> (f64, f64) {
+        cage.positions()
+            .iter()
+            .fold((f64::INFINITY, 0.0), |(low, high), p| {
+                let r = (*p - Point::origin()).magnitude();
+                (low.min(r), high.max(r))
+            })
+    }
+
+    #[test]
+    fn test_catmull_clark_cube() {
+        let once = cube().subdivide(SubdivisionScheme::CatmullClark);
+        assert_eq!((once.positions().len(), once.faces().len()), (26, 24));
+        // the corners move in by the known amount of 5/9
+        assert!((once.positions()[7].x - 5.0 / 9.0).abs() < 1e-12);
+
+        // the cube rounds off towards a sphere like shape
+        let smooth = once
+            .subdivide(SubdivisionScheme::CatmullClark)
+            .subdivide(SubdivisionScheme::CatmullClark);
+        let (low, high) = radius_range(&smooth);
+        assert!(high / low < 1.2);
+
+        // an infinitely sharp edge loop around the middle stays in place
+        let creased = [(0, 1), (1, 5), (5, 4), (4, 0)]
+            .iter()
+            .fold(cube(), |cage, &(a, b)| {
+                cage.with_crease(a, b, f64::INFINITY)
+            });
+        let creased = creased
+            .subdivide(SubdivisionScheme::CatmullClark)
+            .subdivide(SubdivisionScheme::CatmullClark);
+        let bottom = creased.positions().iter().filter(|p| p.y == -1.0).count();
+        assert!(bottom > 4);
+        assert!(creased.positions().iter().all(|p| p.y >= -1.0));
+
+        let mesh = smooth.into_mesh(DebugNormalMaterial {}).unwrap();
+        assert_eq!(mesh.triangles().len(), 2 * 6 * 64);
+    }
+
+    #[test]
+    fn test_loop_boundary_and_adaptive_level() {
+        // a flat square of two triangles keeps its boundary on the square
+        let square = SubdivisionCage::new(
+            vec![
+                Point::new(0.0, 0.0, 0.0),
+                Point::new(1.0, 0.0, 0.0),
+                Point::new(1.0, 1.0, 0.0),
+                Point::new(0.0, 1.0, 0.0),
+            ],
+            vec![vec![0, 1, 2, 3]],
+        )
+        .unwrap();
+        let fine = square
+            .clone()
+            .subdivide(SubdivisionScheme::Loop)
+            .subdivide(SubdivisionScheme::Loop);
+        assert_eq!(fine.faces().len(), 2 * 16);
+        assert!(fine
+            .positions()
+            .iter()
+            .all(|p| p.z == 0.0 && p.x >= 0.0 && p.x <= 1.0));
+        // boundary corners are kept by the corner rule
+        assert_eq!(fine.positions()[1], Point::new(1.0, 0.0, 0.0));
+
+        // a camera two units away sees the unit square about 260 pixels wide
+        let camera = PlaneCamera::new(
+            Point::new(0.5, 0.5, 2.0),
+            -Vector::plus_z(),
+            Vector::plus_y(),
+            1.0,
+        );
+        let screen = ScreenEdgeLength::new(camera, 512, 512, 50.0);
+        assert!(screen.pixels(Point::new(0.0, 0.0, 0.0), Point::new(1.0, 0.0, 0.0)) > 200.0);
+        let adaptive = square
+            .clone()
+            .tessellate(SubdivisionScheme::Loop, 8, Some(&screen));
+        assert_eq!(adaptive.faces().len(), 2 * 4usize.pow(3));
+        let none: Option<&ScreenEdgeLength<f64, PlaneCamera<f64>>> = None;
+        assert_eq!(
+            square
+                .tessellate(SubdivisionScheme::Loop, 2, none)
+                .faces()
+                .len(),
+            32
+        );
+    }
+
+    #[test]
+    fn test_adaptive_levels_per_face() {
+        // the front of the cube is closer, so it needs a level more than the
+        // back, faces with a front edge take its finer level
+        let camera = PlaneCamera::new(
+            Point::new(0.0, 0.0, 4.0),
+            -Vector::plus_z(),
+            Vector::plus_y(),
+            1.0,
+        );
+        let screen = ScreenEdgeLength::new(camera, 512, 512, 75.0);
+        for &scheme in &[SubdivisionScheme::CatmullClark, SubdivisionScheme::Loop] {
+            let adaptive = cube().tessellate(scheme, 5, Some(&screen));
+            let uniform = (0..3).fold(cube(), |cage, _| cage.subdivide(scheme));
+            assert!(adaptive.positions().len() < uniform.positions().len());
+            let at_level_three = |p: &Point<f64>| {
+                uniform
+                    .positions()
+                    .iter()
+                    .any(|q| (*p - *q).magnitude() < 1e-9)
+            };
+            assert!(adaptive
+                .positions()
+                .iter()
+                .filter(|p| p.z > 0.5)
+                .all(at_level_three));
+            // while the back keeps its coarser inside
+            assert!(adaptive.positions().iter().any(|p| !at_level_three(p)));
+
+            // no cracks: every edge is shared by two faces, in opposite
+            // directions
+            let mut edges = HashMap::new();
+            for face in adaptive.faces() {
+                for i in 0..face.len() {
+                    *edges
+                        .entry((face[i], face[(i + 1) % face.len()]))
+                        .or_insert(0) += 1;
+                }
+            }
+            assert!(edges
+                .iter()
+                .all(|(&(a, b), &count)| count == 1 && edges.get(&(b, a)) == Some(&1)));
+        }
+    }
+}
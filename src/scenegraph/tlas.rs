@@ -0,0 +1,236 @@
+use math::{Aabb, Bounded, Float, Ray, Transform};
+use scenegraph::{Instance, LinearBvh, SahBuilder};
+use shading::Material;
+use tracing::{Hitable, Traceable};
+
+/// Instance tagged with its position in the list `Tlas::new` was given, so it
+/// can still be found after the builder reordered everything.
+struct TlasLeaf<F, T, H, M>
+where
+    F: Float,
+    T: Traceable<F, H, M>,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+{
+    id: usize,
+    instance: Instance<F, T, H, M>,
+}
+
+impl<F, T, H, M> Clone for TlasLeaf<F, T, H, M>
+where
+    F: Float,
+    T: Traceable<F, H, M>,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+{
+    fn clone(&self) -> Self {
+        TlasLeaf {
+            id: self.id,
+            instance: self.instance.clone(),
+        }
+    }
+}
+
+impl<F, T, H, M> Traceable<F, Instance<F, T, H, M>, M> for TlasLeaf<F, T, H, M>
+where
+    F: Float,
+    T: Traceable<F, H, M>,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+{
+    fn trace(&self, ray: &Ray<F>) -> Option<(F, &Instance<F, T, H, M>)> {
+        self.instance.trace(ray)
+    }
+
+    fn casts_shadows(&self) -> bool {
+        self.instance.casts_shadows()
+    }
+
+    fn occluded(&self, ray: &Ray<F>, t_max: F) -> bool {
+        self.instance.occluded(ray, t_max)
+    }
+}
+
+impl<F, T, H, M> Bounded<F, Aabb<F>> for TlasLeaf<F, T, H, M>
+where
+    F: Float,
+    T: Traceable<F, H, M> + Bounded<F, Aabb<F>>,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+{
+    fn bounding_volume(&self) -> Aabb<F> {
+        self.instance.bounding_volume()
+    }
+}
+
+type TopLevel<F, T, H, M> = LinearBvh<F, Aabb<F>, Instance<F, T, H, M>, M, TlasLeaf<F, T, H, M>>;
+
+/// Two-level acceleration structure: a small top-level BVH over instances of
+/// shared bottom-level objects, typically `Bvh`s or meshes.
+///
+/// Moving an instance only touches the top level. `set_transform` leaves the
+/// bounds stale so several instances can be moved at once, followed by either
+/// a cheap `refit` or, once instances moved far, a full `rebuild` of the top
+/// level. The bottom-level objects are never rebuilt.
+pub struct Tlas<F, T, H, M>
+where
+    F: Float,
+    T: Traceable<F, H, M> + Bounded<F, Aabb<F>>,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+{
+    bvh: TopLevel<F, T, H, M>,
+    slots: Vec<usize>,
+    builder: SahBuilder<F>,
+}
+
+impl<F, T, H, M> Tlas<F, T, H, M>
+where
+    F: Float,
+    T: Traceable<F, H, M> + Bounded<F, Aabb<F>>,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+{
+    /// Instances are later addressed by their index in `instances`. `None`
+    /// if there are no instances.
+    pub fn new(instances: Vec<Instance<F, T, H, M>>, builder: SahBuilder<F>) -> Option<Self> {
+        let leaves = instances
+            .into_iter()
+            .enumerate()
+            .map(|(id, instance)| TlasLeaf { id, instance })
+            .collect();
+        let bvh = Self::build(&builder, leaves)?;
+
+        let mut tlas = Tlas {
+            bvh,
+            slots: Vec::new(),
+            builder,
+        };
+        tlas.update_slots();
+        Some(tlas)
+    }
+
+    fn build(
+        builder: &SahBuilder<F>,
+        leaves: Vec<TlasLeaf<F, T, H, M>>,
+    ) -> Option<TopLevel<F, T, H, M>> {
+        builder.build(leaves).map(LinearBvh::from_bvh)
+    }
+
+    fn update_slots(&mut self) {
+        let primitives = self.bvh.primitives();
+        self.slots = vec![0; primitives.len()];
+        for (slot, leaf) in primitives.iter().enumerate() {
+            self.slots[leaf.id] = slot;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    pub fn instance(&self, id: usize) -> &Instance<F, T, H, M> {
+        &self.bvh.primitives()[self.slots[id]].instance
+    }
+
+    /// Moves an instance. Traces keep using the old bounds, and may miss the
+    /// instance, until `refit` or `rebuild` is called.
+    pub fn set_transform(&mut self, id: usize, transform: Transform<F>) {
+        let slot = self.slots[id];
+        self.bvh.primitives_mut()[slot]
+            .instance
+            .set_transform(transform);
+    }
+
+    /// Updates the top-level bounds for moved instances, keeping the tree.
+    pub fn refit(&mut self) {
+        self.bvh.refit();
+    }
+
+    /// Builds the top level from scratch for the current instance placement.
+    pub fn rebuild(&mut self) {
+        let leaves = self.bvh.primitives().to_vec();
+        self.bvh = Self::build(&self.builder, leaves).expect("TLAS without instances");
+        self.update_slots();
+    }
+}
+
+impl<F, T, H, M> Bounded<F, Aabb<F>> for Tlas<F, T, H, M>
+where
+    F: Float,
+    T: Traceable<F, H, M> + Bounded<F, Aabb<F>>,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+{
+    fn bounding_volume(&self) -> Aabb<F> {
+        self.bvh.bounding_volume()
+    }
+}
+
+impl<F, T, H, M> Traceable<F, Instance<F, T, H, M>, M> for Tlas<F, T, H, M>
+where
+    F: Float,
+    T: Traceable<F, H, M> + Bounded<F, Aabb<F>>,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+{
+    fn trace(&self, ray: &Ray<F>) -> Option<(F, &Instance<F, T, H, M>)> {
+        self.bvh.trace(ray)
+    }
+
+    fn occluded(&self, ray: &Ray<F>, t_max: F) -> bool {
+        self.bvh.occluded(ray, t_max)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use math::{Point, Vector};
+    use scenegraph::{Bvh, ShadedSphere};
+    use shading::DebugNormalMaterial;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_moved_instances_after_refit_and_rebuild() {
+        let spheres = (0..4)
+            .map(|i| {
+                ShadedSphere::new(
+                    Point::new(i as f64 * 3.0, 0.0, 0.0),
+                    1.0,
+                    DebugNormalMaterial {},
+                )
+            })
+            .collect();
+        let blas: Arc<Bvh<f64, Aabb<f64>, _, _, _>> =
+            Arc::new(SahBuilder::new().build(spheres).unwrap());
+
+        let instances = (0..16)
+            .map(|i| {
+                let offset = Vector::new(0.0, i as f64 * 4.0, 0.0);
+                Instance::new(blas.clone(), Transform::translation(offset))
+            })
+            .collect();
+        let mut tlas = Tlas::new(instances, SahBuilder::new().with_max_leaf_size(1)).unwrap();
+        assert_eq!(tlas.len(), 16);
+
+        let down = |x: f64, y: f64| Ray::new(Point::new(x, y, 10.0), -Vector::plus_z());
+        assert_eq!(tlas.trace(&down(3.0, 20.0)).map(|h| h.0), Some(9.0));
+        assert!(tlas.trace(&down(3.0, 100.0)).is_none());
+
+        let moved = Transform::translation(Vector::new(0.0, 100.0, -5.0));
+        tlas.set_transform(5, moved);
+        tlas.refit();
+        assert!(tlas.trace(&down(3.0, 20.0)).is_none());
+        assert_eq!(tlas.trace(&down(3.0, 100.0)).map(|h| h.0), Some(14.0));
+
+        tlas.rebuild();
+        assert_eq!(tlas.instance(5).transform(), &moved);
+        assert_eq!(tlas.trace(&down(6.0, 100.0)).map(|h| h.0), Some(14.0));
+        assert!(tlas.occluded(&down(9.0, 100.0), 20.0));
+    }
+}
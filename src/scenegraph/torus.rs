@@ -0,0 +1,269 @@
+use math::{turns, Aabb, Bounded, Float, Frame, Point, Ray, Vector, UV};
+use shading::Material;
+use tracing::{HitPoint, Hitable, Traceable};
+
+fn evaluate<F: Float>(coefficients: &[F], x: F) -> F {
+    coefficients
+        .iter()
+        .rev()
+        .fold(F::zero(), |sum, &c| sum * x + c)
+}
+
+/// Real roots within `lo..=hi` of the polynomial with the coefficient of
+/// `x^i` at index `i`, ascending. The roots of the derivative split the
+/// range into monotonic pieces holding at most one root each, which is then
+/// bisected. Slower than closed form solutions, but stays accurate for the
+/// badly conditioned quartics of grazing rays.
+fn roots_in<F: Float>(coefficients: &[F], lo: F, hi: F) -> Vec<F> {
+    if coefficients.len() < 2 {
+        return Vec::new();
+    }
+    if coefficients.len() == 2 {
+        let (c0, c1) = (coefficients[0], coefficients[1]);
+        let root = -c0 / c1;
+        return if c1 != F::zero() && root >= lo && root <= hi {
+            vec![root]
+        } else {
+            Vec::new()
+        };
+    }
+
+    let derivative: Vec<F> = coefficients[1..]
+        .iter()
+        .enumerate()
+        .map(|(i, &c)| c * F::from(i + 1).unwrap())
+        .collect();
+    let mut bounds = vec![lo];
+    bounds.extend(roots_in(&derivative, lo, hi));
+    bounds.push(hi);
+
+    let mut roots = Vec::new();
+    for piece in bounds.windows(2) {
+        let (mut a, mut b) = (piece[0], piece[1]);
+        let (mut fa, fb) = (evaluate(coefficients, a), evaluate(coefficients, b));
+        if fa == F::zero() {
+            if roots.last() != Some(&a) {
+                roots.push(a);
+            }
+            continue;
+        }
+        // a root at `b` is found as the start of the next piece
+        if fb == F::zero() || (fa < F::zero()) == (fb < F::zero()) {
+            continue;
+        }
+
+        for _ in 0..100 {
+            let mid = (a + b) * (F::one() + F::one()).recip();
+            if mid <= a || mid >= b {
+                break;
+            }
+            let fm = evaluate(coefficients, mid);
+            if (fm < F::zero()) == (fa < F::zero()) {
+                a = mid;
+                fa = fm;
+            } else {
+                b = mid;
+            }
+        }
+        roots.push(a);
+    }
+    if evaluate(coefficients, hi) == F::zero() && roots.last() != Some(&hi) {
+        roots.push(hi);
+    }
+    roots
+}
+
+/// Ring torus around an axis. `u` is the angle around the axis and `v` the
+/// angle around the tube, both as fractions of a turn.
+#[derive(Clone, Copy, Debug)]
+pub struct Torus<F: Float, M: Material<F>> {
+    frame: Frame<F>,
+    major_radius: F,
+    minor_radius: F,
+    material: M,
+    casts_shadows: bool,
+}
+
+impl<F: Float, M: Material<F>> Torus<F, M> {
+    /// `major_radius` is the distance from the center to the middle of the
+    /// tube, `minor_radius` the radius of the tube.
+    pub fn new(
+        center: Point<F>,
+        axis: Vector<F>,
+        major_radius: F,
+        minor_radius: F,
+        material: M,
+    ) -> Self {
+        Torus {
+            frame: Frame::from_axis(center, axis),
+            major_radius,
+            minor_radius,
+            material,
+            casts_shadows: true,
+        }
+    }
+
+    pub fn with_casts_shadows(mut self, casts_shadows: bool) -> Self {
+        self.casts_shadows = casts_shadows;
+        self
+    }
+
+    fn local_bounds(&self) -> Aabb<F> {
+        let outer = self.major_radius + self.minor_radius;
+        Aabb {
+            x_min: -outer,
+            x_max: outer,
+            y_min: -outer,
+            y_max: outer,
+            z_min: -self.minor_radius,
+            z_max: self.minor_radius,
+        }
+    }
+}
+
+impl<F: Float, M: Material<F>> Hitable<F> for Torus<F, M> {
+    type Material = M;
+    fn get_hit(&self, ray: &Ray<F>, distance: F) -> HitPoint<F, M> {
+        let point = ray.point_at_distance(distance);
+        let local = self.frame.to_local_point(point);
+        let radial = local.x.hypot(local.y);
+
+        // away from the closest point on the circle through the tube
+        let ring = Vector::new(local.x, local.y, F::zero()) * (self.major_radius / radial);
+        let normal = ((local - Point::origin()) - ring).normalized();
+        let uv = UV::new(
+            turns(local.y, local.x),
+            turns(local.z, radial - self.major_radius),
+        );
+
+        HitPoint::new(
+            point,
+            self.frame.to_world_vector(normal),
+            ray.direction,
+            uv,
+            self.material.clone(),
+        )
+    }
+}
+
+impl<F: Float, M: Material<F>> Traceable<F, Self, M> for Torus<F, M> {
+    fn trace(&self, ray: &Ray<F>) -> Option<(F, &Self)> {
+        let local = self.frame.to_local_ray(ray);
+
+        // restrict the search to the bounding box, and start the polynomial
+        // there to keep its coefficients small. The box is padded a little,
+        // as roots on its faces are easily lost to rounding.
+        let bounds = self.local_bounds();
+        let pad =
+            Vector::new(F::one(), F::one(), F::one()) * self.minor_radius * F::epsilon().sqrt();
+        let (min, max) = (bounds.min_point() - pad, bounds.max_point() + pad);
+        let mut lo = local.t_min;
+        let mut hi = local.t_max;
+        for axis in 0..3 {
+            let t1 = (min[axis] - local.origin[axis]) * local.inv_direction[axis];
+            let t2 = (max[axis] - local.origin[axis]) * local.inv_direction[axis];
+            lo = F::max(lo, F::min(t1, t2));
+            hi = F::min(hi, F::max(t1, t2));
+        }
+        if lo > hi || !hi.is_finite() {
+            return None;
+        }
+
+        let o = local.point_at_distance(lo) - Point::origin();
+        let d = local.direction;
+        let r2 = self.major_radius * self.major_radius;
+        let two = F::one() + F::one();
+        let four = two + two;
+
+        // (|p|² + R² - r²)² = 4R²(x² + y²) with p = o + s d
+        let dd = d.dot(d);
+        let od = o.dot(d);
+        let g = o.dot(o) + r2 - self.minor_radius * self.minor_radius;
+        let coefficients = [
+            g * g - four * r2 * (o.x * o.x + o.y * o.y),
+            four * od * g - four * two * r2 * (o.x * d.x + o.y * d.y),
+            four * od * od + two * dd * g - four * r2 * (d.x * d.x + d.y * d.y),
+            four * dd * od,
+            dd * dd,
+        ];
+
+        roots_in(&coefficients, F::zero(), hi - lo)
+            .into_iter()
+            .map(|s| lo + s)
+            .find(|&t| ray.contains(t))
+            .map(|t| (t, self))
+    }
+
+    fn casts_shadows(&self) -> bool {
+        self.casts_shadows
+    }
+}
+
+impl<F: Float, M: Material<F>> Bounded<F, Aabb<F>> for Torus<F, M> {
+    fn bounding_volume(&self) -> Aabb<F> {
+        let bounds = self.local_bounds();
+        let corners: Vec<_> = (0..8)
+            .map(|i| {
+                self.frame.to_world_point(Point::new(
+                    if i & 1 == 0 {
+                        bounds.x_min
+                    } else {
+                        bounds.x_max
+                    },
+                    if i & 2 == 0 {
+                        bounds.y_min
+                    } else {
+                        bounds.y_max
+                    },
+                    if i & 4 == 0 {
+                        bounds.z_min
+                    } else {
+                        bounds.z_max
+                    },
+                ))
+            })
+            .collect();
+        Aabb::from_points(&corners)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shading::DebugNormalMaterial;
+
+    #[test]
+    fn test_torus_hits_and_holes() {
+        let torus = Torus::new(
+            Point::origin(),
+            Vector::plus_z(),
+            2.0,
+            0.5,
+            DebugNormalMaterial {},
+        );
+        let hit = |origin: Point<f64>, direction: Vector<f64>| {
+            let ray = Ray::new(origin, direction);
+            torus.trace(&ray).map(|(t, h)| (t, h.get_hit(&ray, t).data))
+        };
+
+        // along the axis through the hole
+        assert!(hit(Point::new(0.0, 0.0, 5.0), -Vector::plus_z()).is_none());
+
+        // straight down onto the top of the tube
+        let (t, data) = hit(Point::new(2.0, 0.0, 5.0), -Vector::plus_z()).unwrap();
+        assert!((t - 4.5).abs() < 1e-9);
+        assert!((data.normal - Vector::plus_z()).magnitude() < 1e-9);
+        assert!(data.uv.u.abs() < 1e-9 && (data.uv.v - 0.25).abs() < 1e-9);
+
+        // through the whole ring: the near tube first, then the far one
+        let (t, data) = hit(Point::new(-5.0, 0.0, 0.0), Vector::plus_x()).unwrap();
+        assert!((t - 2.5).abs() < 1e-9);
+        assert!((data.normal + Vector::plus_x()).magnitude() < 1e-9);
+        let ray = Ray::new(Point::new(-5.0, 0.0, 0.0), Vector::plus_x()).with_t_min(4.0);
+        assert!((torus.trace(&ray).unwrap().0 - 6.5).abs() < 1e-9);
+
+        // grazing the top of the tube from the side
+        assert!(hit(Point::new(-5.0, 0.0, 0.49), Vector::plus_x()).is_some());
+        assert!(hit(Point::new(-5.0, 0.0, 0.51), Vector::plus_x()).is_none());
+    }
+}
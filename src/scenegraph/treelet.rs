@@ -0,0 +1,252 @@
+use math::{Bounded, BoundingVolume, Float};
+use scenegraph::{Bvh, BvhLeaf, BvhNode};
+use shading::Material;
+use tracing::Hitable;
+
+#[derive(Clone, Copy)]
+enum Choice {
+    Unit,
+    Flat,
+    Split(usize),
+}
+
+/// Treelet restructuring after Karras and Aila, "Fast Parallel Construction
+/// of High-Quality Bounding Volume Hierarchies".
+///
+/// Each node is grown into a treelet by repeatedly opening the largest
+/// subtree below it, and the topology of that treelet is then replaced with
+/// the one of lowest SAH cost, found by dynamic programming over all subsets
+/// of the treelet leaves. Mostly useful for fast but sloppy builders like
+/// `LbvhBuilder`.
+#[derive(Debug, Clone, Copy)]
+pub struct TreeletOptimizer<F: Float> {
+    treelet_size: usize,
+    max_leaf_size: usize,
+    traversal_cost: F,
+    intersection_cost: F,
+}
+
+impl<F: Float> Default for TreeletOptimizer<F> {
+    fn default() -> Self {
+        TreeletOptimizer {
+            treelet_size: 7,
+            max_leaf_size: 4,
+            traversal_cost: F::from(0.125).unwrap(),
+            intersection_cost: F::one(),
+        }
+    }
+}
+
+impl<F: Float> TreeletOptimizer<F> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of subtrees rearranged at once. The work per node grows with
+    /// `3^treelet_size`, so it is clamped to 3..=10.
+    pub fn with_treelet_size(mut self, treelet_size: usize) -> Self {
+        self.treelet_size = treelet_size.clamp(3, 10);
+        self
+    }
+
+    /// Largest number of primitives the optimizer may gather in one node.
+    pub fn with_max_leaf_size(mut self, max_leaf_size: usize) -> Self {
+        self.max_leaf_size = max_leaf_size.max(1);
+        self
+    }
+
+    /// Relative cost of visiting a node versus intersecting one primitive.
+    pub fn with_costs(mut self, traversal_cost: F, intersection_cost: F) -> Self {
+        self.traversal_cost = traversal_cost;
+        self.intersection_cost = intersection_cost;
+        self
+    }
+
+    /// Optimizes the whole tree, bottom-up.
+    pub fn optimize<B, H, M, L>(&self, bvh: Bvh<F, B, H, M, L>) -> Bvh<F, B, H, M, L>
+    where
+        B: BoundingVolume<F>,
+        H: Hitable<F, Material = M>,
+        M: Material<F>,
+        L: BvhLeaf<F, B, H, M>,
+    {
+        let children = bvh
+            .into_children()
+            .into_iter()
+            .map(|child| match child {
+                BvhNode::Node(node) => BvhNode::Node(self.optimize(node)),
+                leaf => leaf,
+            })
+            .collect();
+        self.optimize_node(Bvh::from_nodes(children).expect("BVH node without children"))
+    }
+
+    /// Optimizes only the treelet rooted at `bvh`, for builders that
+    /// already optimized the subtrees below it.
+    pub fn optimize_node<B, H, M, L>(&self, bvh: Bvh<F, B, H, M, L>) -> Bvh<F, B, H, M, L>
+    where
+        B: BoundingVolume<F>,
+        H: Hitable<F, Material = M>,
+        M: Material<F>,
+        L: BvhLeaf<F, B, H, M>,
+    {
+        let mut units = bvh.into_children();
+
+        // open the subtree with the largest surface area while it still fits
+        loop {
+            let open = units
+                .iter()
+                .enumerate()
+                .filter_map(|(index, unit)| match unit {
+                    BvhNode::Node(node)
+                        if units.len() - 1 + node.children().len() <= self.treelet_size =>
+                    {
+                        Some((index, node.bounding_volume().surface_area()))
+                    }
+                    _ => None,
+                })
+                .fold(None, |best: Option<(usize, F)>, (index, area)| match best {
+                    Some((_, best_area)) if best_area >= area => best,
+                    _ => Some((index, area)),
+                });
+
+            match open {
+                Some((index, _)) => match units.swap_remove(index) {
+                    BvhNode::Node(node) => units.extend(node.into_children()),
+                    BvhNode::Leaf(_) => unreachable!(),
+                },
+                None => break,
+            }
+        }
+
+        if units.len() < 3 {
+            return Bvh::from_nodes(units).expect("BVH node without children");
+        }
+
+        let choices = self.best_topology(&units);
+        let mut units: Vec<_> = units.into_iter().map(Some).collect();
+        let full = (1 << units.len()) - 1;
+        match Self::assemble(full, &choices, &mut units) {
+            BvhNode::Node(node) => node,
+            BvhNode::Leaf(_) => unreachable!(),
+        }
+    }
+
+    /// Cheapest way to arrange every subset of `units`, indexed by bit mask.
+    fn best_topology<B, H, M, L>(&self, units: &[BvhNode<F, B, H, M, L>]) -> Vec<Choice>
+    where
+        B: BoundingVolume<F>,
+        H: Hitable<F, Material = M>,
+        M: Material<F>,
+        L: BvhLeaf<F, B, H, M>,
+    {
+        let count = units.len();
+        let subsets = 1usize << count;
+        let is_primitive: Vec<bool> = units
+            .iter()
+            .map(|unit| match unit {
+                BvhNode::Leaf(_) => true,
+                BvhNode::Node(_) => false,
+            })
+            .collect();
+
+        let mut bounds: Vec<Option<B>> = vec![None; subsets];
+        for (index, unit) in units.iter().enumerate() {
+            bounds[1 << index] = Some(unit.bounding_volume());
+        }
+        let mut areas = vec![F::zero(); subsets];
+        for set in 1..subsets {
+            let low = set & set.wrapping_neg();
+            if set != low {
+                let rest = bounds[set ^ low].unwrap();
+                bounds[set] = Some(rest.combine(&bounds[low].unwrap()));
+            }
+            areas[set] = bounds[set].unwrap().surface_area();
+        }
+
+        // costs of subtrees already below the treelet are the same for every
+        // topology and left out, primitives are paid for by their parent
+        let mut costs = vec![F::zero(); subsets];
+        let mut choices = vec![Choice::Unit; subsets];
+        let as_child = |set: usize, parent_area: F, costs: &[F]| {
+            if set.count_ones() == 1 && is_primitive[set.trailing_zeros() as usize] {
+                parent_area * self.intersection_cost
+            } else {
+                costs[set]
+            }
+        };
+
+        for set in 1..subsets {
+            let size = set.count_ones() as usize;
+            if size == 1 {
+                continue;
+            }
+
+            let area = areas[set];
+            let mut best = None;
+
+            let all_primitives = (0..count).all(|i| set & (1 << i) == 0 || is_primitive[i]);
+            if all_primitives && size <= self.max_leaf_size {
+                let flat =
+                    area * (self.traversal_cost + self.intersection_cost * F::from(size).unwrap());
+                best = Some((flat, Choice::Flat));
+            }
+
+            // halves always containing the lowest unit, to skip mirrored splits
+            let low = set & set.wrapping_neg();
+            let rest = set ^ low;
+            let mut sub = rest;
+            loop {
+                let left = sub | low;
+                if left != set {
+                    let right = set ^ left;
+                    let cost = area * self.traversal_cost
+                        + as_child(left, area, &costs)
+                        + as_child(right, area, &costs);
+                    if best.is_none_or(|(best_cost, _)| cost < best_cost) {
+                        best = Some((cost, Choice::Split(left)));
+                    }
+                }
+                if sub == 0 {
+                    break;
+                }
+                sub = (sub - 1) & rest;
+            }
+
+            let (cost, choice) = best.unwrap();
+            costs[set] = cost;
+            choices[set] = choice;
+        }
+
+        choices
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn assemble<B, H, M, L>(
+        set: usize,
+        choices: &[Choice],
+        units: &mut [Option<BvhNode<F, B, H, M, L>>],
+    ) -> BvhNode<F, B, H, M, L>
+    where
+        B: BoundingVolume<F>,
+        H: Hitable<F, Material = M>,
+        M: Material<F>,
+        L: BvhLeaf<F, B, H, M>,
+    {
+        let children = match choices[set] {
+            Choice::Unit => {
+                let index = set.trailing_zeros() as usize;
+                return units[index].take().expect("treelet unit used twice");
+            }
+            Choice::Flat => (0..units.len())
+                .filter(|i| set & (1 << i) != 0)
+                .map(|i| units[i].take().expect("treelet unit used twice"))
+                .collect(),
+            Choice::Split(left) => vec![
+                Self::assemble(left, choices, units),
+                Self::assemble(set ^ left, choices, units),
+            ],
+        };
+        BvhNode::Node(Bvh::from_nodes(children).expect("BVH node without children"))
+    }
+}
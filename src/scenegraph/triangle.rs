@@ -0,0 +1,276 @@
+use math::{Aabb, Bounded, Dop14, Float, Obb, Point, Ray, Vector, UV};
+use scenegraph::Sphere;
+use shading::Material;
+use tracing::{HitPoint, Hitable, Traceable};
+
+/// Watertight ray/triangle intersection (Woop, Benthin, Wald 2013).
+///
+/// Vertices are transformed into a ray-aligned space where the ray points
+/// down the +z axis, so the edge functions of two triangles sharing an edge
+/// are evaluated on exactly the same values and can never both reject a ray
+/// crossing that edge. Returns the hit distance, within the ray interval, and
+/// barycentric coordinates.
+pub fn intersect_triangle<F: Float>(
+    ray: &Ray<F>,
+    p0: Point<F>,
+    p1: Point<F>,
+    p2: Point<F>,
+) -> Option<(F, [F; 3])> {
+    let dir = ray.direction;
+    let (ax, ay, az) = (dir.x.abs(), dir.y.abs(), dir.z.abs());
+    let kz = if ax > ay && ax > az {
+        0
+    } else if ay > az {
+        1
+    } else {
+        2
+    };
+    let kx = (kz + 1) % 3;
+    let ky = (kx + 1) % 3;
+
+    let shear_x = -dir[kx] / dir[kz];
+    let shear_y = -dir[ky] / dir[kz];
+    let shear_z = dir[kz].recip();
+
+    let p0t = p0 - ray.origin;
+    let p1t = p1 - ray.origin;
+    let p2t = p2 - ray.origin;
+
+    let (x0, y0) = (p0t[kx] + shear_x * p0t[kz], p0t[ky] + shear_y * p0t[kz]);
+    let (x1, y1) = (p1t[kx] + shear_x * p1t[kz], p1t[ky] + shear_y * p1t[kz]);
+    let (x2, y2) = (p2t[kx] + shear_x * p2t[kz], p2t[ky] + shear_y * p2t[kz]);
+
+    let e0 = x1 * y2 - y1 * x2;
+    let e1 = x2 * y0 - y2 * x0;
+    let e2 = x0 * y1 - y0 * x1;
+
+    let zero = F::zero();
+    if (e0 < zero || e1 < zero || e2 < zero) && (e0 > zero || e1 > zero || e2 > zero) {
+        return None;
+    }
+
+    let det = e0 + e1 + e2;
+    if det == zero {
+        return None;
+    }
+
+    let z0 = p0t[kz] * shear_z;
+    let z1 = p1t[kz] * shear_z;
+    let z2 = p2t[kz] * shear_z;
+    let t_scaled = e0 * z0 + e1 * z1 + e2 * z2;
+
+    let inv_det = det.recip();
+    let t = t_scaled * inv_det;
+    if !ray.contains(t) {
+        return None;
+    }
+
+    Some((t, [e0 * inv_det, e1 * inv_det, e2 * inv_det]))
+}
+
+/// Barycentric coordinates of a point lying in the plane of a triangle.
+pub fn barycentric<F: Float>(point: Point<F>, p0: Point<F>, p1: Point<F>, p2: Point<F>) -> [F; 3] {
+    let e1 = p1 - p0;
+    let e2 = p2 - p0;
+    let ep = point - p0;
+
+    let d11 = e1.dot(e1);
+    let d12 = e1.dot(e2);
+    let d22 = e2.dot(e2);
+    let dp1 = ep.dot(e1);
+    let dp2 = ep.dot(e2);
+
+    let inv_denom = (d11 * d22 - d12 * d12).recip();
+    let b1 = (d22 * dp1 - d12 * dp2) * inv_denom;
+    let b2 = (d11 * dp2 - d12 * dp1) * inv_denom;
+    [F::one() - b1 - b2, b1, b2]
+}
+
+/// Builds the hit point for a triangle, interpolating shading normals and UVs
+/// when present. Without per-vertex normals the geometric normal is used, and
+/// without UVs the barycentric coordinates are exposed as parametric UV.
+pub fn triangle_hit<F: Float, M: Material<F>>(
+    ray: &Ray<F>,
+    distance: F,
+    vertices: [Point<F>; 3],
+    normals: Option<[Vector<F>; 3]>,
+    uvs: Option<[UV<F>; 3]>,
+    material: M,
+) -> HitPoint<F, M> {
+    let point = ray.point_at_distance(distance);
+    let [b0, b1, b2] = barycentric(point, vertices[0], vertices[1], vertices[2]);
+
+    let normal = match normals {
+        Some([n0, n1, n2]) => (n0 * b0 + n1 * b1 + n2 * b2).normalized(),
+        None => (vertices[1] - vertices[0])
+            .cross(vertices[2] - vertices[0])
+            .normalized(),
+    };
+
+    let uv = match uvs {
+        Some([uv0, uv1, uv2]) => UV::new(
+            uv0.u * b0 + uv1.u * b1 + uv2.u * b2,
+            uv0.v * b0 + uv1.v * b1 + uv2.v * b2,
+        ),
+        None => UV::new(b1, b2),
+    };
+
+    HitPoint::new(point, normal, ray.direction, uv, material)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Triangle<F: Float, M: Material<F>> {
+    vertices: [Point<F>; 3],
+    normals: Option<[Vector<F>; 3]>,
+    uvs: Option<[UV<F>; 3]>,
+    material: M,
+    casts_shadows: bool,
+}
+
+impl<F: Float, M: Material<F>> Triangle<F, M> {
+    pub fn new(vertices: [Point<F>; 3], material: M) -> Self {
+        Triangle {
+            vertices,
+            normals: None,
+            uvs: None,
+            material,
+            casts_shadows: true,
+        }
+    }
+
+    pub fn with_casts_shadows(mut self, casts_shadows: bool) -> Self {
+        self.casts_shadows = casts_shadows;
+        self
+    }
+
+    pub fn with_normals(mut self, normals: [Vector<F>; 3]) -> Self {
+        self.normals = Some(normals);
+        self
+    }
+
+    pub fn with_uvs(mut self, uvs: [UV<F>; 3]) -> Self {
+        self.uvs = Some(uvs);
+        self
+    }
+
+    pub fn vertices(&self) -> &[Point<F>; 3] {
+        &self.vertices
+    }
+}
+
+impl<F: Float, M: Material<F>> Hitable<F> for Triangle<F, M> {
+    type Material = M;
+    fn get_hit(&self, ray: &Ray<F>, distance: F) -> HitPoint<F, Self::Material> {
+        triangle_hit(
+            ray,
+            distance,
+            self.vertices,
+            self.normals,
+            self.uvs,
+            self.material.clone(),
+        )
+    }
+}
+
+impl<F, M> Traceable<F, Self, M> for Triangle<F, M>
+where
+    F: Float,
+    M: Material<F>,
+{
+    fn trace(&self, ray: &Ray<F>) -> Option<(F, &Self)> {
+        let [p0, p1, p2] = self.vertices;
+        intersect_triangle(ray, p0, p1, p2).map(|(distance, _)| (distance, self))
+    }
+
+    fn casts_shadows(&self) -> bool {
+        self.casts_shadows
+    }
+}
+
+impl<F: Float, M: Material<F>> Bounded<F, Aabb<F>> for Triangle<F, M> {
+    fn bounding_volume(&self) -> Aabb<F> {
+        Aabb::from_points(&self.vertices)
+    }
+}
+
+impl<F: Float, M: Material<F>> Bounded<F, Sphere<F>> for Triangle<F, M> {
+    fn bounding_volume(&self) -> Sphere<F> {
+        Sphere::from_points(&self.vertices)
+    }
+}
+
+impl<F: Float, M: Material<F>> Bounded<F, Obb<F>> for Triangle<F, M> {
+    fn bounding_volume(&self) -> Obb<F> {
+        Obb::from_points(&self.vertices)
+    }
+}
+
+impl<F: Float, M: Material<F>> Bounded<F, Dop14<F>> for Triangle<F, M> {
+    fn bounding_volume(&self) -> Dop14<F> {
+        Dop14::from_points(&self.vertices)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shading::DebugNormalMaterial;
+
+    fn tri(a: (f64, f64), b: (f64, f64), c: (f64, f64)) -> Triangle<f64, DebugNormalMaterial> {
+        Triangle::new(
+            [
+                Point::new(a.0, a.1, 0.0),
+                Point::new(b.0, b.1, 0.0),
+                Point::new(c.0, c.1, 0.0),
+            ],
+            DebugNormalMaterial {},
+        )
+    }
+
+    #[test]
+    fn test_triangle_hit_and_miss() {
+        let t = tri((0.0, 0.0), (1.0, 0.0), (0.0, 1.0));
+        let down = Vector::new(0.0, 0.0, -1.0);
+
+        let hit = t.trace(&Ray::new(Point::new(0.25, 0.25, 2.0), down));
+        assert_eq!(hit.map(|(d, _)| d), Some(2.0));
+
+        assert!(t
+            .trace(&Ray::new(Point::new(0.75, 0.75, 2.0), down))
+            .is_none());
+        assert!(t
+            .trace(&Ray::new(Point::new(0.25, 0.25, -2.0), down))
+            .is_none());
+    }
+
+    #[test]
+    fn test_triangle_interpolated_hit() {
+        let t = tri((0.0, 0.0), (1.0, 0.0), (0.0, 1.0))
+            .with_normals([Vector::plus_z(), Vector::plus_x(), Vector::plus_y()])
+            .with_uvs([UV::new(0.0, 0.0), UV::new(1.0, 0.0), UV::new(0.0, 1.0)]);
+
+        let ray = Ray::new(Point::new(0.5, 0.0, 1.0), Vector::new(0.0, 0.0, -1.0));
+        let (distance, hitable) = t.trace(&ray).unwrap();
+        let hit = hitable.get_hit(&ray, distance).data;
+
+        assert!((hit.normal - Vector::new(0.5, 0.0, 0.5).normalized()).magnitude() < 1e-9);
+        assert!((hit.uv.u - 0.5).abs() < 1e-9);
+        assert!(hit.uv.v.abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_shared_edge_is_watertight() {
+        // two triangles forming a quad, split along its diagonal
+        let a = tri((0.0, 0.0), (1.0, 0.0), (1.0, 1.0));
+        let b = tri((0.0, 0.0), (1.0, 1.0), (0.0, 1.0));
+
+        let steps = 97;
+        for i in 1..steps {
+            let s = i as f64 / steps as f64;
+            let target = Point::new(s, s, 0.0);
+            let dir = Vector::new(0.3 * s, -0.7 * s, -1.0).normalized();
+            let ray = Ray::new(target - dir * 3.0, dir);
+            assert!(a.trace(&ray).is_some() || b.trace(&ray).is_some());
+        }
+    }
+}
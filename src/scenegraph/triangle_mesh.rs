@@ -0,0 +1,287 @@
+use math::{Aabb, Bounded, BoundingVolume, Dop14, Float, Obb, Point, Ray, Vector, UV};
+use scenegraph::{intersect_triangle, triangle_hit, Bvh, Displacement, Sphere};
+use shading::{Material, ScalarTexture};
+use std::cmp::Ordering::Equal;
+use std::sync::Arc;
+use tracing::{HitPoint, Hitable, Traceable};
+
+/// Vertex data shared by all triangles of a mesh. Every attribute is indexed
+/// by the same vertex index.
+#[derive(Debug, Clone)]
+pub struct MeshData<F: Float, M: Material<F>> {
+    positions: Vec<Point<F>>,
+    normals: Option<Vec<Vector<F>>>,
+    uvs: Option<Vec<UV<F>>>,
+    indices: Vec<[usize; 3]>,
+    material: M,
+    casts_shadows: bool,
+}
+
+impl<F: Float, M: Material<F>> MeshData<F, M> {
+    pub fn positions(&self) -> &[Point<F>] {
+        &self.positions
+    }
+
+    pub fn normals(&self) -> Option<&[Vector<F>]> {
+        self.normals.as_ref().map(|n| &n[..])
+    }
+
+    pub fn uvs(&self) -> Option<&[UV<F>]> {
+        self.uvs.as_ref().map(|uv| &uv[..])
+    }
+
+    pub fn indices(&self) -> &[[usize; 3]] {
+        &self.indices
+    }
+
+    pub fn material(&self) -> &M {
+        &self.material
+    }
+
+    pub fn casts_shadows(&self) -> bool {
+        self.casts_shadows
+    }
+}
+
+/// Area weighted average of the face normals around every vertex. Vertices
+/// only used by degenerate faces point up.
+pub fn smooth_normals<F: Float>(positions: &[Point<F>], indices: &[[usize; 3]]) -> Vec<Vector<F>> {
+    let mut sums = vec![Vector::new(F::zero(), F::zero(), F::zero()); positions.len()];
+    for &[i0, i1, i2] in indices {
+        let face = (positions[i1] - positions[i0]).cross(positions[i2] - positions[i0]);
+        for &i in &[i0, i1, i2] {
+            sums[i] = sums[i] + face;
+        }
+    }
+    sums.into_iter()
+        .map(|sum| {
+            if sum.magnitude_sq() > F::zero() {
+                sum.normalized()
+            } else {
+                Vector::plus_y()
+            }
+        })
+        .collect()
+}
+
+/// Single triangle of an indexed mesh. Cheap to clone, so a mesh can be split
+/// into individual `BvhNode::Leaf`s without copying vertex data.
+#[derive(Debug)]
+pub struct MeshTriangle<F: Float, M: Material<F>> {
+    mesh: Arc<MeshData<F, M>>,
+    index: usize,
+}
+
+impl<F: Float, M: Material<F>> Clone for MeshTriangle<F, M> {
+    fn clone(&self) -> Self {
+        MeshTriangle {
+            mesh: self.mesh.clone(),
+            index: self.index,
+        }
+    }
+}
+
+impl<F: Float, M: Material<F>> MeshTriangle<F, M> {
+    pub fn vertices(&self) -> [Point<F>; 3] {
+        let [i0, i1, i2] = self.mesh.indices[self.index];
+        let p = &self.mesh.positions;
+        [p[i0], p[i1], p[i2]]
+    }
+
+    fn normals(&self) -> Option<[Vector<F>; 3]> {
+        let [i0, i1, i2] = self.mesh.indices[self.index];
+        self.mesh.normals.as_ref().map(|n| [n[i0], n[i1], n[i2]])
+    }
+
+    fn uvs(&self) -> Option<[UV<F>; 3]> {
+        let [i0, i1, i2] = self.mesh.indices[self.index];
+        self.mesh.uvs.as_ref().map(|uv| [uv[i0], uv[i1], uv[i2]])
+    }
+
+    pub fn mesh(&self) -> &Arc<MeshData<F, M>> {
+        &self.mesh
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+impl<F: Float, M: Material<F>> Hitable<F> for MeshTriangle<F, M> {
+    type Material = M;
+    fn get_hit(&self, ray: &Ray<F>, distance: F) -> HitPoint<F, Self::Material> {
+        triangle_hit(
+            ray,
+            distance,
+            self.vertices(),
+            self.normals(),
+            self.uvs(),
+            self.mesh.material.clone(),
+        )
+    }
+}
+
+impl<F, M> Traceable<F, Self, M> for MeshTriangle<F, M>
+where
+    F: Float,
+    M: Material<F>,
+{
+    fn trace(&self, ray: &Ray<F>) -> Option<(F, &Self)> {
+        let [p0, p1, p2] = self.vertices();
+        intersect_triangle(ray, p0, p1, p2).map(|(distance, _)| (distance, self))
+    }
+
+    fn casts_shadows(&self) -> bool {
+        self.mesh.casts_shadows
+    }
+}
+
+impl<F: Float, M: Material<F>> Bounded<F, Aabb<F>> for MeshTriangle<F, M> {
+    fn bounding_volume(&self) -> Aabb<F> {
+        Aabb::from_points(&self.vertices())
+    }
+}
+
+impl<F: Float, M: Material<F>> Bounded<F, Sphere<F>> for MeshTriangle<F, M> {
+    fn bounding_volume(&self) -> Sphere<F> {
+        Sphere::from_points(&self.vertices())
+    }
+}
+
+impl<F: Float, M: Material<F>> Bounded<F, Obb<F>> for MeshTriangle<F, M> {
+    fn bounding_volume(&self) -> Obb<F> {
+        Obb::from_points(&self.vertices())
+    }
+}
+
+impl<F: Float, M: Material<F>> Bounded<F, Dop14<F>> for MeshTriangle<F, M> {
+    fn bounding_volume(&self) -> Dop14<F> {
+        Dop14::from_points(&self.vertices())
+    }
+}
+
+/// BVH with every mesh triangle in its own leaf.
+pub type MeshBvh<F, M> = Bvh<F, Aabb<F>, MeshTriangle<F, M>, M, MeshTriangle<F, M>>;
+
+/// Indexed triangle mesh. Can be traced as a single object, or split with
+/// `into_triangles` to put every triangle into its own BVH leaf.
+pub struct TriangleMesh<F: Float, M: Material<F>> {
+    triangles: Vec<MeshTriangle<F, M>>,
+    bound: Aabb<F>,
+}
+
+impl<F: Float, M: Material<F>> TriangleMesh<F, M> {
+    /// Returns `None` for meshes without triangles, when an index points past
+    /// the vertex list, or when normal or UV counts do not match positions.
+    pub fn new(
+        positions: Vec<Point<F>>,
+        normals: Option<Vec<Vector<F>>>,
+        uvs: Option<Vec<UV<F>>>,
+        indices: Vec<[usize; 3]>,
+        material: M,
+    ) -> Option<Self> {
+        let count = positions.len();
+        let attributes_match = normals.as_ref().is_none_or(|n| n.len() == count)
+            && uvs.as_ref().is_none_or(|uv| uv.len() == count);
+        let indices_valid = indices.iter().all(|tri| tri.iter().all(|&i| i < count));
+
+        if indices.is_empty() || !attributes_match || !indices_valid {
+            return None;
+        }
+
+        Some(Self::from_data(MeshData {
+            positions,
+            normals,
+            uvs,
+            indices,
+            material,
+            casts_shadows: true,
+        }))
+    }
+
+    fn from_data(data: MeshData<F, M>) -> Self {
+        let mesh = Arc::new(data);
+        let triangles: Vec<_> = (0..mesh.indices.len())
+            .map(|index| MeshTriangle {
+                mesh: mesh.clone(),
+                index,
+            })
+            .collect();
+
+        let bound = triangles
+            .iter()
+            .map(|tri| tri.bounding_volume())
+            .fold(Aabb::empty(), |a, b| a.combine(&b));
+
+        TriangleMesh { triangles, bound }
+    }
+
+    pub fn with_casts_shadows(mut self, casts_shadows: bool) -> Self {
+        let mesh = self.triangles[0].mesh.clone();
+        self.triangles.clear();
+        // vertex data is only copied if triangles were cloned out of the mesh
+        let mut data = Arc::try_unwrap(mesh).unwrap_or_else(|shared| (*shared).clone());
+        data.casts_shadows = casts_shadows;
+        Self::from_data(data)
+    }
+
+    /// Tessellates the mesh and moves the vertices along their normals, see
+    /// `Displacement`.
+    pub fn with_displacement<T: ScalarTexture<F>>(
+        mut self,
+        displacement: &Displacement<F, T>,
+    ) -> Self {
+        let mesh = self.triangles[0].mesh.clone();
+        self.triangles.clear();
+        let data = Arc::try_unwrap(mesh).unwrap_or_else(|shared| (*shared).clone());
+        let (positions, normals, uvs, indices) = displacement.tessellate(&data);
+        Self::from_data(MeshData {
+            positions,
+            normals: Some(normals),
+            uvs,
+            indices,
+            ..data
+        })
+    }
+
+    pub fn triangles(&self) -> &[MeshTriangle<F, M>] {
+        &self.triangles
+    }
+
+    pub fn into_triangles(self) -> Vec<MeshTriangle<F, M>> {
+        self.triangles
+    }
+}
+
+impl<F, M> Traceable<F, MeshTriangle<F, M>, M> for TriangleMesh<F, M>
+where
+    F: Float,
+    M: Material<F>,
+{
+    fn trace(&self, ray: &Ray<F>) -> Option<(F, &MeshTriangle<F, M>)> {
+        self.bound.test(ray)?;
+        self.triangles
+            .iter()
+            .filter_map(|tri| tri.trace(ray))
+            .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Equal))
+    }
+
+    fn casts_shadows(&self) -> bool {
+        self.triangles[0].mesh.casts_shadows
+    }
+
+    fn occluded(&self, ray: &Ray<F>, t_max: F) -> bool {
+        if !self.casts_shadows() {
+            return false;
+        }
+        let ray = ray.clone().with_t_max(F::min(ray.t_max, t_max));
+        self.bound.test(&ray).is_some()
+            && self.triangles.iter().any(|tri| tri.trace(&ray).is_some())
+    }
+}
+
+impl<F: Float, M: Material<F>> Bounded<F, Aabb<F>> for TriangleMesh<F, M> {
+    fn bounding_volume(&self) -> Aabb<F> {
+        self.bound
+    }
+}
@@ -0,0 +1,214 @@
+use math::{Aabb, Bounded, BoundingVolume, Float, Point2D, Ray};
+use scenegraph::{BvhLeaf, CountedTraceable, LinearBvh, SahBuilder, TraversalCounters};
+use shading::Material;
+use std::fmt;
+use std::time::{Duration, Instant};
+use tracing::{Camera, Hitable, Traceable};
+
+/// Build and trace cost of one bounding volume type, see `benchmark_volume`.
+#[derive(Debug, Clone)]
+pub struct VolumeReport<F: Float> {
+    pub name: String,
+    pub build_time: Duration,
+    pub trace_time: Duration,
+    pub rays: usize,
+    pub hits: usize,
+    /// Summed over all rays.
+    pub counters: TraversalCounters,
+    /// `SahBuilder::sah_cost` of the tree, with the areas of the volume type
+    /// itself, so it is only roughly comparable between types.
+    pub sah_cost: F,
+}
+
+impl<F: Float> fmt::Display for VolumeReport<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let rays = self.rays.max(1) as f64;
+        let nanos = |d: Duration| d.as_secs() as f64 * 1e9 + f64::from(d.subsec_nanos());
+        write!(
+            f,
+            "{:>6}: build {:.1} ms, {:.0} ns/ray, {:.1} nodes and {:.1} primitives per ray, \
+             {} hits, SAH cost {:.2}",
+            self.name,
+            nanos(self.build_time) * 1e-6,
+            nanos(self.trace_time) / rays,
+            f64::from(self.counters.node_visits) / rays,
+            f64::from(self.counters.primitive_tests) / rays,
+            self.hits,
+            self.sah_cost.to_f64().unwrap_or(0.0)
+        )
+    }
+}
+
+/// Primary rays through the centers of a `width` by `height` pixel grid.
+pub fn camera_rays<F, C>(camera: &C, width: usize, height: usize) -> Vec<Ray<F>>
+where
+    F: Float,
+    C: Camera<F>,
+{
+    let half = (F::one() + F::one()).recip();
+    let coordinate = |i: usize, size: usize| (F::from(i).unwrap() + half) / F::from(size).unwrap();
+    (0..width * height)
+        .map(|n| {
+            let point = Point2D::new(coordinate(n % width, width), coordinate(n / width, height));
+            camera.screen_ray(&point)
+        })
+        .collect()
+}
+
+/// Builds a `LinearBvh` bounded by `B` over `leaves` and traces all `rays`
+/// through it, once timed and once counting the traversal work. Run it with
+/// the same leaves and rays for each volume type to compare them.
+pub fn benchmark_volume<F, B, H, M, L>(
+    name: &str,
+    builder: &SahBuilder<F>,
+    leaves: Vec<L>,
+    rays: &[Ray<F>],
+) -> Option<VolumeReport<F>>
+where
+    F: Float,
+    B: BoundingVolume<F>,
+    H: Hitable<F, Material = M>,
+    M: Material<F>,
+    L: BvhLeaf<F, B, H, M> + Bounded<F, Aabb<F>>,
+{
+    let start = Instant::now();
+    let bvh = builder.build(leaves)?;
+    let sah_cost = builder.sah_cost(&bvh);
+    let bvh: LinearBvh<F, B, H, M, L> = LinearBvh::from_bvh(bvh);
+    let build_time = start.elapsed();
+
+    let start = Instant::now();
+    let hits = rays.iter().filter(|ray| bvh.trace(ray).is_some()).count();
+    let trace_time = start.elapsed();
+
+    let mut counters = TraversalCounters::default();
+    for ray in rays {
+        bvh.trace_counted(ray, &mut counters);
+    }
+
+    Some(VolumeReport {
+        name: name.to_string(),
+        build_time,
+        trace_time,
+        rays: rays.len(),
+        hits,
+        counters,
+        sah_cost,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use math::{Dop14, Obb, Point, Transform, Vector};
+    use scenegraph::{ShadedSphere, Sphere, Triangle};
+    use shading::DebugNormalMaterial;
+    use tracing::PlaneCamera;
+
+    type Tri = Triangle<f64, DebugNormalMaterial>;
+
+    /// Long thin ribbon wound around a tilted axis, the kind of geometry
+    /// where axis aligned boxes are loose.
+    fn tilted_ribbon(segments: usize) -> Vec<Tri> {
+        let tilt = Transform::rotation(Vector::new(1.0, 0.3, 0.5), 0.8);
+        let point = |i: usize, side: f64| {
+            let t = i as f64 / segments as f64;
+            let angle = t * 40.0;
+            let radius = 4.0 + side * 0.2;
+            tilt.apply_point(Point::new(
+                radius * angle.cos(),
+                t * 8.0,
+                radius * angle.sin(),
+            ))
+        };
+        (0..segments)
+            .flat_map(|i| {
+                let quad = [
+                    point(i, 0.0),
+                    point(i, 1.0),
+                    point(i + 1, 1.0),
+                    point(i + 1, 0.0),
+                ];
+                vec![
+                    Triangle::new([quad[0], quad[1], quad[2]], DebugNormalMaterial {}),
+                    Triangle::new([quad[0], quad[2], quad[3]], DebugNormalMaterial {}),
+                ]
+            })
+            .collect()
+    }
+
+    fn rays(width: usize, height: usize) -> Vec<Ray<f64>> {
+        let eye = Point::new(0.0, 4.0, 25.0);
+        let direction = (Point::new(0.0, 4.0, 0.0) - eye).normalized();
+        let camera = PlaneCamera::new(eye, direction, Vector::plus_y(), 1.0);
+        camera_rays(&camera, width, height)
+    }
+
+    #[test]
+    fn test_volume_types_agree() {
+        let builder = SahBuilder::new();
+        let ribbon = tilted_ribbon(400);
+        let rays = rays(24, 24);
+
+        let aabb = benchmark_volume::<_, Aabb<_>, _, _, _>("aabb", &builder, ribbon.clone(), &rays);
+        let aabb = aabb.unwrap();
+        assert!(aabb.hits > 0);
+
+        let sphere =
+            benchmark_volume::<_, Sphere<_>, _, _, _>("sphere", &builder, ribbon.clone(), &rays);
+        let obb = benchmark_volume::<_, Obb<_>, _, _, _>("obb", &builder, ribbon.clone(), &rays);
+        let dop = benchmark_volume::<_, Dop14<_>, _, _, _>("dop14", &builder, ribbon, &rays);
+        for report in &[sphere.unwrap(), obb.unwrap(), dop.unwrap()] {
+            assert_eq!(report.hits, aabb.hits, "{}", report);
+        }
+    }
+
+    /// Prints the comparison for a ribbon and a cloud of spheres, run it
+    /// with `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_bounding_volumes() {
+        let builder = SahBuilder::new();
+        let rays = rays(400, 400);
+
+        let ribbon = tilted_ribbon(200_000);
+        println!("ribbon, {} triangles", ribbon.len());
+        let reports = vec![
+            benchmark_volume::<_, Aabb<_>, _, _, _>("aabb", &builder, ribbon.clone(), &rays),
+            benchmark_volume::<_, Sphere<_>, _, _, _>("sphere", &builder, ribbon.clone(), &rays),
+            benchmark_volume::<_, Obb<_>, _, _, _>("obb", &builder, ribbon.clone(), &rays),
+            benchmark_volume::<_, Dop14<_>, _, _, _>("dop14", &builder, ribbon, &rays),
+        ];
+        for report in reports {
+            println!("{}", report.unwrap());
+        }
+
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 11) as f64 / (1u64 << 53) as f64
+        };
+        let spheres: Vec<_> = (0..200_000)
+            .map(|_| {
+                let center = Point::new(
+                    next() * 20.0 - 10.0,
+                    next() * 20.0 - 6.0,
+                    next() * 20.0 - 10.0,
+                );
+                ShadedSphere::new(center, 0.01 + next() * 0.05, DebugNormalMaterial {})
+            })
+            .collect();
+        println!("{} spheres", spheres.len());
+        let reports = vec![
+            benchmark_volume::<_, Aabb<_>, _, _, _>("aabb", &builder, spheres.clone(), &rays),
+            benchmark_volume::<_, Sphere<_>, _, _, _>("sphere", &builder, spheres.clone(), &rays),
+            benchmark_volume::<_, Obb<_>, _, _, _>("obb", &builder, spheres.clone(), &rays),
+            benchmark_volume::<_, Dop14<_>, _, _, _>("dop14", &builder, spheres, &rays),
+        ];
+        for report in reports {
+            println!("{}", report.unwrap());
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use fibers::Spawn;
+use futures::Future;
+use light::{BounceQuota, Spectrum};
+use math::Float;
+use shading::{ConstantMaterial, DebugNormalMaterial, Material};
+use tracing::HitPointData;
+
+/// Enum over all materials of this crate, for scenes mixing materials in one
+/// `Bvh`. `Material::evaluate` is generic over the executor handle, so
+/// materials cannot be boxed as trait objects and new materials are added as
+/// variants here instead.
+///
+/// Scenes using a single material type should keep using it directly and
+/// skip the dispatch.
+#[derive(Clone, Debug)]
+pub enum AnyMaterial<F: Float> {
+    DebugNormal(DebugNormalMaterial),
+    Constant(ConstantMaterial<F>),
+}
+
+impl<F: Float> Material<F> for AnyMaterial<F> {
+    fn evaluate<H: Spawn + Clone>(
+        &self,
+        hit_point: HitPointData<F>,
+        quota: BounceQuota,
+        handle: H,
+    ) -> Box<dyn Future<Item = Spectrum<F>, Error = ()> + Send> {
+        match self {
+            AnyMaterial::DebugNormal(m) => m.evaluate(hit_point, quota, handle),
+            AnyMaterial::Constant(m) => m.evaluate(hit_point, quota, handle),
+        }
+    }
+}
+
+impl<F: Float> From<DebugNormalMaterial> for AnyMaterial<F> {
+    fn from(material: DebugNormalMaterial) -> Self {
+        AnyMaterial::DebugNormal(material)
+    }
+}
+
+impl<F: Float> From<ConstantMaterial<F>> for AnyMaterial<F> {
+    fn from(material: ConstantMaterial<F>) -> Self {
+        AnyMaterial::Constant(material)
+    }
+}
@@ -13,7 +13,7 @@ pub trait Material<F: Float>: Sized + Clone {
     ) -> Box<Future<Item = Spectrum<F>, Error = ()> + Send>;
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct DebugNormalMaterial {}
 
 impl<F: Float> Material<F> for DebugNormalMaterial {
@@ -37,3 +37,32 @@ impl<F: Float> Material<F> for DebugNormalMaterial {
         Box::new(finished(out))
     }
 }
+
+/// Returns the same spectrum for every hit, regardless of lighting.
+#[derive(Clone, Copy, Debug)]
+pub struct ConstantMaterial<F: Float> {
+    pub spectrum: Spectrum<F>,
+}
+
+impl<F: Float> ConstantMaterial<F> {
+    pub fn new(spectrum: Spectrum<F>) -> Self {
+        ConstantMaterial { spectrum }
+    }
+}
+
+impl<F: Float> Material<F> for ConstantMaterial<F> {
+    fn evaluate<H: Spawn + Clone>(
+        &self,
+        _hit_point: HitPointData<F>,
+        quota: BounceQuota,
+        _handle: H,
+    ) -> Box<dyn Future<Item = Spectrum<F>, Error = ()> + Send> {
+        let out = if quota.attempt(BounceType::Diffuse).is_some() {
+            self.spectrum
+        } else {
+            Spectrum::zero()
+        };
+
+        Box::new(finished(out))
+    }
+}
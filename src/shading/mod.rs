@@ -1,3 +1,7 @@
+mod any_material;
 mod material;
+mod texture;
 
+pub use self::any_material::*;
 pub use self::material::*;
+pub use self::texture::*;
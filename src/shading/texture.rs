@@ -0,0 +1,80 @@
+use math::{Float, UV};
+
+/// Single value looked up by texture coordinates, such as a displacement
+/// or roughness map. Closures of a `UV` work as procedural textures.
+pub trait ScalarTexture<F: Float> {
+    fn value(&self, uv: UV<F>) -> F;
+}
+
+impl<F: Float, T: Fn(UV<F>) -> F> ScalarTexture<F> for T {
+    fn value(&self, uv: UV<F>) -> F {
+        self(uv)
+    }
+}
+
+/// Grid of samples stretched over `0..1` in both directions, row after row
+/// with `v` growing down the rows. Lookups are bilinear between the sample
+/// centers and repeat outside of `0..1`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SampledTexture<F: Float> {
+    columns: usize,
+    rows: usize,
+    samples: Vec<F>,
+}
+
+impl<F: Float> SampledTexture<F> {
+    /// None if there are not exactly `columns * rows` samples, or none.
+    pub fn new(columns: usize, rows: usize, samples: Vec<F>) -> Option<Self> {
+        if samples.is_empty() || samples.len() != columns * rows {
+            return None;
+        }
+        Some(SampledTexture {
+            columns,
+            rows,
+            samples,
+        })
+    }
+
+    fn sample(&self, column: isize, row: isize) -> F {
+        let wrap = |i: isize, n: usize| i.rem_euclid(n as isize) as usize;
+        self.samples[wrap(row, self.rows) * self.columns + wrap(column, self.columns)]
+    }
+}
+
+impl<F: Float> ScalarTexture<F> for SampledTexture<F> {
+    fn value(&self, uv: UV<F>) -> F {
+        let half = (F::one() + F::one()).recip();
+        let x = uv.u * F::from(self.columns).unwrap() - half;
+        let y = uv.v * F::from(self.rows).unwrap() - half;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (column, row) = (x0.to_isize().unwrap_or(0), y0.to_isize().unwrap_or(0));
+        let top = fx.lerp(self.sample(column, row), self.sample(column + 1, row));
+        let bottom = fx.lerp(
+            self.sample(column, row + 1),
+            self.sample(column + 1, row + 1),
+        );
+        fy.lerp(top, bottom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bilinear_and_repeat() {
+        let texture = SampledTexture::new(2, 2, vec![0.0, 1.0, 2.0, 3.0]).unwrap();
+        // sample centers hit the samples, halfway between blends them
+        assert_eq!(texture.value(UV::new(0.25, 0.25)), 0.0);
+        assert_eq!(texture.value(UV::new(0.75, 0.75)), 3.0);
+        assert_eq!(texture.value(UV::new(0.5, 0.25)), 0.5);
+        assert_eq!(texture.value(UV::new(1.25, -0.75)), 0.0);
+        // across the border the last column blends into the first
+        assert_eq!(texture.value(UV::new(1.0, 0.25)), 0.5);
+
+        assert!(SampledTexture::<f64>::new(2, 2, vec![0.0; 3]).is_none());
+        let ramp = |uv: UV<f64>| uv.u;
+        assert_eq!(ramp.value(UV::new(0.5, 0.0)), 0.5);
+    }
+}
@@ -0,0 +1,23 @@
+//! Helpers shared by the test modules.
+
+use math::Vector;
+use std::ops::Sub;
+
+/// Whether two points or vectors are less than `1e-9` apart.
+pub fn close<T: Sub<Output = Vector<f64>>>(a: T, b: T) -> bool {
+    close_within(a, b, 1e-9)
+}
+
+pub fn close_within<T: Sub<Output = Vector<f64>>>(a: T, b: T, tolerance: f64) -> bool {
+    (a - b).magnitude() < tolerance
+}
+
+/// Pseudo random numbers in `0..1` from a linear congruential generator, the
+/// same sequence for the same seed on every run.
+pub fn lcg(seed: u32) -> impl FnMut() -> f64 {
+    let mut state = seed;
+    move || {
+        state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+        f64::from(state >> 8) / f64::from(1 << 24)
+    }
+}
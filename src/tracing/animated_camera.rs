@@ -0,0 +1,63 @@
+use math::{AnimatedTransform, Float, Point2D, Ray};
+use tracing::Camera;
+
+/// Camera carried along by `motion`, for motion blur of the whole picture.
+/// `screen_ray` and `lens_ray` give the rays of the camera at rest, which
+/// `at_time` moves to where the motion has taken it.
+#[derive(Debug, Clone)]
+pub struct AnimatedCamera<F: Float, C: Camera<F>> {
+    camera: C,
+    motion: AnimatedTransform<F>,
+}
+
+impl<F: Float, C: Camera<F>> AnimatedCamera<F, C> {
+    pub fn new(camera: C, motion: AnimatedTransform<F>) -> Self {
+        AnimatedCamera { camera, motion }
+    }
+}
+
+impl<F: Float, C: Camera<F>> Camera<F> for AnimatedCamera<F, C> {
+    fn screen_ray(&self, screen_pos: &Point2D<F>) -> Ray<F> {
+        self.camera.screen_ray(screen_pos)
+    }
+
+    fn lens_ray(&self, screen_pos: &Point2D<F>, lens_sample: &Point2D<F>) -> Ray<F> {
+        self.camera.lens_ray(screen_pos, lens_sample)
+    }
+
+    fn at_time(&self, ray: Ray<F>, time: F) -> Ray<F> {
+        let moved = self.camera.at_time(ray, time);
+        self.motion.at(time).apply_ray(&moved)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use math::{Point, Transform, Vector};
+    use tracing::PlaneCamera;
+
+    #[test]
+    fn test_rays_follow_the_motion() {
+        let still = PlaneCamera::new(Point::origin(), -Vector::plus_z(), Vector::plus_y(), 1.0);
+        let motion = AnimatedTransform::new(vec![
+            (0.0, Transform::identity()),
+            (1.0, Transform::translation(Vector::plus_x() * 2.0)),
+        ])
+        .unwrap();
+        let camera = AnimatedCamera::new(still.clone(), motion);
+
+        let center = Point2D::new(0.5, 0.5);
+        let ray = camera.at_time(camera.screen_ray(&center), 0.25);
+        assert_eq!(ray.origin, Point::new(0.5, 0.0, 0.0));
+        assert_eq!(ray.direction, -Vector::plus_z());
+        assert_eq!(ray.time, 0.25);
+
+        // after the last keyframe the camera stands still
+        let late = camera.at_time(camera.screen_ray(&center), 3.0);
+        assert_eq!(late.origin, Point::new(2.0, 0.0, 0.0));
+        let still_ray = still.at_time(still.screen_ray(&center), 3.0);
+        assert_eq!(still_ray.origin, Point::origin());
+        assert_eq!(still_ray.time, 3.0);
+    }
+}
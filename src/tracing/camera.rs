@@ -2,6 +2,27 @@ use math::{Float, Point, Point2D, Ray, Vector};
 
 pub trait Camera<F: Float>: Clone {
     fn screen_ray(&self, screen_pos: &Point2D<F>) -> Ray<F>;
+
+    /// Ray through `screen_pos` from the point of the lens picked by
+    /// `lens_sample`, from `0..1` on either axis. Cameras without a lens
+    /// ignore the sample.
+    fn lens_ray(&self, screen_pos: &Point2D<F>, _lens_sample: &Point2D<F>) -> Ray<F> {
+        self.screen_ray(screen_pos)
+    }
+
+    /// Ray of the camera sent at `time`, moved along with the camera if it
+    /// moves. Cameras standing still only set the time.
+    fn at_time(&self, ray: Ray<F>, time: F) -> Ray<F> {
+        ray.with_time(time)
+    }
+}
+
+/// Cameras that can also map points back onto the screen, for screen space
+/// measures such as adaptive tessellation.
+pub trait Projection<F: Float>: Camera<F> {
+    /// Screen position whose ray passes through `point`, the inverse of
+    /// `screen_ray`. None if no ray of the camera sees the point.
+    fn project(&self, point: &Point<F>) -> Option<Point2D<F>>;
 }
 
 #[derive(Debug, Clone)]
@@ -35,3 +56,42 @@ impl<F: Float> Camera<F> for PlaneCamera<F> {
         Ray::new(self.origin.clone(), dir)
     }
 }
+
+impl<F: Float> Projection<F> for PlaneCamera<F> {
+    fn project(&self, point: &Point<F>) -> Option<Point2D<F>> {
+        let normal = self.plane_x.cross(self.plane_y);
+        // how far the point is along the view direction, relative to the
+        // screen plane
+        let scale =
+            (self.plane_origin - self.origin).dot(normal) / (*point - self.origin).dot(normal);
+        if scale <= F::zero() || !scale.is_finite() {
+            return None;
+        }
+        let on_plane = self.origin + (*point - self.origin) * scale - self.plane_origin;
+        Some(Point2D::new(
+            on_plane.dot(self.plane_x) / self.plane_x.magnitude_sq(),
+            on_plane.dot(self.plane_y) / self.plane_y.magnitude_sq(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_project_inverts_screen_ray() {
+        let camera = PlaneCamera::new(
+            Point::new(1.0, 2.0, 3.0),
+            Vector::new(0.0, -0.5, -1.0),
+            Vector::plus_y(),
+            1.5,
+        );
+        for &(x, y) in &[(0.5f64, 0.5f64), (0.1, 0.9), (0.8, 0.3)] {
+            let ray = camera.screen_ray(&Point2D::new(x, y));
+            let projected = camera.project(&ray.point_at_distance(7.0)).unwrap();
+            assert!((projected.x - x).abs() < 1e-9 && (projected.y - y).abs() < 1e-9);
+        }
+        assert!(camera.project(&Point::new(1.0, 3.0, 5.0)).is_none());
+    }
+}
@@ -0,0 +1,193 @@
+use math::{Float, Point, Point2D, Ray, Vector};
+use tracing::{Camera, Projection};
+
+/// Which eye an omnidirectional stereo camera renders where.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StereoLayout {
+    LeftEye,
+    RightEye,
+    /// The left eye on the upper half of the screen, the right one below,
+    /// as most VR players expect.
+    OverUnder,
+}
+
+/// Full sphere of directions in latitude and longitude, for 360° panoramas
+/// meant for a 2:1 screen. `direction` is at the center of the screen, the
+/// longitude runs along x towards the same side as for a `PlaneCamera`, and
+/// `up` is at the top edge.
+///
+/// With `with_stereo` the rays start on a circle around `origin` the eye
+/// distance across, tangent to it, as in omni-directional stereo (ODS):
+/// each eye sees every direction from where it would be with the head
+/// turned towards it.
+#[derive(Debug, Clone)]
+pub struct EquirectangularCamera<F: Float> {
+    origin: Point<F>,
+    forward: Vector<F>,
+    right: Vector<F>,
+    up: Vector<F>,
+    stereo: Option<(F, StereoLayout)>,
+}
+
+impl<F: Float> EquirectangularCamera<F> {
+    pub fn new(origin: Point<F>, direction: Vector<F>, up: Vector<F>) -> Self {
+        let right = up.cross(direction).normalized();
+        EquirectangularCamera {
+            origin,
+            forward: direction.normalized(),
+            right,
+            up: direction.cross(right).normalized(),
+            stereo: None,
+        }
+    }
+
+    pub fn with_stereo(mut self, eye_distance: F, layout: StereoLayout) -> Self {
+        self.stereo = Some((eye_distance, layout));
+        self
+    }
+
+    /// Signed distance of the eye to the right of the view direction for a
+    /// screen position, and the position within the image of that eye.
+    fn eye(&self, screen_pos: &Point2D<F>) -> (F, Point2D<F>) {
+        let two = F::one() + F::one();
+        let half = two.recip();
+        match self.stereo {
+            None => (F::zero(), screen_pos.clone()),
+            Some((distance, StereoLayout::LeftEye)) => (-distance * half, screen_pos.clone()),
+            Some((distance, StereoLayout::RightEye)) => (distance * half, screen_pos.clone()),
+            Some((distance, StereoLayout::OverUnder)) => {
+                if screen_pos.y < half {
+                    (
+                        -distance * half,
+                        Point2D::new(screen_pos.x, screen_pos.y * two),
+                    )
+                } else {
+                    (
+                        distance * half,
+                        Point2D::new(screen_pos.x, screen_pos.y * two - F::one()),
+                    )
+                }
+            }
+        }
+    }
+
+    fn horizontal(&self, longitude: F) -> Vector<F> {
+        let (sin, cos) = longitude.sin_cos();
+        self.forward * cos + self.right * sin
+    }
+}
+
+impl<F: Float> Camera<F> for EquirectangularCamera<F> {
+    fn screen_ray(&self, screen_pos: &Point2D<F>) -> Ray<F> {
+        let (eye, image) = self.eye(screen_pos);
+        let half = (F::one() + F::one()).recip();
+        let longitude = (image.x - half) * (F::PI() + F::PI());
+        let latitude = (half - image.y) * F::PI();
+
+        let horizontal = self.horizontal(longitude);
+        let (sin, cos) = latitude.sin_cos();
+        // to the right of where the head is turned to
+        let side = self.up.cross(horizontal);
+        Ray::new(self.origin + side * eye, horizontal * cos + self.up * sin)
+    }
+}
+
+impl<F: Float> Projection<F> for EquirectangularCamera<F> {
+    /// With stereo, the position in the upper half of an over and under
+    /// image. None for points inside the circle of the eyes.
+    fn project(&self, point: &Point<F>) -> Option<Point2D<F>> {
+        let offset = *point - self.origin;
+        let (x, y, height) = (
+            offset.dot(self.forward),
+            offset.dot(self.right),
+            offset.dot(self.up),
+        );
+        let eye = self.eye(&Point2D::new(F::zero(), F::zero())).0;
+        let squared = x * x + y * y - eye * eye;
+        if squared < F::zero() || (squared == F::zero() && height == F::zero()) {
+            return None;
+        }
+
+        // the horizontal part of the ray is tangent to the circle of the eyes
+        let along = squared.sqrt();
+        let longitude = y.atan2(x) - eye.atan2(along);
+        let latitude = height.atan2(along);
+
+        let (two, half) = (F::one() + F::one(), (F::one() + F::one()).recip());
+        let tau = F::PI() + F::PI();
+        let turns = longitude / tau + half;
+        let wrapped = turns - turns.floor();
+        let image_y = half - latitude / F::PI();
+        let screen_y = match self.stereo {
+            Some((_, StereoLayout::OverUnder)) => image_y / two,
+            _ => image_y,
+        };
+        Some(Point2D::new(wrapped, screen_y))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use testing::close_within;
+
+    #[test]
+    fn test_full_sphere() {
+        let camera = EquirectangularCamera::new(
+            Point::new(1.0, 2.0, 3.0),
+            -Vector::plus_z() * 3.0,
+            Vector::plus_y(),
+        );
+        let at = |x: f64, y: f64| camera.screen_ray(&Point2D::new(x, y)).direction;
+        assert!(close_within(at(0.5, 0.5), -Vector::plus_z(), 1e-12));
+        assert!(close_within(at(0.0, 0.5), Vector::plus_z(), 1e-12));
+        assert!(close_within(at(0.3, 0.0), Vector::plus_y(), 1e-12));
+        assert!(close_within(at(0.5, 1.0), -Vector::plus_y(), 1e-12));
+        // a quarter turn towards the side a plane camera has at x = 1
+        let plane =
+            ::tracing::PlaneCamera::new(Point::origin(), -Vector::plus_z(), Vector::plus_y(), 1.0);
+        let side = plane.screen_ray(&Point2D::new(1.0, 0.5)).direction;
+        assert!(at(0.75, 0.5).dot(side) > 0.4);
+
+        for &(x, y) in &[(0.5f64, 0.5f64), (0.1, 0.9), (0.8, 0.3), (0.99, 0.45)] {
+            let ray = camera.screen_ray(&Point2D::new(x, y));
+            let projected = camera.project(&ray.point_at_distance(4.0)).unwrap();
+            assert!((projected.x - x).abs() < 1e-9 && (projected.y - y).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_omnidirectional_stereo() {
+        let mono = EquirectangularCamera::new(Point::origin(), -Vector::plus_z(), Vector::plus_y());
+        let stereo = mono.clone().with_stereo(0.064, StereoLayout::OverUnder);
+
+        // looking forward the eyes sit left and right of the center
+        let left = stereo.screen_ray(&Point2D::new(0.5, 0.25));
+        let right = stereo.screen_ray(&Point2D::new(0.5, 0.75));
+        assert!(
+            close_within(left.direction, -Vector::plus_z(), 1e-12)
+                && close_within(right.direction, -Vector::plus_z(), 1e-12)
+        );
+        let plane =
+            ::tracing::PlaneCamera::new(Point::origin(), -Vector::plus_z(), Vector::plus_y(), 1.0);
+        let side = plane.screen_ray(&Point2D::new(1.0, 0.5)).direction;
+        assert!((right.origin - left.origin).dot(side) > 0.0);
+        assert!(((right.origin - left.origin).magnitude() - 0.064).abs() < 1e-12);
+
+        // and swap places looking back
+        let back_left = stereo.screen_ray(&Point2D::new(0.0, 0.25));
+        assert!(close_within(
+            back_left.origin - Point::origin(),
+            right.origin - Point::origin(),
+            1e-12
+        ));
+
+        let left_eye = mono.with_stereo(0.064, StereoLayout::LeftEye);
+        for &(x, y) in &[(0.5f64, 0.5f64), (0.1, 0.9), (0.8, 0.3)] {
+            let ray = left_eye.screen_ray(&Point2D::new(x, y));
+            let projected = left_eye.project(&ray.point_at_distance(2.0)).unwrap();
+            assert!((projected.x - x).abs() < 1e-9 && (projected.y - y).abs() < 1e-9);
+        }
+        assert!(left_eye.project(&Point::new(0.01, 0.0, 0.0)).is_none());
+    }
+}
@@ -0,0 +1,153 @@
+use math::{Float, Point, Point2D, Ray, Vector};
+use tracing::{Camera, Projection};
+
+/// How a fisheye lens maps the angle from the view direction to the
+/// distance from the center of the screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FisheyeProjection {
+    /// Distance grows with the angle, for angular measurements.
+    Equidistant,
+    /// Equal solid angles cover equal areas of the screen, like most real
+    /// fisheye lenses.
+    Equisolid,
+}
+
+/// Fisheye camera covering `field_of_view` radians, up to a full turn,
+/// across the width of the screen. The screen axes are those of a
+/// `PlaneCamera`, with a circle of the width fully inside the field of view
+/// and the height shortened by the aspect ratio.
+#[derive(Debug, Clone)]
+pub struct FisheyeCamera<F: Float> {
+    origin: Point<F>,
+    forward: Vector<F>,
+    right: Vector<F>,
+    down: Vector<F>,
+    aspect_ratio: F,
+    field_of_view: F,
+    projection: FisheyeProjection,
+}
+
+impl<F: Float> FisheyeCamera<F> {
+    pub fn new(
+        origin: Point<F>,
+        direction: Vector<F>,
+        up: Vector<F>,
+        aspect_ratio: F,
+        field_of_view: F,
+        projection: FisheyeProjection,
+    ) -> Self {
+        let right = up.cross(direction).normalized();
+        let tau = F::PI() + F::PI();
+        FisheyeCamera {
+            origin,
+            forward: direction.normalized(),
+            right,
+            down: right.cross(direction).normalized(),
+            aspect_ratio,
+            field_of_view: field_of_view.max(F::epsilon()).min(tau),
+            projection,
+        }
+    }
+
+    /// Angle from the view direction at a distance from the center, where
+    /// 1 is the edge of the width. Clamped to looking straight back.
+    fn angle(&self, distance: F) -> F {
+        let two = F::one() + F::one();
+        let angle = match self.projection {
+            FisheyeProjection::Equidistant => distance * self.field_of_view / two,
+            FisheyeProjection::Equisolid => {
+                let sin = distance * (self.field_of_view / (two + two)).sin();
+                sin.min(F::one()).asin() * two
+            }
+        };
+        angle.min(F::PI())
+    }
+
+    fn distance(&self, angle: F) -> F {
+        let two = F::one() + F::one();
+        match self.projection {
+            FisheyeProjection::Equidistant => angle * two / self.field_of_view,
+            FisheyeProjection::Equisolid => {
+                (angle / two).sin() / (self.field_of_view / (two + two)).sin()
+            }
+        }
+    }
+}
+
+impl<F: Float> Camera<F> for FisheyeCamera<F> {
+    fn screen_ray(&self, screen_pos: &Point2D<F>) -> Ray<F> {
+        let (one, two) = (F::one(), F::one() + F::one());
+        let a = screen_pos.x * two - one;
+        let b = (screen_pos.y * two - one) / self.aspect_ratio;
+        let distance = a.hypot(b);
+        if distance == F::zero() {
+            return Ray::new(self.origin, self.forward);
+        }
+        let (sin, cos) = self.angle(distance).sin_cos();
+        let sideways = (self.right * a + self.down * b) * (distance.recip() * sin);
+        Ray::new(self.origin, self.forward * cos + sideways)
+    }
+}
+
+impl<F: Float> Projection<F> for FisheyeCamera<F> {
+    /// None only for the camera position itself.
+    fn project(&self, point: &Point<F>) -> Option<Point2D<F>> {
+        let offset = *point - self.origin;
+        let (a, b) = (offset.dot(self.right), offset.dot(self.down));
+        let sideways = a.hypot(b);
+        if sideways == F::zero() && offset.dot(self.forward) <= F::zero() {
+            return None;
+        }
+        let half = (F::one() + F::one()).recip();
+        if sideways == F::zero() {
+            return Some(Point2D::new(half, half));
+        }
+        let scale = self.distance(sideways.atan2(offset.dot(self.forward))) / sideways;
+        Some(Point2D::new(
+            a * scale * half + half,
+            b * scale * self.aspect_ratio * half + half,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fisheye_angles() {
+        let fov = 200f64.to_radians();
+        for &projection in &[FisheyeProjection::Equidistant, FisheyeProjection::Equisolid] {
+            let camera = FisheyeCamera::new(
+                Point::new(1.0, 2.0, 3.0),
+                Vector::new(0.0, 0.0, -2.0),
+                Vector::plus_y(),
+                1.5,
+                fov,
+                projection,
+            );
+            let center = camera.screen_ray(&Point2D::new(0.5, 0.5));
+            assert_eq!(center.direction, -Vector::plus_z());
+
+            // the side edges look half the field of view away from forward
+            let edge = camera.screen_ray(&Point2D::new(1.0, 0.5));
+            assert!((edge.direction.magnitude() - 1.0).abs() < 1e-12);
+            assert!((edge.direction.dot(-Vector::plus_z()).acos() - fov / 2.0).abs() < 1e-12);
+            // towards the same side as a plane camera
+            let plane = ::tracing::PlaneCamera::new(
+                Point::origin(),
+                -Vector::plus_z(),
+                Vector::plus_y(),
+                1.5,
+            );
+            assert!(edge.direction.x * plane.screen_ray(&Point2D::new(1.0, 0.5)).direction.x > 0.0);
+
+            for &(x, y) in &[(0.5f64, 0.5f64), (0.1, 0.9), (0.8, 0.3), (0.02, 0.5)] {
+                let ray = camera.screen_ray(&Point2D::new(x, y));
+                let projected = camera.project(&ray.point_at_distance(5.0)).unwrap();
+                assert!((projected.x - x).abs() < 1e-9 && (projected.y - y).abs() < 1e-9);
+            }
+            assert!(camera.project(&Point::new(1.0, 2.0, 3.0)).is_none());
+        }
+    }
+}
@@ -16,6 +16,9 @@ pub struct HitPointData<F: Float> {
     pub normal: Vector<F>,
     pub incoming_dir: Vector<F>,
     pub uv: UV<F>,
+    /// Direction along the surface for anisotropic shading, such as along
+    /// the fibers of a `Curve`.
+    pub tangent: Option<Vector<F>>,
 }
 
 impl<F: Float> HitPointData<F> {
@@ -25,8 +28,14 @@ impl<F: Float> HitPointData<F> {
             normal,
             incoming_dir,
             uv,
+            tangent: None,
         }
     }
+
+    pub fn with_tangent(mut self, tangent: Vector<F>) -> Self {
+        self.tangent = Some(tangent);
+        self
+    }
 }
 
 impl<F: Float, M: Material<F>> HitPoint<F, M> {
@@ -43,6 +52,11 @@ impl<F: Float, M: Material<F>> HitPoint<F, M> {
         }
     }
 
+    pub fn with_tangent(mut self, tangent: Vector<F>) -> Self {
+        self.data = self.data.with_tangent(tangent);
+        self
+    }
+
     pub fn evaluate_material<H: Spawn + Clone + Send + 'static>(
         self,
         quota: BounceQuota,
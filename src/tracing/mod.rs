@@ -1,7 +1,17 @@
+mod animated_camera;
 mod camera;
+mod equirectangular_camera;
+mod fisheye_camera;
 mod hit;
+mod orthographic_camera;
+mod thin_lens_camera;
 mod traceable;
 
+pub use self::animated_camera::*;
 pub use self::camera::*;
+pub use self::equirectangular_camera::*;
+pub use self::fisheye_camera::*;
 pub use self::hit::*;
+pub use self::orthographic_camera::*;
+pub use self::thin_lens_camera::*;
 pub use self::traceable::*;
@@ -0,0 +1,87 @@
+use math::{Float, Point, Point2D, Ray, Vector};
+use tracing::{Camera, Projection};
+
+/// Parallel projection, for elevations and plans without perspective. All
+/// rays point along `direction` and start on a screen `width` wide centered
+/// on `origin`, with the axes of a `PlaneCamera`.
+#[derive(Debug, Clone)]
+pub struct OrthographicCamera<F: Float> {
+    origin: Point<F>,
+    direction: Vector<F>,
+    screen_x: Vector<F>,
+    screen_y: Vector<F>,
+}
+
+impl<F: Float> OrthographicCamera<F> {
+    pub fn new(
+        origin: Point<F>,
+        direction: Vector<F>,
+        up: Vector<F>,
+        aspect_ratio: F,
+        width: F,
+    ) -> Self {
+        let right = up.cross(direction).normalized();
+        OrthographicCamera {
+            origin,
+            direction: direction.normalized(),
+            screen_x: right * width,
+            screen_y: right.cross(direction).normalized() * (width / aspect_ratio),
+        }
+    }
+}
+
+impl<F: Float> Camera<F> for OrthographicCamera<F> {
+    fn screen_ray(&self, screen_pos: &Point2D<F>) -> Ray<F> {
+        let half = (F::one() + F::one()).recip();
+        let start = self.origin
+            + self.screen_x * (screen_pos.x - half)
+            + self.screen_y * (screen_pos.y - half);
+        Ray::new(start, self.direction)
+    }
+}
+
+impl<F: Float> Projection<F> for OrthographicCamera<F> {
+    /// None for points behind the screen.
+    fn project(&self, point: &Point<F>) -> Option<Point2D<F>> {
+        let offset = *point - self.origin;
+        if offset.dot(self.direction) <= F::zero() {
+            return None;
+        }
+        let half = (F::one() + F::one()).recip();
+        Some(Point2D::new(
+            offset.dot(self.screen_x) / self.screen_x.magnitude_sq() + half,
+            offset.dot(self.screen_y) / self.screen_y.magnitude_sq() + half,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parallel_rays() {
+        let camera = OrthographicCamera::new(
+            Point::new(0.0, 2.0, 10.0),
+            -Vector::plus_z(),
+            Vector::plus_y(),
+            2.0,
+            8.0,
+        );
+        let center = camera.screen_ray(&Point2D::new(0.5, 0.5));
+        assert_eq!(center.origin, Point::new(0.0, 2.0, 10.0));
+        assert_eq!(center.direction, -Vector::plus_z());
+
+        // the same corners as a plane camera, only parallel
+        let corner = camera.screen_ray(&Point2D::new(0.0, 0.0));
+        assert_eq!(corner.direction, -Vector::plus_z());
+        assert_eq!(corner.origin, Point::new(4.0, 4.0, 10.0));
+
+        for &(x, y) in &[(0.5f64, 0.5f64), (0.1, 0.9), (0.8, 0.3)] {
+            let ray = camera.screen_ray(&Point2D::new(x, y));
+            let projected = camera.project(&ray.point_at_distance(3.0)).unwrap();
+            assert!((projected.x - x).abs() < 1e-12 && (projected.y - y).abs() < 1e-12);
+        }
+        assert!(camera.project(&Point::new(0.0, 0.0, 11.0)).is_none());
+    }
+}
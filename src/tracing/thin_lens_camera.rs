@@ -0,0 +1,298 @@
+use math::{Float, Point, Point2D, Ray, Vector};
+use tracing::{Camera, PlaneCamera, Projection};
+
+/// Weighted grid shaping the aperture, stretched over `-1..1` on both lens
+/// axes, with columns along the screen x axis and rows along y. Lens samples
+/// land in each cell in proportion to its weight.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApertureMask<F: Float> {
+    columns: usize,
+    rows: usize,
+    /// Cumulative share of the rows up to and including each row.
+    row_cdf: Vec<F>,
+    /// Cumulative share of the cells within their row.
+    cell_cdf: Vec<F>,
+}
+
+impl<F: Float> ApertureMask<F> {
+    /// Weights row after row. None unless there are `columns * rows` of
+    /// them, all positive or zero and not all zero.
+    pub fn new(columns: usize, rows: usize, weights: Vec<F>) -> Option<Self> {
+        if weights.len() != columns * rows || weights.iter().any(|&w| w < F::zero() || w.is_nan()) {
+            return None;
+        }
+        let total = weights.iter().fold(F::zero(), |sum, &w| sum + w);
+        if total <= F::zero() {
+            return None;
+        }
+
+        let mut row_cdf = Vec::with_capacity(rows);
+        let mut cell_cdf = Vec::with_capacity(weights.len());
+        let mut rows_so_far = F::zero();
+        for row in weights.chunks(columns) {
+            let row_total = row.iter().fold(F::zero(), |sum, &w| sum + w);
+            let mut cells_so_far = F::zero();
+            for &w in row {
+                cells_so_far = cells_so_far + w;
+                cell_cdf.push(if row_total > F::zero() {
+                    cells_so_far / row_total
+                } else {
+                    F::one()
+                });
+            }
+            rows_so_far = rows_so_far + row_total;
+            row_cdf.push(rows_so_far / total);
+        }
+        Some(ApertureMask {
+            columns,
+            rows,
+            row_cdf,
+            cell_cdf,
+        })
+    }
+
+    /// Index whose cumulative share first exceeds `u`, and the position of
+    /// `u` within its share.
+    fn invert(cdf: &[F], u: F) -> (usize, F) {
+        let index = cdf.iter().position(|&c| c > u).unwrap_or(cdf.len() - 1);
+        let start = if index == 0 {
+            F::zero()
+        } else {
+            cdf[index - 1]
+        };
+        let width = cdf[index] - start;
+        let f = if width > F::zero() {
+            (u - start) / width
+        } else {
+            F::zero()
+        };
+        (index, f.max(F::zero()).min(F::one()))
+    }
+
+    fn sample(&self, u: F, v: F) -> Point2D<F> {
+        let (row, fy) = Self::invert(&self.row_cdf, v);
+        let cells = &self.cell_cdf[row * self.columns..(row + 1) * self.columns];
+        let (column, fx) = Self::invert(cells, u);
+        let two = F::one() + F::one();
+        let spread = |index: usize, f: F, count: usize| {
+            (F::from(index).unwrap() + f) / F::from(count).unwrap() * two - F::one()
+        };
+        Point2D::new(spread(column, fx, self.columns), spread(row, fy, self.rows))
+    }
+}
+
+/// Shape of the aperture, which out of focus highlights take on.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Bokeh<F: Float> {
+    /// Round like a wide open lens, of the aperture radius.
+    Circle,
+    /// Regular polygon like a stopped down iris, with the corners on the
+    /// aperture radius and the first one `rotation` radians from the x axis.
+    Polygon { blades: usize, rotation: F },
+    /// Any shape, see `ApertureMask`. The aperture radius is half the width
+    /// of the mask.
+    Mask(ApertureMask<F>),
+}
+
+impl<F: Float> Bokeh<F> {
+    /// Point of the aperture, relative to its radius, for a sample from
+    /// `0..1` on either axis. Uniform samples give uniform points.
+    pub fn sample(&self, sample: &Point2D<F>) -> Point2D<F> {
+        let (u, v) = (sample.x, sample.y);
+        let two = F::one() + F::one();
+        match *self {
+            Bokeh::Circle => {
+                // concentric mapping of the square onto the disk, which keeps
+                // stratified samples stratified
+                let (a, b) = (u * two - F::one(), v * two - F::one());
+                if a == F::zero() && b == F::zero() {
+                    return Point2D::new(F::zero(), F::zero());
+                }
+                let quarter = F::FRAC_PI_4();
+                let (radius, angle) = if a.abs() > b.abs() {
+                    (a, quarter * (b / a))
+                } else {
+                    (b, F::FRAC_PI_2() - quarter * (a / b))
+                };
+                Point2D::new(radius * angle.cos(), radius * angle.sin())
+            }
+            Bokeh::Polygon { blades, rotation } => {
+                // one triangle of equal area per blade, then uniform in it
+                let blades = blades.max(3);
+                let scaled = u * F::from(blades).unwrap();
+                let index = scaled.floor().to_usize().unwrap_or(0).min(blades - 1);
+                let u = scaled - F::from(index).unwrap();
+                let corner = |i: usize| {
+                    let angle =
+                        rotation + F::from(i).unwrap() * two * F::PI() / F::from(blades).unwrap();
+                    (angle.cos(), angle.sin())
+                };
+                let ((x0, y0), (x1, y1)) = (corner(index), corner(index + 1));
+                let r = u.sqrt();
+                Point2D::new(r * v.lerp(x0, x1), r * v.lerp(y0, y1))
+            }
+            Bokeh::Mask(ref mask) => mask.sample(u, v),
+        }
+    }
+}
+
+/// Camera with a lens of finite aperture, which only keeps things at the
+/// focus distance sharp. The field of view is that of a `PlaneCamera` with
+/// the same arguments, and `screen_ray` and `project` behave like it, going
+/// through the center of the lens.
+///
+/// The focus distance is measured along the view direction, so the points
+/// in focus lie on a plane parallel to the screen.
+#[derive(Debug, Clone)]
+pub struct ThinLensCamera<F: Float> {
+    pinhole: PlaneCamera<F>,
+    axis: Vector<F>,
+    lens_x: Vector<F>,
+    lens_y: Vector<F>,
+    aperture_radius: F,
+    focus_distance: F,
+    bokeh: Bokeh<F>,
+}
+
+impl<F: Float> ThinLensCamera<F> {
+    /// A round aperture, see `with_bokeh` for other shapes.
+    pub fn new(
+        origin: Point<F>,
+        direction: Vector<F>,
+        up: Vector<F>,
+        aspect_ratio: F,
+        aperture_radius: F,
+        focus_distance: F,
+    ) -> Self {
+        let lens_x = up.cross(direction).normalized();
+        ThinLensCamera {
+            pinhole: PlaneCamera::new(origin, direction, up, aspect_ratio),
+            axis: direction.normalized(),
+            lens_x,
+            lens_y: lens_x.cross(direction).normalized(),
+            aperture_radius,
+            focus_distance,
+            bokeh: Bokeh::Circle,
+        }
+    }
+
+    pub fn with_bokeh(mut self, bokeh: Bokeh<F>) -> Self {
+        self.bokeh = bokeh;
+        self
+    }
+
+    pub fn aperture_radius(&self) -> F {
+        self.aperture_radius
+    }
+
+    pub fn focus_distance(&self) -> F {
+        self.focus_distance
+    }
+}
+
+impl<F: Float> Camera<F> for ThinLensCamera<F> {
+    fn screen_ray(&self, screen_pos: &Point2D<F>) -> Ray<F> {
+        self.pinhole.screen_ray(screen_pos)
+    }
+
+    fn lens_ray(&self, screen_pos: &Point2D<F>, lens_sample: &Point2D<F>) -> Ray<F> {
+        let chief = self.pinhole.screen_ray(screen_pos);
+        // every ray through the lens meets the chief ray on the focus plane
+        let focus = chief.point_at_distance(self.focus_distance / chief.direction.dot(self.axis));
+        let offset = self.bokeh.sample(lens_sample);
+        let lens_point =
+            chief.origin + (self.lens_x * offset.x + self.lens_y * offset.y) * self.aperture_radius;
+        Ray::new(lens_point, (focus - lens_point).normalized())
+    }
+}
+
+impl<F: Float> Projection<F> for ThinLensCamera<F> {
+    fn project(&self, point: &Point<F>) -> Option<Point2D<F>> {
+        self.pinhole.project(point)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use testing::lcg;
+
+    fn samples() -> Vec<Point2D<f64>> {
+        let mut next = lcg(4321);
+        (0..2000).map(|_| Point2D::new(next(), next())).collect()
+    }
+
+    #[test]
+    fn test_focus_plane_is_sharp() {
+        let camera = ThinLensCamera::new(
+            Point::new(0.0, 1.0, 5.0),
+            Vector::new(0.0, 0.0, -1.5),
+            Vector::plus_y(),
+            1.5,
+            0.2,
+            4.0,
+        )
+        .with_bokeh(Bokeh::Polygon {
+            blades: 6,
+            rotation: 0.3,
+        });
+        let screen = Point2D::new(0.3f64, 0.6);
+        let chief = camera.screen_ray(&screen);
+        let focus = chief.point_at_distance(4.0 / chief.direction.dot(-Vector::plus_z()));
+        assert!((focus.z - 1.0).abs() < 1e-12);
+        assert_eq!(
+            camera.project(&focus).map(|p| (p.x - 0.3).abs() < 1e-9),
+            Some(true)
+        );
+
+        let at_depth =
+            |ray: &Ray<f64>, z: f64| ray.point_at_distance((z - ray.origin.z) / ray.direction.z);
+        let mut spread = 0.0f64;
+        for sample in samples().iter().take(50) {
+            let ray = camera.lens_ray(&screen, sample);
+            // through the focus point, from somewhere on the lens
+            assert!((at_depth(&ray, 1.0) - focus).magnitude() < 1e-9);
+            assert!((ray.origin.z - 5.0).abs() < 1e-12);
+            // and spread out behind the focus plane
+            let behind = at_depth(&ray, -3.0) - at_depth(&chief, -3.0);
+            spread = spread.max(behind.magnitude());
+        }
+        assert!(spread > 0.1);
+    }
+
+    #[test]
+    fn test_bokeh_shapes() {
+        let inside_hexagon = |p: &Point2D<f64>| {
+            (0..6).all(|i| {
+                let angle = (i as f64 + 0.5) * ::std::f64::consts::PI / 3.0;
+                p.x * angle.cos() + p.y * angle.sin()
+                    <= (::std::f64::consts::PI / 6.0).cos() + 1e-12
+            })
+        };
+        let hexagon = Bokeh::Polygon {
+            blades: 6,
+            rotation: 0.0,
+        };
+        let mut far_right = 0.0f64;
+        for sample in samples() {
+            let circle = Bokeh::Circle.sample(&sample);
+            assert!(circle.x.hypot(circle.y) <= 1.0 + 1e-12);
+            let p = hexagon.sample(&sample);
+            assert!(inside_hexagon(&p));
+            far_right = far_right.max(p.x);
+        }
+        assert!(far_right > 0.9);
+
+        // a heart or star would do as well, only the right half is open here
+        let half = ApertureMask::new(2, 2, vec![0.0, 1.0, 0.0, 3.0]).unwrap();
+        let mask = Bokeh::Mask(half);
+        let points: Vec<_> = samples().iter().map(|s| mask.sample(s)).collect();
+        assert!(points
+            .iter()
+            .all(|p| p.x >= 0.0 && p.x <= 1.0 && p.y.abs() <= 1.0));
+        let lower = points.iter().filter(|p| p.y > 0.0).count() as f64 / points.len() as f64;
+        assert!((lower - 0.75).abs() < 0.05);
+        assert!(ApertureMask::new(2, 1, vec![0.0, 0.0]).is_none());
+        assert!(ApertureMask::new(2, 1, vec![-1.0, 2.0]).is_none());
+    }
+}
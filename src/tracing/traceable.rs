@@ -2,6 +2,8 @@ use math::{Float, Ray};
 use shading::Material;
 use tracing::Hitable;
 
+/// Anything a ray can be intersected with. Implementations return the
+/// closest hit strictly inside the `t_min`..`t_max` interval of the ray.
 pub trait Traceable<F, H, M>
 where
     F: Float,
@@ -9,4 +11,20 @@ where
     M: Material<F>,
 {
     fn trace(&self, ray: &Ray<F>) -> Option<(F, &H)>;
+
+    /// Objects returning `false` are ignored by `occluded`, so they are still
+    /// visible but do not block shadow rays.
+    fn casts_shadows(&self) -> bool {
+        true
+    }
+
+    /// Any-hit query for shadow rays: whether a shadow casting surface lies
+    /// inside the ray interval, additionally clipped to `t_max`. Unlike
+    /// `trace` it may stop at the first hit found instead of the closest.
+    fn occluded(&self, ray: &Ray<F>, t_max: F) -> bool {
+        self.casts_shadows()
+            && self
+                .trace(&ray.clone().with_t_max(F::min(ray.t_max, t_max)))
+                .is_some()
+    }
 }